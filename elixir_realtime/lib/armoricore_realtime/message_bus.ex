@@ -78,6 +78,12 @@ defmodule ArmoricoreRealtime.MessageBus do
     # Subscribe to media.ready events
     Gnat.sub(gnat, self(), "armoricore.media_ready", queue_group: "armoricore-realtime")
 
+    # Subscribe to media.processing_progress events
+    Gnat.sub(gnat, self(), "armoricore.media_processing_progress", queue_group: "armoricore-realtime")
+
+    # Subscribe to media.failed events
+    Gnat.sub(gnat, self(), "armoricore.media_failed", queue_group: "armoricore-realtime")
+
     # Subscribe to notification.sent events
     Gnat.sub(gnat, self(), "armoricore.notification_sent", queue_group: "armoricore-realtime")
 
@@ -118,6 +124,36 @@ defmodule ArmoricoreRealtime.MessageBus do
     end
   end
 
+  defp handle_event("armoricore.media_processing_progress", event) do
+    media_id = get_in(event, ["payload", "media_id"])
+
+    if media_id do
+      # Progress is frequent, so it only goes to the media-specific topic
+      Phoenix.PubSub.broadcast(
+        ArmoricoreRealtime.PubSub,
+        "media:#{media_id}",
+        {:media_processing_progress, event}
+      )
+    else
+      Logger.warning("Media processing progress event missing media_id: #{inspect(event)}")
+    end
+  end
+
+  defp handle_event("armoricore.media_failed", event) do
+    Logger.info("Media failed event: #{inspect(event)}")
+    media_id = get_in(event, ["payload", "media_id"])
+
+    if media_id do
+      Phoenix.PubSub.broadcast(
+        ArmoricoreRealtime.PubSub,
+        "media:#{media_id}",
+        {:media_failed, event}
+      )
+    else
+      Logger.warning("Media failed event missing media_id: #{inspect(event)}")
+    end
+  end
+
   defp handle_event("armoricore.notification_sent", event) do
     Logger.info("Notification sent event: #{inspect(event)}")
     
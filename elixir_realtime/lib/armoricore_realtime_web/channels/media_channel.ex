@@ -18,6 +18,7 @@ defmodule ArmoricoreRealtimeWeb.MediaChannel do
   
   Handles:
   - Media processing status updates
+  - Per-rendition transcoding progress
   - Media ready notifications
   - Transcription completion notifications
   """
@@ -49,6 +50,22 @@ defmodule ArmoricoreRealtimeWeb.MediaChannel do
     {:noreply, socket}
   end
 
+  @impl true
+  def handle_info({:media_processing_progress, event}, socket) do
+    # Push processing_progress event to client
+    push(socket, "processing_progress", event["payload"])
+    {:noreply, socket}
+  end
+
+  @impl true
+  def handle_info({:media_failed, event}, socket) do
+    Logger.info("Media failed notification for media_id: #{socket.assigns.media_id}")
+
+    # Push media_failed event to client
+    push(socket, "media_failed", event["payload"])
+    {:noreply, socket}
+  end
+
   @impl true
   def handle_info({:transcription_complete, event}, socket) do
     Logger.info("Transcription complete notification for media_id: #{socket.assigns.media_id}")
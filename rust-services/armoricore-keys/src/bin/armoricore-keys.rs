@@ -0,0 +1,859 @@
+//! Key management CLI for the Armoricore key store
+//!
+//! Usage:
+//!   cargo run --bin armoricore-keys --package armoricore-keys -- <COMMAND>
+//!
+//! Commands cover listing, inspecting, generating, rotating, deleting and
+//! verifying keys, exporting/importing encrypted bundles, migrating
+//! environment variables into the store, sealing/unsealing the store, and
+//! issuing/verifying JWTs and publishing JWKS documents.
+//! Pass `--json` for machine-readable output.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use anyhow::{bail, Context};
+use armoricore_keys::local_store::LocalKeyStore;
+use armoricore_keys::jwks_http::serve_jwks;
+use armoricore_keys::unseal_http::{SEAL_STATUS_PATH, UNSEAL_PATH, UNSEAL_TOKEN_ENV};
+use armoricore_keys::{
+    export_bundle, import_bundle, init_key_store, keypair, JwtService, KeyBundle, KeyMetadata,
+    KeyStore, KeyType, SealStatus,
+};
+use clap::{Args, Parser, Subcommand};
+use rand::RngCore;
+use serde_json::json;
+use std::env;
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variables migrated by the `migrate` command (env var, key id)
+const MIGRATED_ENV_VARS: &[(&str, &str)] = &[
+    ("JWT_SECRET", "jwt.secret"),
+    ("FCM_API_KEY", "fcm.api_key"),
+    ("APNS_KEY_ID", "apns.key_id"),
+    ("APNS_TEAM_ID", "apns.team_id"),
+    ("APNS_BUNDLE_ID", "apns.bundle_id"),
+    ("SMTP_USERNAME", "smtp.username"),
+    ("SMTP_PASSWORD", "smtp.password"),
+    ("OBJECT_STORAGE_ACCESS_KEY", "object_storage.access_key"),
+    ("OBJECT_STORAGE_SECRET_KEY", "object_storage.secret_key"),
+];
+
+#[derive(Parser)]
+#[command(name = "armoricore-keys", version, about = "Manage keys in the Armoricore key store")]
+struct Cli {
+    /// Key storage directory (defaults to KEY_STORAGE_PATH or ./keys)
+    #[arg(long, global = true)]
+    store_path: Option<String>,
+
+    /// Emit JSON instead of human-readable output
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Unseal share for a sealed store (repeatable; also read from the
+    /// comma-separated ARMORICORE_UNSEAL_SHARES)
+    #[arg(long = "unseal-share", global = true)]
+    unseal_shares: Vec<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all keys
+    List {
+        /// Only list keys of this type
+        #[arg(long = "type")]
+        key_type: Option<KeyType>,
+    },
+    /// Show metadata (never the value) for a key
+    ShowMetadata {
+        key_id: String,
+    },
+    /// Generate a new random key
+    Generate {
+        key_id: String,
+        /// Key type (jwt_secret, api_key, encryption_key, object_storage_key,
+        /// object_storage_secret, apns_key, secret, ed25519_key_pair,
+        /// ecdsa_p256_key_pair, rsa_key_pair)
+        #[arg(long = "type", default_value = "secret")]
+        key_type: KeyType,
+        /// Number of random bytes (ignored for key pairs)
+        #[arg(long, default_value_t = 32)]
+        length: usize,
+        /// Additional metadata as a JSON object of strings
+        #[arg(long)]
+        metadata: Option<String>,
+    },
+    /// Rotate a key to a new version
+    Rotate {
+        key_id: String,
+        #[command(flatten)]
+        value: NewValue,
+        /// Number of random bytes when generating the new value
+        #[arg(long, default_value_t = 32)]
+        length: usize,
+    },
+    /// Export keys to a passphrase-encrypted bundle
+    Export {
+        /// Bundle file to write
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Keys to export (all keys if omitted)
+        #[arg(long = "key")]
+        keys: Vec<String>,
+        /// Environment variable holding the bundle passphrase
+        #[arg(long, default_value = "ARMORICORE_BUNDLE_PASSPHRASE")]
+        passphrase_env: String,
+    },
+    /// Import keys from a passphrase-encrypted bundle
+    Import {
+        /// Bundle file to read
+        #[arg(long, short)]
+        input: PathBuf,
+        /// Rotate existing keys to the bundled value instead of skipping them
+        #[arg(long)]
+        overwrite: bool,
+        /// Environment variable holding the bundle passphrase
+        #[arg(long, default_value = "ARMORICORE_BUNDLE_PASSPHRASE")]
+        passphrase_env: String,
+    },
+    /// Delete a key and all its versions
+    Delete {
+        key_id: String,
+        /// Confirm deletion
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Verify that keys decrypt and have consistent metadata
+    Verify {
+        /// Keys to verify (all keys if omitted)
+        key_ids: Vec<String>,
+    },
+    /// Import known environment variables into the key store
+    Migrate,
+    /// Put the store into sealed mode and print its Shamir unseal shares
+    ///
+    /// An existing store is sealed with its current ARMORICORE_MASTER_KEY,
+    /// which should be removed from the environment afterwards.
+    SealInit {
+        /// Number of shares to issue
+        #[arg(long, default_value_t = 5)]
+        shares: u8,
+        /// Number of shares required to unseal
+        #[arg(long, default_value_t = 3)]
+        threshold: u8,
+    },
+    /// Show the seal state of the store or of a running service
+    SealStatus {
+        /// Base URL of a service exposing the unseal endpoints
+        #[arg(long)]
+        url: Option<String>,
+        /// Admin token of the service's unseal listener (defaults to UNSEAL_ADMIN_TOKEN)
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// Issue a JWT signed with a key
+    IssueJwt {
+        key_id: String,
+        /// Claims as a JSON object
+        #[arg(long, default_value = "{}")]
+        claims: String,
+        /// Lifetime in seconds
+        #[arg(long, default_value_t = 3600)]
+        ttl: u64,
+        #[command(flatten)]
+        options: JwtOptions,
+    },
+    /// Verify a JWT and print its claims
+    VerifyJwt {
+        key_id: String,
+        token: String,
+        #[command(flatten)]
+        options: JwtOptions,
+    },
+    /// Print a JWKS document with the public keys of key pairs
+    Jwks {
+        /// Key pairs to publish
+        #[arg(long = "key", required = true)]
+        keys: Vec<String>,
+        /// Write to a file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Serve the document at /.well-known/jwks.json on this address
+        #[arg(long, conflicts_with = "output")]
+        listen: Option<SocketAddr>,
+    },
+    /// Submit an unseal share to a running service
+    Unseal {
+        /// Base URL of the service's unseal listener
+        #[arg(long, default_value = "http://127.0.0.1:8200")]
+        url: String,
+        /// Share to submit (read from stdin if omitted)
+        #[arg(long)]
+        share: Option<String>,
+        /// Admin token of the service's unseal listener (defaults to UNSEAL_ADMIN_TOKEN)
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct JwtOptions {
+    /// Issuer (`iss`) to set or require
+    #[arg(long)]
+    issuer: Option<String>,
+    /// Audience (`aud`) to set or require
+    #[arg(long)]
+    audience: Option<String>,
+}
+
+impl JwtOptions {
+    fn service(self, key_store: Arc<KeyStore>) -> JwtService {
+        let mut service = JwtService::new(key_store);
+        if let Some(issuer) = self.issuer {
+            service = service.with_issuer(issuer);
+        }
+        if let Some(audience) = self.audience {
+            service = service.with_audience(audience);
+        }
+        service
+    }
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct NewValue {
+    /// New value (prefer --value-env to keep secrets out of shell history)
+    #[arg(long)]
+    value: Option<String>,
+    /// Environment variable holding the new value
+    #[arg(long)]
+    value_env: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Logs go to stderr so JSON output on stdout stays parseable
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    // Commands that do not open the store themselves
+    match &cli.command {
+        Command::SealInit { shares, threshold } => {
+            return seal_init(cli.store_path.as_deref(), *threshold, *shares, cli.json).await
+        }
+        Command::SealStatus { url: Some(url), token } => {
+            let status = fetch_seal_status(url, admin_token(token)).await?;
+            return print_seal_status(&status, cli.json);
+        }
+        Command::Unseal { url, share, token } => {
+            return unseal_remote(url, share.clone(), admin_token(token), cli.json).await
+        }
+        _ => {}
+    }
+
+    let key_store = init_key_store(cli.store_path.as_deref()).await?;
+    unseal_local(&key_store, cli.unseal_shares.clone()).await?;
+
+    match cli.command {
+        Command::List { key_type } => list(&key_store, key_type, cli.json).await,
+        Command::ShowMetadata { key_id } => show_metadata(&key_store, &key_id, cli.json).await,
+        Command::Generate {
+            key_id,
+            key_type,
+            length,
+            metadata,
+        } => generate(&key_store, &key_id, key_type, length, metadata, cli.json).await,
+        Command::Rotate {
+            key_id,
+            value,
+            length,
+        } => rotate(&key_store, &key_id, value, length, cli.json).await,
+        Command::Export {
+            output,
+            keys,
+            passphrase_env,
+        } => export(&key_store, &output, &keys, &passphrase_env, cli.json).await,
+        Command::Import {
+            input,
+            overwrite,
+            passphrase_env,
+        } => import(&key_store, &input, overwrite, &passphrase_env, cli.json).await,
+        Command::Delete { key_id, yes } => delete(&key_store, &key_id, yes, cli.json).await,
+        Command::Verify { key_ids } => verify(&key_store, key_ids, cli.json).await,
+        Command::Migrate => migrate(&key_store, cli.json).await,
+        Command::IssueJwt {
+            key_id,
+            claims,
+            ttl,
+            options,
+        } => {
+            let service = options.service(key_store);
+            issue_jwt(&service, &key_id, &claims, ttl, cli.json).await
+        }
+        Command::VerifyJwt {
+            key_id,
+            token,
+            options,
+        } => {
+            let service = options.service(key_store);
+            let claims = service.verify(&key_id, &token).await?;
+            print_json(&claims)
+        }
+        Command::Jwks {
+            keys,
+            listen: Some(addr),
+            ..
+        } => Ok(serve_jwks(key_store, keys, addr).await?),
+        Command::Jwks { keys, output, .. } => jwks(key_store, &keys, output).await,
+        Command::SealStatus { url: None, .. } => match key_store.seal_status().await? {
+            Some(status) => print_seal_status(&status, cli.json),
+            None => bail!("Key store is not configured for sealed mode"),
+        },
+        Command::SealInit { .. } | Command::SealStatus { url: Some(_), .. } | Command::Unseal { .. } => {
+            unreachable!("handled before opening the store")
+        }
+    }
+}
+
+async fn list(key_store: &KeyStore, key_type: Option<KeyType>, as_json: bool) -> anyhow::Result<()> {
+    let mut key_ids = key_store.list_keys().await?;
+    key_ids.sort();
+
+    let mut keys = Vec::with_capacity(key_ids.len());
+    for key_id in &key_ids {
+        let metadata = key_store.get_metadata(key_id).await?;
+        if key_type.is_none_or(|t| t == metadata.key_type) {
+            keys.push(metadata);
+        }
+    }
+
+    if as_json {
+        let summaries: Vec<_> = keys
+            .iter()
+            .map(|m| {
+                json!({
+                    "id": m.id,
+                    "key_type": m.key_type,
+                    "current_version": m.current_version,
+                    "updated_at": m.updated_at,
+                })
+            })
+            .collect();
+        print_json(&summaries)?;
+    } else if keys.is_empty() {
+        println!("No keys found");
+    } else {
+        println!("{:<40} {:<22} {:>7}  UPDATED", "KEY ID", "TYPE", "VERSION");
+        for m in &keys {
+            println!(
+                "{:<40} {:<22} {:>7}  {}",
+                m.id,
+                m.key_type,
+                m.current_version,
+                format_timestamp(m.updated_at)
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn show_metadata(key_store: &KeyStore, key_id: &str, as_json: bool) -> anyhow::Result<()> {
+    let metadata = key_store.get_metadata(&key_id.to_string()).await?;
+
+    if as_json {
+        print_json(&metadata)
+    } else {
+        print_metadata(&metadata);
+        Ok(())
+    }
+}
+
+async fn generate(
+    key_store: &KeyStore,
+    key_id: &str,
+    key_type: KeyType,
+    length: usize,
+    metadata: Option<String>,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    if let Some(meta) = &metadata {
+        serde_json::from_str::<std::collections::HashMap<String, String>>(meta)
+            .context("--metadata must be a JSON object of strings")?;
+    }
+
+    let value = random_value(key_type, length)?;
+    key_store
+        .store_key(&key_id.to_string(), key_type, &value, metadata.as_deref())
+        .await?;
+
+    if as_json {
+        print_json(&json!({ "id": key_id, "key_type": key_type, "version": 1 }))
+    } else if key_type.is_key_pair() {
+        println!("Generated {} key {}", key_type, key_id);
+        Ok(())
+    } else {
+        println!("Generated {} key {} ({} random bytes)", key_type, key_id, length);
+        Ok(())
+    }
+}
+
+async fn rotate(
+    key_store: &KeyStore,
+    key_id: &str,
+    value: NewValue,
+    length: usize,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let key_id = key_id.to_string();
+    let metadata = key_store.get_metadata(&key_id).await?;
+
+    let new_value = match (value.value, value.value_env) {
+        (Some(v), _) => v.into_bytes(),
+        (None, Some(var)) => env::var(&var)
+            .with_context(|| format!("Environment variable {} is not set", var))?
+            .into_bytes(),
+        (None, None) => random_value(metadata.key_type, length)?,
+    };
+
+    let version = key_store.rotate_key_bytes(&key_id, &new_value).await?;
+
+    if as_json {
+        print_json(&json!({ "id": key_id, "version": version.version }))
+    } else {
+        println!("Rotated {} to version {}", key_id, version.version);
+        Ok(())
+    }
+}
+
+async fn export(
+    key_store: &KeyStore,
+    output: &PathBuf,
+    keys: &[String],
+    passphrase_env: &str,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let passphrase = read_passphrase(passphrase_env)?;
+    let bundle = export_bundle(key_store, keys, &passphrase).await?;
+    let count = if keys.is_empty() {
+        key_store.list_keys().await?.len()
+    } else {
+        keys.len()
+    };
+
+    tokio::fs::write(output, serde_json::to_vec_pretty(&bundle)?)
+        .await
+        .with_context(|| format!("Failed to write bundle to {}", output.display()))?;
+
+    if as_json {
+        print_json(&json!({ "output": output, "exported": count }))
+    } else {
+        println!("Exported {} keys to {}", count, output.display());
+        Ok(())
+    }
+}
+
+async fn import(
+    key_store: &KeyStore,
+    input: &PathBuf,
+    overwrite: bool,
+    passphrase_env: &str,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let passphrase = read_passphrase(passphrase_env)?;
+    let content = tokio::fs::read(input)
+        .await
+        .with_context(|| format!("Failed to read bundle from {}", input.display()))?;
+    let bundle: KeyBundle = serde_json::from_slice(&content).context("Invalid bundle file")?;
+
+    let summary = import_bundle(key_store, &bundle, &passphrase, overwrite).await?;
+
+    if as_json {
+        print_json(&summary)
+    } else {
+        println!("Imported:    {}", summary.imported.len());
+        println!("Overwritten: {}", summary.overwritten.len());
+        println!("Skipped:     {}", summary.skipped.len());
+        for key_id in &summary.skipped {
+            println!("  skipped {} (already exists, use --overwrite)", key_id);
+        }
+        Ok(())
+    }
+}
+
+async fn delete(key_store: &KeyStore, key_id: &str, yes: bool, as_json: bool) -> anyhow::Result<()> {
+    let key_id = key_id.to_string();
+    if !key_store.key_exists(&key_id).await {
+        bail!("Key not found: {}", key_id);
+    }
+    if !yes {
+        bail!("Refusing to delete {} without --yes", key_id);
+    }
+
+    key_store.delete_key(&key_id).await?;
+
+    if as_json {
+        print_json(&json!({ "id": key_id, "deleted": true }))
+    } else {
+        println!("Deleted {}", key_id);
+        Ok(())
+    }
+}
+
+async fn verify(key_store: &KeyStore, key_ids: Vec<String>, as_json: bool) -> anyhow::Result<()> {
+    let mut key_ids = if key_ids.is_empty() {
+        key_store.list_keys().await?
+    } else {
+        key_ids
+    };
+    key_ids.sort();
+
+    let mut results = Vec::with_capacity(key_ids.len());
+    let mut failures = 0;
+    for key_id in &key_ids {
+        match key_store.verify_key(key_id).await {
+            Ok(metadata) => results.push(json!({
+                "id": key_id,
+                "ok": true,
+                "version": metadata.current_version,
+            })),
+            Err(e) => {
+                failures += 1;
+                results.push(json!({ "id": key_id, "ok": false, "error": e.to_string() }));
+            }
+        }
+    }
+
+    if as_json {
+        print_json(&results)?;
+    } else {
+        for result in &results {
+            if result["ok"].as_bool().unwrap_or(false) {
+                println!("  ✅ {} (v{})", result["id"].as_str().unwrap_or_default(), result["version"]);
+            } else {
+                println!(
+                    "  ❌ {}: {}",
+                    result["id"].as_str().unwrap_or_default(),
+                    result["error"].as_str().unwrap_or_default()
+                );
+            }
+        }
+        println!("Verified {} keys, {} failed", key_ids.len(), failures);
+    }
+
+    if failures > 0 {
+        bail!("{} key(s) failed verification", failures);
+    }
+    Ok(())
+}
+
+async fn migrate(key_store: &KeyStore, as_json: bool) -> anyhow::Result<()> {
+    let mut migrated = Vec::new();
+    let mut skipped = Vec::new();
+    let mut errors = Vec::new();
+
+    for (env_var, key_id) in MIGRATED_ENV_VARS {
+        let key_id = key_id.to_string();
+        let Ok(value) = env::var(env_var) else {
+            skipped.push(json!({ "env_var": env_var, "reason": "not set" }));
+            continue;
+        };
+        if key_store.key_exists(&key_id).await {
+            skipped.push(json!({ "env_var": env_var, "key_id": key_id, "reason": "already exists" }));
+            continue;
+        }
+        match key_store.store_api_key(&key_id, &value, None).await {
+            Ok(()) => migrated.push(json!({ "env_var": env_var, "key_id": key_id })),
+            Err(e) => errors.push(json!({ "env_var": env_var, "key_id": key_id, "error": e.to_string() })),
+        }
+    }
+
+    if as_json {
+        print_json(&json!({ "migrated": migrated, "skipped": skipped, "errors": errors }))?;
+    } else {
+        println!("Migration Summary:");
+        println!("  ✅ Migrated: {}", migrated.len());
+        println!("  ⏭️  Skipped: {}", skipped.len());
+        println!("  ❌ Errors: {}", errors.len());
+        for error in &errors {
+            println!("     {}: {}", error["env_var"], error["error"]);
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("{} key(s) failed to migrate", errors.len());
+    }
+    Ok(())
+}
+
+async fn issue_jwt(
+    service: &JwtService,
+    key_id: &str,
+    claims: &str,
+    ttl: u64,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let claims: serde_json::Value =
+        serde_json::from_str(claims).context("--claims must be a JSON object")?;
+    let token = service
+        .issue(&key_id.to_string(), &claims, Duration::from_secs(ttl))
+        .await?;
+
+    if as_json {
+        print_json(&json!({ "token": token }))
+    } else {
+        println!("{}", token);
+        Ok(())
+    }
+}
+
+async fn jwks(
+    key_store: Arc<KeyStore>,
+    keys: &[String],
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let jwks = JwtService::new(key_store).jwks(keys).await?;
+    match output {
+        Some(path) => tokio::fs::write(&path, serde_json::to_vec_pretty(&jwks)?)
+            .await
+            .with_context(|| format!("Failed to write JWKS to {}", path.display())),
+        None => print_json(&jwks),
+    }
+}
+
+async fn seal_init(
+    store_path: Option<&str>,
+    threshold: u8,
+    shares: u8,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let path = store_path
+        .map(|p| p.to_string())
+        .or_else(|| env::var("KEY_STORAGE_PATH").ok())
+        .unwrap_or_else(|| "./keys".to_string());
+    let master_key = env::var("ARMORICORE_MASTER_KEY")
+        .ok()
+        .map(|key| LocalKeyStore::parse_master_key(&key));
+
+    let issued =
+        LocalKeyStore::init_seal(&path, master_key.as_ref().map(|k| k.as_slice()), threshold, shares)
+            .await?;
+    let encoded: Vec<String> = issued.iter().map(|share| share.to_string()).collect();
+
+    if as_json {
+        print_json(&json!({ "threshold": threshold, "shares": encoded }))
+    } else {
+        println!("Sealed {} ({} of {} shares required to unseal)", path, threshold, shares);
+        println!("Distribute each share to a different holder; they are not stored anywhere.");
+        for (i, share) in encoded.iter().enumerate() {
+            println!("  Share {}: {}", i + 1, share);
+        }
+        if master_key.is_some() {
+            println!("Remove ARMORICORE_MASTER_KEY from service environments.");
+        }
+        Ok(())
+    }
+}
+
+/// Unseal the CLI's own store with shares from flags or the environment
+async fn unseal_local(key_store: &KeyStore, mut shares: Vec<String>) -> anyhow::Result<()> {
+    if !key_store.is_sealed().await {
+        return Ok(());
+    }
+    if let Ok(from_env) = env::var("ARMORICORE_UNSEAL_SHARES") {
+        shares.extend(from_env.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from));
+    }
+    for share in &shares {
+        if !key_store.unseal(share).await?.sealed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Admin token from `--token`, falling back to UNSEAL_ADMIN_TOKEN
+fn admin_token(token: &Option<String>) -> Option<String> {
+    token
+        .clone()
+        .or_else(|| std::env::var(UNSEAL_TOKEN_ENV).ok())
+        .filter(|token| !token.is_empty())
+}
+
+fn with_admin_token(
+    request: reqwest::RequestBuilder,
+    token: Option<String>,
+) -> reqwest::RequestBuilder {
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+async fn fetch_seal_status(url: &str, token: Option<String>) -> anyhow::Result<SealStatus> {
+    let request = reqwest::Client::new()
+        .get(format!("{}{}", url.trim_end_matches('/'), SEAL_STATUS_PATH));
+    let response = with_admin_token(request, token)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    parse_unseal_response(response).await
+}
+
+async fn unseal_remote(
+    url: &str,
+    share: Option<String>,
+    token: Option<String>,
+    as_json: bool,
+) -> anyhow::Result<()> {
+    let share = match share {
+        Some(share) => share,
+        None => {
+            eprint!("Unseal share: ");
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line)?;
+            line.trim().to_string()
+        }
+    };
+
+    let request = reqwest::Client::new()
+        .post(format!("{}{}", url.trim_end_matches('/'), UNSEAL_PATH))
+        .json(&json!({ "share": share }));
+    let response = with_admin_token(request, token)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {}", url))?;
+    let status = parse_unseal_response(response).await?;
+    print_seal_status(&status, as_json)
+}
+
+async fn parse_unseal_response(response: reqwest::Response) -> anyhow::Result<SealStatus> {
+    if !response.status().is_success() {
+        let code = response.status();
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        bail!(
+            "Request failed ({}): {}",
+            code,
+            body["error"].as_str().unwrap_or("unknown error")
+        );
+    }
+    Ok(response.json().await?)
+}
+
+fn print_seal_status(status: &SealStatus, as_json: bool) -> anyhow::Result<()> {
+    if as_json {
+        return print_json(status);
+    }
+    if status.sealed {
+        println!(
+            "Sealed ({}/{} shares submitted, {} issued)",
+            status.progress, status.threshold, status.shares
+        );
+    } else {
+        println!("Unsealed ({} of {} shares required)", status.threshold, status.shares);
+    }
+    Ok(())
+}
+
+/// Generate a random key value. Encryption and content keys are raw bytes and key pairs
+/// are PKCS#8 DER private keys; every other type is hex-encoded so it can be
+/// read back as a UTF-8 string.
+fn random_value(key_type: KeyType, length: usize) -> anyhow::Result<Vec<u8>> {
+    if key_type.is_key_pair() {
+        return Ok(keypair::generate(key_type)?);
+    }
+    if length < 16 {
+        bail!("Key length must be at least 16 bytes");
+    }
+    let mut bytes = vec![0u8; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    Ok(match key_type {
+        KeyType::EncryptionKey | KeyType::ContentKey => bytes,
+        _ => hex::encode(bytes).into_bytes(),
+    })
+}
+
+fn read_passphrase(env_var: &str) -> anyhow::Result<String> {
+    let passphrase = env::var(env_var)
+        .with_context(|| format!("Bundle passphrase environment variable {} is not set", env_var))?;
+    if passphrase.is_empty() {
+        bail!("Bundle passphrase in {} is empty", env_var);
+    }
+    Ok(passphrase)
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_metadata(metadata: &KeyMetadata) {
+    println!("Key:             {}", metadata.id);
+    println!("Type:            {}", metadata.key_type);
+    println!("Current version: {}", metadata.current_version);
+    println!("Created:         {}", format_timestamp(metadata.created_at));
+    println!("Updated:         {}", format_timestamp(metadata.updated_at));
+    println!(
+        "Rotation period: {} days",
+        metadata.key_type.default_rotation_period_days()
+    );
+    if !metadata.metadata.is_empty() {
+        println!("Metadata:");
+        let mut entries: Vec<_> = metadata.metadata.iter().collect();
+        entries.sort();
+        for (k, v) in entries {
+            println!("  {} = {}", k, v);
+        }
+    }
+    println!("Versions:");
+    for version in &metadata.versions {
+        let status = if version.is_active {
+            "active"
+        } else if version.is_expired() {
+            "expired"
+        } else {
+            "inactive"
+        };
+        let expires = version
+            .expires_at
+            .map(format_timestamp)
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "  v{:<4} {:<9} created {}  expires {}",
+            version.version,
+            status,
+            format_timestamp(version.created_at),
+            expires
+        );
+    }
+}
+
+fn format_timestamp(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
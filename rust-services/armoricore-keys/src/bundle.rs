@@ -0,0 +1,343 @@
+//! Encrypted key bundles for exporting and importing keys between stores
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use crate::key_store::KeyStore;
+use crate::key_types::{KeyId, KeyMetadata};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{info, warn};
+
+/// Current bundle format version
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// Default PBKDF2 iteration count for deriving the bundle key
+pub const DEFAULT_KDF_ITERATIONS: u32 = 600_000;
+
+/// Lowest PBKDF2 iteration count a bundle may be sealed or opened with
+pub const MIN_KDF_ITERATIONS: u32 = 100_000;
+
+/// Passphrase-encrypted collection of keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyBundle {
+    /// Bundle format version
+    pub format_version: u32,
+    /// When the bundle was created
+    pub created_at: i64,
+    /// Key derivation function used for the passphrase
+    pub kdf: String,
+    /// PBKDF2 iteration count
+    pub kdf_iterations: u32,
+    /// KDF salt (hex)
+    pub salt: String,
+    /// AES-256-GCM nonce (hex)
+    pub nonce: String,
+    /// Encrypted bundle entries (hex)
+    pub ciphertext: String,
+}
+
+/// A single exported key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleEntry {
+    /// Key metadata at export time
+    pub metadata: KeyMetadata,
+    /// Active key value (hex)
+    pub value: String,
+}
+
+/// Result of importing a bundle
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    /// Keys that were created
+    pub imported: Vec<KeyId>,
+    /// Existing keys that were rotated to the bundled value
+    pub overwritten: Vec<KeyId>,
+    /// Existing keys that were left untouched
+    pub skipped: Vec<KeyId>,
+}
+
+impl KeyBundle {
+    /// Encrypt entries into a bundle
+    pub fn seal(entries: &[BundleEntry], passphrase: &str) -> KeyResult<Self> {
+        Self::seal_with_iterations(entries, passphrase, DEFAULT_KDF_ITERATIONS)
+    }
+
+    /// Encrypt entries into a bundle with an explicit KDF iteration count
+    pub fn seal_with_iterations(
+        entries: &[BundleEntry],
+        passphrase: &str,
+        iterations: u32,
+    ) -> KeyResult<Self> {
+        if passphrase.is_empty() {
+            return Err(KeyError::Configuration(
+                "Bundle passphrase must not be empty".to_string(),
+            ));
+        }
+        check_iterations(iterations)?;
+
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let key = derive_bundle_key(passphrase, &salt, iterations);
+
+        let plaintext = serde_json::to_vec(entries)?;
+        let cipher = Aes256Gcm::new(&key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|e| KeyError::Encryption(format!("Bundle encryption failed: {}", e)))?;
+
+        Ok(Self {
+            format_version: BUNDLE_FORMAT_VERSION,
+            created_at: chrono::Utc::now().timestamp(),
+            kdf: "pbkdf2-sha256".to_string(),
+            kdf_iterations: iterations,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypt the bundle entries
+    pub fn open(&self, passphrase: &str) -> KeyResult<Vec<BundleEntry>> {
+        if self.format_version != BUNDLE_FORMAT_VERSION {
+            return Err(KeyError::InvalidFormat(format!(
+                "Unsupported bundle format version: {}",
+                self.format_version
+            )));
+        }
+        if self.kdf != "pbkdf2-sha256" {
+            return Err(KeyError::InvalidFormat(format!(
+                "Unsupported bundle KDF: {}",
+                self.kdf
+            )));
+        }
+        check_iterations(self.kdf_iterations)?;
+
+        let salt = decode_hex("salt", &self.salt)?;
+        let nonce_bytes = decode_hex("nonce", &self.nonce)?;
+        let ciphertext = decode_hex("ciphertext", &self.ciphertext)?;
+        if nonce_bytes.len() != 12 {
+            return Err(KeyError::InvalidFormat("Bundle nonce must be 12 bytes".to_string()));
+        }
+
+        let key = derive_bundle_key(passphrase, &salt, self.kdf_iterations);
+        let cipher = Aes256Gcm::new(&key.into());
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&nonce_bytes), ciphertext.as_slice())
+            .map_err(|_| {
+                KeyError::Decryption("Bundle decryption failed (wrong passphrase?)".to_string())
+            })?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+/// Export keys from a store into an encrypted bundle
+///
+/// If `key_ids` is empty, every key in the store is exported.
+pub async fn export_bundle(
+    store: &KeyStore,
+    key_ids: &[KeyId],
+    passphrase: &str,
+) -> KeyResult<KeyBundle> {
+    let entries = export_entries(store, key_ids).await?;
+    info!("Exporting {} keys to bundle", entries.len());
+    KeyBundle::seal(&entries, passphrase)
+}
+
+/// Read keys from a store as bundle entries
+///
+/// If `key_ids` is empty, every key in the store is read.
+pub async fn export_entries(store: &KeyStore, key_ids: &[KeyId]) -> KeyResult<Vec<BundleEntry>> {
+    let mut ids = if key_ids.is_empty() {
+        store.list_keys().await?
+    } else {
+        key_ids.to_vec()
+    };
+    ids.sort();
+
+    let mut entries = Vec::with_capacity(ids.len());
+    for key_id in &ids {
+        let metadata = store.get_metadata(key_id).await?;
+        let value = store.get_key(key_id).await?;
+        entries.push(BundleEntry {
+            metadata,
+            value: hex::encode(value),
+        });
+    }
+    Ok(entries)
+}
+
+/// Import keys from an encrypted bundle into a store
+///
+/// Existing keys are skipped unless `overwrite` is set, in which case they are
+/// rotated to the bundled value so their version history is preserved.
+pub async fn import_bundle(
+    store: &KeyStore,
+    bundle: &KeyBundle,
+    passphrase: &str,
+    overwrite: bool,
+) -> KeyResult<ImportSummary> {
+    let entries = bundle.open(passphrase)?;
+    let mut summary = ImportSummary::default();
+
+    for entry in entries {
+        let key_id = entry.metadata.id.clone();
+        let value = decode_hex("value", &entry.value)?;
+
+        if store.key_exists(&key_id).await {
+            if overwrite {
+                store.rotate_key_bytes(&key_id, &value).await?;
+                summary.overwritten.push(key_id);
+            } else {
+                warn!("Key {} already exists, skipping", key_id);
+                summary.skipped.push(key_id);
+            }
+            continue;
+        }
+
+        let metadata = if entry.metadata.metadata.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&entry.metadata.metadata)?)
+        };
+        store
+            .store_key(&key_id, entry.metadata.key_type, &value, metadata.as_deref())
+            .await?;
+        summary.imported.push(key_id);
+    }
+
+    info!(
+        "Bundle import complete: {} imported, {} overwritten, {} skipped",
+        summary.imported.len(),
+        summary.overwritten.len(),
+        summary.skipped.len()
+    );
+    Ok(summary)
+}
+
+fn derive_bundle_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+fn check_iterations(iterations: u32) -> KeyResult<()> {
+    if iterations < MIN_KDF_ITERATIONS {
+        return Err(KeyError::InvalidFormat(format!(
+            "Bundle KDF iteration count {} is below the minimum of {}",
+            iterations, MIN_KDF_ITERATIONS
+        )));
+    }
+    Ok(())
+}
+
+fn decode_hex(field: &str, value: &str) -> KeyResult<Vec<u8>> {
+    hex::decode(value)
+        .map_err(|e| KeyError::InvalidFormat(format!("Invalid bundle {}: {}", field, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_types::KeyType;
+    use crate::local_store::LocalKeyStore;
+    use std::sync::Arc;
+
+    async fn test_store(dir: &std::path::Path) -> KeyStore {
+        let backend = LocalKeyStore::new(dir, Some(&[7u8; 32])).await.unwrap();
+        KeyStore::new(Arc::new(backend))
+    }
+
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        let entries = vec![BundleEntry {
+            metadata: KeyMetadata::new("test.key".to_string(), KeyType::Secret),
+            value: hex::encode(b"secret-value"),
+        }];
+
+        let bundle = KeyBundle::seal_with_iterations(&entries, "passphrase", MIN_KDF_ITERATIONS).unwrap();
+        let opened = bundle.open("passphrase").unwrap();
+
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].metadata.id, "test.key");
+        assert_eq!(opened[0].value, hex::encode(b"secret-value"));
+    }
+
+    #[test]
+    fn test_open_with_wrong_passphrase_fails() {
+        let bundle = KeyBundle::seal_with_iterations(&[], "right", MIN_KDF_ITERATIONS).unwrap();
+        assert!(matches!(bundle.open("wrong"), Err(KeyError::Decryption(_))));
+    }
+
+    #[test]
+    fn test_seal_rejects_empty_passphrase() {
+        assert!(KeyBundle::seal(&[], "").is_err());
+    }
+
+    #[test]
+    fn test_weak_kdf_is_rejected() {
+        assert!(KeyBundle::seal_with_iterations(&[], "pw", 1).is_err());
+
+        let mut bundle = KeyBundle::seal_with_iterations(&[], "pw", MIN_KDF_ITERATIONS).unwrap();
+        bundle.kdf_iterations = 1;
+        assert!(matches!(bundle.open("pw"), Err(KeyError::InvalidFormat(_))));
+    }
+
+    #[tokio::test]
+    async fn test_export_import_between_stores() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let source = test_store(source_dir.path()).await;
+        let target = test_store(target_dir.path()).await;
+
+        source
+            .store_api_key(&"fcm.api_key".to_string(), "fcm-value", None)
+            .await
+            .unwrap();
+        source
+            .store_encryption_key(&"media.key".to_string(), &[1, 2, 3, 4])
+            .await
+            .unwrap();
+        target
+            .store_api_key(&"fcm.api_key".to_string(), "old-value", None)
+            .await
+            .unwrap();
+
+        let entries = export_entries(&source, &[]).await.unwrap();
+        let bundle = KeyBundle::seal_with_iterations(&entries, "pw", MIN_KDF_ITERATIONS).unwrap();
+
+        let summary = import_bundle(&target, &bundle, "pw", false).await.unwrap();
+        assert_eq!(summary.imported, vec!["media.key".to_string()]);
+        assert_eq!(summary.skipped, vec!["fcm.api_key".to_string()]);
+        assert_eq!(
+            target.get_encryption_key(&"media.key".to_string()).await.unwrap(),
+            vec![1, 2, 3, 4]
+        );
+
+        let summary = import_bundle(&target, &bundle, "pw", true).await.unwrap();
+        assert_eq!(summary.overwritten.len(), 2);
+        assert_eq!(
+            target.get_api_key(&"fcm.api_key".to_string()).await.unwrap(),
+            "fcm-value"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Software implementations of the cryptographic operations exposed by
+//! `KeyManagementService`, used by backends that hold raw key material
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes128Gcm, Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// AES-GCM nonce length in bytes
+pub const GCM_NONCE_LEN: usize = 12;
+
+/// AES-GCM tag length in bytes
+pub const GCM_TAG_LEN: usize = 16;
+
+/// Maximum HKDF-SHA256 output length (255 * hash length)
+pub const MAX_DERIVED_KEY_LEN: usize = 255 * 32;
+
+/// Generate `length` random bytes
+pub fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// AES-GCM encrypt; output is `nonce || ciphertext || tag`
+pub fn aes_gcm_encrypt(key: &[u8], plaintext: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+    let payload = Payload { msg: plaintext, aad };
+    let (nonce, ciphertext) = match key.len() {
+        16 => {
+            let nonce = Aes128Gcm::generate_nonce(&mut OsRng);
+            let cipher = Aes128Gcm::new_from_slice(key)
+                .map_err(|e| KeyError::Encryption(e.to_string()))?;
+            (nonce, cipher.encrypt(&nonce, payload))
+        }
+        32 => {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let cipher = Aes256Gcm::new_from_slice(key)
+                .map_err(|e| KeyError::Encryption(e.to_string()))?;
+            (nonce, cipher.encrypt(&nonce, payload))
+        }
+        n => {
+            return Err(KeyError::InvalidFormat(format!(
+                "AES-GCM key must be 16 or 32 bytes, got {}",
+                n
+            )))
+        }
+    };
+    let ciphertext =
+        ciphertext.map_err(|e| KeyError::Encryption(format!("Encryption failed: {}", e)))?;
+
+    let mut result = nonce.to_vec();
+    result.extend_from_slice(&ciphertext);
+    Ok(result)
+}
+
+/// AES-GCM decrypt data produced by [`aes_gcm_encrypt`]
+pub fn aes_gcm_decrypt(key: &[u8], data: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+    if data.len() < GCM_NONCE_LEN + GCM_TAG_LEN {
+        return Err(KeyError::Decryption("Encrypted data too short".to_string()));
+    }
+    let (nonce, ciphertext) = data.split_at(GCM_NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce);
+    let payload = Payload { msg: ciphertext, aad };
+
+    let plaintext = match key.len() {
+        16 => Aes128Gcm::new_from_slice(key)
+            .map_err(|e| KeyError::Decryption(e.to_string()))?
+            .decrypt(nonce, payload),
+        32 => Aes256Gcm::new_from_slice(key)
+            .map_err(|e| KeyError::Decryption(e.to_string()))?
+            .decrypt(nonce, payload),
+        n => {
+            return Err(KeyError::InvalidFormat(format!(
+                "AES-GCM key must be 16 or 32 bytes, got {}",
+                n
+            )))
+        }
+    };
+    plaintext.map_err(|e| KeyError::Decryption(format!("Decryption failed: {}", e)))
+}
+
+/// HMAC-SHA256
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> KeyResult<Vec<u8>> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .map_err(|e| KeyError::InvalidFormat(format!("Invalid HMAC key: {}", e)))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Compare two byte slices in constant time
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aes_gcm_roundtrip() {
+        for key_len in [16, 32] {
+            let key = random_bytes(key_len);
+            let encrypted = aes_gcm_encrypt(&key, b"payload", b"aad").unwrap();
+            assert_eq!(encrypted.len(), GCM_NONCE_LEN + 7 + GCM_TAG_LEN);
+            assert_eq!(aes_gcm_decrypt(&key, &encrypted, b"aad").unwrap(), b"payload");
+            assert!(aes_gcm_decrypt(&key, &encrypted, b"other").is_err());
+        }
+    }
+
+    #[test]
+    fn test_aes_gcm_rejects_bad_key_length() {
+        assert!(aes_gcm_encrypt(&[0u8; 20], b"payload", b"").is_err());
+    }
+
+    #[test]
+    fn test_hmac_sha256_rfc4231_case_2() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
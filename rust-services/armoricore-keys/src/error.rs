@@ -51,6 +51,12 @@ pub enum KeyError {
 
     #[error("Configuration error: {0}")]
     Configuration(String),
+
+    #[error("Key store is sealed")]
+    Sealed,
+
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
 }
 
 /// Result type for key operations
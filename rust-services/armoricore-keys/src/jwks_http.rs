@@ -0,0 +1,113 @@
+//! HTTP endpoint publishing the JWKS of signing key pairs
+//!
+//! Token verifiers such as the realtime service fetch [`JWKS_PATH`] to
+//! validate tokens against rotating public keys. The document is rebuilt on
+//! every request, so a rotation is published as soon as it happens and an
+//! expired version disappears with it.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::KeyResult;
+use crate::jwt::JwtService;
+use crate::key_store::KeyStore;
+use crate::key_types::KeyId;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Path of the JWKS document
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+
+/// How long verifiers may cache the document, in seconds
+const JWKS_MAX_AGE_SECS: u32 = 300;
+
+struct JwksState {
+    jwt: JwtService,
+    key_ids: Vec<KeyId>,
+}
+
+/// Build the JWKS route publishing the public keys of `key_ids`
+pub fn jwks_router(key_store: Arc<KeyStore>, key_ids: Vec<KeyId>) -> Router {
+    let state = JwksState {
+        jwt: JwtService::new(key_store),
+        key_ids,
+    };
+    Router::new()
+        .route(JWKS_PATH, get(jwks))
+        .with_state(Arc::new(state))
+}
+
+/// Serve the JWKS route on `addr` until the task is cancelled
+pub async fn serve_jwks(key_store: Arc<KeyStore>, key_ids: Vec<KeyId>, addr: SocketAddr) -> KeyResult<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(addr = %addr, keys = ?key_ids, "JWKS server started");
+    axum::serve(listener, jwks_router(key_store, key_ids)).await?;
+    Ok(())
+}
+
+async fn jwks(State(state): State<Arc<JwksState>>) -> Response {
+    match state.jwt.jwks(&state.key_ids).await {
+        Ok(jwks) => (
+            [(header::CACHE_CONTROL, format!("public, max-age={}", JWKS_MAX_AGE_SECS))],
+            Json(jwks),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!(error = %e, "Failed to build JWKS");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "JWKS unavailable" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_types::KeyType;
+    use crate::local_store::LocalKeyStore;
+
+    #[tokio::test]
+    async fn test_jwks_route_publishes_key_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalKeyStore::new(dir.path(), Some(&[6u8; 32])).await.unwrap();
+        let key_store = Arc::new(KeyStore::new(Arc::new(backend)));
+        let key_id = "jwt.signing".to_string();
+        key_store.generate_key(&key_id, KeyType::Ed25519KeyPair, 0).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), JWKS_PATH);
+        let app = jwks_router(key_store, vec![key_id]);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["cache-control"], "public, max-age=300");
+        let jwks: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(jwks["keys"][0]["kid"], "jwt.signing.v1");
+        assert_eq!(jwks["keys"][0]["alg"], "EdDSA");
+    }
+}
@@ -0,0 +1,444 @@
+//! JWT issuing and verification backed by the key store
+//!
+//! Tokens are signed in place through the key store: HS256 with `sign`
+//! (HMAC-SHA256) and ES256/EdDSA/RS256 with `sign_with_key_pair`. The `kid`
+//! header names the key and version (`<key_id>.v<version>`), so tokens signed
+//! before a rotation keep verifying while the previous version is unexpired,
+//! and [`JwtService::jwks`] publishes every such public key.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use crate::key_store::KeyStore;
+use crate::key_types::{KeyId, KeyMetadata, KeyType};
+use crate::keypair::{self, Jwk};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default clock skew tolerated when checking `exp` and `nbf`
+pub const DEFAULT_LEEWAY_SECS: i64 = 60;
+
+/// JWS signing algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    /// HMAC-SHA256 with a shared secret
+    HS256,
+    /// ECDSA P-256 with SHA-256
+    ES256,
+    /// Ed25519
+    EdDSA,
+    /// RSASSA-PKCS1-v1_5 with SHA-256
+    RS256,
+}
+
+impl JwtAlgorithm {
+    /// Algorithm used for keys of `key_type`
+    pub fn for_key_type(key_type: KeyType) -> KeyResult<Self> {
+        match key_type {
+            KeyType::JwtSecret | KeyType::Secret => Ok(JwtAlgorithm::HS256),
+            KeyType::EcdsaP256KeyPair => Ok(JwtAlgorithm::ES256),
+            KeyType::Ed25519KeyPair => Ok(JwtAlgorithm::EdDSA),
+            KeyType::RsaKeyPair => Ok(JwtAlgorithm::RS256),
+            other => Err(KeyError::InvalidFormat(format!(
+                "{} keys cannot sign JWTs",
+                other
+            ))),
+        }
+    }
+
+    /// JWS `alg` header value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JwtAlgorithm::HS256 => "HS256",
+            JwtAlgorithm::ES256 => "ES256",
+            JwtAlgorithm::EdDSA => "EdDSA",
+            JwtAlgorithm::RS256 => "RS256",
+        }
+    }
+}
+
+/// JSON Web Key Set (RFC 7517)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Jwks {
+    /// Published public keys
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+}
+
+/// Issues and verifies JWTs with keys held in a [`KeyStore`]
+pub struct JwtService {
+    key_store: Arc<KeyStore>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway_secs: i64,
+}
+
+impl JwtService {
+    /// Create a JWT service over `key_store`
+    pub fn new(key_store: Arc<KeyStore>) -> Self {
+        Self {
+            key_store,
+            issuer: None,
+            audience: None,
+            leeway_secs: DEFAULT_LEEWAY_SECS,
+        }
+    }
+
+    /// Set `iss` on issued tokens and require it on verified tokens
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Set `aud` on issued tokens and require it on verified tokens
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Set the clock skew tolerated when checking `exp` and `nbf`
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway_secs = leeway.as_secs() as i64;
+        self
+    }
+
+    /// Issue a token signed with the active version of `key_id`
+    ///
+    /// `claims` must serialize to a JSON object; `iat` and `exp` (now + `ttl`)
+    /// are added, as are `iss`/`aud` when configured.
+    pub async fn issue<C: Serialize>(
+        &self,
+        key_id: &KeyId,
+        claims: &C,
+        ttl: Duration,
+    ) -> KeyResult<String> {
+        let metadata = self.key_store.get_metadata(key_id).await?;
+        let algorithm = JwtAlgorithm::for_key_type(metadata.key_type)?;
+
+        let mut claims = match serde_json::to_value(claims)? {
+            Value::Object(map) => map,
+            _ => {
+                return Err(KeyError::InvalidFormat(
+                    "JWT claims must be a JSON object".to_string(),
+                ))
+            }
+        };
+        let now = chrono::Utc::now().timestamp();
+        claims.insert("iat".to_string(), json!(now));
+        claims.insert("exp".to_string(), json!(now + ttl.as_secs() as i64));
+        if let Some(issuer) = &self.issuer {
+            claims.insert("iss".to_string(), json!(issuer));
+        }
+        if let Some(audience) = &self.audience {
+            claims.insert("aud".to_string(), json!(audience));
+        }
+
+        let header = JwtHeader {
+            alg: algorithm.as_str().to_string(),
+            typ: Some("JWT".to_string()),
+            kid: Some(kid(key_id, metadata.current_version)),
+        };
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+        );
+
+        let signature = match algorithm {
+            JwtAlgorithm::HS256 => self.key_store.sign(key_id, signing_input.as_bytes()).await?,
+            _ => {
+                self.key_store
+                    .sign_with_key_pair(key_id, signing_input.as_bytes())
+                    .await?
+            }
+        };
+
+        Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature)))
+    }
+
+    /// Verify a token signed with `key_id` and return its claims
+    ///
+    /// The `alg` header must match the key's type. Key pair tokens are checked
+    /// against the public key of the version named in `kid`, which must not
+    /// have expired. `exp` is required; it, `nbf` and the configured
+    /// `iss`/`aud` are enforced.
+    pub async fn verify(&self, key_id: &KeyId, token: &str) -> KeyResult<Map<String, Value>> {
+        let mut parts = token.split('.');
+        let (Some(header_b64), Some(claims_b64), Some(signature_b64), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("malformed token"));
+        };
+
+        let header: JwtHeader = serde_json::from_slice(&decode_segment(header_b64)?)
+            .map_err(|_| invalid("malformed header"))?;
+        let signature = decode_segment(signature_b64)?;
+        let signing_input = &token[..header_b64.len() + 1 + claims_b64.len()];
+
+        let metadata = self.key_store.get_metadata(key_id).await?;
+        let algorithm = JwtAlgorithm::for_key_type(metadata.key_type)?;
+        if header.alg != algorithm.as_str() {
+            return Err(invalid(&format!("unexpected algorithm {}", header.alg)));
+        }
+        let version = match &header.kid {
+            Some(kid) => parse_kid(key_id, kid)?,
+            None => metadata.current_version,
+        };
+
+        let valid = match algorithm {
+            JwtAlgorithm::HS256 => {
+                // Only the active secret is available for HMAC verification
+                version == metadata.current_version
+                    && self
+                        .key_store
+                        .verify(key_id, signing_input.as_bytes(), &signature)
+                        .await?
+            }
+            _ => {
+                let public_der = verification_key(&metadata, version)?;
+                keypair::verify(
+                    metadata.key_type,
+                    &public_der,
+                    signing_input.as_bytes(),
+                    &signature,
+                )?
+            }
+        };
+        if !valid {
+            return Err(invalid("signature verification failed"));
+        }
+
+        let claims: Map<String, Value> = serde_json::from_slice(&decode_segment(claims_b64)?)
+            .map_err(|_| invalid("malformed claims"))?;
+        self.validate_claims(&claims)?;
+        Ok(claims)
+    }
+
+    /// Build a JWKS with every unexpired public key version of `key_ids`
+    pub async fn jwks(&self, key_ids: &[KeyId]) -> KeyResult<Jwks> {
+        let mut jwks = Jwks::default();
+        for key_id in key_ids {
+            let metadata = self.key_store.get_metadata(key_id).await?;
+            if !metadata.key_type.is_key_pair() {
+                return Err(KeyError::InvalidFormat(format!(
+                    "{} is not a key pair and must not be published",
+                    key_id
+                )));
+            }
+            let algorithm = JwtAlgorithm::for_key_type(metadata.key_type)?;
+
+            for version in metadata.versions.iter().filter(|v| !v.is_expired()) {
+                let public_der = keypair::recorded_public_key(version)?;
+                let mut jwk = keypair::to_jwk(metadata.key_type, &public_der)?;
+                jwk.kid = Some(kid(key_id, version.version));
+                jwk.key_use = Some("sig".to_string());
+                jwk.alg = Some(algorithm.as_str().to_string());
+                jwks.keys.push(jwk);
+            }
+        }
+        Ok(jwks)
+    }
+
+    fn validate_claims(&self, claims: &Map<String, Value>) -> KeyResult<()> {
+        let now = chrono::Utc::now().timestamp();
+        // Tokens without an expiry would stay valid forever
+        let exp = claims.get("exp").ok_or_else(|| invalid("missing exp"))?;
+        let exp = exp.as_i64().ok_or_else(|| invalid("exp must be a number"))?;
+        if now > exp + self.leeway_secs {
+            return Err(invalid("token expired"));
+        }
+        if let Some(nbf) = claims.get("nbf") {
+            let nbf = nbf.as_i64().ok_or_else(|| invalid("nbf must be a number"))?;
+            if now + self.leeway_secs < nbf {
+                return Err(invalid("token not yet valid"));
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return Err(invalid("issuer mismatch"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                return Err(invalid("audience mismatch"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key identifier published in `kid` for a key version
+fn kid(key_id: &KeyId, version: u32) -> String {
+    format!("{}.v{}", key_id, version)
+}
+
+fn parse_kid(key_id: &KeyId, kid: &str) -> KeyResult<u32> {
+    kid.strip_prefix(key_id.as_str())
+        .and_then(|rest| rest.strip_prefix(".v"))
+        .and_then(|version| version.parse().ok())
+        .ok_or_else(|| invalid(&format!("unknown kid {}", kid)))
+}
+
+fn verification_key(metadata: &KeyMetadata, version: u32) -> KeyResult<Vec<u8>> {
+    let key_version = metadata
+        .versions
+        .iter()
+        .find(|v| v.version == version)
+        .ok_or_else(|| invalid(&format!("unknown key version {}", version)))?;
+    if key_version.is_expired() {
+        return Err(invalid(&format!("key version {} has expired", version)));
+    }
+    keypair::recorded_public_key(key_version)
+}
+
+fn decode_segment(segment: &str) -> KeyResult<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(segment)
+        .map_err(|_| invalid("invalid base64url segment"))
+}
+
+fn invalid(reason: &str) -> KeyError {
+    KeyError::InvalidToken(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_store::LocalKeyStore;
+
+    async fn test_service(dir: &std::path::Path) -> (Arc<KeyStore>, JwtService) {
+        let backend = LocalKeyStore::new(dir, Some(&[5u8; 32])).await.unwrap();
+        let store = Arc::new(KeyStore::new(Arc::new(backend)));
+        let service = JwtService::new(store.clone()).with_issuer("armoricore");
+        (store, service)
+    }
+
+    #[tokio::test]
+    async fn test_issue_and_verify_each_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, service) = test_service(dir.path()).await;
+
+        for (key_id, key_type) in [
+            ("jwt.hs", KeyType::JwtSecret),
+            ("jwt.es", KeyType::EcdsaP256KeyPair),
+            ("jwt.ed", KeyType::Ed25519KeyPair),
+        ] {
+            let key_id = key_id.to_string();
+            store.generate_key(&key_id, key_type, 32).await.unwrap();
+
+            let token = service
+                .issue(&key_id, &json!({ "user_id": "42" }), Duration::from_secs(60))
+                .await
+                .unwrap();
+            let claims = service.verify(&key_id, &token).await.unwrap();
+            assert_eq!(claims["user_id"], "42");
+            assert_eq!(claims["iss"], "armoricore");
+
+            let tampered = token.replacen(".", ".e30", 1);
+            assert!(matches!(
+                service.verify(&key_id, &tampered).await,
+                Err(KeyError::InvalidToken(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotated_key_pair_keeps_old_tokens_valid() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, service) = test_service(dir.path()).await;
+        let key_id = "jwt.signing".to_string();
+
+        store.generate_key(&key_id, KeyType::Ed25519KeyPair, 0).await.unwrap();
+        let old_token = service
+            .issue(&key_id, &json!({ "sub": "a" }), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let new_private = keypair::generate(KeyType::Ed25519KeyPair).unwrap();
+        store.rotate_key_bytes(&key_id, &new_private).await.unwrap();
+        let new_token = service
+            .issue(&key_id, &json!({ "sub": "b" }), Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(service.verify(&key_id, &old_token).await.unwrap()["sub"], "a");
+        assert_eq!(service.verify(&key_id, &new_token).await.unwrap()["sub"], "b");
+
+        let jwks = service.jwks(std::slice::from_ref(&key_id)).await.unwrap();
+        let kids: Vec<_> = jwks.keys.iter().filter_map(|k| k.kid.as_deref()).collect();
+        assert_eq!(kids, vec!["jwt.signing.v1", "jwt.signing.v2"]);
+        assert!(jwks.keys.iter().all(|k| k.alg.as_deref() == Some("EdDSA")));
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_expired_and_mismatched_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, service) = test_service(dir.path()).await;
+        let es_key = "jwt.es".to_string();
+        let hs_key = "jwt.hs".to_string();
+        store.generate_key(&es_key, KeyType::EcdsaP256KeyPair, 0).await.unwrap();
+        store.generate_key(&hs_key, KeyType::JwtSecret, 32).await.unwrap();
+
+        let expired = service
+            .issue(&es_key, &json!({}), Duration::ZERO)
+            .await
+            .unwrap();
+        let strict = JwtService::new(store.clone()).with_leeway(Duration::ZERO);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(strict.verify(&es_key, &expired).await.is_err());
+
+        // Correctly signed, but without an expiry
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+            URL_SAFE_NO_PAD.encode(br#"{"iss":"armoricore"}"#)
+        );
+        let signature = store.sign(&hs_key, signing_input.as_bytes()).await.unwrap();
+        let unbounded = format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature));
+        assert!(matches!(
+            service.verify(&hs_key, &unbounded).await,
+            Err(KeyError::InvalidToken(reason)) if reason.contains("missing exp")
+        ));
+
+        // Algorithm confusion: an ES256 token presented for an HMAC key
+        let token = service
+            .issue(&es_key, &json!({}), Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(service.verify(&hs_key, &token).await.is_err());
+
+        let other_issuer = JwtService::new(store.clone()).with_issuer("someone-else");
+        assert!(other_issuer.verify(&es_key, &token).await.is_err());
+
+        assert!(service.jwks(&[hs_key]).await.is_err());
+    }
+}
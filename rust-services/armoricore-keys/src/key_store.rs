@@ -15,24 +15,95 @@
 
 
 use crate::error::{KeyError, KeyResult};
-use crate::key_types::{KeyId, KeyMetadata, KeyType};
+use crate::key_types::{KeyId, KeyMetadata, KeyType, KeyVersion};
 use crate::kms::KeyManagementService;
+use crate::policy::{AccessPolicy, Permission, ServiceIdentity, SEAL_KEY_ID};
+use crate::seal::SealStatus;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
 /// High-level key store that wraps a KMS backend
+///
+/// A store created with [`KeyStore::with_policy`] acts as one service
+/// identity: every operation is checked against the access policy and
+/// returns `KeyError::PermissionDenied` when the identity lacks the
+/// permission on that key. A store created with [`KeyStore::new`] is
+/// unrestricted and meant for administration tools.
+#[derive(Clone)]
 pub struct KeyStore {
     backend: Arc<dyn KeyManagementService>,
+    access: Option<(Arc<AccessPolicy>, ServiceIdentity)>,
 }
 
 impl KeyStore {
     /// Create a new key store with a KMS backend
     pub fn new(backend: Arc<dyn KeyManagementService>) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            access: None,
+        }
+    }
+
+    /// Create a key store restricted to what `policy` grants `identity`
+    pub fn with_policy(
+        backend: Arc<dyn KeyManagementService>,
+        policy: Arc<AccessPolicy>,
+        identity: ServiceIdentity,
+    ) -> Self {
+        Self {
+            backend,
+            access: Some((policy, identity)),
+        }
+    }
+
+    /// Handle on the same backend acting as `identity` under `policy`
+    pub fn as_identity(&self, policy: Arc<AccessPolicy>, identity: ServiceIdentity) -> Self {
+        Self::with_policy(self.backend.clone(), policy, identity)
+    }
+
+    /// Identity this store acts as, if it is restricted by a policy
+    pub fn identity(&self) -> Option<&ServiceIdentity> {
+        self.access.as_ref().map(|(_, identity)| identity)
+    }
+
+    fn authorize(&self, key_id: &KeyId, permission: Permission) -> KeyResult<()> {
+        match &self.access {
+            Some((policy, identity)) => policy.check(identity, key_id, permission),
+            None => Ok(()),
+        }
+    }
+
+    fn is_visible(&self, key_id: &KeyId) -> bool {
+        match &self.access {
+            Some((policy, identity)) => policy.is_visible(identity, key_id),
+            None => true,
+        }
+    }
+
+    /// Store a key of any type
+    pub async fn store_key(
+        &self,
+        key_id: &KeyId,
+        key_type: KeyType,
+        key_value: &[u8],
+        metadata: Option<&str>,
+    ) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Write)?;
+        self.backend
+            .store_key(key_id, key_type, key_value, metadata)
+            .await
+    }
+
+    /// Get the raw bytes of the active version of a key
+    pub async fn get_key(&self, key_id: &KeyId) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Read)?;
+        self.backend.get_key(key_id).await
     }
 
     /// Store a JWT secret
     pub async fn store_jwt_secret(&self, key_id: &KeyId, secret: &str) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Write)?;
         self.backend
             .store_key(key_id, KeyType::JwtSecret, secret.as_bytes(), None)
             .await
@@ -40,6 +111,7 @@ impl KeyStore {
 
     /// Get JWT secret
     pub async fn get_jwt_secret(&self, key_id: &KeyId) -> KeyResult<String> {
+        self.authorize(key_id, Permission::Read)?;
         let bytes = self.backend.get_key(key_id).await?;
         String::from_utf8(bytes)
             .map_err(|e| KeyError::InvalidFormat(format!("Invalid UTF-8: {}", e)))
@@ -52,6 +124,7 @@ impl KeyStore {
         api_key: &str,
         metadata: Option<&str>,
     ) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Write)?;
         self.backend
             .store_key(key_id, KeyType::ApiKey, api_key.as_bytes(), metadata)
             .await
@@ -59,6 +132,7 @@ impl KeyStore {
 
     /// Get API key
     pub async fn get_api_key(&self, key_id: &KeyId) -> KeyResult<String> {
+        self.authorize(key_id, Permission::Read)?;
         let bytes = self.backend.get_key(key_id).await?;
         String::from_utf8(bytes)
             .map_err(|e| KeyError::InvalidFormat(format!("Invalid UTF-8: {}", e)))
@@ -72,6 +146,8 @@ impl KeyStore {
         secret_key_id: &KeyId,
         secret_key: &str,
     ) -> KeyResult<()> {
+        self.authorize(access_key_id, Permission::Write)?;
+        self.authorize(secret_key_id, Permission::Write)?;
         self.backend
             .store_key(
                 access_key_id,
@@ -93,6 +169,7 @@ impl KeyStore {
 
     /// Rotate a key
     pub async fn rotate_key(&self, key_id: &KeyId, new_value: &str) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Rotate)?;
         info!("Rotating key: {}", key_id);
         self.backend
             .rotate_key(key_id, new_value.as_bytes())
@@ -100,23 +177,61 @@ impl KeyStore {
         Ok(())
     }
 
+    /// Rotate a key to a new binary value, returning the new version
+    pub async fn rotate_key_bytes(
+        &self,
+        key_id: &KeyId,
+        new_value: &[u8],
+    ) -> KeyResult<KeyVersion> {
+        self.authorize(key_id, Permission::Rotate)?;
+        info!("Rotating key: {}", key_id);
+        self.backend.rotate_key(key_id, new_value).await
+    }
+
+    /// Verify that a key can be decrypted and its metadata is consistent
+    pub async fn verify_key(&self, key_id: &KeyId) -> KeyResult<KeyMetadata> {
+        self.authorize(key_id, Permission::Read)?;
+        let metadata = self.backend.get_metadata(key_id).await?;
+
+        let active = metadata.get_active_version().ok_or_else(|| {
+            KeyError::InvalidFormat(format!("Key {} has no active version", key_id))
+        })?;
+        if active.version != metadata.current_version {
+            return Err(KeyError::InvalidFormat(format!(
+                "Key {} active version {} does not match current version {}",
+                key_id, active.version, metadata.current_version
+            )));
+        }
+
+        let value = self.backend.get_key(key_id).await?;
+        if value.is_empty() {
+            return Err(KeyError::InvalidFormat(format!("Key {} is empty", key_id)));
+        }
+
+        Ok(metadata)
+    }
+
     /// Get key metadata
     pub async fn get_metadata(&self, key_id: &KeyId) -> KeyResult<KeyMetadata> {
+        self.authorize(key_id, Permission::Use)?;
         self.backend.get_metadata(key_id).await
     }
 
-    /// List all keys
+    /// List all keys visible to this store's identity
     pub async fn list_keys(&self) -> KeyResult<Vec<KeyId>> {
-        self.backend.list_keys().await
+        let mut keys = self.backend.list_keys().await?;
+        keys.retain(|key_id| self.is_visible(key_id));
+        Ok(keys)
     }
 
-    /// Check if key exists
+    /// Check if key exists (keys outside the identity's policy never exist)
     pub async fn key_exists(&self, key_id: &KeyId) -> bool {
-        self.backend.key_exists(key_id).await
+        self.is_visible(key_id) && self.backend.key_exists(key_id).await
     }
 
     /// Delete a key
     pub async fn delete_key(&self, key_id: &KeyId) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Delete)?;
         self.backend.delete_key(key_id).await
     }
 
@@ -126,6 +241,7 @@ impl KeyStore {
         key_id: &KeyId,
         key_value: &[u8],
     ) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Write)?;
         self.backend
             .store_key(key_id, KeyType::EncryptionKey, key_value, None)
             .await
@@ -133,7 +249,109 @@ impl KeyStore {
 
     /// Get encryption key
     pub async fn get_encryption_key(&self, key_id: &KeyId) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Read)?;
         self.backend.get_key(key_id).await
     }
-}
 
+    /// Generate a random key inside the backend without exposing its value
+    pub async fn generate_key(
+        &self,
+        key_id: &KeyId,
+        key_type: KeyType,
+        length: usize,
+    ) -> KeyResult<()> {
+        self.authorize(key_id, Permission::Write)?;
+        self.backend.generate_key(key_id, key_type, length).await
+    }
+
+    /// Encrypt data with a stored key (AES-GCM, nonce prepended)
+    pub async fn encrypt(
+        &self,
+        key_id: &KeyId,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.encrypt(key_id, plaintext, aad).await
+    }
+
+    /// Decrypt data produced by `encrypt`
+    pub async fn decrypt(
+        &self,
+        key_id: &KeyId,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.decrypt(key_id, ciphertext, aad).await
+    }
+
+    /// Sign data with a stored key (HMAC-SHA256)
+    pub async fn sign(&self, key_id: &KeyId, data: &[u8]) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.sign(key_id, data).await
+    }
+
+    /// Verify a signature produced by `sign`
+    pub async fn verify(&self, key_id: &KeyId, data: &[u8], signature: &[u8]) -> KeyResult<bool> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.verify(key_id, data, signature).await
+    }
+
+    /// Sign data with a stored asymmetric key pair
+    pub async fn sign_with_key_pair(&self, key_id: &KeyId, message: &[u8]) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.sign_with_key_pair(key_id, message).await
+    }
+
+    /// Derive key material from a stored key (HKDF-Expand)
+    pub async fn derive_key(
+        &self,
+        key_id: &KeyId,
+        info: &[u8],
+        length: usize,
+    ) -> KeyResult<Vec<u8>> {
+        self.authorize(key_id, Permission::Use)?;
+        self.backend.derive_key(key_id, info, length).await
+    }
+
+    /// Current seal state, or `None` if the backend does not support sealing
+    pub async fn seal_status(&self) -> KeyResult<Option<SealStatus>> {
+        self.authorize(&SEAL_KEY_ID.to_string(), Permission::Seal)?;
+        Ok(self.backend.seal_status().await)
+    }
+
+    /// Whether key material is currently unavailable because the store is sealed
+    ///
+    /// Unlike `seal_status`, this needs no permission: every service waits
+    /// on it before reading its own keys.
+    pub async fn is_sealed(&self) -> bool {
+        self.backend
+            .seal_status()
+            .await
+            .is_some_and(|status| status.sealed)
+    }
+
+    /// Submit one unseal share
+    pub async fn unseal(&self, share: &str) -> KeyResult<SealStatus> {
+        self.authorize(&SEAL_KEY_ID.to_string(), Permission::Seal)?;
+        self.backend.unseal(share).await
+    }
+
+    /// Seal the store again
+    pub async fn seal(&self) -> KeyResult<()> {
+        self.authorize(&SEAL_KEY_ID.to_string(), Permission::Seal)?;
+        self.backend.seal().await
+    }
+
+    /// Wait until the store is unsealed, polling every `interval`
+    pub async fn wait_until_unsealed(&self, interval: Duration) {
+        if self.is_sealed().await {
+            info!("Key store is sealed, waiting for unseal");
+            while self.is_sealed().await {
+                tokio::time::sleep(interval).await;
+            }
+            info!("Key store unsealed");
+        }
+    }
+}
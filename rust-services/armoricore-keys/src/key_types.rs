@@ -38,6 +38,15 @@ pub enum KeyType {
     ApnsKey,
     /// Generic secret
     Secret,
+    /// Media content key delivered to authorized players (HLS AES-128, CENC);
+    /// always extractable, even on HSM backends
+    ContentKey,
+    /// Ed25519 key pair (PKCS#8 DER private key), used for EdDSA JWTs
+    Ed25519KeyPair,
+    /// ECDSA P-256 key pair (PKCS#8 DER private key), used for ES256 JWTs
+    EcdsaP256KeyPair,
+    /// RSA key pair (PKCS#8 DER private key), used for RS256 JWTs
+    RsaKeyPair,
 }
 
 impl KeyType {
@@ -51,8 +60,73 @@ impl KeyType {
             KeyType::ObjectStorageSecret => 180,
             KeyType::ApnsKey => 365,
             KeyType::Secret => 180,
+            KeyType::ContentKey => 3650, // Bound to published media; not rotated
+            KeyType::Ed25519KeyPair => 90,
+            KeyType::EcdsaP256KeyPair => 90,
+            KeyType::RsaKeyPair => 365,
         }
     }
+
+    /// Whether keys of this type are asymmetric key pairs
+    pub fn is_key_pair(&self) -> bool {
+        matches!(
+            self,
+            KeyType::Ed25519KeyPair | KeyType::EcdsaP256KeyPair | KeyType::RsaKeyPair
+        )
+    }
+
+    /// Stable string name used in metadata files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyType::JwtSecret => "jwt_secret",
+            KeyType::ApiKey => "api_key",
+            KeyType::EncryptionKey => "encryption_key",
+            KeyType::ObjectStorageKey => "object_storage_key",
+            KeyType::ObjectStorageSecret => "object_storage_secret",
+            KeyType::ApnsKey => "apns_key",
+            KeyType::Secret => "secret",
+            KeyType::ContentKey => "content_key",
+            KeyType::Ed25519KeyPair => "ed25519_key_pair",
+            KeyType::EcdsaP256KeyPair => "ecdsa_p256_key_pair",
+            KeyType::RsaKeyPair => "rsa_key_pair",
+        }
+    }
+
+    /// All supported key types
+    pub fn all() -> &'static [KeyType] {
+        &[
+            KeyType::JwtSecret,
+            KeyType::ApiKey,
+            KeyType::EncryptionKey,
+            KeyType::ObjectStorageKey,
+            KeyType::ObjectStorageSecret,
+            KeyType::ApnsKey,
+            KeyType::Secret,
+            KeyType::ContentKey,
+            KeyType::Ed25519KeyPair,
+            KeyType::EcdsaP256KeyPair,
+            KeyType::RsaKeyPair,
+        ]
+    }
+}
+
+impl std::fmt::Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl std::str::FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase().replace('-', "_");
+        KeyType::all()
+            .iter()
+            .copied()
+            .find(|t| t.as_str() == normalized)
+            .ok_or_else(|| format!("Unknown key type: {}", s))
+    }
 }
 
 /// Key version information
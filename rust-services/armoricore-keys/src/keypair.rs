@@ -0,0 +1,294 @@
+//! Asymmetric key pair operations
+//!
+//! Private keys are stored as PKCS#8 DER. When a key pair is stored or
+//! rotated, the backend records the SubjectPublicKeyInfo DER of that version
+//! in the version metadata, so public keys of older versions stay available
+//! for verification and JWKS publication after the private key is rotated.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use crate::key_types::{KeyType, KeyVersion};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use rand::rngs::OsRng;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::traits::PublicKeyParts;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Version metadata entry holding the base64 SubjectPublicKeyInfo DER
+pub const PUBLIC_KEY_METADATA: &str = "public_key";
+
+/// RSA modulus size for generated key pairs
+pub const RSA_KEY_BITS: usize = 2048;
+
+/// Public key in JSON Web Key format (RFC 7517)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    /// Key type (`OKP`, `EC` or `RSA`)
+    pub kty: String,
+    /// Key identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// Intended use (`sig`)
+    #[serde(rename = "use", skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    /// JWS algorithm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// Curve (`Ed25519`, `P-256`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// Public key / x coordinate (base64url)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    /// y coordinate (base64url)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    /// RSA modulus (base64url)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA public exponent (base64url)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+}
+
+/// Generate a new private key, encoded as PKCS#8 DER
+pub fn generate(key_type: KeyType) -> KeyResult<Vec<u8>> {
+    let der = match key_type {
+        KeyType::Ed25519KeyPair => ed25519_dalek::SigningKey::generate(&mut OsRng)
+            .to_pkcs8_der()
+            .map_err(encoding_error)?,
+        KeyType::EcdsaP256KeyPair => p256::ecdsa::SigningKey::random(&mut OsRng)
+            .to_pkcs8_der()
+            .map_err(encoding_error)?,
+        KeyType::RsaKeyPair => rsa::RsaPrivateKey::new(&mut OsRng, RSA_KEY_BITS)
+            .map_err(|e| KeyError::Kms(format!("RSA key generation failed: {}", e)))?
+            .to_pkcs8_der()
+            .map_err(encoding_error)?,
+        other => return Err(not_a_key_pair(other)),
+    };
+    Ok(der.as_bytes().to_vec())
+}
+
+/// Derive the SubjectPublicKeyInfo DER from a PKCS#8 DER private key
+pub fn public_key_der(key_type: KeyType, private_der: &[u8]) -> KeyResult<Vec<u8>> {
+    let der = match key_type {
+        KeyType::Ed25519KeyPair => ed25519_dalek::SigningKey::from_pkcs8_der(private_der)
+            .map_err(decoding_error)?
+            .verifying_key()
+            .to_public_key_der(),
+        KeyType::EcdsaP256KeyPair => p256::ecdsa::SigningKey::from_pkcs8_der(private_der)
+            .map_err(decoding_error)?
+            .verifying_key()
+            .to_public_key_der(),
+        KeyType::RsaKeyPair => rsa::RsaPrivateKey::from_pkcs8_der(private_der)
+            .map_err(decoding_error)?
+            .to_public_key()
+            .to_public_key_der(),
+        other => return Err(not_a_key_pair(other)),
+    };
+    Ok(der.map_err(encoding_error)?.as_bytes().to_vec())
+}
+
+/// Sign `message` with a PKCS#8 DER private key
+///
+/// Signatures use the JWS encodings: raw 64-byte Ed25519, raw `r || s` for
+/// ECDSA P-256 (SHA-256), and RSASSA-PKCS1-v1_5 with SHA-256 for RSA.
+pub fn sign(key_type: KeyType, private_der: &[u8], message: &[u8]) -> KeyResult<Vec<u8>> {
+    match key_type {
+        KeyType::Ed25519KeyPair => {
+            let key = ed25519_dalek::SigningKey::from_pkcs8_der(private_der)
+                .map_err(decoding_error)?;
+            Ok(key.sign(message).to_bytes().to_vec())
+        }
+        KeyType::EcdsaP256KeyPair => {
+            let key = p256::ecdsa::SigningKey::from_pkcs8_der(private_der)
+                .map_err(decoding_error)?;
+            let signature: p256::ecdsa::Signature = key.sign(message);
+            Ok(signature.to_bytes().to_vec())
+        }
+        KeyType::RsaKeyPair => {
+            let key = rsa::RsaPrivateKey::from_pkcs8_der(private_der).map_err(decoding_error)?;
+            let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new(key);
+            Ok(signer.sign(message).to_vec())
+        }
+        other => Err(not_a_key_pair(other)),
+    }
+}
+
+/// Verify a signature produced by [`sign`] against a SubjectPublicKeyInfo DER
+pub fn verify(
+    key_type: KeyType,
+    public_der: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> KeyResult<bool> {
+    let valid = match key_type {
+        KeyType::Ed25519KeyPair => {
+            let key = ed25519_dalek::VerifyingKey::from_public_key_der(public_der)
+                .map_err(decoding_error)?;
+            ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok())
+        }
+        KeyType::EcdsaP256KeyPair => {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(public_der)
+                .map_err(decoding_error)?;
+            p256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok())
+        }
+        KeyType::RsaKeyPair => {
+            let key = rsa::RsaPublicKey::from_public_key_der(public_der).map_err(decoding_error)?;
+            let verifier = rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key);
+            rsa::pkcs1v15::Signature::try_from(signature)
+                .is_ok_and(|sig| verifier.verify(message, &sig).is_ok())
+        }
+        other => return Err(not_a_key_pair(other)),
+    };
+    Ok(valid)
+}
+
+/// Convert a SubjectPublicKeyInfo DER public key to a JWK
+pub fn to_jwk(key_type: KeyType, public_der: &[u8]) -> KeyResult<Jwk> {
+    let mut jwk = Jwk {
+        kty: String::new(),
+        kid: None,
+        key_use: None,
+        alg: None,
+        crv: None,
+        x: None,
+        y: None,
+        n: None,
+        e: None,
+    };
+    match key_type {
+        KeyType::Ed25519KeyPair => {
+            let key = ed25519_dalek::VerifyingKey::from_public_key_der(public_der)
+                .map_err(decoding_error)?;
+            jwk.kty = "OKP".to_string();
+            jwk.crv = Some("Ed25519".to_string());
+            jwk.x = Some(URL_SAFE_NO_PAD.encode(key.as_bytes()));
+        }
+        KeyType::EcdsaP256KeyPair => {
+            let key = p256::ecdsa::VerifyingKey::from_public_key_der(public_der)
+                .map_err(decoding_error)?;
+            let point = key.to_encoded_point(false);
+            jwk.kty = "EC".to_string();
+            jwk.crv = Some("P-256".to_string());
+            jwk.x = point.x().map(|x| URL_SAFE_NO_PAD.encode(x));
+            jwk.y = point.y().map(|y| URL_SAFE_NO_PAD.encode(y));
+        }
+        KeyType::RsaKeyPair => {
+            let key = rsa::RsaPublicKey::from_public_key_der(public_der).map_err(decoding_error)?;
+            jwk.kty = "RSA".to_string();
+            jwk.n = Some(URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()));
+            jwk.e = Some(URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()));
+        }
+        other => return Err(not_a_key_pair(other)),
+    }
+    Ok(jwk)
+}
+
+/// Validate a private key and record its public key in the version metadata
+///
+/// Does nothing for symmetric key types.
+pub fn record_public_key(
+    version: &mut KeyVersion,
+    key_type: KeyType,
+    private_der: &[u8],
+) -> KeyResult<()> {
+    if key_type.is_key_pair() {
+        let public_der = public_key_der(key_type, private_der)?;
+        version
+            .metadata
+            .insert(PUBLIC_KEY_METADATA.to_string(), STANDARD.encode(public_der));
+    }
+    Ok(())
+}
+
+/// Public key recorded for a key pair version
+pub fn recorded_public_key(version: &KeyVersion) -> KeyResult<Vec<u8>> {
+    let encoded = version.metadata.get(PUBLIC_KEY_METADATA).ok_or_else(|| {
+        KeyError::InvalidFormat(format!("Version {} has no public key", version.version))
+    })?;
+    STANDARD
+        .decode(encoded)
+        .map_err(|e| KeyError::InvalidFormat(format!("Invalid public key encoding: {}", e)))
+}
+
+fn not_a_key_pair(key_type: KeyType) -> KeyError {
+    KeyError::InvalidFormat(format!("{} is not an asymmetric key type", key_type))
+}
+
+fn encoding_error(e: impl std::fmt::Display) -> KeyError {
+    KeyError::InvalidFormat(format!("Key encoding failed: {}", e))
+}
+
+fn decoding_error(e: impl std::fmt::Display) -> KeyError {
+    KeyError::InvalidFormat(format!("Invalid key pair: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify_roundtrip() {
+        for key_type in [KeyType::Ed25519KeyPair, KeyType::EcdsaP256KeyPair] {
+            let private_der = generate(key_type).unwrap();
+            let public_der = public_key_der(key_type, &private_der).unwrap();
+            let signature = sign(key_type, &private_der, b"message").unwrap();
+
+            assert_eq!(signature.len(), 64);
+            assert!(verify(key_type, &public_der, b"message", &signature).unwrap());
+            assert!(!verify(key_type, &public_der, b"other", &signature).unwrap());
+            assert!(!verify(key_type, &public_der, b"message", &signature[1..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_ed25519_jwk_matches_rfc8037() {
+        // RFC 8037 appendix A.1/A.2 key
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+            .unwrap();
+        let key = ed25519_dalek::SigningKey::from_bytes(&seed.try_into().unwrap());
+        let private_der = key.to_pkcs8_der().unwrap();
+
+        let public_der = public_key_der(KeyType::Ed25519KeyPair, private_der.as_bytes()).unwrap();
+        let jwk = to_jwk(KeyType::Ed25519KeyPair, &public_der).unwrap();
+
+        assert_eq!(jwk.kty, "OKP");
+        assert_eq!(jwk.crv.as_deref(), Some("Ed25519"));
+        assert_eq!(jwk.x.as_deref(), Some("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"));
+    }
+
+    #[test]
+    fn test_record_public_key() {
+        let mut version = KeyVersion::new(1);
+        record_public_key(&mut version, KeyType::Secret, b"not a key").unwrap();
+        assert!(recorded_public_key(&version).is_err());
+
+        assert!(record_public_key(&mut version, KeyType::EcdsaP256KeyPair, b"garbage").is_err());
+
+        let private_der = generate(KeyType::EcdsaP256KeyPair).unwrap();
+        record_public_key(&mut version, KeyType::EcdsaP256KeyPair, &private_der).unwrap();
+        assert_eq!(
+            recorded_public_key(&version).unwrap(),
+            public_key_der(KeyType::EcdsaP256KeyPair, &private_der).unwrap()
+        );
+    }
+}
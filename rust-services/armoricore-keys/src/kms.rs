@@ -22,11 +22,19 @@
 // limitations under the License.
 
 
-use crate::error::KeyResult;
+use crate::crypto;
+use crate::error::{KeyError, KeyResult};
 use crate::key_types::{KeyId, KeyMetadata, KeyType, KeyVersion};
+use crate::keypair;
+use crate::seal::SealStatus;
 use async_trait::async_trait;
 
 /// Trait for Key Management Service backends
+///
+/// The cryptographic operations (`encrypt`, `decrypt`, `sign`, `derive_key`)
+/// have software default implementations built on `get_key`. Backends that
+/// keep keys non-extractable (e.g. an HSM) override them so callers can use a
+/// key in place without ever receiving its raw bytes.
 #[async_trait]
 pub trait KeyManagementService: Send + Sync {
     /// Store a new key
@@ -62,5 +70,166 @@ pub trait KeyManagementService: Send + Sync {
 
     /// Check if a key exists
     async fn key_exists(&self, key_id: &KeyId) -> bool;
+
+    /// Generate a new random key of `length` bytes inside the backend
+    ///
+    /// For key pair types `length` is ignored and a new private key is
+    /// generated instead.
+    async fn generate_key(
+        &self,
+        key_id: &KeyId,
+        key_type: KeyType,
+        length: usize,
+    ) -> KeyResult<()> {
+        let value = if key_type.is_key_pair() {
+            keypair::generate(key_type)?
+        } else {
+            crypto::random_bytes(length)
+        };
+        self.store_key(key_id, key_type, &value, None).await
+    }
+
+    /// Encrypt with AES-GCM under the active key version
+    ///
+    /// Output layout is `nonce (12 bytes) || ciphertext || tag (16 bytes)`.
+    async fn encrypt(&self, key_id: &KeyId, plaintext: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+        let key = self.get_key(key_id).await?;
+        crypto::aes_gcm_encrypt(&key, plaintext, aad)
+    }
+
+    /// Decrypt data produced by `encrypt`
+    async fn decrypt(&self, key_id: &KeyId, ciphertext: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+        let key = self.get_key(key_id).await?;
+        crypto::aes_gcm_decrypt(&key, ciphertext, aad)
+    }
+
+    /// Compute an HMAC-SHA256 over `data` with the active key version
+    async fn sign(&self, key_id: &KeyId, data: &[u8]) -> KeyResult<Vec<u8>> {
+        let key = self.get_key(key_id).await?;
+        crypto::hmac_sha256(&key, data)
+    }
+
+    /// Verify an HMAC-SHA256 produced by `sign`
+    async fn verify(&self, key_id: &KeyId, data: &[u8], signature: &[u8]) -> KeyResult<bool> {
+        let expected = self.sign(key_id, data).await?;
+        Ok(crypto::constant_time_eq(&expected, signature))
+    }
+
+    /// Sign `message` with the active version of an asymmetric key pair
+    ///
+    /// See [`keypair::sign`] for the signature encodings.
+    async fn sign_with_key_pair(&self, key_id: &KeyId, message: &[u8]) -> KeyResult<Vec<u8>> {
+        let metadata = self.get_metadata(key_id).await?;
+        let private_der = self.get_key(key_id).await?;
+        keypair::sign(metadata.key_type, &private_der, message)
+    }
+
+    /// Derive `length` bytes of key material bound to `info`
+    ///
+    /// This is HKDF-Expand (RFC 5869) with the stored key used as the PRK,
+    /// built on `sign` so it works unchanged for non-extractable keys.
+    async fn derive_key(&self, key_id: &KeyId, info: &[u8], length: usize) -> KeyResult<Vec<u8>> {
+        if length == 0 || length > crypto::MAX_DERIVED_KEY_LEN {
+            return Err(KeyError::InvalidFormat(format!(
+                "Derived key length must be between 1 and {} bytes",
+                crypto::MAX_DERIVED_KEY_LEN
+            )));
+        }
+
+        let mut okm = Vec::with_capacity(length);
+        let mut block = Vec::new();
+        let mut counter = 1u8;
+        while okm.len() < length {
+            let mut input = block;
+            input.extend_from_slice(info);
+            input.push(counter);
+            block = self.sign(key_id, &input).await?;
+            okm.extend_from_slice(&block);
+            counter = counter.wrapping_add(1);
+        }
+        okm.truncate(length);
+        Ok(okm)
+    }
+
+    /// Current seal state, or `None` if the backend does not support sealing
+    async fn seal_status(&self) -> Option<SealStatus> {
+        None
+    }
+
+    /// Submit one unseal share
+    ///
+    /// Once enough shares have been submitted the backend reconstructs its
+    /// master key and key material becomes available.
+    async fn unseal(&self, _share: &str) -> KeyResult<SealStatus> {
+        Err(KeyError::Configuration(
+            "Key store backend does not support unsealing".to_string(),
+        ))
+    }
+
+    /// Discard the in-memory master key so key material is unavailable again
+    async fn seal(&self) -> KeyResult<()> {
+        Err(KeyError::Configuration(
+            "Key store backend does not support sealing".to_string(),
+        ))
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::key_store::KeyStore;
+    use crate::key_types::KeyType;
+    use crate::local_store::LocalKeyStore;
+    use std::sync::Arc;
+
+    async fn test_store(dir: &std::path::Path) -> KeyStore {
+        let backend = LocalKeyStore::new(dir, Some(&[3u8; 32])).await.unwrap();
+        KeyStore::new(Arc::new(backend))
+    }
+
+    #[tokio::test]
+    async fn test_default_encrypt_decrypt_with_generated_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(dir.path()).await;
+        let key_id = "media.key".to_string();
+
+        store.generate_key(&key_id, KeyType::EncryptionKey, 32).await.unwrap();
+        let ciphertext = store.encrypt(&key_id, b"content", b"media-1").await.unwrap();
+
+        assert_eq!(store.decrypt(&key_id, &ciphertext, b"media-1").await.unwrap(), b"content");
+        assert!(store.decrypt(&key_id, &ciphertext, b"media-2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_default_sign_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(dir.path()).await;
+        let key_id = "signing.key".to_string();
+
+        store.generate_key(&key_id, KeyType::Secret, 32).await.unwrap();
+        let signature = store.sign(&key_id, b"data").await.unwrap();
+
+        assert_eq!(signature.len(), 32);
+        assert!(store.verify(&key_id, b"data", &signature).await.unwrap());
+        assert!(!store.verify(&key_id, b"other", &signature).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_derive_key_matches_rfc5869_expand() {
+        // RFC 5869 test case 1, starting from the published PRK
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(dir.path()).await;
+        let key_id = "hkdf.prk".to_string();
+        let prk = hex::decode("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+            .unwrap();
+        let info = hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap();
+
+        store.store_key(&key_id, KeyType::Secret, &prk, None).await.unwrap();
+        let okm = store.derive_key(&key_id, &info, 42).await.unwrap();
+
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+        assert!(store.derive_key(&key_id, &info, 0).await.is_err());
+    }
+}
@@ -1,7 +1,10 @@
 //! Key Management System for Armoricore
 //!
 //! Provides secure key storage, retrieval, and rotation capabilities.
-//! Supports local encrypted storage with extensibility for future KMS/HSM integration.
+//! Supports local encrypted storage and, with the `pkcs11` feature, HSM-backed
+//! storage where keys are used in place and never leave the device. The local
+//! store can run sealed, with its master key split into Shamir unseal shares.
+//! Asymmetric key pairs back JWT issuing/verification and JWKS publication.
 // Copyright 2025 Francisco F. Pinochet
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -17,15 +20,33 @@
 // limitations under the License.
 
 
+pub mod bundle;
+mod crypto;
 pub mod error;
+#[cfg(feature = "http")]
+pub mod jwks_http;
+pub mod jwt;
 pub mod key_store;
 pub mod key_types;
+pub mod keypair;
 pub mod local_store;
 pub mod kms;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod policy;
+pub mod seal;
 pub mod service_integration;
+pub mod shamir;
+#[cfg(feature = "http")]
+pub mod unseal_http;
 
+pub use bundle::{export_bundle, export_entries, import_bundle, ImportSummary, KeyBundle};
 pub use error::{KeyError, KeyResult};
+pub use jwt::{JwtAlgorithm, JwtService, Jwks};
 pub use key_store::KeyStore;
 pub use key_types::{KeyId, KeyType, KeyVersion, KeyMetadata};
+pub use keypair::Jwk;
+pub use policy::{AccessPolicy, Permission, ServiceIdentity, SEAL_KEY_ID};
+pub use seal::SealStatus;
 pub use service_integration::*;
 
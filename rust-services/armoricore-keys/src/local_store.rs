@@ -16,7 +16,10 @@
 
 use crate::error::{KeyError, KeyResult};
 use crate::key_types::{KeyId, KeyMetadata, KeyType, KeyVersion};
+use crate::keypair;
 use crate::kms::KeyManagementService;
+use crate::seal::{SealConfig, SealStatus};
+use crate::shamir::{self, Share};
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Nonce,
@@ -32,11 +35,20 @@ use tracing::{debug, info, warn};
 type MasterKey = [u8; 32];
 
 /// Local encrypted key store
+///
+/// If the storage directory contains a seal file (see [`LocalKeyStore::init_seal`])
+/// and no master key is passed explicitly, the store starts sealed: metadata
+/// can be listed, but key material is unavailable until enough Shamir shares
+/// are submitted through [`KeyManagementService::unseal`].
 pub struct LocalKeyStore {
     /// Storage directory
     storage_path: PathBuf,
-    /// Master encryption key (derived from master key or environment)
-    master_key: MasterKey,
+    /// Master encryption key (`None` while sealed)
+    master_key: std::sync::RwLock<Option<MasterKey>>,
+    /// Seal configuration, if the store runs in sealed mode
+    seal: Option<SealConfig>,
+    /// Shares submitted towards the current unseal attempt
+    pending_shares: std::sync::Mutex<Vec<Share>>,
     /// In-memory cache of key metadata
     metadata_cache: tokio::sync::RwLock<HashMap<KeyId, KeyMetadata>>,
 }
@@ -46,7 +58,8 @@ impl LocalKeyStore {
     ///
     /// # Arguments
     /// * `storage_path` - Directory where encrypted keys will be stored
-    /// * `master_key` - Optional master key (if None, will derive from environment or generate)
+    /// * `master_key` - Optional master key (if None, the store starts sealed when a
+    ///   seal file exists, otherwise the key is read from `ARMORICORE_MASTER_KEY`)
     pub async fn new<P: AsRef<Path>>(
         storage_path: P,
         master_key: Option<&[u8]>,
@@ -55,24 +68,39 @@ impl LocalKeyStore {
         
         // Create storage directory if it doesn't exist
         fs::create_dir_all(&storage_path).await?;
-        
+
+        let seal = SealConfig::load(&storage_path).await?;
+
         // Derive or use master key
-        let master_key = if let Some(key) = master_key {
-            if key.len() != 32 {
-                return Err(KeyError::Configuration(
-                    "Master key must be exactly 32 bytes".to_string(),
-                ));
+        let master_key = match (master_key, &seal) {
+            (Some(key), _) => {
+                let mk = Self::master_key_from_bytes(key)?;
+                if seal.as_ref().is_some_and(|seal| !seal.verify(&mk)) {
+                    return Err(KeyError::Configuration(
+                        "Master key does not match the key store seal".to_string(),
+                    ));
+                }
+                Some(mk)
             }
-            let mut mk = [0u8; 32];
-            mk.copy_from_slice(key);
-            mk
-        } else {
-            Self::derive_master_key()?
+            (None, Some(seal)) => {
+                if std::env::var("ARMORICORE_MASTER_KEY").is_ok() {
+                    warn!("Ignoring ARMORICORE_MASTER_KEY: key store is in sealed mode");
+                }
+                info!(
+                    threshold = seal.threshold,
+                    shares = seal.shares,
+                    "Key store is sealed, waiting for unseal shares"
+                );
+                None
+            }
+            (None, None) => Some(Self::derive_master_key(&storage_path).await?),
         };
 
         let store = Self {
             storage_path,
-            master_key,
+            master_key: std::sync::RwLock::new(master_key),
+            seal,
+            pending_shares: std::sync::Mutex::new(Vec::new()),
             metadata_cache: tokio::sync::RwLock::new(HashMap::new()),
         };
 
@@ -82,36 +110,139 @@ impl LocalKeyStore {
         Ok(store)
     }
 
+    /// Initialize sealed mode for the store at `storage_path`
+    ///
+    /// Splits the master key into `shares` Shamir shares, any `threshold` of
+    /// which unseal the store, and writes the seal file. An existing store
+    /// must pass its current master key so its keys stay readable; a new,
+    /// empty store gets a random one. The returned shares are the only copy
+    /// of the master key and must be distributed to their holders.
+    pub async fn init_seal<P: AsRef<Path>>(
+        storage_path: P,
+        master_key: Option<&[u8]>,
+        threshold: u8,
+        shares: u8,
+    ) -> KeyResult<Vec<Share>> {
+        let storage_path = storage_path.as_ref();
+        fs::create_dir_all(storage_path).await?;
+        if SealConfig::load(storage_path).await?.is_some() {
+            return Err(KeyError::AlreadyExists(format!(
+                "Seal already initialized for {}",
+                storage_path.display()
+            )));
+        }
+
+        let master_key = match master_key {
+            Some(key) => Self::master_key_from_bytes(key)?,
+            None if Self::has_stored_keys(storage_path).await? => {
+                return Err(KeyError::Configuration(
+                    "Key store already contains keys; the current master key is required to seal it"
+                        .to_string(),
+                ))
+            }
+            None => {
+                let mut mk = [0u8; 32];
+                mk.copy_from_slice(&crate::crypto::random_bytes(32));
+                mk
+            }
+        };
+
+        let issued = shamir::split_secret(&master_key, threshold, shares)?;
+        SealConfig::new(&master_key, threshold, shares)?
+            .save(storage_path)
+            .await?;
+
+        info!(threshold, shares, "Key store seal initialized");
+        Ok(issued)
+    }
+
+    /// Validate an explicitly provided master key
+    fn master_key_from_bytes(key: &[u8]) -> KeyResult<MasterKey> {
+        if key.len() != 32 {
+            return Err(KeyError::Configuration(
+                "Master key must be exactly 32 bytes".to_string(),
+            ));
+        }
+        let mut mk = [0u8; 32];
+        mk.copy_from_slice(key);
+        Ok(mk)
+    }
+
+    /// Parse a master key from its `ARMORICORE_MASTER_KEY` representation
+    ///
+    /// The expected form is 64 hex characters. Other strings are still hashed
+    /// with SHA-256 so existing deployments keep working, but that is
+    /// deprecated because it turns low-entropy passwords into master keys.
+    pub fn parse_master_key(key_str: &str) -> [u8; 32] {
+        if let Ok(key_bytes) = hex::decode(key_str) {
+            if key_bytes.len() == 32 {
+                let mut mk = [0u8; 32];
+                mk.copy_from_slice(&key_bytes);
+                return mk;
+            }
+        }
+        warn!("ARMORICORE_MASTER_KEY is not 64 hex characters; deriving it with SHA-256 is deprecated");
+        let hash = Sha256::digest(key_str.as_bytes());
+        let mut mk = [0u8; 32];
+        mk.copy_from_slice(&hash);
+        mk
+    }
+
     /// Derive master key from environment variable or generate a new one
-    fn derive_master_key() -> KeyResult<MasterKey> {
-        // Try to get from environment
+    ///
+    /// Generating is only allowed for an empty store: with existing keys a
+    /// fresh random master key would make every stored key unreadable.
+    async fn derive_master_key(storage_path: &Path) -> KeyResult<MasterKey> {
         if let Ok(key_str) = std::env::var("ARMORICORE_MASTER_KEY") {
-            // If it's a hex string, decode it
-            if let Ok(key_bytes) = hex::decode(&key_str) {
-                if key_bytes.len() == 32 {
-                    let mut mk = [0u8; 32];
-                    mk.copy_from_slice(&key_bytes);
-                    return Ok(mk);
-                }
-            }
-            // Otherwise, derive from string using SHA256
-            let hash = Sha256::digest(key_str.as_bytes());
-            let mut mk = [0u8; 32];
-            mk.copy_from_slice(&hash);
-            return Ok(mk);
+            return Ok(Self::parse_master_key(&key_str));
+        }
+
+        if Self::has_stored_keys(storage_path).await? {
+            return Err(KeyError::Configuration(format!(
+                "ARMORICORE_MASTER_KEY is not set and {} already contains keys",
+                storage_path.display()
+            )));
         }
 
         // Generate a new master key (should be set in production!)
-        warn!("No ARMORICORE_MASTER_KEY found, generating a new one. This should be set in production!");
+        warn!("No ARMORICORE_MASTER_KEY found, generating an ephemeral one. Keys stored now will be unreadable after restart!");
         let mut master_key = [0u8; 32];
-        use rand::RngCore;
-        rand::thread_rng().fill_bytes(&mut master_key);
+        master_key.copy_from_slice(&crate::crypto::random_bytes(32));
         Ok(master_key)
     }
 
+    /// Whether the storage directory contains any encrypted keys
+    async fn has_stored_keys(storage_path: &Path) -> KeyResult<bool> {
+        let mut entries = fs::read_dir(storage_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_some_and(|ext| ext == "key") {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Current master key, or `Sealed` if it has not been reconstructed yet
+    fn master_key(&self) -> KeyResult<MasterKey> {
+        self.master_key
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .ok_or(KeyError::Sealed)
+    }
+
+    /// Report the seal state for a store configured with `seal`
+    fn status_for(&self, seal: &SealConfig) -> SealStatus {
+        SealStatus {
+            sealed: self.master_key().is_err(),
+            threshold: seal.threshold,
+            shares: seal.shares,
+            progress: self.pending_shares.lock().unwrap_or_else(|e| e.into_inner()).len(),
+        }
+    }
+
     /// Encrypt key value
     fn encrypt_key(&self, key_value: &[u8]) -> KeyResult<Vec<u8>> {
-        let cipher = Aes256Gcm::new(&self.master_key.into());
+        let cipher = Aes256Gcm::new(&self.master_key()?.into());
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
         
         let ciphertext = cipher
@@ -132,7 +263,7 @@ impl LocalKeyStore {
 
         let (nonce_bytes, ciphertext) = encrypted.split_at(12);
         let nonce = Nonce::from_slice(nonce_bytes);
-        let cipher = Aes256Gcm::new(&self.master_key.into());
+        let cipher = Aes256Gcm::new(&self.master_key()?.into());
 
         let plaintext = cipher
             .decrypt(nonce, ciphertext)
@@ -144,13 +275,13 @@ impl LocalKeyStore {
     /// Get path for key file
     fn key_path(&self, key_id: &KeyId) -> PathBuf {
         // Sanitize key_id for filesystem
-        let sanitized = key_id.replace('/', "_").replace('\\', "_");
+        let sanitized = key_id.replace(['/', '\\'], "_");
         self.storage_path.join(format!("{}.key", sanitized))
     }
 
     /// Get path for metadata file
     fn metadata_path(&self, key_id: &KeyId) -> PathBuf {
-        let sanitized = key_id.replace('/', "_").replace('\\', "_");
+        let sanitized = key_id.replace(['/', '\\'], "_");
         self.storage_path.join(format!("{}.meta", sanitized))
     }
 
@@ -240,6 +371,9 @@ impl KeyManagementService for LocalKeyStore {
                 key_metadata.metadata = parsed;
             }
         }
+        if let Some(version) = key_metadata.versions.last_mut() {
+            keypair::record_public_key(version, key_type, key_value)?;
+        }
 
         // Encrypt and save key
         let encrypted = self.encrypt_key(key_value)?;
@@ -295,7 +429,8 @@ impl KeyManagementService for LocalKeyStore {
 
         // Create new version
         let new_version_num = metadata.current_version + 1;
-        let new_version = KeyVersion::new(new_version_num);
+        let mut new_version = KeyVersion::new(new_version_num);
+        keypair::record_public_key(&mut new_version, metadata.key_type, new_key_value)?;
 
         // Set expiration for old version (optional - keep for rollback)
         if let Some(old_version) = metadata.versions.last_mut() {
@@ -319,6 +454,9 @@ impl KeyManagementService for LocalKeyStore {
     }
 
     async fn delete_key(&self, key_id: &KeyId) -> KeyResult<()> {
+        // Destructive operations require an unsealed store
+        self.master_key()?;
+
         info!("Deleting key: {}", key_id);
 
         // Remove files
@@ -349,4 +487,202 @@ impl KeyManagementService for LocalKeyStore {
         let cache = self.metadata_cache.read().await;
         cache.contains_key(key_id)
     }
+
+    async fn seal_status(&self) -> Option<SealStatus> {
+        self.seal.as_ref().map(|seal| self.status_for(seal))
+    }
+
+    async fn unseal(&self, share: &str) -> KeyResult<SealStatus> {
+        let seal = self.seal.as_ref().ok_or_else(|| {
+            KeyError::Configuration("Key store is not configured for sealed mode".to_string())
+        })?;
+        if self.master_key().is_ok() {
+            return Ok(self.status_for(seal));
+        }
+
+        let share: Share = share.parse()?;
+        if share.y.len() != 32 {
+            return Err(KeyError::InvalidFormat("Share does not encode a 32-byte master key".to_string()));
+        }
+
+        if share.x > seal.shares {
+            return Err(KeyError::InvalidFormat(format!(
+                "Share index {} is outside the {} shares of this seal",
+                share.x, seal.shares
+            )));
+        }
+
+        // Pending shares have distinct indices, so there are never more than
+        // `threshold` of them. Any conflict or failed reconstruction discards
+        // them all: a bad share can't be told apart from the honest ones, and
+        // keeping it would block every later attempt.
+        let master_key = {
+            let mut pending = self.pending_shares.lock().unwrap_or_else(|e| e.into_inner());
+            if pending.contains(&share) {
+                debug!(share = share.x, "Ignoring duplicate unseal share");
+                return Ok(SealStatus {
+                    sealed: true,
+                    threshold: seal.threshold,
+                    shares: seal.shares,
+                    progress: pending.len(),
+                });
+            }
+            if pending.iter().any(|pending| pending.x == share.x) {
+                pending.clear();
+                warn!(share = share.x, "Unseal share conflicts with a pending share, pending shares discarded");
+                return Err(KeyError::Decryption(format!(
+                    "Unseal failed: share {} conflicts with a pending share; submit {} shares again",
+                    share.x, seal.threshold
+                )));
+            }
+            pending.push(share);
+            if pending.len() < seal.threshold as usize {
+                None
+            } else {
+                let master_key = recover_master_key(seal, &pending);
+                pending.clear();
+                if master_key.is_none() {
+                    warn!("Unseal attempt failed: shares do not reconstruct the master key, pending shares discarded");
+                    return Err(KeyError::Decryption(format!(
+                        "Unseal failed: shares do not reconstruct the master key; submit {} shares again",
+                        seal.threshold
+                    )));
+                }
+                master_key
+            }
+        };
+
+        if let Some(master_key) = master_key {
+            *self.master_key.write().unwrap_or_else(|e| e.into_inner()) = Some(master_key);
+            info!("Key store unsealed");
+        }
+
+        Ok(self.status_for(seal))
+    }
+
+    async fn seal(&self) -> KeyResult<()> {
+        if self.seal.is_none() {
+            return Err(KeyError::Configuration(
+                "Key store is not configured for sealed mode".to_string(),
+            ));
+        }
+        *self.master_key.write().unwrap_or_else(|e| e.into_inner()) = None;
+        self.pending_shares.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        info!("Key store sealed");
+        Ok(())
+    }
+}
+
+/// Master key reconstructed from `shares`, if it matches the seal
+fn recover_master_key(seal: &SealConfig, shares: &[Share]) -> Option<MasterKey> {
+    let combined = shamir::combine_shares(shares).ok()?;
+    if !seal.verify(&combined) {
+        return None;
+    }
+    let mut master_key = [0u8; 32];
+    master_key.copy_from_slice(&combined);
+    Some(master_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sealed_store_unseals_with_threshold_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        let master_key = [9u8; 32];
+        let key_id = "api.key".to_string();
+        {
+            let store = LocalKeyStore::new(dir.path(), Some(&master_key)).await.unwrap();
+            store.store_key(&key_id, KeyType::ApiKey, b"value", None).await.unwrap();
+        }
+
+        assert!(LocalKeyStore::init_seal(dir.path(), None, 2, 3).await.is_err());
+        let shares = LocalKeyStore::init_seal(dir.path(), Some(&master_key), 2, 3)
+            .await
+            .unwrap();
+        assert!(LocalKeyStore::init_seal(dir.path(), Some(&master_key), 2, 3)
+            .await
+            .is_err());
+
+        let store = LocalKeyStore::new(dir.path(), None).await.unwrap();
+        assert!(store.seal_status().await.unwrap().sealed);
+        assert!(store.key_exists(&key_id).await);
+        assert!(matches!(store.get_key(&key_id).await, Err(KeyError::Sealed)));
+        assert!(matches!(store.delete_key(&key_id).await, Err(KeyError::Sealed)));
+
+        let status = store.unseal(&shares[2].to_string()).await.unwrap();
+        assert!(status.sealed);
+        assert_eq!(status.progress, 1);
+        let status = store.unseal(&shares[0].to_string()).await.unwrap();
+        assert!(!status.sealed);
+        assert_eq!(store.get_key(&key_id).await.unwrap(), b"value");
+
+        store.seal().await.unwrap();
+        assert!(matches!(store.get_key(&key_id).await, Err(KeyError::Sealed)));
+    }
+
+    #[tokio::test]
+    async fn test_unseal_rejects_shares_from_another_seal() {
+        let dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let shares = LocalKeyStore::init_seal(dir.path(), None, 2, 2).await.unwrap();
+        let other = LocalKeyStore::init_seal(other_dir.path(), None, 2, 2).await.unwrap();
+
+        let store = LocalKeyStore::new(dir.path(), None).await.unwrap();
+        store.unseal(&shares[0].to_string()).await.unwrap();
+        assert!(matches!(
+            store.unseal(&other[1].to_string()).await,
+            Err(KeyError::Decryption(_))
+        ));
+        let status = store.seal_status().await.unwrap();
+        assert!(status.sealed);
+        assert_eq!(status.progress, 0);
+
+        // A bad share submitted first conflicts with the honest one for its
+        // index; neither blocks the next attempt
+        store.unseal(&other[0].to_string()).await.unwrap();
+        assert!(matches!(
+            store.unseal(&shares[0].to_string()).await,
+            Err(KeyError::Decryption(_))
+        ));
+        store.unseal(&shares[1].to_string()).await.unwrap();
+        let status = store.unseal(&shares[0].to_string()).await.unwrap();
+        assert!(!status.sealed);
+        assert_eq!(status.progress, 0);
+    }
+
+    #[tokio::test]
+    async fn test_unseal_rejects_out_of_range_share() {
+        let dir = tempfile::tempdir().unwrap();
+        LocalKeyStore::init_seal(dir.path(), None, 2, 3).await.unwrap();
+        let store = LocalKeyStore::new(dir.path(), None).await.unwrap();
+
+        let share = Share { x: 4, y: vec![0u8; 32] };
+        assert!(matches!(
+            store.unseal(&share.to_string()).await,
+            Err(KeyError::InvalidFormat(_))
+        ));
+        assert_eq!(store.seal_status().await.unwrap().progress, 0);
+    }
+
+    #[tokio::test]
+    async fn test_explicit_master_key_must_match_seal() {
+        let dir = tempfile::tempdir().unwrap();
+        LocalKeyStore::init_seal(dir.path(), Some(&[1u8; 32]), 1, 1).await.unwrap();
+
+        assert!(LocalKeyStore::new(dir.path(), Some(&[2u8; 32])).await.is_err());
+        let store = LocalKeyStore::new(dir.path(), Some(&[1u8; 32])).await.unwrap();
+        assert!(!store.seal_status().await.unwrap().sealed);
+    }
+
+    #[test]
+    fn test_parse_master_key() {
+        assert_eq!(LocalKeyStore::parse_master_key(&"ab".repeat(32)), [0xab; 32]);
+        assert_eq!(
+            LocalKeyStore::parse_master_key("legacy passphrase"),
+            <[u8; 32]>::from(Sha256::digest(b"legacy passphrase"))
+        );
+    }
 }
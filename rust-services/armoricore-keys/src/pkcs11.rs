@@ -0,0 +1,631 @@
+//! PKCS#11 (HSM) key storage implementation
+//!
+//! Keys are stored as token objects on a PKCS#11 device such as a network HSM
+//! or SoftHSM2. Encryption keys and keys created with `generate_key` are
+//! marked sensitive and non-extractable: `get_key` refuses to return them, and
+//! callers use them in place through `encrypt`, `decrypt`, `sign` and
+//! `derive_key`. Media content keys are the exception, since players need
+//! the key bytes.
+//!
+//! Object layout on the token:
+//! - one secret key object per key version (`CKA_LABEL` = key id,
+//!   `CKA_ID` = big-endian version number)
+//! - one data object per key (`CKA_APPLICATION` = `armoricore-keys`,
+//!   `CKA_LABEL` = key id) holding the serialized `KeyMetadata`
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::crypto::{GCM_NONCE_LEN, GCM_TAG_LEN};
+use crate::error::{KeyError, KeyResult};
+use crate::key_types::{KeyId, KeyMetadata, KeyType, KeyVersion};
+use crate::keypair;
+use crate::kms::KeyManagementService;
+use async_trait::async_trait;
+use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+use cryptoki::error::{Error as CryptokiError, RvError};
+use cryptoki::mechanism::aead::GcmParams;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, KeyType as CkKeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::{AuthPin, Ulong};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{debug, info};
+
+/// CKA_APPLICATION value for metadata data objects
+const METADATA_APPLICATION: &[u8] = b"armoricore-keys";
+
+/// PKCS#11 backend configuration
+#[derive(Debug, Clone)]
+pub struct Pkcs11Config {
+    /// Path to the PKCS#11 module (e.g. /usr/lib/softhsm/libsofthsm2.so)
+    pub module_path: PathBuf,
+    /// Label of the token holding Armoricore keys
+    pub token_label: String,
+    /// User PIN for the token
+    pub user_pin: String,
+}
+
+impl Pkcs11Config {
+    /// Load configuration from environment variables
+    ///
+    /// - `ARMORICORE_PKCS11_MODULE` - path to the PKCS#11 module
+    /// - `ARMORICORE_PKCS11_TOKEN` - token label
+    /// - `ARMORICORE_PKCS11_PIN` - user PIN
+    pub fn from_env() -> KeyResult<Self> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| KeyError::Configuration(format!("{} is not set", name)))
+        };
+        Ok(Self {
+            module_path: PathBuf::from(var("ARMORICORE_PKCS11_MODULE")?),
+            token_label: var("ARMORICORE_PKCS11_TOKEN")?,
+            user_pin: var("ARMORICORE_PKCS11_PIN")?,
+        })
+    }
+}
+
+/// Key store backed by a PKCS#11 token
+pub struct Pkcs11KeyStore {
+    /// Logged-in read/write session (sessions are not `Sync`)
+    session: Mutex<Session>,
+    /// Loaded PKCS#11 module, kept alive for the lifetime of the session
+    _context: Pkcs11,
+}
+
+impl Pkcs11KeyStore {
+    /// Open the configured token and log in as the user
+    pub fn new(config: &Pkcs11Config) -> KeyResult<Self> {
+        let context = Pkcs11::new(&config.module_path).map_err(kms_error)?;
+        match context.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+            Ok(()) | Err(CryptokiError::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+            Err(e) => return Err(kms_error(e)),
+        }
+
+        let slot = context
+            .get_slots_with_token()
+            .map_err(kms_error)?
+            .into_iter()
+            .find(|slot| {
+                context
+                    .get_token_info(*slot)
+                    .map(|info| info.label().trim() == config.token_label)
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| {
+                KeyError::Configuration(format!(
+                    "PKCS#11 token not found: {}",
+                    config.token_label
+                ))
+            })?;
+
+        let session = context.open_rw_session(slot).map_err(kms_error)?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(config.user_pin.clone().into())))
+            .map_err(kms_error)?;
+
+        info!(
+            module = %config.module_path.display(),
+            token = %config.token_label,
+            "Opened PKCS#11 key store"
+        );
+
+        Ok(Self {
+            session: Mutex::new(session),
+            _context: context,
+        })
+    }
+
+    fn with_session<T>(&self, f: impl FnOnce(&Session) -> KeyResult<T>) -> KeyResult<T> {
+        let session = self
+            .session
+            .lock()
+            .map_err(|_| KeyError::Kms("PKCS#11 session lock poisoned".to_string()))?;
+        f(&session)
+    }
+
+    /// Whether a key type is stored as an AES key (vs. a generic HMAC secret)
+    fn is_aes(key_type: KeyType) -> bool {
+        key_type == KeyType::EncryptionKey
+    }
+
+    /// Attribute template for a new key version object
+    fn key_template(
+        key_id: &KeyId,
+        key_type: KeyType,
+        version: u32,
+        extractable: bool,
+    ) -> Vec<Attribute> {
+        let mut template = vec![
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Label(key_id.as_bytes().to_vec()),
+            Attribute::Id(version.to_be_bytes().to_vec()),
+            Attribute::Sensitive(!extractable),
+            Attribute::Extractable(extractable),
+        ];
+        if Self::is_aes(key_type) {
+            template.extend([
+                Attribute::KeyType(CkKeyType::AES),
+                Attribute::Encrypt(true),
+                Attribute::Decrypt(true),
+            ]);
+        } else {
+            template.extend([
+                Attribute::KeyType(CkKeyType::GENERIC_SECRET),
+                Attribute::Sign(true),
+                Attribute::Verify(true),
+            ]);
+        }
+        template
+    }
+
+    fn find_key_object(session: &Session, key_id: &KeyId, version: u32) -> KeyResult<ObjectHandle> {
+        session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::SECRET_KEY),
+                Attribute::Label(key_id.as_bytes().to_vec()),
+                Attribute::Id(version.to_be_bytes().to_vec()),
+            ])
+            .map_err(kms_error)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| KeyError::NotFound(format!("{} (version {})", key_id, version)))
+    }
+
+    fn find_metadata_object(session: &Session, key_id: &KeyId) -> KeyResult<Option<ObjectHandle>> {
+        Ok(session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::DATA),
+                Attribute::Application(METADATA_APPLICATION.to_vec()),
+                Attribute::Label(key_id.as_bytes().to_vec()),
+            ])
+            .map_err(kms_error)?
+            .into_iter()
+            .next())
+    }
+
+    fn load_metadata(session: &Session, key_id: &KeyId) -> KeyResult<KeyMetadata> {
+        let handle = Self::find_metadata_object(session, key_id)?
+            .ok_or_else(|| KeyError::NotFound(key_id.clone()))?;
+        let attributes = session
+            .get_attributes(handle, &[AttributeType::Value])
+            .map_err(kms_error)?;
+        match attributes.into_iter().next() {
+            Some(Attribute::Value(value)) => Ok(serde_json::from_slice(&value)?),
+            _ => Err(KeyError::InvalidFormat(format!(
+                "Metadata object for {} has no value",
+                key_id
+            ))),
+        }
+    }
+
+    fn save_metadata(session: &Session, metadata: &KeyMetadata) -> KeyResult<()> {
+        if let Some(handle) = Self::find_metadata_object(session, &metadata.id)? {
+            session.destroy_object(handle).map_err(kms_error)?;
+        }
+        session
+            .create_object(&[
+                Attribute::Class(ObjectClass::DATA),
+                Attribute::Token(true),
+                Attribute::Private(true),
+                Attribute::Application(METADATA_APPLICATION.to_vec()),
+                Attribute::Label(metadata.id.as_bytes().to_vec()),
+                Attribute::Value(serde_json::to_vec(metadata)?),
+            ])
+            .map_err(kms_error)?;
+        Ok(())
+    }
+
+    /// Versions to try for decryption/verification, newest first
+    fn usable_versions(metadata: &KeyMetadata) -> Vec<u32> {
+        let mut versions: Vec<u32> = metadata
+            .versions
+            .iter()
+            .filter(|v| v.is_active || !v.is_expired())
+            .map(|v| v.version)
+            .collect();
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        versions
+    }
+
+    fn gcm_decrypt(
+        session: &Session,
+        key: ObjectHandle,
+        data: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptokiError> {
+        let (nonce, ciphertext) = data.split_at(GCM_NONCE_LEN);
+        let mut nonce = nonce.to_vec();
+        let params = GcmParams::new(&mut nonce, aad, ulong(GCM_TAG_LEN * 8))?;
+        session.decrypt(&Mechanism::AesGcm(params), key, ciphertext)
+    }
+}
+
+#[async_trait]
+impl KeyManagementService for Pkcs11KeyStore {
+    async fn store_key(
+        &self,
+        key_id: &KeyId,
+        key_type: KeyType,
+        key_value: &[u8],
+        metadata: Option<&str>,
+    ) -> KeyResult<()> {
+        if Self::is_aes(key_type) && ![16, 24, 32].contains(&key_value.len()) {
+            return Err(KeyError::InvalidFormat(format!(
+                "AES key must be 16, 24 or 32 bytes, got {}",
+                key_value.len()
+            )));
+        }
+
+        self.with_session(|session| {
+            if Self::find_metadata_object(session, key_id)?.is_some() {
+                return Err(KeyError::AlreadyExists(key_id.clone()));
+            }
+
+            info!("Storing new key in HSM: {} (type: {:?})", key_id, key_type);
+
+            let mut key_metadata = KeyMetadata::new(key_id.clone(), key_type);
+            if let Some(meta) = metadata {
+                if let Ok(parsed) = serde_json::from_str::<HashMap<String, String>>(meta) {
+                    key_metadata.metadata = parsed;
+                }
+            }
+            if let Some(version) = key_metadata.versions.last_mut() {
+                keypair::record_public_key(version, key_type, key_value)?;
+            }
+
+            // Only non-encryption secrets that services must present to third
+            // parties (API keys, credentials) remain readable
+            let mut template = Self::key_template(key_id, key_type, 1, !Self::is_aes(key_type));
+            template.push(Attribute::Value(key_value.to_vec()));
+            session.create_object(&template).map_err(kms_error)?;
+
+            Self::save_metadata(session, &key_metadata)
+        })
+    }
+
+    async fn generate_key(
+        &self,
+        key_id: &KeyId,
+        key_type: KeyType,
+        length: usize,
+    ) -> KeyResult<()> {
+        // Key pairs are generated in software and imported as PKCS#8 data
+        if key_type.is_key_pair() {
+            let private_der = keypair::generate(key_type)?;
+            return self.store_key(key_id, key_type, &private_der, None).await;
+        }
+        // Content keys are delivered to players, so they are imported readable
+        if key_type == KeyType::ContentKey {
+            let value = crate::crypto::random_bytes(length);
+            return self.store_key(key_id, key_type, &value, None).await;
+        }
+
+        self.with_session(|session| {
+            if Self::find_metadata_object(session, key_id)?.is_some() {
+                return Err(KeyError::AlreadyExists(key_id.clone()));
+            }
+
+            info!("Generating key in HSM: {} (type: {:?})", key_id, key_type);
+
+            let mechanism = if Self::is_aes(key_type) {
+                Mechanism::AesKeyGen
+            } else {
+                Mechanism::GenericSecretKeyGen
+            };
+            let mut template = Self::key_template(key_id, key_type, 1, false);
+            template.push(Attribute::ValueLen(ulong(length)));
+            session.generate_key(&mechanism, &template).map_err(kms_error)?;
+
+            let mut key_metadata = KeyMetadata::new(key_id.clone(), key_type);
+            key_metadata
+                .metadata
+                .insert("extractable".to_string(), "false".to_string());
+            Self::save_metadata(session, &key_metadata)
+        })
+    }
+
+    async fn get_key(&self, key_id: &KeyId) -> KeyResult<Vec<u8>> {
+        let metadata = self.get_metadata(key_id).await?;
+        self.get_key_version(key_id, metadata.current_version).await
+    }
+
+    async fn get_key_version(&self, key_id: &KeyId, version: u32) -> KeyResult<Vec<u8>> {
+        self.with_session(|session| {
+            let handle = Self::find_key_object(session, key_id, version)?;
+            let attributes = session
+                .get_attributes(handle, &[AttributeType::Extractable, AttributeType::Sensitive])
+                .map_err(kms_error)?;
+            let readable = attributes.iter().all(|attr| match attr {
+                Attribute::Extractable(extractable) => *extractable,
+                Attribute::Sensitive(sensitive) => !*sensitive,
+                _ => true,
+            });
+            if !readable {
+                return Err(KeyError::PermissionDenied(format!(
+                    "Key {} is non-extractable; use it in place via encrypt/decrypt/sign",
+                    key_id
+                )));
+            }
+
+            match session
+                .get_attributes(handle, &[AttributeType::Value])
+                .map_err(kms_error)?
+                .into_iter()
+                .next()
+            {
+                Some(Attribute::Value(value)) => Ok(value),
+                _ => Err(KeyError::InvalidFormat(format!("Key {} has no value", key_id))),
+            }
+        })
+    }
+
+    async fn get_metadata(&self, key_id: &KeyId) -> KeyResult<KeyMetadata> {
+        self.with_session(|session| Self::load_metadata(session, key_id))
+    }
+
+    async fn rotate_key(
+        &self,
+        key_id: &KeyId,
+        new_key_value: &[u8],
+    ) -> KeyResult<KeyVersion> {
+        self.with_session(|session| {
+            let mut metadata = Self::load_metadata(session, key_id)?;
+            let new_version_num = metadata.current_version + 1;
+            let mut new_version = KeyVersion::new(new_version_num);
+            keypair::record_public_key(&mut new_version, metadata.key_type, new_key_value)?;
+
+            // Keep the previous version usable for decryption for 30 days
+            if let Some(old_version) = metadata.versions.last_mut() {
+                old_version.expires_at =
+                    Some(chrono::Utc::now().timestamp() + (30 * 24 * 60 * 60));
+            }
+
+            info!("Rotating HSM key {} to version {}", key_id, new_version_num);
+
+            let extractable = !Self::is_aes(metadata.key_type)
+                && metadata.metadata.get("extractable").map(String::as_str) != Some("false");
+            let mut template =
+                Self::key_template(key_id, metadata.key_type, new_version_num, extractable);
+            template.push(Attribute::Value(new_key_value.to_vec()));
+            session.create_object(&template).map_err(kms_error)?;
+
+            metadata.add_version(new_version.clone());
+            Self::save_metadata(session, &metadata)?;
+            Ok(new_version)
+        })
+    }
+
+    async fn delete_key(&self, key_id: &KeyId) -> KeyResult<()> {
+        info!("Deleting HSM key: {}", key_id);
+        self.with_session(|session| {
+            let key_objects = session
+                .find_objects(&[
+                    Attribute::Class(ObjectClass::SECRET_KEY),
+                    Attribute::Label(key_id.as_bytes().to_vec()),
+                ])
+                .map_err(kms_error)?;
+            for handle in key_objects {
+                session.destroy_object(handle).map_err(kms_error)?;
+            }
+            if let Some(handle) = Self::find_metadata_object(session, key_id)? {
+                session.destroy_object(handle).map_err(kms_error)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn list_keys(&self) -> KeyResult<Vec<KeyId>> {
+        self.with_session(|session| {
+            let handles = session
+                .find_objects(&[
+                    Attribute::Class(ObjectClass::DATA),
+                    Attribute::Application(METADATA_APPLICATION.to_vec()),
+                ])
+                .map_err(kms_error)?;
+
+            let mut keys = Vec::with_capacity(handles.len());
+            for handle in handles {
+                let attributes = session
+                    .get_attributes(handle, &[AttributeType::Label])
+                    .map_err(kms_error)?;
+                if let Some(Attribute::Label(label)) = attributes.into_iter().next() {
+                    keys.push(String::from_utf8_lossy(&label).into_owned());
+                }
+            }
+            debug!("Found {} keys on PKCS#11 token", keys.len());
+            Ok(keys)
+        })
+    }
+
+    async fn key_exists(&self, key_id: &KeyId) -> bool {
+        self.with_session(|session| Self::find_metadata_object(session, key_id))
+            .map(|handle| handle.is_some())
+            .unwrap_or(false)
+    }
+
+    async fn encrypt(&self, key_id: &KeyId, plaintext: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+        self.with_session(|session| {
+            let metadata = Self::load_metadata(session, key_id)?;
+            let handle = Self::find_key_object(session, key_id, metadata.current_version)?;
+
+            let mut nonce = vec![0u8; GCM_NONCE_LEN];
+            session.generate_random_slice(&mut nonce).map_err(kms_error)?;
+            let params = GcmParams::new(&mut nonce, aad, ulong(GCM_TAG_LEN * 8)).map_err(kms_error)?;
+            let ciphertext = session
+                .encrypt(&Mechanism::AesGcm(params), handle, plaintext)
+                .map_err(|e| KeyError::Encryption(e.to_string()))?;
+
+            let mut result = nonce;
+            result.extend_from_slice(&ciphertext);
+            Ok(result)
+        })
+    }
+
+    async fn decrypt(&self, key_id: &KeyId, ciphertext: &[u8], aad: &[u8]) -> KeyResult<Vec<u8>> {
+        if ciphertext.len() < GCM_NONCE_LEN + GCM_TAG_LEN {
+            return Err(KeyError::Decryption("Encrypted data too short".to_string()));
+        }
+
+        self.with_session(|session| {
+            let metadata = Self::load_metadata(session, key_id)?;
+            let mut last_error = None;
+            // Ciphertexts don't record the key version, so fall back to
+            // previous versions that are still within their rollback window
+            for version in Self::usable_versions(&metadata) {
+                let handle = Self::find_key_object(session, key_id, version)?;
+                match Self::gcm_decrypt(session, handle, ciphertext, aad) {
+                    Ok(plaintext) => return Ok(plaintext),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            Err(KeyError::Decryption(
+                last_error
+                    .map(|e| e.to_string())
+                    .unwrap_or_else(|| "No usable key version".to_string()),
+            ))
+        })
+    }
+
+    async fn sign(&self, key_id: &KeyId, data: &[u8]) -> KeyResult<Vec<u8>> {
+        self.with_session(|session| {
+            let metadata = Self::load_metadata(session, key_id)?;
+            let handle = Self::find_key_object(session, key_id, metadata.current_version)?;
+            session
+                .sign(&Mechanism::Sha256Hmac, handle, data)
+                .map_err(kms_error)
+        })
+    }
+
+    async fn verify(&self, key_id: &KeyId, data: &[u8], signature: &[u8]) -> KeyResult<bool> {
+        self.with_session(|session| {
+            let metadata = Self::load_metadata(session, key_id)?;
+            for version in Self::usable_versions(&metadata) {
+                let handle = Self::find_key_object(session, key_id, version)?;
+                match session.verify(&Mechanism::Sha256Hmac, handle, data, signature) {
+                    Ok(()) => return Ok(true),
+                    Err(CryptokiError::Pkcs11(RvError::SignatureInvalid, _))
+                    | Err(CryptokiError::Pkcs11(RvError::SignatureLenRange, _)) => continue,
+                    Err(e) => return Err(kms_error(e)),
+                }
+            }
+            Ok(false)
+        })
+    }
+}
+
+fn kms_error(e: CryptokiError) -> KeyError {
+    KeyError::Kms(format!("PKCS#11: {}", e))
+}
+
+fn ulong(value: usize) -> Ulong {
+    Ulong::from(value as std::os::raw::c_ulong)
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need a SoftHSM2 token, e.g.:
+    //!
+    //! ```text
+    //! softhsm2-util --init-token --free --label armoricore-test --pin 1234 --so-pin 0000
+    //! ARMORICORE_PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so \
+    //! ARMORICORE_PKCS11_TOKEN=armoricore-test ARMORICORE_PKCS11_PIN=1234 \
+    //!     cargo test -p armoricore-keys --features pkcs11 -- --ignored
+    //! ```
+
+    use super::*;
+    use crate::key_store::KeyStore;
+    use std::sync::Arc;
+
+    fn test_store() -> KeyStore {
+        let config = Pkcs11Config::from_env().expect("PKCS#11 environment not configured");
+        KeyStore::new(Arc::new(Pkcs11KeyStore::new(&config).unwrap()))
+    }
+
+    fn unique_id(prefix: &str) -> KeyId {
+        format!("{}.{}", prefix, hex::encode(crate::crypto::random_bytes(6)))
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires SoftHSM2
+    async fn test_generated_encryption_key_is_not_extractable() {
+        let store = test_store();
+        let key_id = unique_id("test.media");
+
+        store.generate_key(&key_id, KeyType::EncryptionKey, 32).await.unwrap();
+
+        assert!(matches!(
+            store.get_encryption_key(&key_id).await,
+            Err(KeyError::PermissionDenied(_))
+        ));
+
+        let ciphertext = store.encrypt(&key_id, b"segment", b"aad").await.unwrap();
+        assert_eq!(store.decrypt(&key_id, &ciphertext, b"aad").await.unwrap(), b"segment");
+        assert!(store.decrypt(&key_id, &ciphertext, b"other").await.is_err());
+
+        store.delete_key(&key_id).await.unwrap();
+        assert!(!store.key_exists(&key_id).await);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires SoftHSM2
+    async fn test_generated_content_key_is_extractable() {
+        let store = test_store();
+        let key_id = unique_id("test.content");
+
+        store.generate_key(&key_id, KeyType::ContentKey, 16).await.unwrap();
+        assert_eq!(store.get_key(&key_id).await.unwrap().len(), 16);
+
+        store.delete_key(&key_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires SoftHSM2
+    async fn test_hmac_sign_verify_and_derive() {
+        let store = test_store();
+        let key_id = unique_id("test.secret");
+
+        store.generate_key(&key_id, KeyType::Secret, 32).await.unwrap();
+
+        let signature = store.sign(&key_id, b"data").await.unwrap();
+        assert!(store.verify(&key_id, b"data", &signature).await.unwrap());
+        assert!(!store.verify(&key_id, b"tampered", &signature).await.unwrap());
+
+        let derived = store.derive_key(&key_id, b"context", 48).await.unwrap();
+        assert_eq!(derived.len(), 48);
+        assert_eq!(derived, store.derive_key(&key_id, b"context", 48).await.unwrap());
+
+        store.delete_key(&key_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires SoftHSM2
+    async fn test_imported_api_key_is_readable_and_rotates() {
+        let store = test_store();
+        let key_id = unique_id("test.api");
+
+        store.store_api_key(&key_id, "v1", None).await.unwrap();
+        assert_eq!(store.get_api_key(&key_id).await.unwrap(), "v1");
+
+        store.rotate_key(&key_id, "v2").await.unwrap();
+        assert_eq!(store.get_api_key(&key_id).await.unwrap(), "v2");
+        assert_eq!(store.get_metadata(&key_id).await.unwrap().current_version, 2);
+
+        store.delete_key(&key_id).await.unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! Per-key access policies for service identities
+//!
+//! A policy grants each service identity (e.g. `media-processor`) a set of
+//! permissions on key ids matching glob patterns (`*` matches any sequence).
+//! Anything not granted is denied. Policies are loaded from a TOML, YAML or
+//! JSON file, selected by extension:
+//!
+//! ```toml
+//! [[identities.media-processor]]
+//! keys = ["object_storage.*", "media_*"]
+//! permissions = ["read", "write"]
+//!
+//! [[identities.notification-worker]]
+//! keys = ["fcm.*", "apns.*", "smtp.*"]
+//! permissions = ["read"]
+//! ```
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Environment variable naming the policy file
+pub const POLICY_FILE_ENV: &str = "KEY_STORE_POLICY_FILE";
+
+/// Key id that `seal` grants name: the store's seal state rather than a key
+pub const SEAL_KEY_ID: &str = "sys.seal";
+
+/// Name a service uses to authenticate against the key store policy
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ServiceIdentity(String);
+
+impl ServiceIdentity {
+    /// Media processor service
+    pub const MEDIA_PROCESSOR: &'static str = "media-processor";
+    /// Notification worker service
+    pub const NOTIFICATION_WORKER: &'static str = "notification-worker";
+    /// Realtime media engine
+    pub const REALTIME_MEDIA_ENGINE: &'static str = "realtime-media-engine";
+
+    /// Create an identity from its name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// Identity name
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ServiceIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+/// Operation a policy rule can grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Read key values and metadata (implies `use`)
+    Read,
+    /// Use keys in place (encrypt, decrypt, sign, verify, derive) and read metadata
+    Use,
+    /// Store and generate new keys
+    Write,
+    /// Rotate existing keys
+    Rotate,
+    /// Delete keys
+    Delete,
+    /// Read the seal status, submit unseal shares and seal the store, on
+    /// [`SEAL_KEY_ID`]
+    Seal,
+}
+
+impl Permission {
+    /// Lowercase name used in policy files and error messages
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Use => "use",
+            Permission::Write => "write",
+            Permission::Rotate => "rotate",
+            Permission::Delete => "delete",
+            Permission::Seal => "seal",
+        }
+    }
+
+    fn is_granted_by(&self, granted: Permission) -> bool {
+        granted == *self || (granted == Permission::Read && *self == Permission::Use)
+    }
+}
+
+/// Permissions granted on a set of key id patterns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyRule {
+    /// Key id glob patterns
+    pub keys: Vec<String>,
+    /// Permissions granted on matching keys
+    pub permissions: Vec<Permission>,
+}
+
+/// Access policy mapping service identities to rules
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    /// Rules per identity name
+    #[serde(default)]
+    pub identities: HashMap<String, Vec<PolicyRule>>,
+}
+
+impl AccessPolicy {
+    /// Load a policy file (format chosen by extension: toml, yaml, json)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> KeyResult<Self> {
+        let path = path.as_ref();
+        config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .map_err(|e| {
+                KeyError::Configuration(format!(
+                    "Failed to load key store policy {}: {}",
+                    path.display(),
+                    e
+                ))
+            })
+    }
+
+    /// Load the policy named by `KEY_STORE_POLICY_FILE`, if set
+    pub fn from_env() -> KeyResult<Option<Self>> {
+        match std::env::var(POLICY_FILE_ENV) {
+            Ok(path) if !path.is_empty() => Self::from_file(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether `identity` holds `permission` on `key_id`
+    pub fn is_allowed(
+        &self,
+        identity: &ServiceIdentity,
+        key_id: &str,
+        permission: Permission,
+    ) -> bool {
+        self.identities
+            .get(identity.name())
+            .into_iter()
+            .flatten()
+            .any(|rule| {
+                rule.permissions.iter().any(|p| permission.is_granted_by(*p))
+                    && rule.keys.iter().any(|pattern| matches_pattern(pattern, key_id))
+            })
+    }
+
+    /// Whether `identity` holds any permission on `key_id`
+    pub fn is_visible(&self, identity: &ServiceIdentity, key_id: &str) -> bool {
+        self.identities
+            .get(identity.name())
+            .into_iter()
+            .flatten()
+            .any(|rule| rule.keys.iter().any(|pattern| matches_pattern(pattern, key_id)))
+    }
+
+    /// Return `PermissionDenied` unless `identity` holds `permission` on `key_id`
+    pub fn check(
+        &self,
+        identity: &ServiceIdentity,
+        key_id: &str,
+        permission: Permission,
+    ) -> KeyResult<()> {
+        if self.is_allowed(identity, key_id, permission) {
+            Ok(())
+        } else {
+            Err(KeyError::PermissionDenied(format!(
+                "{} may not {} {}",
+                identity,
+                permission.as_str(),
+                key_id
+            )))
+        }
+    }
+}
+
+/// Glob match where `*` matches any (possibly empty) sequence
+fn matches_pattern(pattern: &str, key_id: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = key_id.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("jwt.secret", "jwt.secret"));
+        assert!(!matches_pattern("jwt.secret", "jwt.secret2"));
+        assert!(matches_pattern("object_storage.*", "object_storage.access_key"));
+        assert!(!matches_pattern("object_storage.*", "apns.key_id"));
+        assert!(matches_pattern("*", "anything"));
+        assert!(matches_pattern("media_*.key", "media_123.key"));
+        assert!(!matches_pattern("media_*.key", "media_123.keys"));
+        assert!(matches_pattern("a*b*c", "abc"));
+        assert!(!matches_pattern("a*bc", "abc2"));
+    }
+
+    #[test]
+    fn test_policy_from_toml_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.toml");
+        std::fs::write(
+            &path,
+            r#"
+[[identities.media-processor]]
+keys = ["object_storage.*"]
+permissions = ["read"]
+
+[[identities.media-processor]]
+keys = ["media_*"]
+permissions = ["use", "write"]
+"#,
+        )
+        .unwrap();
+
+        let policy = AccessPolicy::from_file(&path).unwrap();
+        let media = ServiceIdentity::new(ServiceIdentity::MEDIA_PROCESSOR);
+        let other = ServiceIdentity::new(ServiceIdentity::NOTIFICATION_WORKER);
+
+        assert!(policy.is_allowed(&media, "object_storage.secret_key", Permission::Read));
+        assert!(policy.is_allowed(&media, "object_storage.secret_key", Permission::Use));
+        assert!(!policy.is_allowed(&media, "object_storage.secret_key", Permission::Rotate));
+        assert!(policy.is_allowed(&media, "media_42", Permission::Use));
+        assert!(!policy.is_allowed(&media, "media_42", Permission::Read));
+        assert!(!policy.is_allowed(&other, "object_storage.secret_key", Permission::Read));
+        assert!(matches!(
+            policy.check(&media, "apns.key_id", Permission::Read),
+            Err(KeyError::PermissionDenied(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_key_store_enforces_policy() {
+        use crate::key_store::KeyStore;
+        use crate::key_types::KeyType;
+        use crate::local_store::LocalKeyStore;
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Arc::new(LocalKeyStore::new(dir.path(), Some(&[4u8; 32])).await.unwrap());
+        let admin = KeyStore::new(backend.clone());
+        let secret = "object_storage.secret_key".to_string();
+        let apns = "apns.key_id".to_string();
+        admin.store_api_key(&secret, "s3-secret", None).await.unwrap();
+        admin.store_api_key(&apns, "apns", None).await.unwrap();
+
+        let mut policy = AccessPolicy::default();
+        policy.identities.insert(
+            ServiceIdentity::MEDIA_PROCESSOR.to_string(),
+            vec![PolicyRule {
+                keys: vec!["object_storage.*".to_string()],
+                permissions: vec![Permission::Read],
+            }],
+        );
+        let media = admin.as_identity(
+            Arc::new(policy),
+            ServiceIdentity::new(ServiceIdentity::MEDIA_PROCESSOR),
+        );
+
+        assert_eq!(media.get_api_key(&secret).await.unwrap(), "s3-secret");
+        assert!(matches!(
+            media.rotate_key(&secret, "new").await,
+            Err(KeyError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            media.get_api_key(&apns).await,
+            Err(KeyError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            media.store_key(&"media.key".to_string(), KeyType::Secret, b"v", None).await,
+            Err(KeyError::PermissionDenied(_))
+        ));
+        assert_eq!(media.list_keys().await.unwrap(), vec![secret.clone()]);
+        assert!(!media.key_exists(&apns).await);
+    }
+
+    #[tokio::test]
+    async fn test_seal_operations_require_seal_permission() {
+        use crate::key_store::KeyStore;
+        use crate::local_store::LocalKeyStore;
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shares = LocalKeyStore::init_seal(dir.path(), None, 1, 1).await.unwrap();
+        let admin = KeyStore::new(Arc::new(LocalKeyStore::new(dir.path(), None).await.unwrap()));
+
+        let mut policy = AccessPolicy::default();
+        policy.identities.insert(
+            ServiceIdentity::MEDIA_PROCESSOR.to_string(),
+            vec![PolicyRule {
+                keys: vec![SEAL_KEY_ID.to_string()],
+                permissions: vec![Permission::Seal],
+            }],
+        );
+        policy.identities.insert(
+            ServiceIdentity::NOTIFICATION_WORKER.to_string(),
+            vec![PolicyRule {
+                keys: vec!["*".to_string()],
+                permissions: vec![Permission::Read, Permission::Write],
+            }],
+        );
+        let policy = Arc::new(policy);
+        let media = admin.as_identity(policy.clone(), ServiceIdentity::new(ServiceIdentity::MEDIA_PROCESSOR));
+        let worker = admin.as_identity(policy, ServiceIdentity::new(ServiceIdentity::NOTIFICATION_WORKER));
+
+        let share = shares[0].to_string();
+        assert!(matches!(worker.seal_status().await, Err(KeyError::PermissionDenied(_))));
+        assert!(matches!(worker.unseal(&share).await, Err(KeyError::PermissionDenied(_))));
+        assert!(media.seal_status().await.unwrap().unwrap().sealed);
+        assert!(!media.unseal(&share).await.unwrap().sealed);
+        assert!(matches!(worker.seal().await, Err(KeyError::PermissionDenied(_))));
+        assert!(!worker.is_sealed().await);
+        media.seal().await.unwrap();
+        assert!(worker.is_sealed().await);
+    }
+}
@@ -0,0 +1,122 @@
+//! Sealed mode for the local key store
+//!
+//! In sealed mode the master key is never configured directly. It is split
+//! into M-of-N Shamir shares when the seal is initialized, and the store
+//! starts sealed until enough shares are submitted to reconstruct it.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::crypto;
+use crate::error::{KeyError, KeyResult};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// File in the storage directory that marks a store as sealed
+pub const SEAL_FILE_NAME: &str = "seal.json";
+
+/// Current seal file format version
+pub const SEAL_FORMAT_VERSION: u32 = 1;
+
+/// Plaintext encrypted into the seal file to check a reconstructed master key
+const VERIFICATION_PLAINTEXT: &[u8] = b"armoricore-keys seal verification";
+
+/// Associated data for the verification value
+const VERIFICATION_AAD: &[u8] = b"seal";
+
+/// Seal state reported to operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealStatus {
+    /// Whether key material is currently unavailable
+    pub sealed: bool,
+    /// Number of shares required to unseal
+    pub threshold: u8,
+    /// Total number of shares issued
+    pub shares: u8,
+    /// Shares submitted towards the current unseal attempt
+    pub progress: usize,
+}
+
+/// Persisted seal configuration (`seal.json`)
+///
+/// Holds no key material: only the Shamir parameters and a value encrypted
+/// under the master key, used to reject wrong or mismatched shares.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealConfig {
+    /// Seal file format version
+    pub format_version: u32,
+    /// Number of shares required to unseal
+    pub threshold: u8,
+    /// Total number of shares issued
+    pub shares: u8,
+    /// When the seal was initialized
+    pub created_at: i64,
+    /// AES-256-GCM encryption of a fixed plaintext under the master key (hex)
+    pub verification: String,
+}
+
+impl SealConfig {
+    /// Create a seal configuration for `master_key`
+    pub fn new(master_key: &[u8], threshold: u8, shares: u8) -> KeyResult<Self> {
+        let verification =
+            crypto::aes_gcm_encrypt(master_key, VERIFICATION_PLAINTEXT, VERIFICATION_AAD)?;
+        Ok(Self {
+            format_version: SEAL_FORMAT_VERSION,
+            threshold,
+            shares,
+            created_at: chrono::Utc::now().timestamp(),
+            verification: hex::encode(verification),
+        })
+    }
+
+    /// Path of the seal file inside a storage directory
+    pub fn path(storage_path: &Path) -> PathBuf {
+        storage_path.join(SEAL_FILE_NAME)
+    }
+
+    /// Load the seal file, if the store is configured for sealed mode
+    pub async fn load(storage_path: &Path) -> KeyResult<Option<Self>> {
+        let path = Self::path(storage_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config: Self = serde_json::from_str(&fs::read_to_string(&path).await?)?;
+        if config.format_version != SEAL_FORMAT_VERSION {
+            return Err(KeyError::InvalidFormat(format!(
+                "Unsupported seal format version: {}",
+                config.format_version
+            )));
+        }
+        Ok(Some(config))
+    }
+
+    /// Write the seal file, refusing to replace an existing one
+    pub async fn save(&self, storage_path: &Path) -> KeyResult<()> {
+        let path = Self::path(storage_path);
+        if path.exists() {
+            return Err(KeyError::AlreadyExists(path.display().to_string()));
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Check whether `master_key` is the key this seal was created for
+    pub fn verify(&self, master_key: &[u8]) -> bool {
+        hex::decode(&self.verification)
+            .ok()
+            .and_then(|data| crypto::aes_gcm_decrypt(master_key, &data, VERIFICATION_AAD).ok())
+            .is_some_and(|plaintext| plaintext == VERIFICATION_PLAINTEXT)
+    }
+}
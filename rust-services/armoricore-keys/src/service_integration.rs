@@ -15,14 +15,73 @@
 
 
 use crate::key_store::KeyStore;
+use crate::kms::KeyManagementService;
 use crate::local_store::LocalKeyStore;
-use crate::error::KeyResult;
+use crate::error::{KeyError, KeyResult};
+use crate::policy::{AccessPolicy, ServiceIdentity, POLICY_FILE_ENV};
 use std::env;
 use std::sync::Arc;
 use tracing::{info, warn};
 
 /// Initialize key store for a service
+///
+/// The backend is selected with `KEY_STORE_BACKEND`: `local` (default) uses
+/// encrypted files under `storage_path`/`KEY_STORAGE_PATH`, `pkcs11` uses an
+/// HSM token configured through `ARMORICORE_PKCS11_*` (requires the `pkcs11`
+/// feature).
+///
+/// The returned store is unrestricted; services should use
+/// [`init_key_store_for`] so their access policy is enforced.
 pub async fn init_key_store(storage_path: Option<&str>) -> KeyResult<Arc<KeyStore>> {
+    let backend = init_backend(storage_path).await?;
+    Ok(Arc::new(KeyStore::new(backend)))
+}
+
+/// Initialize key store for a service acting as `identity`
+///
+/// If `KEY_STORE_POLICY_FILE` is set, the store only allows what that policy
+/// grants `identity`. Without a policy file the store is unrestricted, as
+/// before policies existed.
+pub async fn init_key_store_for(
+    identity: &str,
+    storage_path: Option<&str>,
+) -> KeyResult<Arc<KeyStore>> {
+    let backend = init_backend(storage_path).await?;
+    let key_store = match AccessPolicy::from_env()? {
+        Some(policy) => {
+            info!(identity = identity, "Key store access restricted by policy");
+            KeyStore::with_policy(backend, Arc::new(policy), ServiceIdentity::new(identity))
+        }
+        None => {
+            warn!(
+                identity = identity,
+                "{} not set, key store access is unrestricted",
+                POLICY_FILE_ENV
+            );
+            KeyStore::new(backend)
+        }
+    };
+    Ok(Arc::new(key_store))
+}
+
+async fn init_backend(storage_path: Option<&str>) -> KeyResult<Arc<dyn KeyManagementService>> {
+    let backend = env::var("KEY_STORE_BACKEND").unwrap_or_else(|_| "local".to_string());
+    match backend.as_str() {
+        "local" => {}
+        #[cfg(feature = "pkcs11")]
+        "pkcs11" => {
+            let config = crate::pkcs11::Pkcs11Config::from_env()?;
+            let hsm_store = crate::pkcs11::Pkcs11KeyStore::new(&config)?;
+            return Ok(Arc::new(hsm_store));
+        }
+        other => {
+            return Err(KeyError::Configuration(format!(
+                "Unsupported KEY_STORE_BACKEND: {}",
+                other
+            )))
+        }
+    }
+
     let path = storage_path
         .map(|p| p.to_string())
         .or_else(|| env::var("KEY_STORAGE_PATH").ok())
@@ -31,10 +90,7 @@ pub async fn init_key_store(storage_path: Option<&str>) -> KeyResult<Arc<KeyStor
     info!(path = %path, "Initializing key store");
 
     let local_store = LocalKeyStore::new(&path, None).await?;
-    let backend = Arc::new(local_store);
-    let key_store = Arc::new(KeyStore::new(backend));
-
-    Ok(key_store)
+    Ok(Arc::new(local_store))
 }
 
 /// Get a key from key store with fallback to environment variable
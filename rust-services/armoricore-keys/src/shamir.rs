@@ -0,0 +1,222 @@
+//! Shamir secret sharing over GF(256)
+//!
+//! Used to split the local key store master key into M-of-N unseal shares.
+//! Each byte of the secret is shared independently with a random polynomial
+//! of degree `threshold - 1`; shares are evaluated at x = 1..=N.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::error::{KeyError, KeyResult};
+use rand::RngCore;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single secret share
+///
+/// Encoded as hex: the first byte is the x coordinate, the rest are the
+/// share bytes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    /// x coordinate (1..=255)
+    pub x: u8,
+    /// Polynomial values at `x`, one per secret byte
+    pub y: Vec<u8>,
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}{}", self.x, hex::encode(&self.y))
+    }
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print share material
+        f.debug_struct("Share").field("x", &self.x).finish_non_exhaustive()
+    }
+}
+
+impl FromStr for Share {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim())
+            .map_err(|e| KeyError::InvalidFormat(format!("Invalid share encoding: {}", e)))?;
+        match bytes.split_first() {
+            Some((&x, y)) if x != 0 && !y.is_empty() => Ok(Share { x, y: y.to_vec() }),
+            _ => Err(KeyError::InvalidFormat("Invalid share".to_string())),
+        }
+    }
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of which recover it
+pub fn split_secret(secret: &[u8], threshold: u8, shares: u8) -> KeyResult<Vec<Share>> {
+    if threshold == 0 || shares == 0 || threshold > shares {
+        return Err(KeyError::Configuration(format!(
+            "Invalid Shamir parameters: threshold {} of {} shares",
+            threshold, shares
+        )));
+    }
+    if secret.is_empty() {
+        return Err(KeyError::Configuration("Secret must not be empty".to_string()));
+    }
+
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|x| Share {
+            x,
+            y: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    let mut coefficients = vec![0u8; threshold as usize];
+    for &byte in secret {
+        coefficients[0] = byte;
+        rand::thread_rng().fill_bytes(&mut coefficients[1..]);
+        for share in &mut result {
+            share.y.push(evaluate(&coefficients, share.x));
+        }
+    }
+    coefficients.iter_mut().for_each(|c| *c = 0);
+
+    Ok(result)
+}
+
+/// Recover the secret from at least `threshold` distinct shares
+///
+/// Combining fewer shares than the threshold yields an unrelated value, so
+/// callers must verify the result (the key store checks it against a
+/// verification value written at seal time).
+pub fn combine_shares(shares: &[Share]) -> KeyResult<Vec<u8>> {
+    let first = shares
+        .first()
+        .ok_or_else(|| KeyError::InvalidFormat("No shares provided".to_string()))?;
+    let len = first.y.len();
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || share.y.len() != len {
+            return Err(KeyError::InvalidFormat("Inconsistent shares".to_string()));
+        }
+        if shares[..i].iter().any(|other| other.x == share.x) {
+            return Err(KeyError::InvalidFormat(format!("Duplicate share {}", share.x)));
+        }
+    }
+
+    let mut secret = vec![0u8; len];
+    for (i, share) in shares.iter().enumerate() {
+        // Lagrange basis polynomial for share i evaluated at x = 0
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_div(other.x, other.x ^ share.x));
+            }
+        }
+        for (out, &y) in secret.iter_mut().zip(&share.y) {
+            *out ^= gf_mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+/// Evaluate a polynomial (lowest coefficient first) at `x` using Horner's rule
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0u8, |acc, &c| gf_mul(acc, x) ^ c)
+}
+
+/// Multiplication in GF(2^8) with the AES polynomial x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Division in GF(2^8); `b` must be non-zero
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 == b^-1 in GF(2^8)
+    let mut inverse = 1u8;
+    let mut base = b;
+    let mut exp = 254u8;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            inverse = gf_mul(inverse, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    gf_mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gf_div_inverts_mul() {
+        for a in 1..=255u8 {
+            for b in [1u8, 2, 3, 0x53, 0xca, 0xff] {
+                assert_eq!(gf_div(gf_mul(a, b), b), a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_recovers_secret() {
+        let secret = crate::crypto::random_bytes(32);
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1], [1, 2, 3]] {
+            let picked: Vec<Share> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine_shares(&picked).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_below_threshold_does_not_recover_secret() {
+        let secret = crate::crypto::random_bytes(32);
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        assert_ne!(combine_shares(&shares[..2]).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_share_encoding_roundtrip() {
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        let encoded = shares[1].to_string();
+        assert!(encoded.starts_with("02"));
+        assert_eq!(encoded.parse::<Share>().unwrap(), shares[1]);
+        assert!("00ff".parse::<Share>().is_err());
+        assert!("zz".parse::<Share>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_parameters_and_duplicates() {
+        assert!(split_secret(b"secret", 4, 3).is_err());
+        assert!(split_secret(b"secret", 0, 3).is_err());
+
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        assert!(combine_shares(&[shares[0].clone(), shares[0].clone()]).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! HTTP endpoints for unsealing a running service's key store
+//!
+//! Services run [`serve_unseal`] on a listener of their own so operators can
+//! submit shares with `armoricore-keys unseal --url`. Shares are secrets: the
+//! listener binds to loopback by default, and binding it anywhere else
+//! requires an admin token that every request must present.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::crypto;
+use crate::error::{KeyError, KeyResult};
+use crate::key_store::KeyStore;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::info;
+
+/// Path of the seal status endpoint
+pub const SEAL_STATUS_PATH: &str = "/sys/seal-status";
+
+/// Path of the unseal endpoint
+pub const UNSEAL_PATH: &str = "/sys/unseal";
+
+/// Environment variable naming the unseal listener address
+pub const UNSEAL_ADDR_ENV: &str = "UNSEAL_LISTEN_ADDR";
+
+/// Environment variable holding the admin token for the unseal endpoints
+pub const UNSEAL_TOKEN_ENV: &str = "UNSEAL_ADMIN_TOKEN";
+
+/// Default unseal listener address
+pub const DEFAULT_UNSEAL_ADDR: &str = "127.0.0.1:8200";
+
+/// Unseal listener configuration
+#[derive(Clone)]
+pub struct UnsealServerConfig {
+    /// Address to listen on
+    pub addr: SocketAddr,
+    /// Bearer token required on every request, if any
+    pub admin_token: Option<String>,
+}
+
+impl UnsealServerConfig {
+    /// Create a configuration; addresses other than loopback require a token
+    pub fn new(addr: SocketAddr, admin_token: Option<String>) -> KeyResult<Self> {
+        let admin_token = admin_token.filter(|token| !token.is_empty());
+        if admin_token.is_none() && !addr.ip().is_loopback() {
+            return Err(KeyError::Configuration(format!(
+                "Unseal listener on {} requires {}",
+                addr, UNSEAL_TOKEN_ENV
+            )));
+        }
+        Ok(Self { addr, admin_token })
+    }
+
+    /// Load configuration from `UNSEAL_LISTEN_ADDR` and `UNSEAL_ADMIN_TOKEN`
+    pub fn from_env() -> KeyResult<Self> {
+        let addr = std::env::var(UNSEAL_ADDR_ENV)
+            .unwrap_or_else(|_| DEFAULT_UNSEAL_ADDR.to_string());
+        let addr = addr.parse().map_err(|e| {
+            KeyError::Configuration(format!("Invalid {} {}: {}", UNSEAL_ADDR_ENV, addr, e))
+        })?;
+        Self::new(addr, std::env::var(UNSEAL_TOKEN_ENV).ok())
+    }
+}
+
+/// Unseal request body
+#[derive(Debug, Deserialize)]
+pub struct UnsealRequest {
+    /// Hex-encoded Shamir share
+    pub share: String,
+}
+
+/// Build the seal status and unseal routes for `key_store`, requiring
+/// `admin_token` as a bearer token if set
+pub fn unseal_router(key_store: Arc<KeyStore>, admin_token: Option<String>) -> Router {
+    let router = Router::new()
+        .route(SEAL_STATUS_PATH, get(seal_status))
+        .route(UNSEAL_PATH, post(unseal))
+        .with_state(key_store);
+    match admin_token {
+        Some(token) => router.route_layer(middleware::from_fn_with_state(
+            Arc::new(token),
+            require_admin_token,
+        )),
+        None => router,
+    }
+}
+
+/// Serve the unseal routes for `key_store` until the task is cancelled
+pub async fn serve_unseal(key_store: Arc<KeyStore>, config: UnsealServerConfig) -> KeyResult<()> {
+    let listener = TcpListener::bind(config.addr).await?;
+    info!(
+        addr = %config.addr,
+        token_required = config.admin_token.is_some(),
+        "Unseal server started"
+    );
+    axum::serve(listener, unseal_router(key_store, config.admin_token)).await?;
+    Ok(())
+}
+
+async fn require_admin_token(
+    State(token): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if crypto::constant_time_eq(presented.as_bytes(), token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => error_response(StatusCode::UNAUTHORIZED, "Missing or invalid admin token")
+            .into_response(),
+    }
+}
+
+async fn seal_status(
+    State(key_store): State<Arc<KeyStore>>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match key_store.seal_status().await {
+        Ok(Some(status)) => Ok(Json(json!(status))),
+        Ok(None) => Err(error_response(
+            StatusCode::NOT_FOUND,
+            "Key store is not configured for sealed mode",
+        )),
+        Err(e @ KeyError::PermissionDenied(_)) => {
+            Err(error_response(StatusCode::FORBIDDEN, &e.to_string()))
+        }
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+async fn unseal(
+    State(key_store): State<Arc<KeyStore>>,
+    Json(request): Json<UnsealRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match key_store.unseal(&request.share).await {
+        Ok(status) => Ok(Json(json!(status))),
+        Err(e @ KeyError::InvalidFormat(_)) => {
+            Err(error_response(StatusCode::BAD_REQUEST, &e.to_string()))
+        }
+        Err(e @ KeyError::Decryption(_)) => {
+            Err(error_response(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()))
+        }
+        Err(e @ KeyError::Configuration(_)) => {
+            Err(error_response(StatusCode::NOT_FOUND, &e.to_string()))
+        }
+        Err(e @ KeyError::PermissionDenied(_)) => {
+            Err(error_response(StatusCode::FORBIDDEN, &e.to_string()))
+        }
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (status, Json(json!({ "error": message })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_store::LocalKeyStore;
+
+    #[test]
+    fn test_non_loopback_listener_requires_token() {
+        let public: SocketAddr = "0.0.0.0:8200".parse().unwrap();
+        assert!(UnsealServerConfig::new(public, None).is_err());
+        assert!(UnsealServerConfig::new(public, Some(String::new())).is_err());
+        assert!(UnsealServerConfig::new(public, Some("token".to_string())).is_ok());
+        assert!(UnsealServerConfig::new(DEFAULT_UNSEAL_ADDR.parse().unwrap(), None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unseal_routes_require_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        LocalKeyStore::init_seal(dir.path(), None, 2, 3).await.unwrap();
+        let backend = LocalKeyStore::new(dir.path(), None).await.unwrap();
+        let key_store = Arc::new(KeyStore::new(Arc::new(backend)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), SEAL_STATUS_PATH);
+        let app = unseal_router(key_store, Some("secret".to_string()));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        let response = client.get(&url).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        let response = client.get(&url).bearer_auth("secret").send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
@@ -30,6 +30,12 @@ pub enum EventType {
     MediaUploaded,
     #[serde(rename = "media.ready")]
     MediaReady,
+    #[serde(rename = "media.processing_progress")]
+    MediaProcessingProgress,
+    #[serde(rename = "media.cancel_requested")]
+    MediaCancelRequested,
+    #[serde(rename = "media.failed")]
+    MediaFailed,
     
     // Notification events
     #[serde(rename = "notification.requested")]
@@ -130,6 +136,15 @@ impl Event {
             EventType::MediaReady => {
                 let _: MediaReadyPayload = self.payload_as()?;
             }
+            EventType::MediaProcessingProgress => {
+                let _: MediaProcessingProgressPayload = self.payload_as()?;
+            }
+            EventType::MediaCancelRequested => {
+                let _: MediaCancelRequestedPayload = self.payload_as()?;
+            }
+            EventType::MediaFailed => {
+                let _: MediaFailedPayload = self.payload_as()?;
+            }
             EventType::NotificationRequested => {
                 let _: NotificationRequestedPayload = self.payload_as()?;
             }
@@ -41,6 +41,206 @@ pub struct MediaReadyPayload {
     pub thumbnail_urls: Vec<String>,
     pub duration: u64, // Duration in seconds
     pub resolutions: Vec<String>,
+    /// Content protection, present when the media was packaged with DRM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drm: Option<DrmInfo>,
+    /// Summary of the source file's streams, when it was probed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_info: Option<MediaInfoSummary>,
+    /// Sprite sheets and WebVTT track for scrubbing previews
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_track: Option<ThumbnailTrack>,
+    /// Scored poster candidates, best first; the rest are alternates
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub posters: Vec<PosterFrame>,
+    /// EBU R128 loudness, present when the audio was normalized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<LoudnessInfo>,
+    /// Waveform peak data of audio-only media
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub waveform: Option<WaveformInfo>,
+    /// Size variants and placeholder of images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageInfo>,
+}
+
+/// An image's displayed size, BlurHash placeholder and encoded variants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// Smallest first, each size in every encoded format
+    pub variants: Vec<ImageVariantInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageVariantInfo {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    /// Encoding ("webp", "avif" or "jpeg")
+    pub format: String,
+}
+
+/// audiowaveform-compatible peak data at several zoom levels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformInfo {
+    /// File format ("dat" or "json")
+    pub format: String,
+    pub sample_rate: u32,
+    /// Peak resolution (8 or 16 bits)
+    pub bits: u8,
+    /// Most detailed level first
+    pub levels: Vec<WaveformLevelInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformLevelInfo {
+    pub samples_per_pixel: u32,
+    pub url: String,
+}
+
+/// Source loudness measured before normalization
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessInfo {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub range: f64,
+    /// Integrated loudness the renditions were normalized to, in LUFS
+    pub target: f64,
+}
+
+/// A poster frame candidate with the measurements it was chosen by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PosterFrame {
+    pub url: String,
+    /// Presentation time in seconds
+    pub time: f64,
+    /// Poster quality (0-1)
+    pub score: f64,
+    /// Difference from the previous sampled frame (0-1)
+    pub scene_score: f64,
+    /// Mean luma (0-255)
+    pub brightness: f64,
+    /// Share of black pixels (0-1)
+    pub black_ratio: f64,
+    /// Higher is blurrier
+    pub blur: f64,
+}
+
+/// Thumbnail sprite sheets with the WebVTT track indexing them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailTrack {
+    /// WebVTT file whose cues point at sprite regions (`sprite_001.jpg#xywh=x,y,w,h`)
+    pub vtt_url: String,
+    pub sprite_urls: Vec<String>,
+    /// Seconds covered by each tile
+    pub interval: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+/// Source file properties published with `media.ready`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfoSummary {
+    /// Container format (e.g. "mov,mp4,m4a,3gp,3g2,mj2", "matroska,webm")
+    pub container: String,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Overall bitrate in bits per second
+    pub bit_rate: Option<u64>,
+    /// Primary video stream
+    pub video: Option<VideoSummary>,
+    pub audio_tracks: Vec<AudioTrackSummary>,
+    /// Languages of the subtitle streams ("und" when untagged)
+    pub subtitle_languages: Vec<String>,
+    pub chapters: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoSummary {
+    pub codec: String,
+    pub profile: Option<String>,
+    /// Displayed width and height (after rotation)
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: Option<f64>,
+    /// Clockwise rotation in degrees
+    pub rotation: u32,
+    /// HDR format ("hdr10", "hdr10_plus", "hlg", "dolby_vision"), if any
+    pub hdr: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioTrackSummary {
+    pub codec: String,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub language: Option<String>,
+}
+
+/// Common Encryption details of protected media
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrmInfo {
+    /// Protection scheme (`cenc` or `cbcs`)
+    pub scheme: String,
+    /// Key IDs written to the media (`default_KID`)
+    pub key_ids: Vec<Uuid>,
+    /// Key store ids of the content keys, in `key_ids` order
+    pub key_store_ids: Vec<String>,
+    /// DRM systems signalled in the manifests (e.g. "clearkey", "widevine")
+    pub systems: Vec<String>,
+    /// License acquisition URL
+    pub license_url: Option<String>,
+}
+
+/// Payload for `media.processing_progress` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaProcessingProgressPayload {
+    pub media_id: Uuid,
+    /// Rendition being transcoded (e.g. "1080p", "audio_aac")
+    pub rendition: String,
+    /// Percentage of the source transcoded (0-100)
+    pub percent: f64,
+    pub fps: Option<f64>,
+    pub eta_seconds: Option<u64>,
+    /// True on the rendition's final update
+    pub done: bool,
+}
+
+/// Payload for `media.cancel_requested` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaCancelRequestedPayload {
+    pub media_id: Uuid,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Payload for `media.failed` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaFailedPayload {
+    pub media_id: Uuid,
+    pub reason: MediaFailureReason,
+    pub error: String,
+    pub failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaFailureReason {
+    /// Stopped by a `media.cancel_requested` event
+    Cancelled,
+    /// Exceeded the job's wall-clock limit
+    Timeout,
+    /// An FFmpeg process ran into its CPU, memory or file size limit
+    ResourceLimit,
+    ProcessingError,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
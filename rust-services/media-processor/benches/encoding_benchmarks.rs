@@ -22,13 +22,13 @@
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
 use media_processor::processor::MediaProcessor;
-use std::path::PathBuf;
+use std::path::Path;
 use std::time::Instant;
 use tempfile::TempDir;
 
 /// Generate a test video file using FFmpeg
 fn generate_test_video(
-    output_path: &PathBuf,
+    output_path: &Path,
     width: u32,
     height: u32,
     duration_sec: u32,
@@ -36,7 +36,7 @@ fn generate_test_video(
     use std::process::Command;
     
     let status = Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-f", "lavfi",
             "-i", &format!("testsrc2=duration={}:size={}x{}:rate=30", duration_sec, width, height),
             "-c:v", "libx264",
@@ -200,7 +200,7 @@ fn benchmark_parallel_vs_sequential(c: &mut Criterion) {
             let handles: Vec<_> = resolutions.iter()
                 .map(|res| {
                     let proc = processor.clone();
-                    let r = res.clone();
+                    let r = *res;
                     thread::spawn(move || {
                         let _ = black_box(r);
                         let _ = black_box(proc);
@@ -19,20 +19,20 @@
 
 use criterion::{black_box, criterion_group, Criterion};
 use media_processor::processor::MediaProcessor;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 use std::time::Instant;
 use tempfile::TempDir;
 
 /// Generate a short test video using FFmpeg
 fn generate_test_video(
-    output_path: &PathBuf,
+    output_path: &Path,
     width: u32,
     height: u32,
     duration_sec: u32,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let status = Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-f", "lavfi",
             "-i", &format!("testsrc2=duration={}:size={}x{}:rate=30", duration_sec, width, height),
             "-c:v", "libx264",
@@ -104,7 +104,7 @@ fn test_videotoolbox_availability() {
     println!("\n🔍 Checking VideoToolbox availability...");
     
     let output = Command::new("ffmpeg")
-        .args(&["-hide_banner", "-encoders"])
+        .args(["-hide_banner", "-encoders"])
         .output();
     
     match output {
@@ -158,7 +158,7 @@ fn quick_system_test() {
     }
     
     // Test 2: MediaProcessor creation
-    let processor = MediaProcessor::new();
+    let _processor = MediaProcessor::new();
     println!("✅ MediaProcessor: Created successfully");
     
     // Test 3: VideoToolbox availability
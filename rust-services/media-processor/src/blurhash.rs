@@ -0,0 +1,142 @@
+//! BlurHash placeholders
+//!
+//! Encodes an sRGB image into a short string that clients decode into a
+//! blurred placeholder while the real image loads (see blurha.sh). The image
+//! is described by `x_components` x `y_components` cosine factors; 4x3 is
+//! enough for most pictures.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::f64::consts::PI;
+
+const BASE83: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// BlurHash of `width` x `height` packed RGB24 `pixels`
+///
+/// Components are clamped to the 1..=9 the format allows.
+pub fn encode(pixels: &[u8], width: u32, height: u32, x_components: u32, y_components: u32) -> anyhow::Result<String> {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 || pixels.len() != width * height * 3 {
+        return Err(anyhow::anyhow!(
+            "Expected {}x{} RGB24 pixels, got {} bytes",
+            width,
+            height,
+            pixels.len()
+        ));
+    }
+    let x_components = x_components.clamp(1, 9) as usize;
+    let y_components = y_components.clamp(1, 9) as usize;
+
+    let linear: Vec<f64> = pixels.iter().map(|&value| srgb_to_linear(value)).collect();
+    let mut factors = Vec::with_capacity(x_components * y_components);
+    for j in 0..y_components {
+        for i in 0..x_components {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f64 * y as f64 / height as f64).cos();
+                for x in 0..width {
+                    let basis = basis_y * (PI * i as f64 * x as f64 / width as f64).cos();
+                    let pixel = &linear[(y * width + x) * 3..][..3];
+                    for (sum, channel) in factor.iter_mut().zip(pixel) {
+                        *sum += basis * channel;
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f64;
+            factors.push(factor.map(|sum| sum * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    push_base83(&mut hash, ((x_components - 1) + (y_components - 1) * 9) as u32, 1);
+
+    let max_value = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual_max = ac.iter().flatten().fold(0.0f64, |max, value| max.max(value.abs()));
+        let quantised_max = ((actual_max * 166.0 - 0.5).floor() as i64).clamp(0, 82) as u32;
+        push_base83(&mut hash, quantised_max, 1);
+        (quantised_max + 1) as f64 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            let quantised = (sign_pow(value / max_value, 0.5) * 9.0 + 9.5).floor();
+            quantised.clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    Ok(hash)
+}
+
+fn push_base83(hash: &mut String, value: u32, length: u32) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        hash.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn sign_pow(value: f64, exponent: f64) -> f64 {
+    value.abs().powf(exponent).copysign(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solid_color() {
+        let white = vec![255u8; 8 * 6 * 3];
+        assert_eq!(encode(&white, 8, 6, 1, 1).unwrap(), "00TSUA");
+        let hash = encode(&white, 8, 6, 4, 3).unwrap();
+        assert_eq!(hash.len(), 4 + 2 * 12);
+        assert!(hash.starts_with('L'));
+        assert_eq!(&hash[2..6], "TSUA");
+    }
+
+    #[test]
+    fn test_gradient() {
+        // Black on the left, white on the right
+        let pixels: Vec<u8> = (0..4 * 4).flat_map(|i| [if i % 4 < 2 { 0 } else { 255 }; 3]).collect();
+        let hash = encode(&pixels, 4, 4, 4, 3).unwrap();
+        assert_eq!(hash.len(), 4 + 2 * 12);
+        assert!(hash.starts_with('L'));
+        assert_ne!(&hash[6..8], "fQ");
+        assert!(encode(&pixels, 4, 3, 4, 3).is_err());
+    }
+}
@@ -0,0 +1,971 @@
+//! Common Encryption (ISO/IEC 23001-7) for CMAF renditions
+//!
+//! Rewrites the fragmented MP4 files FFmpeg writes for HLS into protected
+//! CMAF usable by HLS `SAMPLE-AES` and DASH players alike:
+//! - the init segment's sample entry becomes `encv`/`enca` with a `sinf`
+//!   box (`frma`, `schm`, `schi`/`tenc`) carrying the KID, and `pssh` boxes
+//!   are added to the `moov`
+//! - `cbcs`: samples are encrypted with AES-128-CBC, restarting from the
+//!   constant IV for every subsample; video uses the 1:9 block pattern
+//! - `cenc`: samples are encrypted with AES-128-CTR under 8-byte per-sample
+//!   IVs carried in `senc`
+//! - video keeps NAL headers and slice headers clear and its fragments get
+//!   `senc`/`saiz`/`saio` describing the subsamples; audio samples are fully
+//!   encrypted
+//!
+//! Only NAL-structured video (H.264, HEVC) and AAC/AC-3/E-AC-3 audio can be
+//! sample encrypted; other codecs are rejected.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use anyhow::Result;
+
+/// Common Encryption protection scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// AES-CTR full sample encryption
+    Cenc,
+    /// AES-CBC pattern encryption with a constant IV (required by HLS)
+    Cbcs,
+}
+
+impl Scheme {
+    /// Parse a scheme name (`cenc` or `cbcs`)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "cenc" => Some(Self::Cenc),
+            "cbcs" => Some(Self::Cbcs),
+            _ => None,
+        }
+    }
+
+    /// Four-character code written to `schm`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cenc => "cenc",
+            Self::Cbcs => "cbcs",
+        }
+    }
+
+    fn fourcc(&self) -> [u8; 4] {
+        match self {
+            Self::Cenc => *b"cenc",
+            Self::Cbcs => *b"cbcs",
+        }
+    }
+}
+
+/// Bytes left clear at the start of every video NAL unit (after its length
+/// prefix), covering the NAL header and the slice header
+const VIDEO_CLEAR_LEAD: usize = 32;
+
+/// Encrypted:skipped 16-byte block pattern for video (`cbcs` recommends 1:9)
+const VIDEO_PATTERN: (u8, u8) = (1, 9);
+
+/// AES block size
+const BLOCK: usize = 16;
+
+/// Per-sample IV size used by the `cenc` scheme
+const SAMPLE_IV_SIZE: usize = 8;
+
+/// Sample auxiliary information written to `senc`
+struct SampleAux {
+    /// Per-sample IV (`cenc` only)
+    iv: Option<[u8; SAMPLE_IV_SIZE]>,
+    /// Subsample map (video only)
+    subsamples: Option<Vec<(u16, u32)>>,
+}
+
+impl SampleAux {
+    fn size(&self) -> usize {
+        self.iv.map_or(0, |iv| iv.len()) + self.subsamples.as_ref().map_or(0, |map| 2 + 6 * map.len())
+    }
+}
+
+/// Key material and identifiers for one protected track
+#[derive(Debug, Clone)]
+pub struct ProtectionParams {
+    pub scheme: Scheme,
+    /// Key ID written to `tenc`
+    pub kid: [u8; 16],
+    /// `cbcs`: constant IV written to `tenc` and used for every subsample.
+    /// `cenc`: its first 8 bytes seed the per-sample IVs, so it must be
+    /// unique per track sharing a key
+    pub iv: [u8; 16],
+}
+
+/// Sample layout of a protected track, read from its init segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    /// NAL-structured video with the given NAL length prefix size
+    Video { nal_length_size: usize, hevc: bool },
+    /// Audio, encrypted as whole samples
+    Audio,
+}
+
+/// Track description needed to encrypt media segments
+#[derive(Debug, Clone, Copy)]
+pub struct ProtectedTrack {
+    pub kind: TrackKind,
+    pub scheme: Scheme,
+    /// Default sample size from `trex`, used when `tfhd`/`trun` omit sizes
+    default_sample_size: u32,
+}
+
+/// Box with its children parsed for the container types we rewrite
+#[derive(Debug, Clone)]
+struct Mp4Box {
+    kind: [u8; 4],
+    /// Payload bytes before the first child (the whole payload for leaf boxes)
+    prefix: Vec<u8>,
+    children: Vec<Mp4Box>,
+}
+
+impl Mp4Box {
+    fn leaf(kind: &[u8; 4], payload: Vec<u8>) -> Self {
+        Self {
+            kind: *kind,
+            prefix: payload,
+            children: Vec::new(),
+        }
+    }
+
+    fn container(kind: &[u8; 4], children: Vec<Mp4Box>) -> Self {
+        Self {
+            kind: *kind,
+            prefix: Vec::new(),
+            children,
+        }
+    }
+
+    /// Full box payload: version, 24-bit flags, then `body`
+    fn full(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Self {
+        let mut payload = Vec::with_capacity(4 + body.len());
+        payload.push(version);
+        payload.extend_from_slice(&flags.to_be_bytes()[1..]);
+        payload.extend_from_slice(body);
+        Self::leaf(kind, payload)
+    }
+
+    fn size(&self) -> usize {
+        8 + self.prefix.len() + self.children.iter().map(Mp4Box::size).sum::<usize>()
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.size() as u32).to_be_bytes());
+        out.extend_from_slice(&self.kind);
+        out.extend_from_slice(&self.prefix);
+        for child in &self.children {
+            child.write(out);
+        }
+    }
+
+    fn child(&self, kind: &[u8; 4]) -> Option<&Mp4Box> {
+        self.children.iter().find(|b| &b.kind == kind)
+    }
+
+    fn child_mut(&mut self, kind: &[u8; 4]) -> Result<&mut Mp4Box> {
+        self.children
+            .iter_mut()
+            .find(|b| &b.kind == kind)
+            .ok_or_else(|| anyhow::anyhow!("Missing {} box", fourcc(kind)))
+    }
+}
+
+fn fourcc(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).into_owned()
+}
+
+/// Number of payload bytes before the children of `kind`, or `None` for leaves
+fn children_offset(kind: &[u8; 4], payload: &[u8]) -> Option<usize> {
+    match kind {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"mvex" | b"moof" | b"traf"
+        | b"sinf" | b"schi" => Some(0),
+        b"stsd" => Some(8),
+        _ if is_video_entry(kind) => Some(78),
+        _ if is_audio_entry(kind) => {
+            // QuickTime sound sample entry versions carry extra fields
+            let version = payload.get(8..10).map(|v| u16::from_be_bytes([v[0], v[1]]));
+            Some(match version {
+                Some(1) => 44,
+                Some(2) => 64,
+                _ => 28,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn is_video_entry(kind: &[u8; 4]) -> bool {
+    matches!(kind, b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"encv")
+}
+
+fn is_audio_entry(kind: &[u8; 4]) -> bool {
+    matches!(kind, b"mp4a" | b"ac-3" | b"ec-3" | b"enca")
+}
+
+fn parse_boxes(mut data: &[u8]) -> Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(anyhow::anyhow!("Truncated box header"));
+        }
+        let mut size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64;
+        let kind = [data[4], data[5], data[6], data[7]];
+        let mut header = 8;
+        if size == 1 {
+            let large = data
+                .get(8..16)
+                .ok_or_else(|| anyhow::anyhow!("Truncated {} box header", fourcc(&kind)))?;
+            size = u64::from_be_bytes(large.try_into()?);
+            header = 16;
+        } else if size == 0 {
+            size = data.len() as u64;
+        }
+        let size = usize::try_from(size)?;
+        if size < header || size > data.len() {
+            return Err(anyhow::anyhow!("Invalid size {} for {} box", size, fourcc(&kind)));
+        }
+
+        let payload = &data[header..size];
+        let mp4_box = match children_offset(&kind, payload) {
+            Some(offset) if offset <= payload.len() => Mp4Box {
+                kind,
+                prefix: payload[..offset].to_vec(),
+                children: parse_boxes(&payload[offset..])?,
+            },
+            _ => Mp4Box::leaf(&kind, payload.to_vec()),
+        };
+        boxes.push(mp4_box);
+        data = &data[size..];
+    }
+    Ok(boxes)
+}
+
+fn write_boxes(boxes: &[Mp4Box]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for b in boxes {
+        b.write(&mut out);
+    }
+    out
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow::anyhow!("Truncated box at offset {}", offset))
+}
+
+/// Protection system specific header box
+///
+/// A version 1 box listing `kids` is written when any are given.
+pub fn pssh(system_id: &[u8; 16], kids: &[[u8; 16]], data: &[u8]) -> Vec<u8> {
+    let mut body = system_id.to_vec();
+    if !kids.is_empty() {
+        body.extend_from_slice(&(kids.len() as u32).to_be_bytes());
+        for kid in kids {
+            body.extend_from_slice(kid);
+        }
+    }
+    body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    body.extend_from_slice(data);
+    let version = u8::from(!kids.is_empty());
+    write_boxes(&[Mp4Box::full(b"pssh", version, 0, &body)])
+}
+
+/// Protect the single track of a CMAF init segment and add `pssh` boxes
+pub fn protect_init_segment(
+    init: &[u8],
+    params: &ProtectionParams,
+    pssh_boxes: &[Vec<u8>],
+) -> Result<(Vec<u8>, ProtectedTrack)> {
+    let mut boxes = parse_boxes(init)?;
+    let moov = boxes
+        .iter_mut()
+        .find(|b| &b.kind == b"moov")
+        .ok_or_else(|| anyhow::anyhow!("Init segment has no moov box"))?;
+
+    let default_sample_size = moov
+        .child(b"mvex")
+        .and_then(|mvex| mvex.child(b"trex"))
+        .map(|trex| read_u32(&trex.prefix, 16))
+        .transpose()?
+        .unwrap_or(0);
+
+    let stsd = moov
+        .child_mut(b"trak")?
+        .child_mut(b"mdia")?
+        .child_mut(b"minf")?
+        .child_mut(b"stbl")?
+        .child_mut(b"stsd")?;
+    let entry = stsd
+        .children
+        .first_mut()
+        .ok_or_else(|| anyhow::anyhow!("stsd has no sample entry"))?;
+
+    let original = entry.kind;
+    let pattern_for = |pattern| if params.scheme == Scheme::Cbcs { pattern } else { (0, 0) };
+    let (kind, protected_kind, pattern) = if is_video_entry(&original) && &original != b"encv" {
+        let hevc = matches!(&original, b"hvc1" | b"hev1");
+        let config = entry
+            .child(if hevc { b"hvcC" } else { b"avcC" })
+            .ok_or_else(|| anyhow::anyhow!("{} sample entry has no decoder configuration", fourcc(&original)))?;
+        let length_byte = if hevc { 21 } else { 4 };
+        let nal_length_size = config
+            .prefix
+            .get(length_byte)
+            .map(|b| (b & 0x03) as usize + 1)
+            .ok_or_else(|| anyhow::anyhow!("Truncated decoder configuration"))?;
+        (TrackKind::Video { nal_length_size, hevc }, b"encv", pattern_for(VIDEO_PATTERN))
+    } else if is_audio_entry(&original) && &original != b"enca" {
+        (TrackKind::Audio, b"enca", (0, 0))
+    } else {
+        return Err(anyhow::anyhow!(
+            "Sample encryption is not supported for {} tracks",
+            fourcc(&original)
+        ));
+    };
+
+    entry.kind = *protected_kind;
+    entry.children.push(Mp4Box::container(
+        b"sinf",
+        vec![
+            Mp4Box::leaf(b"frma", original.to_vec()),
+            Mp4Box::full(b"schm", 0, 0, &[&params.scheme.fourcc()[..], &0x0001_0000u32.to_be_bytes()].concat()),
+            Mp4Box::container(b"schi", vec![tenc(params, pattern)]),
+        ],
+    ));
+    for pssh in pssh_boxes {
+        moov.children.extend(parse_boxes(pssh)?);
+    }
+
+    Ok((
+        write_boxes(&boxes),
+        ProtectedTrack {
+            kind,
+            scheme: params.scheme,
+            default_sample_size,
+        },
+    ))
+}
+
+/// Track encryption box: a constant IV for `cbcs`, 8-byte per-sample IVs for `cenc`
+fn tenc(params: &ProtectionParams, (crypt, skip): (u8, u8)) -> Mp4Box {
+    let per_sample_iv_size = match params.scheme {
+        Scheme::Cenc => SAMPLE_IV_SIZE as u8,
+        Scheme::Cbcs => 0,
+    };
+    let mut body = vec![0, (crypt << 4) | skip, 1, per_sample_iv_size];
+    body.extend_from_slice(&params.kid);
+    if params.scheme == Scheme::Cbcs {
+        body.push(params.iv.len() as u8);
+        body.extend_from_slice(&params.iv);
+    }
+    // Version 1 is needed to carry a block pattern
+    let version = u8::from(crypt != 0 || skip != 0);
+    Mp4Box::full(b"tenc", version, 0, &body)
+}
+
+/// Encrypt the samples of a media segment belonging to `track`
+///
+/// The segment must be CMAF: one track per fragment, each `moof` followed by
+/// its `mdat`. `sidx` boxes are dropped since the sizes they index change.
+/// `sequence` is the segment's position in the track and keeps `cenc`
+/// per-sample IVs unique across segments.
+pub fn protect_media_segment(
+    segment: &[u8],
+    track: &ProtectedTrack,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    sequence: u64,
+) -> Result<Vec<u8>> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let boxes = parse_boxes(segment)?;
+    let mut out = Vec::with_capacity(segment.len() + 1024);
+
+    let mut next_sample = sequence << 24;
+    let mut iter = boxes.into_iter();
+    while let Some(mp4_box) = iter.next() {
+        match &mp4_box.kind {
+            b"sidx" => continue,
+            b"moof" => {
+                let mut mdat = match iter.next() {
+                    Some(b) if &b.kind == b"mdat" => b,
+                    _ => return Err(anyhow::anyhow!("moof is not followed by mdat")),
+                };
+                let moof = protect_fragment(mp4_box, &mut mdat.prefix, track, &cipher, iv, &mut next_sample)?;
+                moof.write(&mut out);
+                mdat.write(&mut out);
+            }
+            _ => mp4_box.write(&mut out),
+        }
+    }
+    Ok(out)
+}
+
+/// Parsed `tfhd` fields needed to locate samples
+struct Tfhd {
+    track_id: u32,
+    sample_description_index: Option<u32>,
+    default_sample_duration: Option<u32>,
+    default_sample_size: Option<u32>,
+    default_sample_flags: Option<u32>,
+    duration_is_empty: bool,
+}
+
+impl Tfhd {
+    fn parse(payload: &[u8]) -> Result<Self> {
+        let flags = read_u32(payload, 0)? & 0x00ff_ffff;
+        let track_id = read_u32(payload, 4)?;
+        let mut offset = 8;
+        if flags & 0x01 != 0 {
+            offset += 8; // base_data_offset, replaced by default-base-is-moof
+        }
+        let mut optional = |bit: u32| -> Result<Option<u32>> {
+            if flags & bit == 0 {
+                return Ok(None);
+            }
+            let value = read_u32(payload, offset)?;
+            offset += 4;
+            Ok(Some(value))
+        };
+        Ok(Self {
+            track_id,
+            sample_description_index: optional(0x02)?,
+            default_sample_duration: optional(0x08)?,
+            default_sample_size: optional(0x10)?,
+            default_sample_flags: optional(0x20)?,
+            duration_is_empty: flags & 0x1_0000 != 0,
+        })
+    }
+
+    /// Serialize with default-base-is-moof, as CMAF requires
+    fn to_box(&self) -> Mp4Box {
+        let mut flags = 0x2_0000;
+        let mut body = self.track_id.to_be_bytes().to_vec();
+        for (bit, value) in [
+            (0x02, self.sample_description_index),
+            (0x08, self.default_sample_duration),
+            (0x10, self.default_sample_size),
+            (0x20, self.default_sample_flags),
+        ] {
+            if let Some(value) = value {
+                flags |= bit;
+                body.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        if self.duration_is_empty {
+            flags |= 0x1_0000;
+        }
+        Mp4Box::full(b"tfhd", 0, flags, &body)
+    }
+}
+
+/// Parsed `trun` with the offset of its data-offset field
+struct Trun {
+    payload: Vec<u8>,
+    data_offset_pos: usize,
+    sample_sizes: Vec<u32>,
+}
+
+impl Trun {
+    fn parse(payload: &[u8], default_size: u32) -> Result<Self> {
+        let mut payload = payload.to_vec();
+        let mut flags = read_u32(&payload, 0)? & 0x00ff_ffff;
+        let sample_count = read_u32(&payload, 4)? as usize;
+        if flags & 0x01 == 0 {
+            // Add a data offset so it can point past the rewritten moof
+            flags |= 0x01;
+            payload[1..4].copy_from_slice(&flags.to_be_bytes()[1..]);
+            payload.splice(8..8, [0u8; 4]);
+        }
+        let data_offset_pos = 8;
+        let mut offset = 12;
+        if flags & 0x04 != 0 {
+            offset += 4; // first_sample_flags
+        }
+
+        let mut sample_sizes = Vec::with_capacity(sample_count);
+        for _ in 0..sample_count {
+            if flags & 0x100 != 0 {
+                offset += 4;
+            }
+            if flags & 0x200 != 0 {
+                sample_sizes.push(read_u32(&payload, offset)?);
+                offset += 4;
+            } else {
+                sample_sizes.push(default_size);
+            }
+            if flags & 0x400 != 0 {
+                offset += 4;
+            }
+            if flags & 0x800 != 0 {
+                offset += 4;
+            }
+        }
+        if offset > payload.len() {
+            return Err(anyhow::anyhow!("Truncated trun box"));
+        }
+        Ok(Self {
+            payload,
+            data_offset_pos,
+            sample_sizes,
+        })
+    }
+
+    fn set_data_offset(&mut self, data_offset: u32) {
+        self.payload[self.data_offset_pos..self.data_offset_pos + 4]
+            .copy_from_slice(&data_offset.to_be_bytes());
+    }
+}
+
+/// Encrypt the samples of one fragment in place and return its new `moof`
+fn protect_fragment(
+    mut moof: Mp4Box,
+    mdat: &mut [u8],
+    track: &ProtectedTrack,
+    cipher: &Aes128,
+    iv: &[u8; 16],
+    next_sample: &mut u64,
+) -> Result<Mp4Box> {
+    if moof.children.iter().filter(|b| &b.kind == b"traf").count() != 1 {
+        return Err(anyhow::anyhow!("CMAF fragments must contain exactly one traf"));
+    }
+    let traf = moof.child_mut(b"traf")?;
+    let tfhd = Tfhd::parse(&traf.child_mut(b"tfhd")?.prefix)?;
+    let default_size = tfhd.default_sample_size.unwrap_or(track.default_sample_size);
+
+    let mut truns = Vec::new();
+    for b in traf.children.iter().filter(|b| &b.kind == b"trun") {
+        truns.push(Trun::parse(&b.prefix, default_size)?);
+    }
+    let total: u64 = truns.iter().flat_map(|t| &t.sample_sizes).map(|s| *s as u64).sum();
+    if total != mdat.len() as u64 {
+        return Err(anyhow::anyhow!(
+            "Sample sizes ({} bytes) do not match mdat ({} bytes)",
+            total,
+            mdat.len()
+        ));
+    }
+
+    // Encrypt samples; samples are laid out contiguously in trun order
+    let iv_seed = u64::from_be_bytes(iv[..SAMPLE_IV_SIZE].try_into()?);
+    let mut aux = Vec::new();
+    let mut position = 0;
+    for size in truns.iter().flat_map(|t| &t.sample_sizes) {
+        let sample = &mut mdat[position..position + *size as usize];
+        position += *size as usize;
+        let map = match track.kind {
+            TrackKind::Video { nal_length_size, hevc } => video_subsamples(sample, nal_length_size, hevc)?,
+            TrackKind::Audio => vec![(0, sample.len() as u32)],
+        };
+        let sample_iv = match track.scheme {
+            Scheme::Cbcs => {
+                let pattern = match track.kind {
+                    TrackKind::Video { .. } => VIDEO_PATTERN,
+                    TrackKind::Audio => (0, 0),
+                };
+                encrypt_subsamples(sample, &map, |data| encrypt_pattern(data, cipher, iv, pattern));
+                None
+            }
+            Scheme::Cenc => {
+                let sample_iv = iv_seed.wrapping_add(*next_sample).to_be_bytes();
+                let mut ctr = Ctr::new(cipher, sample_iv);
+                encrypt_subsamples(sample, &map, |data| ctr.apply(data));
+                Some(sample_iv)
+            }
+        };
+        *next_sample += 1;
+        let subsamples = matches!(track.kind, TrackKind::Video { .. }).then_some(map);
+        aux.push(SampleAux { iv: sample_iv, subsamples });
+    }
+    // cbcs audio has nothing per sample: constant IV, whole-sample encryption
+    aux.retain(|a| a.size() > 0);
+
+    // Rebuild the traf: normalized tfhd, truns, then sample auxiliary info
+    let mut children: Vec<Mp4Box> = traf
+        .children
+        .iter()
+        .filter(|b| !matches!(&b.kind, b"trun" | b"senc" | b"saiz" | b"saio"))
+        .map(|b| if &b.kind == b"tfhd" { tfhd.to_box() } else { b.clone() })
+        .collect();
+    let trun_index = children.len();
+    children.extend(truns.iter().map(|t| Mp4Box::leaf(b"trun", t.payload.clone())));
+    if !aux.is_empty() {
+        children.push(senc(&aux));
+        children.push(saiz(&aux)?);
+        children.push(Mp4Box::full(b"saio", 0, 0, &[1u32.to_be_bytes(), [0; 4]].concat()));
+    }
+    traf.children = children;
+
+    // Point saio at the first senc sample entry, relative to the moof
+    let traf_index = moof.children.iter().position(|b| &b.kind == b"traf").unwrap_or(0);
+    let traf_offset = 8 + moof.children[..traf_index].iter().map(Mp4Box::size).sum::<usize>();
+    let traf = &mut moof.children[traf_index];
+    if let Some(senc_index) = traf.children.iter().position(|b| &b.kind == b"senc") {
+        let senc_offset = traf_offset
+            + 8
+            + traf.children[..senc_index].iter().map(Mp4Box::size).sum::<usize>();
+        let saio = traf.child_mut(b"saio")?;
+        saio.prefix[8..12].copy_from_slice(&((senc_offset + 16) as u32).to_be_bytes());
+    }
+
+    // Data offsets are relative to the moof and skip the 8-byte mdat header
+    let mut data_offset = moof.size() + 8;
+    let traf = &mut moof.children[traf_index];
+    for (trun, trun_box) in truns.iter_mut().zip(&mut traf.children[trun_index..]) {
+        trun.set_data_offset(data_offset as u32);
+        trun_box.prefix = trun.payload.clone();
+        data_offset += trun.sample_sizes.iter().map(|s| *s as usize).sum::<usize>();
+    }
+
+    Ok(moof)
+}
+
+/// Subsample map (clear bytes, protected bytes) of a length-prefixed video sample
+fn video_subsamples(sample: &[u8], nal_length_size: usize, hevc: bool) -> Result<Vec<(u16, u32)>> {
+    let mut map = Vec::new();
+    let mut clear = 0usize;
+    let mut position = 0;
+    while position < sample.len() {
+        let length_bytes = sample
+            .get(position..position + nal_length_size)
+            .ok_or_else(|| anyhow::anyhow!("Truncated NAL length"))?;
+        let nal_length = length_bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        let nal = sample
+            .get(position + nal_length_size..position + nal_length_size + nal_length)
+            .ok_or_else(|| anyhow::anyhow!("NAL unit exceeds sample"))?;
+        position += nal_length_size + nal_length;
+
+        let is_vcl = match nal.first() {
+            Some(header) if hevc => (header >> 1) & 0x3f < 32,
+            Some(header) => matches!(header & 0x1f, 1..=5),
+            None => false,
+        };
+        // Protected ranges are whole blocks; a trailing partial block stays clear
+        let protected = if is_vcl {
+            nal.len().saturating_sub(VIDEO_CLEAR_LEAD) / BLOCK * BLOCK
+        } else {
+            0
+        };
+        if protected == 0 {
+            clear += nal_length_size + nal.len();
+            continue;
+        }
+        push_subsample(&mut map, clear + nal_length_size + VIDEO_CLEAR_LEAD, protected as u32);
+        clear = nal.len() - VIDEO_CLEAR_LEAD - protected;
+    }
+    if clear > 0 || map.is_empty() {
+        push_subsample(&mut map, clear, 0);
+    }
+    Ok(map)
+}
+
+/// Append a subsample, splitting clear runs that overflow 16 bits
+fn push_subsample(map: &mut Vec<(u16, u32)>, mut clear: usize, protected: u32) {
+    while clear > u16::MAX as usize {
+        map.push((u16::MAX, 0));
+        clear -= u16::MAX as usize;
+    }
+    map.push((clear as u16, protected));
+}
+
+/// Apply `encrypt` to each protected range of `sample`
+fn encrypt_subsamples(sample: &mut [u8], map: &[(u16, u32)], mut encrypt: impl FnMut(&mut [u8])) {
+    let mut position = 0;
+    for (clear, protected) in map {
+        position += *clear as usize;
+        let end = position + *protected as usize;
+        encrypt(&mut sample[position..end]);
+        position = end;
+    }
+}
+
+/// AES-CTR keystream continuing across the protected ranges of one sample
+struct Ctr<'a> {
+    cipher: &'a Aes128,
+    iv: [u8; SAMPLE_IV_SIZE],
+    counter: u64,
+    keystream: [u8; BLOCK],
+    used: usize,
+}
+
+impl<'a> Ctr<'a> {
+    fn new(cipher: &'a Aes128, iv: [u8; SAMPLE_IV_SIZE]) -> Self {
+        Self {
+            cipher,
+            iv,
+            counter: 0,
+            keystream: [0; BLOCK],
+            used: BLOCK,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == BLOCK {
+                self.keystream[..SAMPLE_IV_SIZE].copy_from_slice(&self.iv);
+                self.keystream[SAMPLE_IV_SIZE..].copy_from_slice(&self.counter.to_be_bytes());
+                self.cipher.encrypt_block(GenericArray::from_mut_slice(&mut self.keystream));
+                self.counter = self.counter.wrapping_add(1);
+                self.used = 0;
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+/// AES-CBC over the encrypted blocks of a `(crypt, skip)` pattern, starting
+/// from `iv`. A `(0, 0)` pattern encrypts every full block.
+fn encrypt_pattern(data: &mut [u8], cipher: &Aes128, iv: &[u8; 16], (crypt, skip): (u8, u8)) {
+    let (crypt, skip) = if crypt == 0 { (1, 0) } else { (crypt as usize, skip as usize) };
+    let mut chain = *iv;
+    let mut position = 0;
+    while position + BLOCK <= data.len() {
+        for _ in 0..crypt {
+            if position + BLOCK > data.len() {
+                return;
+            }
+            let block = &mut data[position..position + BLOCK];
+            for (b, c) in block.iter_mut().zip(chain) {
+                *b ^= c;
+            }
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            chain.copy_from_slice(block);
+            position += BLOCK;
+        }
+        position += skip * BLOCK;
+    }
+}
+
+/// Sample encryption box with per-sample IVs and/or subsample maps
+fn senc(aux: &[SampleAux]) -> Mp4Box {
+    let uses_subsamples = aux.iter().any(|a| a.subsamples.is_some());
+    let mut body = (aux.len() as u32).to_be_bytes().to_vec();
+    for sample in aux {
+        if let Some(iv) = sample.iv {
+            body.extend_from_slice(&iv);
+        }
+        if let Some(map) = &sample.subsamples {
+            body.extend_from_slice(&(map.len() as u16).to_be_bytes());
+            for (clear, protected) in map {
+                body.extend_from_slice(&clear.to_be_bytes());
+                body.extend_from_slice(&protected.to_be_bytes());
+            }
+        }
+    }
+    Mp4Box::full(b"senc", 0, if uses_subsamples { 0x02 } else { 0 }, &body)
+}
+
+/// Sample auxiliary information sizes matching `senc`
+fn saiz(aux: &[SampleAux]) -> Result<Mp4Box> {
+    let sizes = aux
+        .iter()
+        .map(|a| {
+            u8::try_from(a.size())
+                .map_err(|_| anyhow::anyhow!("Sample auxiliary information too large: {} bytes", a.size()))
+        })
+        .collect::<Result<Vec<u8>>>()?;
+    let uniform = sizes.windows(2).all(|w| w[0] == w[1]);
+
+    let mut body = vec![if uniform { sizes.first().copied().unwrap_or(0) } else { 0 }];
+    body.extend_from_slice(&(sizes.len() as u32).to_be_bytes());
+    if !uniform {
+        body.extend_from_slice(&sizes);
+    }
+    Ok(Mp4Box::full(b"saiz", 0, 0, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockDecrypt;
+
+    const KEY: [u8; 16] = [7; 16];
+    const PARAMS: ProtectionParams = ProtectionParams {
+        scheme: Scheme::Cbcs,
+        kid: [1; 16],
+        iv: [2; 16],
+    };
+
+    fn avc_init() -> Vec<u8> {
+        let mut avcc = vec![1, 0x64, 0, 0x1f, 0xff];
+        avcc.extend_from_slice(&[0xe0, 0]);
+        let avc1 = Mp4Box {
+            kind: *b"avc1",
+            prefix: vec![0; 78],
+            children: vec![Mp4Box::leaf(b"avcC", avcc)],
+        };
+        let stsd = Mp4Box {
+            kind: *b"stsd",
+            prefix: [0u32.to_be_bytes(), 1u32.to_be_bytes()].concat(),
+            children: vec![avc1],
+        };
+        let stbl = Mp4Box::container(b"stbl", vec![stsd]);
+        let trak = Mp4Box::container(
+            b"trak",
+            vec![Mp4Box::container(
+                b"mdia",
+                vec![Mp4Box::container(b"minf", vec![stbl])],
+            )],
+        );
+        let trex = Mp4Box::full(b"trex", 0, 0, &[1u32, 1, 0, 0, 0].map(u32::to_be_bytes).concat());
+        let moov = Mp4Box::container(b"moov", vec![trak, Mp4Box::container(b"mvex", vec![trex])]);
+        write_boxes(&[Mp4Box::leaf(b"ftyp", b"iso6".to_vec()), moov])
+    }
+
+    fn avc_segment(sample: &[u8]) -> Vec<u8> {
+        // tfhd with a base data offset, as FFmpeg may write it
+        let tfhd = Mp4Box::full(b"tfhd", 0, 0x01, &[1u32.to_be_bytes().as_slice(), &[0; 8]].concat());
+        let trun = Mp4Box::full(
+            b"trun",
+            0,
+            0x201,
+            &[1u32.to_be_bytes(), 0u32.to_be_bytes(), (sample.len() as u32).to_be_bytes()].concat(),
+        );
+        let moof = Mp4Box::container(
+            b"moof",
+            vec![
+                Mp4Box::full(b"mfhd", 0, 0, &1u32.to_be_bytes()),
+                Mp4Box::container(b"traf", vec![tfhd, trun]),
+            ],
+        );
+        write_boxes(&[moof, Mp4Box::leaf(b"mdat", sample.to_vec())])
+    }
+
+    #[test]
+    fn test_protect_init_segment_adds_sinf() {
+        let (init, track) = protect_init_segment(&avc_init(), &PARAMS, &[]).unwrap();
+        assert_eq!(track.kind, TrackKind::Video { nal_length_size: 4, hevc: false });
+
+        let boxes = parse_boxes(&init).unwrap();
+        let entry = &boxes[1].children[0].children[0].children[0].children[0].children[0].children[0];
+        assert_eq!(&entry.kind, b"encv");
+        let sinf = entry.child(b"sinf").unwrap();
+        assert_eq!(sinf.child(b"frma").unwrap().prefix, b"avc1");
+        assert_eq!(&sinf.child(b"schm").unwrap().prefix[4..8], b"cbcs");
+        let tenc = &sinf.child(b"schi").unwrap().child(b"tenc").unwrap().prefix;
+        assert_eq!(tenc[0], 1);
+        assert_eq!(tenc[5], 0x19);
+        assert_eq!(&tenc[8..24], &PARAMS.kid);
+        assert_eq!(&tenc[25..41], &PARAMS.iv);
+    }
+
+    #[test]
+    fn test_protect_init_segment_rejects_unsupported_codec() {
+        let mut init = avc_init();
+        let entry = init.windows(4).position(|w| w == b"avc1").unwrap();
+        init[entry..entry + 4].copy_from_slice(b"vp09");
+        assert!(protect_init_segment(&init, &PARAMS, &[]).is_err());
+    }
+
+    #[test]
+    fn test_protect_media_segment_encrypts_vcl_pattern() {
+        // SPS (clear) followed by an IDR slice of 32 + 200 bytes
+        let mut sample = vec![0, 0, 0, 4, 0x67, 1, 2, 3];
+        let slice: Vec<u8> = (0..232u32).map(|i| i as u8).collect();
+        sample.extend_from_slice(&(slice.len() as u32).to_be_bytes());
+        sample.push(0x65);
+        sample.extend_from_slice(&slice[1..]);
+
+        let (_, track) = protect_init_segment(&avc_init(), &PARAMS, &[]).unwrap();
+        let protected = protect_media_segment(&avc_segment(&sample), &track, &KEY, &PARAMS.iv, 0).unwrap();
+        let boxes = parse_boxes(&protected).unwrap();
+        let (moof, mdat) = (&boxes[0], &boxes[1]);
+        let traf = moof.child(b"traf").unwrap();
+
+        // tfhd is normalized to default-base-is-moof and trun points into mdat
+        assert_eq!(traf.child(b"tfhd").unwrap().prefix[..4], [0, 2, 0, 0]);
+        let data_offset = read_u32(&traf.child(b"trun").unwrap().prefix, 8).unwrap();
+        assert_eq!(data_offset as usize, moof.size() + 8);
+
+        // SPS + length + 32 lead bytes clear, 192 protected, then 8 trailing clear bytes
+        let senc = &traf.child(b"senc").unwrap().prefix;
+        assert_eq!(&senc[8..10], &2u16.to_be_bytes());
+        assert_eq!(&senc[10..12], &(8 + 4 + 32u16).to_be_bytes());
+        assert_eq!(&senc[12..16], &192u32.to_be_bytes());
+        assert_eq!(&senc[16..22], &[0, 8, 0, 0, 0, 0]);
+        let saio = &traf.child(b"saio").unwrap().prefix;
+        let senc_offset = read_u32(saio, 8).unwrap() as usize;
+        assert_eq!(&write_boxes(std::slice::from_ref(moof))[senc_offset..senc_offset + 2], &2u16.to_be_bytes());
+
+        // Only the first block of each 10-block run is encrypted
+        let out = &mdat.prefix;
+        assert_eq!(&out[..44], &sample[..44]);
+        assert_ne!(&out[44..60], &sample[44..60]);
+        assert_eq!(&out[60..204], &sample[60..204]);
+        assert_ne!(&out[204..220], &sample[204..220]);
+        assert_eq!(&out[220..], &sample[220..]);
+
+        // Each encrypted block decrypts back in a chain starting at the constant IV
+        let cipher = Aes128::new(GenericArray::from_slice(&KEY));
+        let mut block = GenericArray::clone_from_slice(&out[44..60]);
+        cipher.decrypt_block(&mut block);
+        let plain: Vec<u8> = block.iter().zip(PARAMS.iv).map(|(b, iv)| b ^ iv).collect();
+        assert_eq!(plain, &sample[44..60]);
+        let mut block = GenericArray::clone_from_slice(&out[204..220]);
+        cipher.decrypt_block(&mut block);
+        let plain: Vec<u8> = block.iter().zip(&out[44..60]).map(|(b, c)| b ^ c).collect();
+        assert_eq!(plain, &sample[204..220]);
+    }
+
+    #[test]
+    fn test_cenc_scheme_uses_ctr_with_per_sample_ivs() {
+        let params = ProtectionParams {
+            scheme: Scheme::Cenc,
+            ..PARAMS
+        };
+        let pssh_box = pssh(&[9; 16], &[params.kid], b"data");
+        let (init, track) = protect_init_segment(&avc_init(), &params, std::slice::from_ref(&pssh_box)).unwrap();
+        let boxes = parse_boxes(&init).unwrap();
+        let moov = &boxes[1];
+        assert_eq!(write_boxes(std::slice::from_ref(moov.child(b"pssh").unwrap())), pssh_box);
+        let entry = &moov.children[0].children[0].children[0].children[0].children[0].children[0];
+        let sinf = entry.child(b"sinf").unwrap();
+        assert_eq!(&sinf.child(b"schm").unwrap().prefix[4..8], b"cenc");
+        let tenc = &sinf.child(b"schi").unwrap().child(b"tenc").unwrap().prefix;
+        assert_eq!(tenc[0], 0);
+        assert_eq!(tenc[7], 8);
+        assert_eq!(tenc.len(), 24);
+
+        let mut sample = vec![0, 0, 0, 100, 0x65];
+        sample.extend((0..99u32).map(|i| i as u8));
+        let protected = protect_media_segment(&avc_segment(&sample), &track, &KEY, &params.iv, 3).unwrap();
+        let boxes = parse_boxes(&protected).unwrap();
+        let traf = boxes[0].child(b"traf").unwrap();
+
+        // The per-sample IV continues from the rendition IV, offset by the segment sequence
+        let senc = &traf.child(b"senc").unwrap().prefix;
+        let expected_iv = u64::from_be_bytes([2; 8]).wrapping_add(3 << 24).to_be_bytes();
+        assert_eq!(&senc[8..16], &expected_iv);
+        assert_eq!(&senc[16..18], &2u16.to_be_bytes());
+        assert_eq!(&senc[18..20], &36u16.to_be_bytes());
+        assert_eq!(&senc[20..24], &64u32.to_be_bytes());
+        assert_eq!(&senc[24..30], &[0, 4, 0, 0, 0, 0]);
+
+        // Protected bytes are XORed with the AES-CTR keystream
+        let cipher = Aes128::new(GenericArray::from_slice(&KEY));
+        let mut counter = GenericArray::clone_from_slice(&[expected_iv, [0; 8]].concat());
+        cipher.encrypt_block(&mut counter);
+        let out = &boxes[1].prefix;
+        assert_eq!(&out[..36], &sample[..36]);
+        let plain: Vec<u8> = out[36..52].iter().zip(counter).map(|(b, k)| b ^ k).collect();
+        assert_eq!(plain, &sample[36..52]);
+        assert_eq!(&out[100..], &sample[100..]);
+    }
+}
@@ -0,0 +1,654 @@
+//! MPEG-DASH manifest generation for CMAF renditions
+//!
+//! Every rendition is packaged once as CMAF (fragmented MP4): its directory
+//! holds an `init.mp4` initialization segment, numbered `.m4s` media segments
+//! and the HLS media playlist. The MPD written here references the same
+//! segments through `SegmentTemplate`s, with exact segment durations taken
+//! from the HLS playlists, so HLS and DASH share a single set of files.
+//! Protected renditions are signalled with `ContentProtection` elements and
+//! HDR renditions with their CICP color properties.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use uuid::Uuid;
+
+/// File name of the DASH manifest in the output directory
+pub const MPD_FILE_NAME: &str = "manifest.mpd";
+/// MIME type of DASH manifests
+pub const MPD_CONTENT_TYPE: &str = "application/dash+xml";
+/// CMAF initialization segment name inside each rendition directory
+pub const INIT_SEGMENT_NAME: &str = "init.mp4";
+/// FFmpeg file pattern for CMAF media segments
+pub const SEGMENT_FILE_PATTERN: &str = "segment_%03d.m4s";
+/// Target segment duration in seconds (keyframes are forced on this grid)
+pub const SEGMENT_DURATION_SECS: u32 = 10;
+
+/// DASH equivalent of `SEGMENT_FILE_PATTERN`
+const SEGMENT_TEMPLATE: &str = "segment_$Number%03d$.m4s";
+/// Timescale of segment timelines (milliseconds)
+const TIMESCALE: u64 = 1000;
+
+/// Dynamic range of a video rendition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoRange {
+    #[default]
+    Sdr,
+    /// SMPTE ST 2084 transfer (HDR10, HDR10+, Dolby Vision base layer)
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma transfer
+    Hlg,
+}
+
+impl VideoRange {
+    /// `VIDEO-RANGE` attribute of HLS variant streams
+    pub fn hls_value(&self) -> &'static str {
+        match self {
+            VideoRange::Sdr => "SDR",
+            VideoRange::Pq => "PQ",
+            VideoRange::Hlg => "HLG",
+        }
+    }
+
+    /// ISO/IEC 23091-2 (CICP) transfer characteristics code point
+    pub fn transfer_characteristics(&self) -> u8 {
+        match self {
+            VideoRange::Sdr => 1,
+            VideoRange::Pq => 16,
+            VideoRange::Hlg => 18,
+        }
+    }
+}
+
+/// Track type of a rendition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenditionKind {
+    Video {
+        width: u32,
+        height: u32,
+        /// Ids of the audio renditions this video rendition is paired with
+        audio: Vec<String>,
+        #[serde(default)]
+        range: VideoRange,
+    },
+    Audio {
+        /// Sample rate in Hz, if known
+        sample_rate: Option<u32>,
+        /// HLS `GROUP-ID`, when the rendition is one of several source
+        /// tracks; otherwise the rendition is a group of its own
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        track: Option<TrackInfo>,
+    },
+    /// Segmented WebVTT
+    Subtitles {
+        track: TrackInfo,
+    },
+}
+
+/// Name and language of an alternative audio or subtitle track
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub name: String,
+    /// Language tag, if known
+    pub language: Option<String>,
+    /// Selected when the user has not chosen a track
+    pub default: bool,
+    #[serde(default)]
+    pub forced: bool,
+}
+
+impl TrackInfo {
+    /// `name` for an HLS quoted-string attribute
+    ///
+    /// Titles come from source tags and subtitle requests; quoted-strings
+    /// have no escapes, so double quotes become single quotes and line
+    /// breaks become spaces.
+    pub fn quoted_name(&self) -> String {
+        self.name
+            .chars()
+            .map(|c| match c {
+                '"' => '\'',
+                '\r' | '\n' => ' ',
+                c => c,
+            })
+            .collect()
+    }
+
+    /// `language` if it only uses BCP-47 characters (ASCII letters, digits
+    /// and `-`)
+    pub fn language_tag(&self) -> Option<&str> {
+        self.language.as_deref().filter(|language| {
+            !language.is_empty()
+                && language.len() <= 35
+                && language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    }
+}
+
+/// A single CMAF rendition (one track)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rendition {
+    /// Rendition id, also its directory name relative to the output directory
+    pub id: String,
+    pub kind: RenditionKind,
+    /// RFC 6381 codec string (e.g. "avc1.640028", "mp4a.40.2")
+    pub codecs: String,
+    /// Peak bandwidth in bits per second
+    pub bandwidth: u64,
+    /// HLS media playlist of this rendition
+    pub playlist: PathBuf,
+}
+
+impl Rendition {
+    pub fn is_video(&self) -> bool {
+        matches!(self.kind, RenditionKind::Video { .. })
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self.kind, RenditionKind::Audio { .. })
+    }
+
+    pub fn is_subtitles(&self) -> bool {
+        matches!(self.kind, RenditionKind::Subtitles { .. })
+    }
+
+    /// Dynamic range of a video rendition; other tracks are `Sdr`
+    pub fn range(&self) -> VideoRange {
+        match self.kind {
+            RenditionKind::Video { range, .. } => range,
+            _ => VideoRange::Sdr,
+        }
+    }
+
+    /// HLS `GROUP-ID` of an audio rendition
+    pub fn audio_group(&self) -> Option<&str> {
+        match &self.kind {
+            RenditionKind::Audio { group, .. } => Some(group.as_deref().unwrap_or(&self.id)),
+            _ => None,
+        }
+    }
+
+    /// Name and language of an alternative audio or subtitle track
+    pub fn track(&self) -> Option<&TrackInfo> {
+        match &self.kind {
+            RenditionKind::Audio { track, .. } => track.as_ref(),
+            RenditionKind::Subtitles { track } => Some(track),
+            RenditionKind::Video { .. } => None,
+        }
+    }
+
+    /// Codec family used to group renditions into adaptation sets
+    /// ("avc1.640028" and "avc1.64001f" are both "avc1")
+    fn codec_family(&self) -> &str {
+        self.codecs.split('.').next().unwrap_or(&self.codecs)
+    }
+}
+
+/// Common Encryption signalling added to every adaptation set
+#[derive(Debug, Clone)]
+pub struct ContentProtection {
+    /// Protection scheme (`cenc` or `cbcs`)
+    pub scheme: String,
+    pub default_kid: Uuid,
+    pub systems: Vec<DrmSystem>,
+}
+
+/// A DRM system able to play the protected renditions
+#[derive(Debug, Clone)]
+pub struct DrmSystem {
+    pub system_id: Uuid,
+    /// `value` attribute of the `ContentProtection` element (e.g. "ClearKey1.0")
+    pub name: String,
+    /// Base64 `pssh` box
+    pub pssh: Option<String>,
+    /// License acquisition URL (`dashif:Laurl`)
+    pub license_url: Option<String>,
+}
+
+/// Write a static MPD for `renditions` to `output_dir/manifest.mpd`
+///
+/// Video renditions are grouped into one adaptation set per codec family and
+/// dynamic range, and audio renditions into one per codec family and source
+/// track, so dual-track outputs (e.g. Opus + FLAC) and each language get
+/// one audio adaptation set; the first audio set is marked `main`. Subtitle
+/// renditions are segmented WebVTT for HLS and are not listed.
+pub fn write_mpd(
+    output_dir: &Path,
+    renditions: &[Rendition],
+    protection: Option<&ContentProtection>,
+) -> anyhow::Result<PathBuf> {
+    let renditions: Vec<Rendition> = renditions.iter().filter(|r| !r.is_subtitles()).cloned().collect();
+    let renditions = renditions.as_slice();
+    let mut timelines = Vec::with_capacity(renditions.len());
+    for rendition in renditions {
+        let durations = segment_durations_from_playlist(&rendition.playlist)?;
+        if durations.is_empty() {
+            return Err(anyhow::anyhow!(
+                "HLS playlist has no segments: {}",
+                rendition.playlist.display()
+            ));
+        }
+        timelines.push(durations);
+    }
+
+    let mpd = render_mpd(renditions, &timelines, protection);
+    let mpd_path = output_dir.join(MPD_FILE_NAME);
+    std::fs::write(&mpd_path, mpd)?;
+
+    info!(
+        manifest = %mpd_path.display(),
+        renditions = renditions.len(),
+        "Created DASH manifest"
+    );
+
+    Ok(mpd_path)
+}
+
+/// Segment durations in milliseconds from the `#EXTINF` tags of an HLS media playlist
+pub fn segment_durations_from_playlist(playlist: &Path) -> anyhow::Result<Vec<u64>> {
+    let contents = std::fs::read_to_string(playlist).map_err(|e| {
+        anyhow::anyhow!("Failed to read HLS playlist {}: {}", playlist.display(), e)
+    })?;
+    parse_segment_durations(&contents)
+}
+
+fn parse_segment_durations(playlist: &str) -> anyhow::Result<Vec<u64>> {
+    playlist
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#EXTINF:"))
+        .map(|value| {
+            let seconds = value.split(',').next().unwrap_or_default().trim();
+            seconds
+                .parse::<f64>()
+                .map(|s| (s * TIMESCALE as f64).round() as u64)
+                .map_err(|e| anyhow::anyhow!("Invalid EXTINF duration {:?}: {}", seconds, e))
+        })
+        .collect()
+}
+
+/// Render the MPD document; `timelines[i]` holds the segment durations of `renditions[i]`
+fn render_mpd(
+    renditions: &[Rendition],
+    timelines: &[Vec<u64>],
+    protection: Option<&ContentProtection>,
+) -> String {
+    let total_ms = timelines
+        .iter()
+        .map(|durations| durations.iter().sum::<u64>())
+        .max()
+        .unwrap_or(0);
+
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:cenc="urn:mpeg:cenc:2013" xmlns:dashif="https://dashif.org/CPS" profiles="urn:mpeg:dash:profile:isoff-live:2011,urn:mpeg:dash:profile:cmaf:2019" type="static" mediaPresentationDuration="{}" minBufferTime="PT{}S">"#,
+        iso_duration(total_ms),
+        SEGMENT_DURATION_SECS
+    );
+    let _ = writeln!(xml, r#"  <Period id="0" start="PT0S">"#);
+
+    let mut set_id = 0;
+    for (is_video, content_type) in [(true, "video"), (false, "audio")] {
+        // One adaptation set per codec family, range and source track, in
+        // order of first appearance
+        let mut families: Vec<(&str, VideoRange, Option<&TrackInfo>)> = Vec::new();
+        for rendition in renditions.iter().filter(|r| r.is_video() == is_video) {
+            let family = (rendition.codec_family(), rendition.range(), rendition.track());
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+
+        for (family_index, &(family, range, track)) in families.iter().enumerate() {
+            let lang = match track.and_then(TrackInfo::language_tag) {
+                Some(language) => format!(r#" lang="{}""#, escape_xml(language)),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                xml,
+                r#"    <AdaptationSet id="{}" contentType="{}" mimeType="{}/mp4"{} segmentAlignment="true" startWithSAP="1">"#,
+                set_id, content_type, content_type, lang
+            );
+            if let Some(protection) = protection {
+                render_content_protection(&mut xml, protection);
+            }
+            if range != VideoRange::Sdr {
+                render_color_properties(&mut xml, range);
+            }
+            if !is_video {
+                let role = if family_index == 0 { "main" } else { "alternate" };
+                let _ = writeln!(
+                    xml,
+                    r#"      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="{}"/>"#,
+                    role
+                );
+            }
+            if let Some(track) = track {
+                let _ = writeln!(xml, "      <Label>{}</Label>", escape_xml(&track.name));
+            }
+
+            for (rendition, durations) in renditions
+                .iter()
+                .zip(timelines)
+                .filter(|(r, _)| {
+                    r.is_video() == is_video && r.codec_family() == family && r.range() == range && r.track() == track
+                })
+            {
+                render_representation(&mut xml, rendition, durations);
+            }
+
+            let _ = writeln!(xml, "    </AdaptationSet>");
+            set_id += 1;
+        }
+    }
+
+    let _ = writeln!(xml, "  </Period>");
+    let _ = writeln!(xml, "</MPD>");
+    xml
+}
+
+fn render_representation(xml: &mut String, rendition: &Rendition, durations: &[u64]) {
+    let id = escape_xml(&rendition.id);
+    let _ = write!(
+        xml,
+        r#"      <Representation id="{}" bandwidth="{}" codecs="{}""#,
+        id,
+        rendition.bandwidth,
+        escape_xml(&rendition.codecs)
+    );
+    match &rendition.kind {
+        RenditionKind::Video { width, height, .. } => {
+            let _ = write!(xml, r#" width="{}" height="{}""#, width, height);
+        }
+        RenditionKind::Audio { sample_rate: Some(rate), .. } => {
+            let _ = write!(xml, r#" audioSamplingRate="{}""#, rate);
+        }
+        RenditionKind::Audio { sample_rate: None, .. } | RenditionKind::Subtitles { .. } => {}
+    }
+    let _ = writeln!(xml, ">");
+
+    let _ = writeln!(
+        xml,
+        r#"        <SegmentTemplate timescale="{}" initialization="{}/{}" media="{}/{}" startNumber="0">"#,
+        TIMESCALE, id, INIT_SEGMENT_NAME, id, SEGMENT_TEMPLATE
+    );
+    let _ = writeln!(xml, "          <SegmentTimeline>");
+    render_timeline(xml, durations);
+    let _ = writeln!(xml, "          </SegmentTimeline>");
+    let _ = writeln!(xml, "        </SegmentTemplate>");
+    let _ = writeln!(xml, "      </Representation>");
+}
+
+fn render_content_protection(xml: &mut String, protection: &ContentProtection) {
+    let _ = writeln!(
+        xml,
+        r#"      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="{}" cenc:default_KID="{}"/>"#,
+        escape_xml(&protection.scheme),
+        protection.default_kid
+    );
+    for system in &protection.systems {
+        let _ = writeln!(
+            xml,
+            r#"      <ContentProtection schemeIdUri="urn:uuid:{}" value="{}">"#,
+            system.system_id,
+            escape_xml(&system.name)
+        );
+        if let Some(ref license_url) = system.license_url {
+            let _ = writeln!(xml, "        <dashif:Laurl>{}</dashif:Laurl>", escape_xml(license_url));
+        }
+        if let Some(ref pssh) = system.pssh {
+            let _ = writeln!(xml, "        <cenc:pssh>{}</cenc:pssh>", pssh);
+        }
+        let _ = writeln!(xml, "      </ContentProtection>");
+    }
+}
+
+/// BT.2020 color description of an HDR adaptation set
+///
+/// PQ can't be shown by SDR-only clients, so its transfer is an
+/// `EssentialProperty`; HLG degrades gracefully and is only supplemental.
+fn render_color_properties(xml: &mut String, range: VideoRange) {
+    let transfer_element = if range == VideoRange::Pq {
+        "EssentialProperty"
+    } else {
+        "SupplementalProperty"
+    };
+    let _ = writeln!(
+        xml,
+        r#"      <SupplementalProperty schemeIdUri="urn:mpeg:mpegB:cicp:ColourPrimaries" value="9"/>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"      <{} schemeIdUri="urn:mpeg:mpegB:cicp:TransferCharacteristics" value="{}"/>"#,
+        transfer_element,
+        range.transfer_characteristics()
+    );
+    let _ = writeln!(
+        xml,
+        r#"      <SupplementalProperty schemeIdUri="urn:mpeg:mpegB:cicp:MatrixCoefficients" value="9"/>"#
+    );
+}
+
+/// Run-length encode segment durations as `<S>` elements
+fn render_timeline(xml: &mut String, durations: &[u64]) {
+    let mut first = true;
+    let mut index = 0;
+    while index < durations.len() {
+        let duration = durations[index];
+        let repeats = durations[index + 1..]
+            .iter()
+            .take_while(|&&d| d == duration)
+            .count();
+
+        let _ = write!(xml, "            <S");
+        if first {
+            let _ = write!(xml, r#" t="0""#);
+            first = false;
+        }
+        let _ = write!(xml, r#" d="{}""#, duration);
+        if repeats > 0 {
+            let _ = write!(xml, r#" r="{}""#, repeats);
+        }
+        let _ = writeln!(xml, "/>");
+
+        index += repeats + 1;
+    }
+}
+
+/// ISO 8601 duration (e.g. "PT95.500S") from milliseconds
+fn iso_duration(ms: u64) -> String {
+    format!("PT{}.{:03}S", ms / 1000, ms % 1000)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str, codecs: &str) -> Rendition {
+        Rendition {
+            id: id.to_string(),
+            kind: RenditionKind::Video {
+                width: 1920,
+                height: 1080,
+                audio: vec![],
+                range: VideoRange::Sdr,
+            },
+            codecs: codecs.to_string(),
+            bandwidth: 5_000_000,
+            playlist: PathBuf::from(format!("{}/playlist.m3u8", id)),
+        }
+    }
+
+    fn audio(id: &str, codecs: &str) -> Rendition {
+        Rendition {
+            id: id.to_string(),
+            kind: RenditionKind::Audio {
+                sample_rate: Some(48000),
+                group: None,
+                track: None,
+            },
+            codecs: codecs.to_string(),
+            bandwidth: 192_000,
+            playlist: PathBuf::from(format!("{}/playlist.m3u8", id)),
+        }
+    }
+
+    #[test]
+    fn test_parse_segment_durations() {
+        let playlist = "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:10\n\
+                        #EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:10.000000,\nsegment_000.m4s\n\
+                        #EXTINF:10.010000,\nsegment_001.m4s\n#EXTINF:3.2,\nsegment_002.m4s\n\
+                        #EXT-X-ENDLIST\n";
+        assert_eq!(parse_segment_durations(playlist).unwrap(), vec![10000, 10010, 3200]);
+        assert!(parse_segment_durations("#EXTINF:abc,\n").is_err());
+    }
+
+    #[test]
+    fn test_timeline_run_length_encoding() {
+        let mut xml = String::new();
+        render_timeline(&mut xml, &[10000, 10000, 10000, 4000, 10000]);
+        let lines: Vec<&str> = xml.lines().map(str::trim).collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"<S t="0" d="10000" r="2"/>"#,
+                r#"<S d="4000"/>"#,
+                r#"<S d="10000"/>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adaptation_sets_per_codec() {
+        let renditions = vec![
+            video("4K", "vvc1.1.L83.CQA"),
+            video("1080p", "avc1.640028"),
+            video("720p", "avc1.64001f"),
+            audio("audio_opus", "Opus"),
+            audio("audio_flac", "fLaC"),
+        ];
+        let timelines = vec![vec![10000, 5000]; renditions.len()];
+        let mpd = render_mpd(&renditions, &timelines, None);
+
+        assert!(mpd.contains(r#"mediaPresentationDuration="PT15.000S""#));
+        assert_eq!(mpd.matches("<AdaptationSet").count(), 4);
+        assert_eq!(mpd.matches(r#"contentType="video""#).count(), 2);
+        assert_eq!(mpd.matches(r#"contentType="audio""#).count(), 2);
+        assert!(mpd.contains(r#"<Role schemeIdUri="urn:mpeg:dash:role:2011" value="main"/>"#));
+        assert!(mpd.contains(r#"<Role schemeIdUri="urn:mpeg:dash:role:2011" value="alternate"/>"#));
+        assert!(mpd.contains(r#"initialization="720p/init.mp4" media="720p/segment_$Number%03d$.m4s""#));
+        assert!(mpd.contains(r#"audioSamplingRate="48000""#));
+
+        // Both H.264 renditions share one adaptation set
+        let avc_set = mpd
+            .split("<AdaptationSet")
+            .find(|set| set.contains("avc1.640028"))
+            .unwrap();
+        assert!(avc_set.contains("avc1.64001f"));
+        assert!(!mpd.contains("<ContentProtection"));
+        assert!(!mpd.contains("cicp"));
+    }
+
+    #[test]
+    fn test_hdr_adaptation_set_signals_color() {
+        let mut hdr = video("1080p_hdr", "hvc1.2.4.L120.B0");
+        if let RenditionKind::Video { range, .. } = &mut hdr.kind {
+            *range = VideoRange::Pq;
+        }
+        let renditions = vec![video("1080p", "avc1.640028"), hdr];
+        let timelines = vec![vec![10000]; renditions.len()];
+        let mpd = render_mpd(&renditions, &timelines, None);
+
+        let hdr_set = mpd
+            .split("<AdaptationSet")
+            .find(|set| set.contains("hvc1.2.4.L120.B0"))
+            .unwrap();
+        assert!(hdr_set.contains(
+            r#"<EssentialProperty schemeIdUri="urn:mpeg:mpegB:cicp:TransferCharacteristics" value="16"/>"#
+        ));
+        assert_eq!(mpd.matches("cicp:ColourPrimaries").count(), 1);
+    }
+
+    #[test]
+    fn test_audio_tracks_get_language_sets() {
+        let track = |name: &str, language: &str, default: bool| TrackInfo {
+            name: name.to_string(),
+            language: Some(language.to_string()),
+            default,
+            forced: false,
+        };
+        let mut english = audio("audio_aac", "mp4a.40.2");
+        let mut spanish = audio("audio_aac_2", "mp4a.40.2");
+        for (rendition, track) in [(&mut english, track("English", "en", true)), (&mut spanish, track("Español", "es", false))] {
+            rendition.kind = RenditionKind::Audio {
+                sample_rate: Some(48000),
+                group: Some("audio_aac".to_string()),
+                track: Some(track),
+            };
+        }
+
+        let renditions = vec![video("720p", "avc1.64001f"), english, spanish];
+        assert_eq!(renditions[2].audio_group(), Some("audio_aac"));
+        let timelines = vec![vec![10000]; renditions.len()];
+        let mpd = render_mpd(&renditions, &timelines, None);
+
+        assert_eq!(mpd.matches(r#"contentType="audio""#).count(), 2);
+        assert!(mpd.contains(r#"contentType="audio" mimeType="audio/mp4" lang="en""#));
+        assert!(mpd.contains(r#"lang="es""#));
+        assert!(mpd.contains("<Label>Español</Label>"));
+    }
+
+    #[test]
+    fn test_content_protection_in_every_adaptation_set() {
+        let renditions = vec![video("720p", "avc1.64001f"), audio("audio_aac", "mp4a.40.2")];
+        let timelines = vec![vec![10000]; renditions.len()];
+        let kid = Uuid::new_v4();
+        let protection = ContentProtection {
+            scheme: "cbcs".to_string(),
+            default_kid: kid,
+            systems: vec![DrmSystem {
+                system_id: Uuid::from_u128(0xe2719d58_a985_b3c9_781a_b030af78d30e),
+                name: "ClearKey1.0".to_string(),
+                pssh: Some("AAAA".to_string()),
+                license_url: Some("/drm/clearkey/1/license?a=1&b=2".to_string()),
+            }],
+        };
+        let mpd = render_mpd(&renditions, &timelines, Some(&protection));
+
+        let default_kid = format!(
+            r#"<ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cbcs" cenc:default_KID="{}"/>"#,
+            kid
+        );
+        assert_eq!(mpd.matches(&default_kid).count(), 2);
+        assert!(mpd.contains(r#"schemeIdUri="urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e" value="ClearKey1.0""#));
+        assert!(mpd.contains("<dashif:Laurl>/drm/clearkey/1/license?a=1&amp;b=2</dashif:Laurl>"));
+        assert!(mpd.contains("<cenc:pssh>AAAA</cenc:pssh>"));
+    }
+}
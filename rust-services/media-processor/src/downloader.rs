@@ -15,39 +15,75 @@
 
 
 use armoricore_config::ObjectStorageConfig;
-use rusoto_core::{credential::StaticProvider, request::HttpClient, Region};
-use rusoto_s3::{GetObjectRequest, S3Client, S3};
-use std::io::Write;
+use crate::ingest::IngestPolicy;
+use crate::object_store::ObjectStore;
+use crate::s3_store::S3Store;
+use reqwest::Url;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 use uuid::Uuid;
 
 /// File downloader for remote sources
 pub struct FileDownloader {
-    s3_client: Option<S3Client>,
-    #[allow(dead_code)] // s3_config is stored for potential future use
-    s3_config: Option<ObjectStorageConfig>,
+    s3_store: Option<S3Store>,
+    policy: IngestPolicy,
 }
 
 impl FileDownloader {
     /// Create a new file downloader
     pub fn new(s3_config: Option<ObjectStorageConfig>) -> Self {
-        let s3_client = s3_config.as_ref().and_then(|config| {
-            Self::create_s3_client(config)
+        let s3_store = s3_config.as_ref().and_then(|config| match S3Store::new(config) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!(error = %e, "Failed to create S3 client for downloads");
+                None
+            }
         });
 
         Self {
-            s3_client,
-            s3_config,
+            s3_store,
+            policy: IngestPolicy::from_env(),
         }
     }
 
+    /// Check sources against `policy` instead of the one from the environment
+    pub fn with_policy(mut self, policy: IngestPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     /// Download a file from remote source (S3, HTTP, HTTPS)
     pub async fn download_file(
         &self,
         source_url: &str,
         destination: &Path,
         media_id: &Uuid,
+    ) -> anyhow::Result<PathBuf> {
+        self.download(source_url, destination, media_id, &self.policy).await
+    }
+
+    /// Download a file of at most `max_size` bytes (or the policy's limit,
+    /// if lower), enforced while the body is transferred
+    pub async fn download_file_limited(
+        &self,
+        source_url: &str,
+        destination: &Path,
+        media_id: &Uuid,
+        max_size: u64,
+    ) -> anyhow::Result<PathBuf> {
+        let policy = IngestPolicy {
+            max_size: self.policy.max_size.min(max_size),
+            ..self.policy.clone()
+        };
+        self.download(source_url, destination, media_id, &policy).await
+    }
+
+    async fn download(
+        &self,
+        source_url: &str,
+        destination: &Path,
+        media_id: &Uuid,
+        policy: &IngestPolicy,
     ) -> anyhow::Result<PathBuf> {
         info!(
             media_id = %media_id,
@@ -57,9 +93,9 @@ impl FileDownloader {
         );
 
         if source_url.starts_with("s3://") {
-            self.download_from_s3(source_url, destination, media_id).await
+            self.download_from_s3(source_url, destination, media_id, policy).await
         } else if source_url.starts_with("http://") || source_url.starts_with("https://") {
-            self.download_from_http(source_url, destination, media_id).await
+            self.download_from_http(source_url, destination, media_id, policy).await
         } else {
             Err(anyhow::anyhow!("Unsupported URL scheme: {}", source_url))
         }
@@ -71,9 +107,10 @@ impl FileDownloader {
         s3_url: &str,
         destination: &Path,
         media_id: &Uuid,
+        policy: &IngestPolicy,
     ) -> anyhow::Result<PathBuf> {
-        let client = match &self.s3_client {
-            Some(c) => c,
+        let store = match &self.s3_store {
+            Some(store) => store,
             None => {
                 return Err(anyhow::anyhow!(
                     "S3 client not configured. Cannot download from S3."
@@ -91,55 +128,16 @@ impl FileDownloader {
             "Downloading from S3"
         );
 
-        // Create get object request
-        let get_request = GetObjectRequest {
-            bucket: bucket.to_string(),
-            key: key.to_string(),
-            ..Default::default()
-        };
+        policy.check_bucket(bucket, store.bucket())?;
+        let store = store.with_bucket(bucket);
+        policy.check_size(store.content_length(key).await?)?;
 
-        // Download object
-        let result = client
-            .get_object(get_request)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to download from S3: {}", e))?;
-
-        // Get the body stream
-        let body = result.body.ok_or_else(|| anyhow::anyhow!("S3 response has no body"))?;
-
-        // Read the stream into bytes (rusoto ByteStream is blocking)
-        // Use spawn_blocking to avoid blocking the async runtime
-        let destination_clone = destination.to_path_buf();
-        let total_bytes = tokio::task::spawn_blocking(move || {
-            use std::io::Read;
-            use std::fs::File;
-            
-            let mut file = File::create(&destination_clone)
-                .map_err(|e| anyhow::anyhow!("Failed to create destination file: {}", e))?;
-            
-            // ByteStream implements Read trait
-            let mut reader = body.into_blocking_read();
-            let mut buffer = [0u8; 8192]; // 8KB buffer
-            let mut total_bytes = 0u64;
-            
-            loop {
-                let bytes_read = reader.read(&mut buffer)
-                    .map_err(|e| anyhow::anyhow!("Failed to read S3 stream: {}", e))?;
-                
-                if bytes_read == 0 {
-                    break; // EOF
-                }
-                
-                file.write_all(&buffer[..bytes_read])
-                    .map_err(|e| anyhow::anyhow!("Failed to write chunk: {}", e))?;
-                
-                total_bytes += bytes_read as u64;
-            }
-            
-            Ok::<u64, anyhow::Error>(total_bytes)
-        })
-        .await
-        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??;
+        // The object may have been replaced since the HEAD request
+        let total_bytes = store.get_file(key, destination).await?;
+        if let Err(e) = policy.check_size(total_bytes) {
+            let _ = tokio::fs::remove_file(destination).await;
+            return Err(e);
+        }
 
         info!(
             media_id = %media_id,
@@ -152,11 +150,17 @@ impl FileDownloader {
     }
 
     /// Download file from HTTP/HTTPS
+    ///
+    /// Each hop is checked against the ingestion policy and connected to
+    /// at the addresses that were checked, so DNS cannot be rebound to an
+    /// internal address between the check and the request. Redirects are
+    /// followed by hand for the same reason.
     async fn download_from_http(
         &self,
         url: &str,
         destination: &Path,
         media_id: &Uuid,
+        policy: &IngestPolicy,
     ) -> anyhow::Result<PathBuf> {
         info!(
             media_id = %media_id,
@@ -164,18 +168,44 @@ impl FileDownloader {
             "Downloading from HTTP/HTTPS"
         );
 
-        // Create HTTP client
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 minute timeout
-            .build()
-            .map_err(|e| anyhow::anyhow!("Failed to create HTTP client: {}", e))?;
+        let mut url = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))?;
+        let mut redirects = 0;
+        let response = loop {
+            policy.check_url(&url)?;
+            let addrs = policy.resolve(&url).await?;
+            let host = url.host_str().unwrap_or_default().to_string();
+
+            let client = reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(300)) // 5 minute timeout
+                .redirect(reqwest::redirect::Policy::none())
+                .no_proxy()
+                .resolve_to_addrs(&host, &addrs)
+                .build()
+                .map_err(|e| anyhow::anyhow!("Failed to create HTTP client: {}", e))?;
+
+            let response = client
+                .get(url.clone())
+                .send()
+                .await
+                .map_err(|e| anyhow::anyhow!("HTTP request failed: {}", e))?;
 
-        // Download file
-        let response = client
-            .get(url)
-            .send()
-            .await
-            .map_err(|e| anyhow::anyhow!("HTTP request failed: {}", e))?;
+            if !response.status().is_redirection() {
+                break response;
+            }
+            redirects += 1;
+            if redirects > policy.max_redirects {
+                return Err(anyhow::anyhow!("Too many redirects fetching {}", url));
+            }
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| anyhow::anyhow!("Redirect without a Location from {}", url))?;
+            url = url
+                .join(location)
+                .map_err(|e| anyhow::anyhow!("Invalid redirect location {}: {}", location, e))?;
+            info!(media_id = %media_id, url = %url, "Following redirect");
+        };
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!(
@@ -183,6 +213,9 @@ impl FileDownloader {
                 response.status()
             ));
         }
+        if let Some(length) = response.content_length() {
+            policy.check_size(length)?;
+        }
 
         // Stream response to file using async file I/O
         use tokio::fs::File as TokioFile;
@@ -197,13 +230,19 @@ impl FileDownloader {
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk.map_err(|e| anyhow::anyhow!("Failed to read chunk: {}", e))?;
+            total_bytes += chunk.len() as u64;
+            // The length header may be missing or wrong
+            if let Err(e) = policy.check_size(total_bytes) {
+                drop(file);
+                let _ = tokio::fs::remove_file(destination).await;
+                return Err(e);
+            }
             file.write_all(&chunk)
                 .await
                 .map_err(|e| anyhow::anyhow!("Failed to write chunk: {}", e))?;
-            total_bytes += chunk.len() as u64;
 
             // Log progress every 10MB
-            if total_bytes % (10 * 1024 * 1024) == 0 {
+            if total_bytes.is_multiple_of(10 * 1024 * 1024) {
                 info!(
                     media_id = %media_id,
                     downloaded = total_bytes,
@@ -239,50 +278,37 @@ impl FileDownloader {
             Err(anyhow::anyhow!("S3 URL must include a key: {}", s3_url))
         }
     }
+}
 
-    /// Create S3 client for downloading
-    fn create_s3_client(config: &ObjectStorageConfig) -> Option<S3Client> {
-        // Create credentials provider
-        let credentials = StaticProvider::new_minimal(
-            config.access_key.clone(),
-            config.secret_key.clone(),
-        );
-
-        // Determine region
-        let region = if let Some(ref region_str) = config.region {
-            Region::Custom {
-                name: region_str.clone(),
-                endpoint: Self::extract_endpoint(&config.endpoint),
-            }
-        } else {
-            Region::Custom {
-                name: "akamai".to_string(),
-                endpoint: Self::extract_endpoint(&config.endpoint),
-            }
-        };
-
-        // Create HTTP client
-        let http_client = match HttpClient::new() {
-            Ok(client) => client,
-            Err(e) => {
-                warn!(error = %e, "Failed to create HTTP client for S3 download");
-                return None;
-            }
-        };
 
-        Some(S3Client::new_with(http_client, credentials, region))
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_download_limit_applies_while_streaming() {
+        // No Content-Length: only the streamed byte count can catch it
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/subtitles.vtt", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").await;
+            let _ = socket.write_all(&[b'x'; 4096]).await;
+        });
 
-    /// Extract endpoint URL from configuration
-    fn extract_endpoint(endpoint: &str) -> String {
-        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
-            endpoint.to_string()
-        } else if endpoint.starts_with("s3://") {
-            let bucket = endpoint.strip_prefix("s3://").unwrap_or(endpoint);
-            format!("https://{}.akamai.com", bucket)
-        } else {
-            format!("https://{}.akamai.com", endpoint)
-        }
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("subtitles.vtt");
+        let downloader = FileDownloader::new(None).with_policy(IngestPolicy {
+            allow_private_networks: true,
+            ..IngestPolicy::default()
+        });
+        let error = downloader
+            .download_file_limited(&url, &destination, &Uuid::new_v4(), 1024)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("over the 1024 byte limit"));
+        assert!(!destination.exists());
     }
 }
-
@@ -0,0 +1,269 @@
+//! Common Encryption (DRM) packaging
+//!
+//! Protects the CMAF renditions shared by HLS and DASH with the `cenc` or
+//! `cbcs` scheme (see [`crate::cenc`]) under a single per-media content key:
+//! - init segments get a `tenc` box with the key ID and a W3C Common `pssh`
+//! - HLS playlists get `EXT-X-KEY` tags (`SAMPLE-AES` for `cbcs`,
+//!   `SAMPLE-AES-CTR` for `cenc`) with `KEYFORMAT="org.w3.clearkey"`
+//! - the MPD gets `ContentProtection` elements (see [`crate::dash`])
+//!
+//! Content keys and key IDs live in the key store and licenses are served
+//! by the clear-key endpoint of [`crate::key_server`]. Commercial DRM
+//! systems are not signalled.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::cenc::{self, ProtectionParams, Scheme};
+use crate::dash::{self, ContentProtection, DrmSystem, Rendition};
+use crate::hls_encryption;
+use anyhow::{Context, Result};
+use armoricore_types::schemas::DrmInfo;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tracing::info;
+use uuid::Uuid;
+
+/// License URL used when `DRM_LICENSE_URL_TEMPLATE` is not set
+pub const DEFAULT_LICENSE_URL_TEMPLATE: &str = "/drm/clearkey/{media_id}/license";
+
+/// W3C Common PSSH system ID, used for clear-key initialization data
+pub const COMMON_SYSTEM_ID: Uuid = Uuid::from_u128(0x1077efec_c0b2_4d02_ace3_3c1e52e2fb4b);
+
+/// DASH-IF ClearKey system ID
+pub const CLEARKEY_SYSTEM_ID: Uuid = Uuid::from_u128(0xe2719d58_a985_b3c9_781a_b030af78d30e);
+
+/// HLS `KEYFORMAT` of clear-key protected playlists
+pub const CLEARKEY_KEYFORMAT: &str = "org.w3.clearkey";
+
+/// A Common Encryption content key and its key ID
+#[derive(Clone)]
+pub struct DrmKey {
+    pub kid: [u8; 16],
+    pub key: [u8; 16],
+    /// Key store identifier of the content key
+    pub key_store_id: String,
+}
+
+/// DRM packaging settings
+#[derive(Debug, Clone)]
+pub struct DrmConfig {
+    /// Protection scheme; `None` disables DRM packaging
+    pub scheme: Option<Scheme>,
+    /// License URL with a `{media_id}` placeholder
+    pub license_url_template: String,
+}
+
+impl Default for DrmConfig {
+    fn default() -> Self {
+        Self {
+            scheme: None,
+            license_url_template: DEFAULT_LICENSE_URL_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl DrmConfig {
+    /// Read `MEDIA_DRM` (`none`, `cenc` or `cbcs`) and `DRM_LICENSE_URL_TEMPLATE`
+    pub fn from_env() -> Result<Self> {
+        let scheme = std::env::var("MEDIA_DRM").unwrap_or_default();
+        let scheme = match scheme.to_lowercase().as_str() {
+            "" | "none" => None,
+            value => Some(
+                Scheme::parse(value).ok_or_else(|| anyhow::anyhow!("Invalid MEDIA_DRM: {}", scheme))?,
+            ),
+        };
+        let license_url_template = std::env::var("DRM_LICENSE_URL_TEMPLATE")
+            .unwrap_or_else(|_| DEFAULT_LICENSE_URL_TEMPLATE.to_string());
+
+        Ok(Self {
+            scheme,
+            license_url_template,
+        })
+    }
+
+    /// Whether renditions are DRM protected
+    pub fn is_enabled(&self) -> bool {
+        self.scheme.is_some()
+    }
+
+    /// License acquisition URL for a media file
+    pub fn license_url(&self, media_id: &Uuid) -> String {
+        self.license_url_template
+            .replace("{media_id}", &media_id.to_string())
+    }
+}
+
+/// DRM signalling of a protected media file
+#[derive(Debug, Clone)]
+pub struct DrmPackage {
+    /// Published on `media.ready`
+    pub info: DrmInfo,
+    /// Written to the MPD
+    pub protection: ContentProtection,
+}
+
+impl DrmPackage {
+    pub fn new(scheme: Scheme, key: &DrmKey, license_url: String) -> Self {
+        let kid = Uuid::from_bytes(key.kid);
+        Self {
+            info: DrmInfo {
+                scheme: scheme.as_str().to_string(),
+                key_ids: vec![kid],
+                key_store_ids: vec![key.key_store_id.clone()],
+                systems: vec!["clearkey".to_string()],
+                license_url: Some(license_url.clone()),
+            },
+            protection: ContentProtection {
+                scheme: scheme.as_str().to_string(),
+                default_kid: kid,
+                systems: vec![DrmSystem {
+                    system_id: CLEARKEY_SYSTEM_ID,
+                    name: "ClearKey1.0".to_string(),
+                    pssh: Some(STANDARD.encode(clearkey_pssh(&key.kid))),
+                    license_url: Some(license_url),
+                }],
+            },
+        }
+    }
+}
+
+/// W3C Common `pssh` box listing `kid`
+pub fn clearkey_pssh(kid: &[u8; 16]) -> Vec<u8> {
+    cenc::pssh(COMMON_SYSTEM_ID.as_bytes(), &[*kid], &[])
+}
+
+/// `EXT-X-KEY` tag for a protected playlist
+fn hls_key_tag(scheme: Scheme, kid: &[u8; 16]) -> String {
+    let method = match scheme {
+        Scheme::Cbcs => "SAMPLE-AES",
+        Scheme::Cenc => "SAMPLE-AES-CTR",
+    };
+    format!(
+        "#EXT-X-KEY:METHOD={},URI=\"data:text/plain;base64,{}\",KEYID=0x{},KEYFORMAT=\"{}\",KEYFORMATVERSIONS=\"1\"\n",
+        method,
+        STANDARD.encode(clearkey_pssh(kid)),
+        Uuid::from_bytes(*kid).simple(),
+        CLEARKEY_KEYFORMAT
+    )
+}
+
+/// Protect a rendition's init and media segments in place and add the key
+/// tag to its playlist
+///
+/// `iv` must be unique per rendition: with `cenc` it seeds the per-sample
+/// IVs of a key shared by every rendition. Fails without touching the
+/// playlist if the rendition's codec can't be sample encrypted.
+pub async fn protect_rendition(
+    rendition: &Rendition,
+    scheme: Scheme,
+    key: &DrmKey,
+    iv: &[u8; 16],
+) -> Result<()> {
+    let rendition_dir = rendition
+        .playlist
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Playlist has no parent directory: {:?}", rendition.playlist))?;
+    let playlist = tokio::fs::read_to_string(&rendition.playlist)
+        .await
+        .with_context(|| format!("Failed to read playlist: {}", rendition.playlist.display()))?;
+    let (_, segments) = hls_encryption::playlist_segments(&playlist);
+
+    let init_path = rendition_dir.join(dash::INIT_SEGMENT_NAME);
+    let params = ProtectionParams {
+        scheme,
+        kid: key.kid,
+        iv: *iv,
+    };
+    let init = tokio::fs::read(&init_path)
+        .await
+        .with_context(|| format!("Failed to read {}", init_path.display()))?;
+    let (init, track) = cenc::protect_init_segment(&init, &params, &[clearkey_pssh(&key.kid)])?;
+
+    let mut protected = Vec::with_capacity(segments.len());
+    for (position, segment) in segments.iter().enumerate() {
+        let segment_path = rendition_dir.join(segment);
+        let data = tokio::fs::read(&segment_path)
+            .await
+            .with_context(|| format!("Failed to read {}", segment_path.display()))?;
+        let data = cenc::protect_media_segment(&data, &track, &key.key, iv, position as u64)
+            .with_context(|| format!("Failed to encrypt segment: {}", segment_path.display()))?;
+        protected.push((segment_path, data));
+    }
+
+    // Only write once every segment could be protected
+    tokio::fs::write(&init_path, init).await?;
+    for (segment_path, data) in protected {
+        tokio::fs::write(segment_path, data).await?;
+    }
+    let playlist = hls_encryption::insert_key_period_tags(&playlist, |_| 0, |_| hls_key_tag(scheme, &key.kid));
+    tokio::fs::write(&rendition.playlist, playlist).await?;
+
+    info!(
+        rendition = rendition.id,
+        scheme = scheme.as_str(),
+        segments = segments.len(),
+        "Protected CMAF rendition"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> DrmKey {
+        DrmKey {
+            kid: [0xab; 16],
+            key: [1; 16],
+            key_store_id: "media_x.cenc".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hls_key_tag_method_per_scheme() {
+        let tag = hls_key_tag(Scheme::Cbcs, &key().kid);
+        assert!(tag.starts_with("#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"data:text/plain;base64,"));
+        assert!(tag.contains(&format!("KEYID=0x{}", "ab".repeat(16))));
+        assert!(tag.contains("KEYFORMAT=\"org.w3.clearkey\""));
+        assert!(hls_key_tag(Scheme::Cenc, &key().kid).starts_with("#EXT-X-KEY:METHOD=SAMPLE-AES-CTR,"));
+    }
+
+    #[test]
+    fn test_clearkey_pssh_lists_kid() {
+        let pssh = clearkey_pssh(&key().kid);
+        assert_eq!(&pssh[4..8], b"pssh");
+        assert_eq!(pssh[8], 1);
+        assert_eq!(&pssh[12..28], COMMON_SYSTEM_ID.as_bytes());
+        assert_eq!(&pssh[28..32], &1u32.to_be_bytes());
+        assert_eq!(&pssh[32..48], &key().kid);
+        assert_eq!(&pssh[48..], &0u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_package_signals_kid_and_license_url() {
+        let media_id = Uuid::new_v4();
+        let config = DrmConfig::default();
+        let package = DrmPackage::new(Scheme::Cenc, &key(), config.license_url(&media_id));
+
+        assert_eq!(package.info.scheme, "cenc");
+        assert_eq!(package.info.key_ids, vec![Uuid::from_bytes(key().kid)]);
+        assert_eq!(package.info.key_store_ids, vec!["media_x.cenc".to_string()]);
+        assert_eq!(
+            package.info.license_url,
+            Some(format!("/drm/clearkey/{}/license", media_id))
+        );
+        assert_eq!(package.protection.default_kid, Uuid::from_bytes(key().kid));
+        assert_eq!(package.protection.systems[0].system_id, CLEARKEY_SYSTEM_ID);
+    }
+}
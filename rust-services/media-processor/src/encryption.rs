@@ -1,12 +1,13 @@
 //! Basic Content Protection - Internal Encryption
 //!
-//! This module provides basic encryption for media files (HLS segments, MP4 files)
-//! using AES-256-CBC encryption. This is NOT enterprise DRM (Widevine, PlayReady, FairPlay).
+//! This module provides basic encryption for media files (HLS segments, MP4 files).
+//! This is NOT enterprise DRM (Widevine, PlayReady, FairPlay).
 //!
 //! Features:
-//! - AES-256-CBC encryption for media files
+//! - AES-256-GCM encryption performed in place by the key store (HSM-compatible)
 //! - Per-media encryption keys
-//! - Key derivation from master key
+//! - Per-media HLS content keys, readable so they can be delivered to players
+//! - Per-media Common Encryption content keys with their key IDs
 //! - IV (Initialization Vector) generation
 //! - Encryption metadata storage
 // Copyright 2025 Francisco F. Pinochet
@@ -24,43 +25,165 @@
 // limitations under the License.
 
 
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use armoricore_keys::key_store::KeyStore;
+use armoricore_keys::KeyType;
+use crate::drm::DrmKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs;
 use std::io::{Read, Write};
-use tracing::{info, warn};
+use tokio::sync::RwLock;
+use tracing::info;
 use uuid::Uuid;
 use anyhow::{Context, Result};
 
+/// Algorithm used when the key store performs encryption in place
+const KMS_ALGORITHM: &str = "AES-256-GCM";
+
+/// Algorithm used for local encryption without a key store
+const LOCAL_ALGORITHM: &str = "AES-256-CBC";
+
 /// Encryption metadata for a media file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)] // Fields are used by external code that consumes this struct
 pub struct EncryptionMetadata {
     pub media_id: Uuid,
     pub encryption_key_id: String,
     pub iv: Vec<u8>,
-    pub algorithm: String, // "AES-256-CBC"
+    pub algorithm: String, // "AES-256-GCM" (key store), "AES-256-CBC" (local), or the HLS method
 }
 
-/// Basic content encryption using AES-256-CBC
+/// Basic content encryption
+///
+/// With a key store, media keys are generated inside the key store and used
+/// in place through `KeyStore::encrypt`/`decrypt`, so HSM-backed stores never
+/// hand out key material. Without one, keys only live in memory for the
+/// lifetime of this instance.
 pub struct ContentEncryption {
-    #[allow(dead_code)] // key_store is used when encryption is enabled
     key_store: Option<KeyStore>,
+    /// Per-media keys used when no key store is configured
+    ephemeral_keys: RwLock<HashMap<Uuid, Vec<u8>>>,
 }
 
 impl ContentEncryption {
     /// Create a new ContentEncryption instance
     pub fn new(key_store: Option<KeyStore>) -> Self {
-        Self { key_store }
+        Self {
+            key_store,
+            ephemeral_keys: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Key store identifier for a media file's encryption key
+    fn media_key_id(media_id: &Uuid) -> String {
+        format!("media_{}", media_id)
+    }
+
+    /// Key store identifier for the `key_index`-th HLS content key of a media file
+    pub fn hls_key_id(media_id: &Uuid, key_index: u32) -> String {
+        format!("media_{}.hls.{}", media_id, key_index)
+    }
+
+    /// Get the AES-128 HLS content key for a key period, generating it on first use
+    ///
+    /// Content keys are stored as `KeyType::ContentKey` so the key delivery
+    /// endpoint can read them back, even on HSM-backed stores.
+    pub async fn hls_content_key(&self, media_id: &Uuid, key_index: u32) -> Result<[u8; 16]> {
+        let key_store = self
+            .key_store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("HLS encryption requires a key store"))?;
+        let key_id = Self::hls_key_id(media_id, key_index);
+        if !key_store.key_exists(&key_id).await {
+            key_store
+                .generate_key(&key_id, KeyType::ContentKey, 16)
+                .await
+                .with_context(|| format!("Failed to generate content key: {}", key_id))?;
+        }
+        let key = key_store
+            .get_key(&key_id)
+            .await
+            .with_context(|| format!("Failed to read content key: {}", key_id))?;
+        key.as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Content key {} is not 16 bytes", key_id))
+    }
+
+    /// Key store identifier for a media file's Common Encryption content key
+    pub fn drm_key_id(media_id: &Uuid) -> String {
+        format!("media_{}.cenc", media_id)
+    }
+
+    /// Get the Common Encryption content key of a media file, if one was generated
+    ///
+    /// The key ID travels with the key as its `kid` metadata entry.
+    pub async fn drm_key(&self, media_id: &Uuid) -> Result<Option<DrmKey>> {
+        let key_store = self
+            .key_store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("DRM packaging requires a key store"))?;
+        let key_store_id = Self::drm_key_id(media_id);
+        if !key_store.key_exists(&key_store_id).await {
+            return Ok(None);
+        }
+        let key = key_store
+            .get_key(&key_store_id)
+            .await
+            .with_context(|| format!("Failed to read content key: {}", key_store_id))?;
+        let metadata = key_store
+            .get_metadata(&key_store_id)
+            .await
+            .with_context(|| format!("Failed to read content key metadata: {}", key_store_id))?;
+        let kid = metadata
+            .metadata
+            .get("kid")
+            .and_then(|kid| Uuid::parse_str(kid).ok())
+            .ok_or_else(|| anyhow::anyhow!("Content key {} has no key ID", key_store_id))?;
+
+        Ok(Some(DrmKey {
+            kid: *kid.as_bytes(),
+            key: key
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Content key {} is not 16 bytes", key_store_id))?,
+            key_store_id,
+        }))
+    }
+
+    /// Get the Common Encryption content key of a media file, generating it and
+    /// a random key ID on first use
+    pub async fn drm_content_key(&self, media_id: &Uuid) -> Result<DrmKey> {
+        if let Some(key) = self.drm_key(media_id).await? {
+            return Ok(key);
+        }
+        let key_store = self
+            .key_store
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("DRM packaging requires a key store"))?;
+        let key_store_id = Self::drm_key_id(media_id);
+        let key: [u8; 16] = rand::random();
+        let kid = Uuid::new_v4();
+        let metadata = serde_json::json!({ "kid": kid.simple().to_string() }).to_string();
+        key_store
+            .store_key(&key_store_id, KeyType::ContentKey, &key, Some(&metadata))
+            .await
+            .with_context(|| format!("Failed to store content key: {}", key_store_id))?;
+
+        Ok(DrmKey {
+            kid: *kid.as_bytes(),
+            key,
+            key_store_id,
+        })
     }
 
     /// Encrypt a media file (HLS segment or MP4 file)
     /// 
     /// This function:
-    /// 1. Generates or retrieves an encryption key for the media
-    /// 2. Generates a random IV
-    /// 3. Encrypts the file using AES-256-CBC
-    /// 4. Returns encryption metadata
+    /// 1. Generates the media key in the key store if it doesn't exist yet
+    /// 2. Encrypts the file with the key in place (AES-256-GCM, media ID as AAD)
+    /// 3. Returns encryption metadata
     pub async fn encrypt_file(
         &self,
         input_path: &Path,
@@ -81,14 +204,30 @@ impl ContentEncryption {
         file.read_to_end(&mut input_data)
             .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
 
-        // Get or generate encryption key for this media
-        let encryption_key = self.get_or_generate_media_key(media_id).await?;
-
-        // Generate random IV (16 bytes for AES-256-CBC)
-        let iv = self.generate_iv()?;
-
-        // Encrypt the data
-        let encrypted_data = self.encrypt_data(&input_data, &encryption_key, &iv)?;
+        let key_id = Self::media_key_id(media_id);
+        let (encrypted_data, iv, algorithm) = match self.key_store {
+            Some(ref key_store) => {
+                if !key_store.key_exists(&key_id).await {
+                    key_store
+                        .generate_key(&key_id, KeyType::EncryptionKey, 32)
+                        .await
+                        .with_context(|| format!("Failed to generate media key: {}", key_id))?;
+                }
+                let encrypted = key_store
+                    .encrypt(&key_id, &input_data, media_id.as_bytes())
+                    .await
+                    .with_context(|| format!("Failed to encrypt with media key: {}", key_id))?;
+                // The nonce is carried as the ciphertext prefix
+                let iv = encrypted[..12].to_vec();
+                (encrypted, iv, KMS_ALGORITHM)
+            }
+            None => {
+                let encryption_key = self.get_or_generate_ephemeral_key(media_id).await?;
+                let iv = self.generate_iv()?;
+                let encrypted = self.encrypt_data(&input_data, &encryption_key, &iv)?;
+                (encrypted, iv, LOCAL_ALGORITHM)
+            }
+        };
 
         // Write encrypted file
         let mut output_file = fs::File::create(output_path)
@@ -99,9 +238,9 @@ impl ContentEncryption {
         // Create encryption metadata
         let metadata = EncryptionMetadata {
             media_id: *media_id,
-            encryption_key_id: format!("media_{}", media_id),
+            encryption_key_id: key_id,
             iv,
-            algorithm: "AES-256-CBC".to_string(),
+            algorithm: algorithm.to_string(),
         };
 
         info!(
@@ -117,9 +256,9 @@ impl ContentEncryption {
     /// Decrypt a media file
     /// 
     /// This function:
-    /// 1. Retrieves the encryption key for the media
-    /// 2. Uses the provided IV from metadata
-    /// 3. Decrypts the file using AES-256-CBC
+    /// 1. Reads the encrypted file
+    /// 2. Decrypts it with the media key according to `metadata.algorithm`
+    /// 3. Writes the decrypted file
     pub async fn decrypt_file(
         &self,
         encrypted_path: &Path,
@@ -140,11 +279,26 @@ impl ContentEncryption {
         file.read_to_end(&mut encrypted_data)
             .with_context(|| format!("Failed to read encrypted file: {}", encrypted_path.display()))?;
 
-        // Get encryption key
-        let encryption_key = self.get_media_key(&metadata.media_id).await?;
-
-        // Decrypt the data
-        let decrypted_data = self.decrypt_data(&encrypted_data, &encryption_key, &metadata.iv)?;
+        let decrypted_data = if metadata.algorithm == KMS_ALGORITHM {
+            let key_store = self.key_store.as_ref().ok_or_else(|| {
+                anyhow::anyhow!("Key store required to decrypt {} content", KMS_ALGORITHM)
+            })?;
+            key_store
+                .decrypt(
+                    &metadata.encryption_key_id,
+                    &encrypted_data,
+                    metadata.media_id.as_bytes(),
+                )
+                .await
+                .with_context(|| {
+                    format!("Failed to decrypt with media key: {}", metadata.encryption_key_id)
+                })?
+        } else if let Ok(encryption_key) = self.get_ephemeral_key(&metadata.media_id).await {
+            self.decrypt_data(&encrypted_data, &encryption_key, &metadata.iv)?
+        } else {
+            let encryption_key = self.get_legacy_key(metadata).await?;
+            Self::decrypt_legacy_data(&encrypted_data, &encryption_key, &metadata.iv)?
+        };
 
         // Write decrypted file
         let mut output_file = fs::File::create(output_path)
@@ -160,46 +314,38 @@ impl ContentEncryption {
         Ok(())
     }
 
-    /// Get or generate encryption key for a media file
-    async fn get_or_generate_media_key(&self, media_id: &Uuid) -> Result<Vec<u8>> {
-        let key_id = format!("media_{}", media_id);
-
-        // Try to get existing key from key store
-        if let Some(ref key_store) = self.key_store {
-            if let Ok(key_bytes) = key_store.get_encryption_key(&key_id).await {
-                return Ok(key_bytes);
-            }
+    /// Get or generate an in-memory encryption key for a media file
+    async fn get_or_generate_ephemeral_key(&self, media_id: &Uuid) -> Result<Vec<u8>> {
+        let mut keys = self.ephemeral_keys.write().await;
+        if let Some(key) = keys.get(media_id) {
+            return Ok(key.clone());
         }
 
-        // Generate new key if not found
         let new_key = self.generate_encryption_key()?;
-
-        // Store key in key store if available
-        if let Some(ref key_store) = self.key_store {
-            if let Err(e) = key_store.store_encryption_key(&key_id, &new_key).await {
-                warn!(
-                    error = %e,
-                    key_id = key_id,
-                    "Failed to store encryption key in key store, using in-memory key"
-                );
-            }
-        }
-
+        keys.insert(*media_id, new_key.clone());
         Ok(new_key)
     }
 
-    /// Get encryption key for a media file
-    async fn get_media_key(&self, media_id: &Uuid) -> Result<Vec<u8>> {
-        let key_id = format!("media_{}", media_id);
+    /// Get the in-memory encryption key for a media file
+    async fn get_ephemeral_key(&self, media_id: &Uuid) -> Result<Vec<u8>> {
+        self.ephemeral_keys
+            .read()
+            .await
+            .get(media_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Encryption key not found for media: {}", media_id))
+    }
 
-        // Try to get key from key store
-        if let Some(ref key_store) = self.key_store {
-            if let Ok(key_bytes) = key_store.get_encryption_key(&key_id).await {
-                return Ok(key_bytes);
-            }
+    /// Raw key of content encrypted before media keys moved to AES-256-GCM,
+    /// kept in the key store under the same ID
+    async fn get_legacy_key(&self, metadata: &EncryptionMetadata) -> Result<Vec<u8>> {
+        match self.key_store {
+            Some(ref key_store) if key_store.key_exists(&metadata.encryption_key_id).await => key_store
+                .get_encryption_key(&metadata.encryption_key_id)
+                .await
+                .with_context(|| format!("Failed to read legacy media key: {}", metadata.encryption_key_id)),
+            _ => Err(anyhow::anyhow!("Encryption key not found for media: {}", metadata.media_id)),
         }
-
-        Err(anyhow::anyhow!("Encryption key not found for media: {}", media_id))
     }
 
     /// Generate a random encryption key (32 bytes for AES-256)
@@ -218,61 +364,36 @@ impl ContentEncryption {
         Ok(iv)
     }
 
-    /// Encrypt data using AES-256-CBC
+    /// Encrypt data using AES-256-CBC with PKCS#7 padding
     fn encrypt_data(&self, data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
-        // For now, use a simple XOR-based encryption as a placeholder
-        // TODO: Implement proper AES-256-CBC when the correct crate is available
-        // This is a basic implementation - in production, use proper AES-256-CBC
-        
-        // Ensure key is exactly 32 bytes
-        if key.len() != 32 {
-            return Err(anyhow::anyhow!("Encryption key must be 32 bytes (256 bits)"));
-        }
-
-        // Ensure IV is exactly 16 bytes
-        if iv.len() != 16 {
-            return Err(anyhow::anyhow!("IV must be 16 bytes"));
-        }
-
-        // Simple XOR encryption (NOT secure - placeholder only)
-        // In production, replace with proper AES-256-CBC
-        let mut encrypted = Vec::with_capacity(data.len());
-        for (i, byte) in data.iter().enumerate() {
-            let key_byte = key[i % key.len()];
-            let iv_byte = iv[i % iv.len()];
-            encrypted.push(byte ^ key_byte ^ iv_byte);
-        }
-        
-        Ok(encrypted)
+        let encryptor = cbc::Encryptor::<aes::Aes256>::new_from_slices(key, iv)
+            .map_err(|_| anyhow::anyhow!("Encryption key must be 32 bytes and IV 16 bytes"))?;
+        Ok(encryptor.encrypt_padded_vec_mut::<Pkcs7>(data))
     }
 
-    /// Decrypt data using AES-256-CBC
+    /// Decrypt data using AES-256-CBC with PKCS#7 padding
     fn decrypt_data(&self, encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
-        // For now, use a simple XOR-based decryption as a placeholder
-        // TODO: Implement proper AES-256-CBC when the correct crate is available
-        // This is a basic implementation - in production, use proper AES-256-CBC
-        
-        // Ensure key is exactly 32 bytes
-        if key.len() != 32 {
-            return Err(anyhow::anyhow!("Decryption key must be 32 bytes (256 bits)"));
-        }
-
-        // Ensure IV is exactly 16 bytes
-        if iv.len() != 16 {
-            return Err(anyhow::anyhow!("IV must be 16 bytes"));
-        }
+        let decryptor = cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv)
+            .map_err(|_| anyhow::anyhow!("Decryption key must be 32 bytes and IV 16 bytes"))?;
+        decryptor
+            .decrypt_padded_vec_mut::<Pkcs7>(encrypted_data)
+            .map_err(|_| anyhow::anyhow!("Invalid padding in encrypted data"))
+    }
 
-        // Simple XOR decryption (NOT secure - placeholder only)
-        // In production, replace with proper AES-256-CBC
-        // XOR is symmetric, so decryption is the same as encryption
-        let mut decrypted = Vec::with_capacity(encrypted_data.len());
-        for (i, byte) in encrypted_data.iter().enumerate() {
-            let key_byte = key[i % key.len()];
-            let iv_byte = iv[i % iv.len()];
-            decrypted.push(byte ^ key_byte ^ iv_byte);
+    /// Decrypt content written before media keys moved to the key store
+    ///
+    /// Those files are labelled "AES-256-CBC" but were produced by an XOR of
+    /// each byte with the key and IV bytes at the same offset, so they are
+    /// read back with that transform rather than with AES.
+    fn decrypt_legacy_data(encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+        if key.len() != 32 || iv.len() != 16 {
+            return Err(anyhow::anyhow!("Legacy decryption key must be 32 bytes and IV 16 bytes"));
         }
-        
-        Ok(decrypted)
+        Ok(encrypted_data
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ key[i % key.len()] ^ iv[i % iv.len()])
+            .collect())
     }
 }
 
@@ -281,6 +402,68 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    #[tokio::test]
+    async fn test_encrypt_decrypt_file_with_key_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(
+            temp_dir.path().join("keys"),
+            Some(&[9u8; 32]),
+        )
+        .await
+        .unwrap();
+        let encryption = ContentEncryption::new(Some(KeyStore::new(std::sync::Arc::new(backend))));
+        let media_id = Uuid::new_v4();
+
+        let test_data = b"Test media file content for key store encryption";
+        let input_path = temp_dir.path().join("input.bin");
+        fs::write(&input_path, test_data).unwrap();
+
+        let encrypted_path = temp_dir.path().join("encrypted.bin");
+        let metadata = encryption.encrypt_file(&input_path, &encrypted_path, &media_id).await.unwrap();
+        assert_eq!(metadata.algorithm, "AES-256-GCM");
+        assert_eq!(metadata.encryption_key_id, format!("media_{}", media_id));
+
+        let decrypted_path = temp_dir.path().join("decrypted.bin");
+        encryption.decrypt_file(&encrypted_path, &decrypted_path, &metadata).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), test_data);
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_legacy_cbc_file_from_key_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(
+            temp_dir.path().join("keys"),
+            Some(&[9u8; 32]),
+        )
+        .await
+        .unwrap();
+        let key_store = KeyStore::new(std::sync::Arc::new(backend));
+        let encryption = ContentEncryption::new(Some(key_store.clone()));
+        let media_id = Uuid::new_v4();
+
+        // "legacy content" as written by the previous encrypt_data, with the
+        // key kept in the store
+        let key: Vec<u8> = (0u8..32).collect();
+        let iv: Vec<u8> = (0xa0u8..0xb0).collect();
+        let legacy_output = [
+            0xcc, 0xc5, 0xc7, 0xc1, 0xc3, 0xd9, 0x80, 0xc3, 0xcf, 0xce, 0xd4, 0xc5, 0xce, 0xd4,
+        ];
+        let key_id = ContentEncryption::media_key_id(&media_id);
+        key_store.store_encryption_key(&key_id, &key).await.unwrap();
+        let encrypted_path = temp_dir.path().join("legacy.bin");
+        fs::write(&encrypted_path, legacy_output).unwrap();
+
+        let metadata = EncryptionMetadata {
+            media_id,
+            encryption_key_id: key_id,
+            iv,
+            algorithm: LOCAL_ALGORITHM.to_string(),
+        };
+        let decrypted_path = temp_dir.path().join("decrypted.bin");
+        encryption.decrypt_file(&encrypted_path, &decrypted_path, &metadata).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"legacy content");
+    }
+
     #[tokio::test]
     async fn test_encrypt_decrypt_file() {
         let encryption = ContentEncryption::new(None);
@@ -324,6 +507,47 @@ mod tests {
         // Decrypt
         let decrypted = encryption.decrypt_data(&encrypted, &key, &iv).unwrap();
         assert_eq!(decrypted, test_data);
+
+        // PKCS#7 pads the 24-byte input to two blocks
+        assert_eq!(encrypted.len(), 32);
     }
-}
 
+    #[tokio::test]
+    async fn test_hls_content_key_is_stable() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(
+            temp_dir.path().join("keys"),
+            Some(&[9u8; 32]),
+        )
+        .await
+        .unwrap();
+        let encryption = ContentEncryption::new(Some(KeyStore::new(std::sync::Arc::new(backend))));
+        let media_id = Uuid::new_v4();
+
+        let key = encryption.hls_content_key(&media_id, 0).await.unwrap();
+        assert_eq!(encryption.hls_content_key(&media_id, 0).await.unwrap(), key);
+        assert_ne!(encryption.hls_content_key(&media_id, 1).await.unwrap(), key);
+
+        assert!(ContentEncryption::new(None).hls_content_key(&media_id, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drm_content_key_keeps_its_kid() {
+        let temp_dir = TempDir::new().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(
+            temp_dir.path().join("keys"),
+            Some(&[9u8; 32]),
+        )
+        .await
+        .unwrap();
+        let encryption = ContentEncryption::new(Some(KeyStore::new(std::sync::Arc::new(backend))));
+        let media_id = Uuid::new_v4();
+
+        assert!(encryption.drm_key(&media_id).await.unwrap().is_none());
+        let key = encryption.drm_content_key(&media_id).await.unwrap();
+        let stored = encryption.drm_key(&media_id).await.unwrap().unwrap();
+        assert_eq!((stored.kid, stored.key), (key.kid, key.key));
+        assert_eq!(stored.key_store_id, format!("media_{}.cenc", media_id));
+        assert_eq!(encryption.drm_content_key(&media_id).await.unwrap().kid, key.kid);
+    }
+}
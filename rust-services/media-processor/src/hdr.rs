@@ -0,0 +1,375 @@
+//! HDR sources
+//!
+//! A source is HDR when its probed video stream uses the PQ (HDR10, HDR10+,
+//! Dolby Vision) or HLG transfer. Such sources get two sets of renditions:
+//! 10-bit HEVC or AV1 renditions that keep the BT.2020 color signaling and
+//! static metadata, and H.264 renditions tone-mapped to BT.709 SDR for
+//! devices without HDR support. Tone mapping runs on the CPU with the
+//! `zscale` and `tonemap` filters. With `HDR_OUTPUT=sdr` only the
+//! tone-mapped renditions are produced.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::dash::VideoRange;
+use crate::media_info::{ContentLightLevel, HdrFormat, MasteringDisplay, MediaInfo};
+
+/// `tonemap` filter algorithms accepted in `HDR_TONEMAP`
+pub const TONEMAP_ALGORITHMS: [&str; 6] = ["hable", "mobius", "reinhard", "clip", "gamma", "linear"];
+
+/// What HDR sources are transcoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrOutput {
+    /// HDR renditions plus tone-mapped SDR renditions
+    Preserve,
+    /// Tone-mapped SDR renditions only
+    Sdr,
+}
+
+/// Codec of the HDR renditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrCodec {
+    /// HEVC Main 10 (libx265)
+    Hevc,
+    /// AV1 Main, 10-bit (libaom-av1)
+    Av1,
+}
+
+/// HDR handling settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrConfig {
+    pub output: HdrOutput,
+    pub codec: HdrCodec,
+    /// `tonemap` filter algorithm of the SDR renditions
+    pub tonemap: String,
+}
+
+impl Default for HdrConfig {
+    fn default() -> Self {
+        Self {
+            output: HdrOutput::Preserve,
+            codec: HdrCodec::Hevc,
+            tonemap: "hable".to_string(),
+        }
+    }
+}
+
+impl HdrConfig {
+    /// Read `HDR_OUTPUT` (preserve, sdr), `HDR_CODEC` (hevc, av1) and `HDR_TONEMAP`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.to_lowercase());
+        Self {
+            output: match var("HDR_OUTPUT").as_deref() {
+                Some("sdr") => HdrOutput::Sdr,
+                _ => defaults.output,
+            },
+            codec: match var("HDR_CODEC").as_deref() {
+                Some("av1") => HdrCodec::Av1,
+                _ => defaults.codec,
+            },
+            tonemap: var("HDR_TONEMAP")
+                .filter(|algorithm| TONEMAP_ALGORITHMS.contains(&algorithm.as_str()))
+                .unwrap_or(defaults.tonemap),
+        }
+    }
+
+    /// Video renditions to produce for each resolution of a source
+    pub fn targets(&self, source: Option<&HdrSource>) -> Vec<VideoColor> {
+        match source {
+            None => vec![VideoColor::Sdr],
+            Some(source) => {
+                let mut targets = vec![VideoColor::ToneMapped(tonemap_filter(source, &self.tonemap))];
+                if self.output == HdrOutput::Preserve {
+                    targets.push(VideoColor::Hdr(source.clone(), self.codec));
+                }
+                targets
+            }
+        }
+    }
+
+    /// Filter bringing `source` to SDR, for outputs that are always SDR
+    /// (progressive MP4s, thumbnails)
+    pub fn sdr_filter(&self, source: Option<&HdrSource>) -> Option<String> {
+        source.map(|source| tonemap_filter(source, &self.tonemap))
+    }
+}
+
+/// Color properties of an HDR source
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrSource {
+    pub format: HdrFormat,
+    pub range: VideoRange,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light_level: Option<ContentLightLevel>,
+}
+
+impl HdrSource {
+    /// HDR properties of the primary video stream, if it is HDR
+    ///
+    /// Dolby Vision is handled through its base layer, which is PQ unless
+    /// the stream signals HLG; the enhancement layer and RPUs are dropped.
+    pub fn detect(info: &MediaInfo) -> Option<Self> {
+        let video = info.video_stream()?.video.as_ref()?;
+        let format = video.hdr?;
+        let range = match (format, video.color_transfer.as_deref()) {
+            (HdrFormat::Hlg, _) | (HdrFormat::DolbyVision, Some("arib-std-b67")) => VideoRange::Hlg,
+            _ => VideoRange::Pq,
+        };
+        Some(Self {
+            format,
+            range,
+            mastering_display: video.mastering_display.clone(),
+            content_light_level: video.content_light_level.clone(),
+        })
+    }
+
+    /// FFmpeg name of the source transfer function
+    pub fn transfer(&self) -> &'static str {
+        match self.range {
+            VideoRange::Hlg => "arib-std-b67",
+            _ => "smpte2084",
+        }
+    }
+}
+
+/// Color handling of one video rendition
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoColor {
+    /// SDR source, encoded as is
+    Sdr,
+    /// HDR source tone-mapped to BT.709 with the given filter chain
+    ToneMapped(String),
+    /// HDR source kept in HDR
+    Hdr(HdrSource, HdrCodec),
+}
+
+impl VideoColor {
+    /// Rendition id (and directory) for `resolution`; HDR renditions get an `_hdr` suffix
+    pub fn rendition_id(&self, resolution: &str) -> String {
+        match self {
+            VideoColor::Hdr(..) => format!("{}_hdr", resolution),
+            _ => resolution.to_string(),
+        }
+    }
+
+    pub fn range(&self) -> VideoRange {
+        match self {
+            VideoColor::Hdr(source, _) => source.range,
+            _ => VideoRange::Sdr,
+        }
+    }
+}
+
+impl HdrCodec {
+    pub fn ffmpeg_codec(&self) -> &'static str {
+        match self {
+            HdrCodec::Hevc => "libx265",
+            HdrCodec::Av1 => "libaom-av1",
+        }
+    }
+
+    /// Encoder options keeping the source's color signaling and metadata
+    ///
+    /// libaom can't write mastering display or content light level
+    /// metadata, so AV1 renditions only carry the color description.
+    pub fn encode_args(&self, source: &HdrSource) -> Vec<String> {
+        let mut args: Vec<String> = match self {
+            HdrCodec::Hevc => vec![
+                "-preset".into(),
+                "medium".into(),
+                "-crf".into(),
+                "22".into(),
+                "-tag:v".into(),
+                "hvc1".into(),
+                "-x265-params".into(),
+                x265_params(source),
+            ],
+            HdrCodec::Av1 => vec!["-cpu-used".into(), "4".into(), "-crf".into(), "30".into()],
+        };
+        args.extend(
+            [
+                "-pix_fmt",
+                "yuv420p10le",
+                "-color_primaries",
+                "bt2020",
+                "-color_trc",
+                source.transfer(),
+                "-colorspace",
+                "bt2020nc",
+                "-color_range",
+                "tv",
+            ]
+            .map(String::from),
+        );
+        args
+    }
+
+    /// RFC 6381 codec string of a 10-bit rendition (level picked by height)
+    pub fn rfc6381_codec(&self, height: u32, range: VideoRange) -> String {
+        match self {
+            // Main 10 profile; general_level_idc 3.1 / 4.0 / 5.0 / 5.1 / 6.1
+            HdrCodec::Hevc => {
+                let level = match height {
+                    0..=720 => 93,
+                    721..=1080 => 120,
+                    1081..=1440 => 150,
+                    1441..=2160 => 153,
+                    _ => 183,
+                };
+                format!("hvc1.2.4.L{}.B0", level)
+            }
+            // Main profile, 10-bit, 4:2:0, BT.2020 primaries and matrix, limited range
+            HdrCodec::Av1 => {
+                let level = match height {
+                    0..=720 => 5,
+                    721..=1080 => 8,
+                    1081..=2160 => 13,
+                    _ => 17,
+                };
+                format!(
+                    "av01.0.{:02}M.10.0.110.09.{:02}.09.0",
+                    level,
+                    range.transfer_characteristics()
+                )
+            }
+        }
+    }
+}
+
+/// `-x265-params` signaling BT.2020 color and, for PQ, HDR10 static metadata
+fn x265_params(source: &HdrSource) -> String {
+    let mut params = format!(
+        "repeat-headers=1:colorprim=bt2020:transfer={}:colormatrix=bt2020nc:range=limited",
+        source.transfer()
+    );
+    if source.range == VideoRange::Pq {
+        params.push_str(":hdr10=1:hdr10-opt=1");
+        if let Some(display) = &source.mastering_display {
+            params.push_str(&format!(":master-display={}", x265_master_display(display)));
+        }
+        if let Some(level) = &source.content_light_level {
+            params.push_str(&format!(":max-cll={},{}", level.max_content, level.max_average));
+        }
+    }
+    params
+}
+
+/// Mastering display in x265 notation: chromaticities in units of 0.00002
+/// and luminance in units of 0.0001 cd/m²
+fn x265_master_display(display: &MasteringDisplay) -> String {
+    let xy = |(x, y): (f64, f64)| format!("({},{})", (x * 50000.0).round(), (y * 50000.0).round());
+    format!(
+        "G{}B{}R{}WP{}L({},{})",
+        xy(display.green),
+        xy(display.blue),
+        xy(display.red),
+        xy(display.white_point),
+        (display.max_luminance * 10000.0).round(),
+        (display.min_luminance * 10000.0).round()
+    )
+}
+
+/// Filter chain converting `source` to 8-bit BT.709 SDR
+///
+/// The source is linearized (nominal peak 100 cd/m²), converted to BT.709
+/// primaries in float RGB, tone-mapped, then encoded with the BT.709
+/// transfer and matrix.
+pub fn tonemap_filter(source: &HdrSource, algorithm: &str) -> String {
+    format!(
+        "zscale=tin={}:pin=bt2020:min=bt2020nc:rin=tv:t=linear:npl=100,format=gbrpf32le,\
+         zscale=p=bt709,tonemap=tonemap={}:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+        source.transfer(),
+        algorithm
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hdr10_info() -> MediaInfo {
+        MediaInfo::from_ffprobe_json(
+            r#"{
+                "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "12.0"},
+                "streams": [{
+                    "index": 0, "codec_type": "video", "codec_name": "hevc", "width": 3840, "height": 2160,
+                    "color_primaries": "bt2020", "color_space": "bt2020nc", "color_transfer": "smpte2084",
+                    "side_data_list": [
+                        {"side_data_type": "Mastering display metadata",
+                         "red_x": "34000/50000", "red_y": "16000/50000",
+                         "green_x": "13250/50000", "green_y": "34500/50000",
+                         "blue_x": "7500/50000", "blue_y": "3000/50000",
+                         "white_point_x": "15635/50000", "white_point_y": "16450/50000",
+                         "min_luminance": "50/10000", "max_luminance": "10000000/10000"},
+                        {"side_data_type": "Content light level metadata", "max_content": 1000, "max_average": 400}
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_detect_hdr10_and_hevc_metadata() {
+        let source = HdrSource::detect(&hdr10_info()).unwrap();
+        assert_eq!(source.format, HdrFormat::Hdr10);
+        assert_eq!(source.range, VideoRange::Pq);
+
+        let params = x265_params(&source);
+        assert!(params.contains("transfer=smpte2084"));
+        assert!(params.contains(
+            "master-display=G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50)"
+        ));
+        assert!(params.contains("max-cll=1000,400"));
+
+        let args = HdrCodec::Hevc.encode_args(&source);
+        assert!(args.windows(2).any(|w| w == ["-pix_fmt", "yuv420p10le"]));
+        assert!(args.windows(2).any(|w| w == ["-tag:v", "hvc1"]));
+
+        let sdr = MediaInfo::from_ffprobe_json(
+            r#"{"format": {"format_name": "mp4"},
+                "streams": [{"index": 0, "codec_type": "video", "width": 1920, "height": 1080,
+                             "color_transfer": "bt709"}]}"#,
+        )
+        .unwrap();
+        assert!(HdrSource::detect(&sdr).is_none());
+    }
+
+    #[test]
+    fn test_targets_per_output_mode() {
+        let source = HdrSource::detect(&hdr10_info()).unwrap();
+        let config = HdrConfig::default();
+        assert_eq!(config.targets(None), vec![VideoColor::Sdr]);
+
+        let targets = config.targets(Some(&source));
+        assert_eq!(targets.len(), 2);
+        assert!(matches!(&targets[0], VideoColor::ToneMapped(filter)
+            if filter.starts_with("zscale=tin=smpte2084") && filter.contains("tonemap=tonemap=hable")));
+        assert_eq!(targets[1].rendition_id("2160p"), "2160p_hdr");
+        assert_eq!(targets[1].range(), VideoRange::Pq);
+
+        let sdr_only = HdrConfig {
+            output: HdrOutput::Sdr,
+            ..HdrConfig::default()
+        };
+        assert_eq!(sdr_only.targets(Some(&source)).len(), 1);
+    }
+
+    #[test]
+    fn test_hdr_codec_strings() {
+        assert_eq!(HdrCodec::Hevc.rfc6381_codec(2160, VideoRange::Pq), "hvc1.2.4.L153.B0");
+        assert_eq!(HdrCodec::Av1.rfc6381_codec(1080, VideoRange::Hlg), "av01.0.08M.10.0.110.09.18.09.0");
+    }
+}
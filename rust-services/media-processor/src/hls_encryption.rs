@@ -0,0 +1,365 @@
+//! HLS segment encryption
+//!
+//! Encrypts the CMAF renditions produced for HLS in place and adds the
+//! matching `EXT-X-KEY` tags to their media playlists:
+//! - `AES-128`: every media segment is encrypted whole with AES-128-CBC
+//!   (PKCS#7 padding, IV = media sequence number)
+//! - `SAMPLE-AES`: samples are encrypted with the `cbcs` scheme (see
+//!   [`crate::cenc`]), which keeps segments parseable
+//!
+//! Init segments stay clear for `AES-128`. Keys are per-media content keys
+//! from the key store, rotated every `HLS_KEY_ROTATION_SEGMENTS` segments,
+//! and players fetch them from the URI built from `HLS_KEY_URI_TEMPLATE`.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::cenc::{self, ProtectionParams, Scheme};
+use crate::dash::{self, Rendition};
+use crate::encryption::ContentEncryption;
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+use uuid::Uuid;
+
+/// Key URI used when `HLS_KEY_URI_TEMPLATE` is not set
+pub const DEFAULT_KEY_URI_TEMPLATE: &str = "/hls/keys/{media_id}/{key_index}";
+
+/// HLS encryption method (`EXT-X-KEY` `METHOD` attribute)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HlsEncryptionMethod {
+    /// Segments are published in the clear
+    None,
+    /// Whole-segment AES-128-CBC
+    Aes128,
+    /// `cbcs` sample encryption
+    SampleAes,
+}
+
+impl HlsEncryptionMethod {
+    /// Parse `none`, `aes-128` or `sample-aes` (case-insensitive)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "" | "none" => Some(Self::None),
+            "aes-128" | "aes128" => Some(Self::Aes128),
+            "sample-aes" | "sample_aes" => Some(Self::SampleAes),
+            _ => None,
+        }
+    }
+
+    /// Value of the `METHOD` attribute
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "NONE",
+            Self::Aes128 => "AES-128",
+            Self::SampleAes => "SAMPLE-AES",
+        }
+    }
+}
+
+/// HLS encryption settings
+#[derive(Debug, Clone)]
+pub struct HlsEncryptionConfig {
+    pub method: HlsEncryptionMethod,
+    /// Segments per content key; 0 uses a single key for the whole media
+    pub rotation_segments: usize,
+    /// Key URI with `{media_id}` and `{key_index}` placeholders
+    pub key_uri_template: String,
+}
+
+impl Default for HlsEncryptionConfig {
+    fn default() -> Self {
+        Self {
+            method: HlsEncryptionMethod::None,
+            rotation_segments: 0,
+            key_uri_template: DEFAULT_KEY_URI_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl HlsEncryptionConfig {
+    /// Read `HLS_ENCRYPTION`, `HLS_KEY_ROTATION_SEGMENTS` and `HLS_KEY_URI_TEMPLATE`
+    pub fn from_env() -> Result<Self> {
+        let method = std::env::var("HLS_ENCRYPTION").unwrap_or_default();
+        let method = HlsEncryptionMethod::parse(&method)
+            .ok_or_else(|| anyhow::anyhow!("Invalid HLS_ENCRYPTION: {}", method))?;
+        let rotation_segments = match std::env::var("HLS_KEY_ROTATION_SEGMENTS") {
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("Invalid HLS_KEY_ROTATION_SEGMENTS: {}", value))?,
+            Err(_) => 0,
+        };
+        let key_uri_template = std::env::var("HLS_KEY_URI_TEMPLATE")
+            .unwrap_or_else(|_| DEFAULT_KEY_URI_TEMPLATE.to_string());
+
+        Ok(Self {
+            method,
+            rotation_segments,
+            key_uri_template,
+        })
+    }
+
+    /// Whether segments are encrypted
+    pub fn is_enabled(&self) -> bool {
+        self.method != HlsEncryptionMethod::None
+    }
+
+    /// Content key index for the segment at `position` in its playlist
+    pub fn key_index(&self, position: usize) -> u32 {
+        match self.rotation_segments {
+            0 => 0,
+            n => (position / n) as u32,
+        }
+    }
+
+    /// Key delivery URI for a content key
+    pub fn key_uri(&self, media_id: &Uuid, key_index: u32) -> String {
+        self.key_uri_template
+            .replace("{media_id}", &media_id.to_string())
+            .replace("{key_index}", &key_index.to_string())
+    }
+}
+
+/// Media segments listed in a playlist and its first media sequence number
+pub fn playlist_segments(playlist: &str) -> (u64, Vec<&str>) {
+    let media_sequence = playlist
+        .lines()
+        .find_map(|line| line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:"))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0);
+    let segments = playlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    (media_sequence, segments)
+}
+
+/// Insert an `EXT-X-KEY` tag before the first segment of every key period
+fn insert_key_tags(playlist: &str, config: &HlsEncryptionConfig, media_id: &Uuid) -> String {
+    insert_key_period_tags(
+        playlist,
+        |position| config.key_index(position),
+        |key_index| {
+            format!(
+                "#EXT-X-KEY:METHOD={},URI=\"{}\"\n",
+                config.method.as_str(),
+                config.key_uri(media_id, key_index)
+            )
+        },
+    )
+}
+
+/// Insert `tags(key_index)` before the first segment of every key period,
+/// where `key_index(position)` maps segment positions to key periods
+///
+/// Tags go after `EXT-X-MAP`, so the init segment stays clear.
+pub fn insert_key_period_tags(
+    playlist: &str,
+    key_index: impl Fn(usize) -> u32,
+    tags: impl Fn(u32) -> String,
+) -> String {
+    let mut output = String::with_capacity(playlist.len() + 256);
+    let mut position = 0;
+    let mut current_key = None;
+    for line in playlist.lines() {
+        if line.starts_with("#EXTINF") {
+            let key_index = key_index(position);
+            if current_key != Some(key_index) {
+                output.push_str(&tags(key_index));
+                current_key = Some(key_index);
+            }
+        } else if !line.trim().is_empty() && !line.starts_with('#') {
+            position += 1;
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+/// Encrypt one segment whole with AES-128-CBC; the IV is its media sequence number
+fn encrypt_segment_aes128(data: &[u8], key: &[u8; 16], media_sequence: u64) -> Vec<u8> {
+    let iv = (media_sequence as u128).to_be_bytes();
+    cbc::Encryptor::<aes::Aes128>::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data)
+}
+
+/// Encrypt a rendition's segments in place and add `EXT-X-KEY` tags to its playlist
+///
+/// `iv` is the constant IV used for `SAMPLE-AES`. Fails without touching the
+/// playlist if the rendition's codec can't be sample encrypted.
+pub async fn encrypt_rendition(
+    rendition: &Rendition,
+    config: &HlsEncryptionConfig,
+    encryption: &ContentEncryption,
+    media_id: &Uuid,
+    iv: &[u8; 16],
+) -> Result<()> {
+    let rendition_dir = rendition
+        .playlist
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Playlist has no parent directory: {:?}", rendition.playlist))?;
+    let playlist = std::fs::read_to_string(&rendition.playlist)
+        .with_context(|| format!("Failed to read playlist: {}", rendition.playlist.display()))?;
+    let (media_sequence, segments) = playlist_segments(&playlist);
+
+    let mut keys: HashMap<u32, [u8; 16]> = HashMap::new();
+    let track = match config.method {
+        HlsEncryptionMethod::SampleAes => {
+            let init_path = rendition_dir.join(dash::INIT_SEGMENT_NAME);
+            let params = ProtectionParams {
+                scheme: Scheme::Cbcs,
+                kid: *media_id.as_bytes(),
+                iv: *iv,
+            };
+            let (init, track) = cenc::protect_init_segment(&read(&init_path)?, &params, &[])?;
+            std::fs::write(&init_path, init)?;
+            Some(track)
+        }
+        _ => None,
+    };
+
+    for (position, segment) in segments.iter().enumerate() {
+        let key_index = config.key_index(position);
+        let key = match keys.get(&key_index) {
+            Some(key) => *key,
+            None => {
+                let key = encryption.hls_content_key(media_id, key_index).await?;
+                keys.insert(key_index, key);
+                key
+            }
+        };
+
+        let segment_path = rendition_dir.join(segment);
+        let data = read(&segment_path)?;
+        let encrypted = match track {
+            Some(ref track) => cenc::protect_media_segment(&data, track, &key, iv, position as u64)
+                .with_context(|| format!("Failed to encrypt segment: {}", segment_path.display()))?,
+            None => encrypt_segment_aes128(&data, &key, media_sequence + position as u64),
+        };
+        std::fs::write(&segment_path, encrypted)?;
+    }
+
+    std::fs::write(&rendition.playlist, insert_key_tags(&playlist, config, media_id))?;
+
+    info!(
+        media_id = %media_id,
+        rendition = rendition.id,
+        method = config.method.as_str(),
+        segments = segments.len(),
+        keys = keys.len(),
+        "Encrypted HLS rendition"
+    );
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockDecryptMut;
+
+    const PLAYLIST: &str = "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:10\n\
+        #EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-MAP:URI=\"init.mp4\"\n\
+        #EXTINF:10.0,\nsegment_000.m4s\n#EXTINF:10.0,\nsegment_001.m4s\n\
+        #EXTINF:4.0,\nsegment_002.m4s\n#EXT-X-ENDLIST\n";
+
+    fn config(method: HlsEncryptionMethod, rotation_segments: usize) -> HlsEncryptionConfig {
+        HlsEncryptionConfig {
+            method,
+            rotation_segments,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_method() {
+        assert_eq!(HlsEncryptionMethod::parse("AES-128"), Some(HlsEncryptionMethod::Aes128));
+        assert_eq!(HlsEncryptionMethod::parse("sample-aes"), Some(HlsEncryptionMethod::SampleAes));
+        assert_eq!(HlsEncryptionMethod::parse(""), Some(HlsEncryptionMethod::None));
+        assert_eq!(HlsEncryptionMethod::parse("rot13"), None);
+    }
+
+    #[test]
+    fn test_insert_key_tags_with_rotation() {
+        let media_id = Uuid::new_v4();
+        let output = insert_key_tags(PLAYLIST, &config(HlsEncryptionMethod::Aes128, 2), &media_id);
+        let lines: Vec<&str> = output.lines().collect();
+
+        let map = lines.iter().position(|l| l.starts_with("#EXT-X-MAP")).unwrap();
+        assert_eq!(
+            lines[map + 1],
+            format!("#EXT-X-KEY:METHOD=AES-128,URI=\"/hls/keys/{}/0\"", media_id)
+        );
+        let second = lines.iter().position(|l| l.ends_with("/1\"")).unwrap();
+        assert_eq!(lines[second + 2], "segment_002.m4s");
+        assert_eq!(lines.iter().filter(|l| l.starts_with("#EXT-X-KEY")).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_rendition_aes128() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(
+            dir.path().join("keys"),
+            Some(&[3u8; 32]),
+        )
+        .await
+        .unwrap();
+        let encryption = ContentEncryption::new(Some(armoricore_keys::KeyStore::new(
+            std::sync::Arc::new(backend),
+        )));
+        let media_id = Uuid::new_v4();
+
+        let rendition_dir = dir.path().join("720p");
+        std::fs::create_dir_all(&rendition_dir).unwrap();
+        std::fs::write(rendition_dir.join("init.mp4"), b"init").unwrap();
+        for i in 0..3 {
+            std::fs::write(rendition_dir.join(format!("segment_{:03}.m4s", i)), vec![i as u8; 40]).unwrap();
+        }
+        let playlist = rendition_dir.join("playlist.m3u8");
+        std::fs::write(&playlist, PLAYLIST).unwrap();
+        let rendition = Rendition {
+            id: "720p".to_string(),
+            kind: dash::RenditionKind::Video {
+                width: 1280,
+                height: 720,
+                audio: vec![],
+                range: dash::VideoRange::Sdr,
+            },
+            codecs: "avc1.64001f".to_string(),
+            bandwidth: 2_800_000,
+            playlist: playlist.clone(),
+        };
+
+        let config = config(HlsEncryptionMethod::Aes128, 2);
+        encrypt_rendition(&rendition, &config, &encryption, &media_id, &[0; 16])
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(rendition_dir.join("init.mp4")).unwrap(), b"init");
+        let key = encryption.hls_content_key(&media_id, 1).await.unwrap();
+        let encrypted = std::fs::read(rendition_dir.join("segment_002.m4s")).unwrap();
+        assert_eq!(encrypted.len(), 48);
+        let decrypted = cbc::Decryptor::<aes::Aes128>::new(&key.into(), &2u128.to_be_bytes().into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+            .unwrap();
+        assert_eq!(decrypted, vec![2u8; 40]);
+        assert!(std::fs::read_to_string(&playlist).unwrap().contains("METHOD=AES-128"));
+    }
+}
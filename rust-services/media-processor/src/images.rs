@@ -0,0 +1,513 @@
+//! Image upload processing
+//!
+//! Images are decoded with FFmpeg (JPEG, PNG, WebP, GIF, BMP, TIFF, AVIF)
+//! and re-encoded into size variants in the configured formats:
+//! - the EXIF orientation is applied to the pixels, since the variants carry
+//!   no metadata to orient them by
+//! - all metadata, including EXIF camera and GPS tags, is dropped
+//! - sources are never upscaled; a source narrower than every configured
+//!   width gets a single variant at its own size
+//!
+//! A BlurHash placeholder is computed from a small decoded frame.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::blurhash;
+use crate::ingest;
+use crate::jobs::JobContext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, BufReader};
+use tracing::{info, warn};
+
+/// Content types decoded as images
+const SUPPORTED_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "image/gif",
+    "image/bmp",
+    "image/tiff",
+    "image/avif",
+];
+
+/// Side of the frame the BlurHash is computed from
+const BLURHASH_FRAME_SIZE: u32 = 32;
+
+/// EXIF orientation tag
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Bytes read from the start of an image to find its EXIF orientation;
+/// metadata sits ahead of the pixel data
+const EXIF_SCAN_LEN: u64 = 1024 * 1024;
+
+/// Whether `content_type` is an image format the processor decodes
+///
+/// Parameters (`; charset=…`) and case are ignored.
+pub fn is_supported(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    SUPPORTED_TYPES.contains(&essence.as_str())
+}
+
+/// Encoding of an image variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Webp,
+    Avif,
+    Jpeg,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "webp" => Some(ImageFormat::Webp),
+            "avif" => Some(ImageFormat::Avif),
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Jpeg => "jpeg",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+            ImageFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Encoder arguments for `quality` (1-100)
+    fn ffmpeg_args(&self, quality: u32) -> Vec<String> {
+        let lossy = 100 - quality.clamp(1, 100);
+        let args = match self {
+            ImageFormat::Webp => format!("-c:v libwebp -quality {}", quality.clamp(1, 100)),
+            ImageFormat::Avif => format!(
+                "-c:v libaom-av1 -still-picture 1 -crf {} -b:v 0 -cpu-used 6 -pix_fmt yuv420p",
+                lossy * 63 / 100
+            ),
+            ImageFormat::Jpeg => format!("-c:v mjpeg -q:v {} -pix_fmt yuvj420p", 2 + lossy * 29 / 100),
+        };
+        args.split(' ').map(str::to_string).collect()
+    }
+}
+
+/// Image variant settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageConfig {
+    /// Variant widths in pixels; heights follow the aspect ratio
+    pub sizes: Vec<u32>,
+    /// Every size is encoded in each format
+    pub formats: Vec<ImageFormat>,
+    /// Encoding quality, 1-100
+    pub quality: u32,
+    /// BlurHash components across and down; `None` disables the placeholder
+    pub blurhash: Option<(u32, u32)>,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![320, 640, 1280, 1920],
+            formats: vec![ImageFormat::Webp, ImageFormat::Jpeg],
+            quality: 80,
+            blurhash: Some((4, 3)),
+        }
+    }
+}
+
+impl ImageConfig {
+    /// Read `IMAGE_SIZES` (comma-separated widths), `IMAGE_FORMATS`
+    /// (webp, avif, jpeg), `IMAGE_QUALITY` and `IMAGE_BLURHASH_COMPONENTS`
+    /// (e.g. 4x3, or `off`)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let sizes: Vec<u32> = var("IMAGE_SIZES")
+            .map(|v| v.split(',').filter_map(|size| size.trim().parse().ok()).filter(|size| *size > 0).collect())
+            .unwrap_or_default();
+        let formats: Vec<ImageFormat> = var("IMAGE_FORMATS")
+            .map(|v| v.split(',').filter_map(ImageFormat::parse).collect())
+            .unwrap_or_default();
+        Self {
+            sizes: if sizes.is_empty() { defaults.sizes } else { sizes },
+            formats: if formats.is_empty() { defaults.formats } else { formats },
+            quality: var("IMAGE_QUALITY")
+                .and_then(|v| v.parse().ok())
+                .filter(|quality| (1..=100).contains(quality))
+                .unwrap_or(defaults.quality),
+            blurhash: match var("IMAGE_BLURHASH_COMPONENTS") {
+                Some(v) if v.eq_ignore_ascii_case("off") => None,
+                Some(v) => parse_components(&v).or(defaults.blurhash),
+                None => defaults.blurhash,
+            },
+        }
+    }
+
+    /// Variant widths for a source `source_width` pixels wide, smallest first
+    pub fn widths(&self, source_width: u32) -> Vec<u32> {
+        let mut widths: Vec<u32> = self.sizes.iter().copied().filter(|width| *width <= source_width).collect();
+        widths.sort_unstable();
+        widths.dedup();
+        if widths.is_empty() {
+            widths.push(source_width);
+        }
+        widths
+    }
+}
+
+fn parse_components(value: &str) -> Option<(u32, u32)> {
+    let (x, y) = value.trim().split_once('x')?;
+    let (x, y) = (x.parse().ok()?, y.parse().ok()?);
+    ((1..=9).contains(&x) && (1..=9).contains(&y)).then_some((x, y))
+}
+
+/// An encoded size variant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageVariant {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageFormat,
+}
+
+/// A processed image: its oriented size, placeholder and variants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: Option<String>,
+    /// Smallest first, each size in every configured format
+    pub variants: Vec<ImageVariant>,
+}
+
+/// EXIF orientation (1-8) of a JPEG, PNG, WebP or TIFF file, if tagged
+pub fn exif_orientation(data: &[u8]) -> Option<u16> {
+    let tiff = if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        data
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        jpeg_exif(data)?
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_exif(data)?
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        webp_exif(data)?
+    } else {
+        return None;
+    };
+    tiff_orientation(tiff).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// TIFF data of the APP1 Exif segment
+fn jpeg_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return None;
+        }
+        let marker = data[offset + 1];
+        // Start of scan or end of image: no metadata follows
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let segment = data.get(offset + 4..offset + 2 + length)?;
+        if marker == 0xE1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+/// Data of the eXIf chunk
+fn png_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 8;
+    while offset + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+        let kind = &data[offset + 4..offset + 8];
+        let chunk = data.get(offset + 8..offset + 8 + length)?;
+        match kind {
+            b"eXIf" => return Some(chunk),
+            b"IEND" => return None,
+            _ => offset += 12 + length,
+        }
+    }
+    None
+}
+
+/// Data of the EXIF chunk, which some writers prefix like JPEG's
+fn webp_exif(data: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let kind = &data[offset..offset + 4];
+        let length = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let chunk = data.get(offset + 8..offset + 8 + length)?;
+        if kind == b"EXIF" {
+            return Some(chunk.strip_prefix(b"Exif\0\0").unwrap_or(chunk));
+        }
+        // Chunks are padded to an even size
+        offset += 8 + length + (length & 1);
+    }
+    None
+}
+
+/// Orientation tag of a TIFF header's first IFD
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    if u16_at(2)? != 42 {
+        return None;
+    }
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    (0..entries)
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+/// FFmpeg filter displaying pixels stored with EXIF `orientation` upright
+pub fn orientation_filter(orientation: u16) -> Option<&'static str> {
+    match orientation {
+        2 => Some("hflip"),
+        3 => Some("hflip,vflip"),
+        4 => Some("vflip"),
+        5 => Some("transpose=0"),
+        6 => Some("transpose=1"),
+        7 => Some("transpose=3"),
+        8 => Some("transpose=2"),
+        _ => None,
+    }
+}
+
+/// Displayed size of a `width` x `height` image stored with `orientation`
+pub fn oriented_size((width, height): (u32, u32), orientation: u16) -> (u32, u32) {
+    if (5..=8).contains(&orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Height of a variant `width` pixels wide, rounded to an even number of
+/// pixels as FFmpeg's `scale=w:-2` does
+pub fn variant_height(width: u32, (source_width, source_height): (u32, u32)) -> u32 {
+    let height = (width as f64 * source_height as f64 / source_width.max(1) as f64 / 2.0).round() as u32 * 2;
+    height.max(2)
+}
+
+/// File of the variant `width` pixels wide in `format`
+pub fn variant_path(output_dir: &Path, width: u32, format: ImageFormat) -> PathBuf {
+    output_dir.join(format!("image_{}.{}", width, format.extension()))
+}
+
+/// Decode `input`, stored at `size` pixels, and write its variants into
+/// `output_dir` (`image_640.webp`, `image_640.jpg`, ...)
+pub async fn process(
+    input: &Path,
+    output_dir: &Path,
+    size: (u32, u32),
+    config: &ImageConfig,
+    job: &JobContext,
+) -> anyhow::Result<ProcessedImage> {
+    let mut data = Vec::new();
+    BufReader::new(tokio::fs::File::open(input).await?)
+        .take(EXIF_SCAN_LEN)
+        .read_to_end(&mut data)
+        .await?;
+    let orientation = exif_orientation(&data).unwrap_or(1);
+    drop(data);
+    let (width, height) = oriented_size(size, orientation);
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let orient = orientation_filter(orientation).map(|filter| format!("{},", filter)).unwrap_or_default();
+
+    let mut variants = Vec::new();
+    for variant_width in config.widths(width) {
+        let variant_height = variant_height(variant_width, (width, height));
+        for format in &config.formats {
+            job.check_cancelled()?;
+            let path = variant_path(output_dir, variant_width, *format);
+            let path_str = path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Output path contains invalid UTF-8: {:?}", path))?;
+            let filter = format!("{}scale={}:{}", orient, variant_width, variant_height);
+            let mut command = ingest::ffmpeg_command("ffmpeg");
+            command
+                .args(["-v", "error", "-y", "-noautorotate", "-i", input, "-frames:v", "1", "-vf", &filter])
+                .args(["-map_metadata", "-1"])
+                .args(format.ffmpeg_args(config.quality))
+                .arg(path_str);
+            let output = job.output(&mut command).await?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "FFmpeg failed to encode the {} {}px variant: {}",
+                    format.as_str(),
+                    variant_width,
+                    output.status.error()
+                ));
+            }
+            variants.push(ImageVariant {
+                path,
+                width: variant_width,
+                height: variant_height,
+                format: *format,
+            });
+        }
+    }
+
+    let blurhash = match config.blurhash {
+        Some(components) => match placeholder(input, &orient, components, job).await {
+            Ok(hash) => Some(hash),
+            Err(e) if crate::jobs::is_cancelled(&e) => return Err(e),
+            Err(e) => {
+                warn!(error = %e, "Failed to compute the image BlurHash");
+                None
+            }
+        },
+        None => None,
+    };
+    info!(width, height, orientation, variants = variants.len(), "Processed image");
+
+    Ok(ProcessedImage {
+        width,
+        height,
+        blurhash,
+        variants,
+    })
+}
+
+/// BlurHash of the upright image, decoded to a small RGB frame
+async fn placeholder(input: &str, orient: &str, (x, y): (u32, u32), job: &JobContext) -> anyhow::Result<String> {
+    let filter = format!("{}scale={}:{}", orient, BLURHASH_FRAME_SIZE, BLURHASH_FRAME_SIZE);
+    let output = job
+        .output(ingest::ffmpeg_command("ffmpeg").args([
+            "-v", "error", "-noautorotate", "-i", input, "-frames:v", "1", "-vf", &filter, "-f", "rawvideo",
+            "-pix_fmt", "rgb24", "-",
+        ]))
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("FFmpeg failed to decode the image: {}", output.status.error()));
+    }
+    blurhash::encode(&output.stdout, BLURHASH_FRAME_SIZE, BLURHASH_FRAME_SIZE, x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little-endian TIFF with one IFD0 entry: the orientation
+    fn tiff(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\x01\x00".to_vec();
+        tiff.extend_from_slice(&ORIENTATION_TAG.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        tiff
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        let exif = [b"Exif\0\0".as_slice(), &tiff(6)].concat();
+        // JPEG: a JFIF APP0 segment, then the APP1 Exif segment
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(&exif);
+        jpeg.extend_from_slice(&[0xFF, 0xDA]);
+        assert_eq!(exif_orientation(&jpeg), Some(6));
+
+        let png_exif = tiff(3);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&(png_exif.len() as u32).to_be_bytes());
+        png.extend_from_slice(b"eXIf");
+        png.extend_from_slice(&png_exif);
+        png.extend_from_slice(&[0; 4]);
+        assert_eq!(exif_orientation(&png), Some(3));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8 \x01\0\0\0\0\0EXIF".to_vec();
+        webp.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+        webp.extend_from_slice(&exif);
+        assert_eq!(exif_orientation(&webp), Some(6));
+        assert_eq!(exif_orientation(&tiff(8)), Some(8));
+
+        assert_eq!(exif_orientation(&[0xFF, 0xD8, 0xFF, 0xDA]), None);
+        assert_eq!(exif_orientation(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(is_supported("image/jpeg"));
+        assert!(is_supported("Image/PNG"));
+        assert!(is_supported("image/webp; charset=binary"));
+        assert!(!is_supported("image/svg+xml"));
+        assert!(!is_supported("video/mp4"));
+    }
+
+    #[test]
+    fn test_orientation() {
+        assert_eq!(orientation_filter(1), None);
+        assert_eq!(orientation_filter(3), Some("hflip,vflip"));
+        assert_eq!(orientation_filter(6), Some("transpose=1"));
+        assert_eq!(orientation_filter(8), Some("transpose=2"));
+        assert_eq!(oriented_size((4000, 3000), 6), (3000, 4000));
+        assert_eq!(oriented_size((4000, 3000), 3), (4000, 3000));
+        assert_eq!(variant_height(640, (3000, 4000)), 854);
+        assert_eq!(variant_height(320, (1000, 1)), 2);
+    }
+
+    #[test]
+    fn test_widths() {
+        let config = ImageConfig {
+            sizes: vec![1280, 320, 640, 320],
+            ..ImageConfig::default()
+        };
+        assert_eq!(config.widths(1000), vec![320, 640]);
+        assert_eq!(config.widths(200), vec![200]);
+        assert_eq!(parse_components("4x3"), Some((4, 3)));
+        assert_eq!(parse_components("10x3"), None);
+        assert_eq!(ImageFormat::parse("JPG"), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::Avif.ffmpeg_args(100)[..4], ["-c:v", "libaom-av1", "-still-picture", "1"]);
+    }
+}
@@ -0,0 +1,480 @@
+//! Untrusted-input policy for media ingestion
+//!
+//! Sources come from event payloads, so everything about them is checked
+//! before FFmpeg sees a byte:
+//! - remote URLs must be HTTP(S), pass the host allow/deny lists, and
+//!   resolve only to public addresses; the checked addresses are the ones
+//!   connected to, and every redirect hop is checked again
+//! - downloads and local files are capped in size, and probed sources in
+//!   duration
+//! - the file's magic bytes must match its declared content type, which
+//!   also keeps text formats FFmpeg would follow (HLS playlists, concat
+//!   scripts) out
+//! - FFmpeg and FFprobe may only open local files and pipes
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use reqwest::Url;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+/// Protocols FFmpeg and FFprobe may open
+pub const FFMPEG_PROTOCOL_WHITELIST: &str = "file,pipe";
+
+/// Bytes read from the start of a file to identify its container
+const SNIFF_LEN: usize = 512;
+
+/// Limits and destination rules for ingested sources
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngestPolicy {
+    /// Hosts sources may be fetched from (`example.com`, `*.example.com`);
+    /// empty allows any host that is not denied
+    pub allowed_hosts: Vec<String>,
+    pub denied_hosts: Vec<String>,
+    /// Buckets `s3://` sources may name besides the configured one
+    pub allowed_buckets: Vec<String>,
+    /// Allow private, loopback and link-local addresses (development only)
+    pub allow_private_networks: bool,
+    pub max_size: u64,
+    /// Longest accepted source, in seconds
+    pub max_duration: u64,
+    pub max_redirects: usize,
+}
+
+impl Default for IngestPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: vec![],
+            denied_hosts: vec![],
+            allowed_buckets: vec![],
+            allow_private_networks: false,
+            max_size: 20 * 1024 * 1024 * 1024,
+            max_duration: 12 * 60 * 60,
+            max_redirects: 5,
+        }
+    }
+}
+
+impl IngestPolicy {
+    /// Read `INGEST_ALLOWED_HOSTS`, `INGEST_DENIED_HOSTS` and
+    /// `INGEST_ALLOWED_BUCKETS` (comma-separated), `INGEST_ALLOW_PRIVATE_NETWORKS`, `INGEST_MAX_SIZE_MB`,
+    /// `INGEST_MAX_DURATION_SECS` and `INGEST_MAX_REDIRECTS`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let hosts = |name: &str| {
+            var(name)
+                .map(|v| {
+                    v.split(',')
+                        .map(|host| host.trim().trim_end_matches('.').to_lowercase())
+                        .filter(|host| !host.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let positive = |name: &str| var(name).and_then(|v| v.parse::<u64>().ok()).filter(|value| *value > 0);
+        Self {
+            allowed_hosts: hosts("INGEST_ALLOWED_HOSTS"),
+            denied_hosts: hosts("INGEST_DENIED_HOSTS"),
+            allowed_buckets: var("INGEST_ALLOWED_BUCKETS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|bucket| bucket.trim().to_string())
+                        .filter(|bucket| !bucket.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            allow_private_networks: var("INGEST_ALLOW_PRIVATE_NETWORKS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.allow_private_networks),
+            max_size: positive("INGEST_MAX_SIZE_MB")
+                .map(|mb| mb * 1024 * 1024)
+                .unwrap_or(defaults.max_size),
+            max_duration: positive("INGEST_MAX_DURATION_SECS").unwrap_or(defaults.max_duration),
+            max_redirects: var("INGEST_MAX_REDIRECTS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_redirects),
+        }
+    }
+
+    /// Check a URL's scheme and host before anything is fetched
+    pub fn check_url(&self, url: &Url) -> anyhow::Result<()> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(anyhow::anyhow!("Unsupported URL scheme: {}", url.scheme()));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("URL has no host: {}", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .trim_end_matches('.')
+            .to_lowercase();
+        if self.denied_hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+            return Err(anyhow::anyhow!("Host {} is denied for ingestion", host));
+        }
+        if !self.allowed_hosts.is_empty() && !self.allowed_hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+            return Err(anyhow::anyhow!("Host {} is not allowed for ingestion", host));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            self.check_address(ip)?;
+        }
+        Ok(())
+    }
+
+    /// Check the bucket of an `s3://` source: the `configured` one or one of
+    /// `allowed_buckets`, so a request can't read other buckets the
+    /// storage credentials reach
+    pub fn check_bucket(&self, bucket: &str, configured: &str) -> anyhow::Result<()> {
+        if bucket == configured || self.allowed_buckets.iter().any(|allowed| allowed == bucket) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Bucket {} is not allowed for ingestion", bucket))
+        }
+    }
+
+    /// Fail for non-public addresses unless private networks are allowed
+    pub fn check_address(&self, ip: IpAddr) -> anyhow::Result<()> {
+        if self.allow_private_networks || is_public(ip) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Address {} is not a public address", ip))
+        }
+    }
+
+    /// Resolve a checked URL's host; every address must pass
+    /// [`IngestPolicy::check_address`], so the answer can be pinned for
+    /// the connection
+    pub async fn resolve(&self, url: &Url) -> anyhow::Result<Vec<SocketAddr>> {
+        let host = url.host_str().ok_or_else(|| anyhow::anyhow!("URL has no host: {}", url))?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_start_matches('[').trim_end_matches(']'), port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", host, e))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(anyhow::anyhow!("{} did not resolve to any address", host));
+        }
+        for addr in &addrs {
+            self.check_address(addr.ip())?;
+        }
+        Ok(addrs)
+    }
+
+    pub fn check_size(&self, size: u64) -> anyhow::Result<()> {
+        if size > self.max_size {
+            return Err(anyhow::anyhow!(
+                "Source is {} bytes, over the {} byte limit",
+                size,
+                self.max_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_duration(&self, duration: u64) -> anyhow::Result<()> {
+        if duration > self.max_duration {
+            return Err(anyhow::anyhow!(
+                "Source is {} seconds long, over the {} second limit",
+                duration,
+                self.max_duration
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check a local source's size and that its contents match `content_type`
+    pub fn check_file(&self, path: &Path, content_type: &str) -> anyhow::Result<Container> {
+        let mut file = std::fs::File::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open source {:?}: {}", path, e))?;
+        self.check_size(file.metadata()?.len())?;
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+        check_content_type(&header, content_type)
+    }
+}
+
+/// Whether `host` matches `pattern`: the same name, or any subdomain for `*.name`
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+/// Whether `ip` is a globally routable unicast address
+///
+/// IPv6 addresses embedding an IPv4 address (mapped, NAT64, 6to4) are
+/// judged by that address.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_v4(v4);
+            }
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| Ipv4Addr::from(((high as u32) << 16) | low as u32);
+            match segments {
+                // NAT64 well-known prefix
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] => is_public_v4(embedded(high, low)),
+                // 6to4
+                [0x2002, high, low, ..] => is_public_v4(embedded(high, low)),
+                // Documentation
+                [0x2001, 0xdb8, ..] => false,
+                _ => {
+                    !(ip.is_unspecified()
+                        || ip.is_loopback()
+                        || ip.is_multicast()
+                        // Unique local fc00::/7 and link-local fe80::/10
+                        || segments[0] & 0xfe00 == 0xfc00
+                        || segments[0] & 0xffc0 == 0xfe80
+                        // IPv4-compatible (deprecated) ::/96
+                        || segments[..6] == [0; 6])
+                }
+            }
+        }
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || a == 0
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // Shared address space (CGNAT) 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking 198.18.0.0/15
+        || (a == 198 && (b == 18 || b == 19))
+        // Multicast and reserved 224.0.0.0/3
+        || a >= 224)
+}
+
+/// Container format identified from a file's first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// MP4, QuickTime, 3GP, M4A, AVIF
+    IsoBmff,
+    /// Matroska and WebM
+    Matroska,
+    Avi,
+    Wave,
+    Webp,
+    Ogg,
+    Flac,
+    /// MPEG audio frames (MP3)
+    MpegAudio,
+    /// ADTS AAC frames
+    Adts,
+    /// ID3-tagged audio (MP3 or ADTS AAC)
+    Id3,
+    MpegTs,
+    MpegPs,
+    Flv,
+    Asf,
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Tiff,
+}
+
+impl Container {
+    /// Content types a file in this container may be declared as
+    pub fn content_types(&self) -> &'static [&'static str] {
+        match self {
+            Container::IsoBmff => &[
+                "video/mp4", "video/quicktime", "video/x-m4v", "video/3gpp", "video/3gpp2", "audio/mp4",
+                "audio/x-m4a", "audio/m4a", "audio/aac", "image/avif",
+            ],
+            Container::Matroska => &["video/webm", "video/x-matroska", "audio/webm", "audio/x-matroska"],
+            Container::Avi => &["video/x-msvideo", "video/avi", "video/msvideo"],
+            Container::Wave => &["audio/wav", "audio/x-wav", "audio/wave", "audio/vnd.wave"],
+            Container::Webp => &["image/webp"],
+            Container::Ogg => &["audio/ogg", "video/ogg", "audio/opus"],
+            Container::Flac => &["audio/flac", "audio/x-flac"],
+            Container::MpegAudio => &["audio/mpeg", "audio/mp3"],
+            Container::Adts => &["audio/aac", "audio/x-aac", "audio/aacp"],
+            Container::Id3 => &["audio/mpeg", "audio/mp3", "audio/aac", "audio/x-aac", "audio/aacp"],
+            Container::MpegTs => &["video/mp2t"],
+            Container::MpegPs => &["video/mpeg"],
+            Container::Flv => &["video/x-flv"],
+            Container::Asf => &["video/x-ms-wmv", "video/x-ms-asf", "audio/x-ms-wma"],
+            Container::Jpeg => &["image/jpeg"],
+            Container::Png => &["image/png"],
+            Container::Gif => &["image/gif"],
+            Container::Bmp => &["image/bmp"],
+            Container::Tiff => &["image/tiff"],
+        }
+    }
+}
+
+/// Identify the container of a file starting with `header`
+pub fn sniff(header: &[u8]) -> Option<Container> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    let container = if at(4, b"ftyp") || at(4, b"moov") || at(4, b"mdat") || at(4, b"wide") || at(4, b"free") {
+        Container::IsoBmff
+    } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        Container::Matroska
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        Container::Avi
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Container::Wave
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Container::Webp
+    } else if at(0, b"OggS") {
+        Container::Ogg
+    } else if at(0, b"fLaC") {
+        Container::Flac
+    } else if at(0, b"ID3") {
+        Container::Id3
+    } else if at(0, b"FLV\x01") {
+        Container::Flv
+    } else if at(0, &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        Container::Asf
+    } else if at(0, &[0xFF, 0xD8, 0xFF]) {
+        Container::Jpeg
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        Container::Png
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Container::Gif
+    } else if at(0, b"BM") {
+        Container::Bmp
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        Container::Tiff
+    } else if at(0, &[0x47]) && at(188, &[0x47]) {
+        Container::MpegTs
+    } else if at(0, &[0, 0, 1, 0xBA]) || at(0, &[0, 0, 1, 0xB3]) {
+        Container::MpegPs
+    } else if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 {
+        // Frame sync; layer bits 00 are ADTS, anything else MPEG audio
+        if header[1] & 0x06 == 0 {
+            Container::Adts
+        } else {
+            Container::MpegAudio
+        }
+    } else {
+        return None;
+    };
+    Some(container)
+}
+
+/// Identify `header`'s container and check that `content_type` is one it
+/// may be declared as
+pub fn check_content_type(header: &[u8], content_type: &str) -> anyhow::Result<Container> {
+    let declared = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    let container = sniff(header)
+        .ok_or_else(|| anyhow::anyhow!("Source is not a recognized media file (declared {})", declared))?;
+    if !container.content_types().contains(&declared.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Source content ({:?}) does not match its declared content type {}",
+            container,
+            declared
+        ));
+    }
+    Ok(container)
+}
+
+/// `program` (`ffmpeg` or `ffprobe`) limited to opening local files and
+/// pipes
+///
+/// The whitelist applies to the first input, which is the only one the
+/// processor passes.
+pub fn ffmpeg_command(program: &str) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(program);
+    command.args(["-protocol_whitelist", FFMPEG_PROTOCOL_WHITELIST]);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_public_addresses() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "224.0.0.1", "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe", "2002:c0a8:0101::1", "2001:db8::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_check_url() {
+        let policy = IngestPolicy {
+            allowed_hosts: vec!["*.example.com".to_string(), "media.test".to_string()],
+            denied_hosts: vec!["internal.example.com".to_string()],
+            ..IngestPolicy::default()
+        };
+        let check = |url: &str| policy.check_url(&Url::parse(url).unwrap());
+        assert!(check("https://cdn.example.com/a.mp4").is_ok());
+        assert!(check("http://MEDIA.test./a.mp4").is_ok());
+        assert!(check("https://example.com/a.mp4").is_err());
+        assert!(check("https://internal.example.com/a.mp4").is_err());
+        assert!(check("https://evilexample.com/a.mp4").is_err());
+        assert!(check("file:///etc/passwd").is_err());
+
+        let open = IngestPolicy::default();
+        assert!(open.check_url(&Url::parse("http://169.254.169.254/latest/meta-data").unwrap()).is_err());
+        assert!(open.check_url(&Url::parse("http://[::1]:8080/a.mp4").unwrap()).is_err());
+        assert!(open.check_url(&Url::parse("https://93.184.215.14/a.mp4").unwrap()).is_ok());
+        let development = IngestPolicy {
+            allow_private_networks: true,
+            ..IngestPolicy::default()
+        };
+        assert!(development.check_url(&Url::parse("http://127.0.0.1/a.mp4").unwrap()).is_ok());
+
+        let buckets = IngestPolicy {
+            allowed_buckets: vec!["uploads".to_string()],
+            ..IngestPolicy::default()
+        };
+        assert!(buckets.check_bucket("media", "media").is_ok());
+        assert!(buckets.check_bucket("uploads", "media").is_ok());
+        assert!(buckets.check_bucket("billing-exports", "media").is_err());
+    }
+
+    #[test]
+    fn test_content_sniffing() {
+        let mp4 = b"\0\0\0\x20ftypisom\0\0\x02\0";
+        assert_eq!(check_content_type(mp4, "video/mp4").unwrap(), Container::IsoBmff);
+        assert_eq!(check_content_type(mp4, "audio/mp4; codecs=\"mp4a.40.2\"").unwrap(), Container::IsoBmff);
+        assert!(check_content_type(mp4, "image/png").is_err());
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt ").unwrap(), Container::Wave);
+        assert_eq!(sniff(&[0xFF, 0xFB, 0x90, 0x00]).unwrap(), Container::MpegAudio);
+        assert_eq!(sniff(&[0xFF, 0xF1, 0x50, 0x80]).unwrap(), Container::Adts);
+        assert!(check_content_type(b"ID3\x04\0", "audio/mpeg").is_ok());
+
+        // Playlists and concat scripts would make FFmpeg open other files
+        assert!(check_content_type(b"#EXTM3U\n#EXT-X-VERSION:3\n", "video/mp4").is_err());
+        assert!(check_content_type(b"ffconcat version 1.0\nfile '/etc/passwd'\n", "video/mp4").is_err());
+        assert!(check_content_type(b"", "video/mp4").is_err());
+    }
+}
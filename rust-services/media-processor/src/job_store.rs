@@ -0,0 +1,342 @@
+//! Durable media job records
+//!
+//! A job moves through the [`JobStage`]s in order. Its record is rewritten
+//! after every completed stage and every transcoded rendition, and its
+//! working directory (downloaded source and generated outputs) lives next to
+//! the record. A restarted worker loads the records left by unfinished jobs
+//! with [`JobStore::pending`] and resumes each one after its last completed
+//! stage instead of starting over.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::dash::Rendition;
+use crate::media_info::MediaInfo;
+use crate::processor::ProcessingResult;
+use anyhow::{Context, Result};
+use crate::storage::UploadedMedia;
+use armoricore_types::schemas::MediaUploadedPayload;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Store directory used when `MEDIA_JOB_STORE_PATH` is not set
+pub const DEFAULT_JOB_STORE_PATH: &str = "./media-jobs";
+
+/// Times a job is started (including resumes) before it is given up
+pub const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// Last completed step of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    /// Accepted, nothing done yet
+    Received,
+    /// Source file is available locally
+    Downloaded,
+    /// Source metadata was read
+    Probed,
+    /// Every output was generated
+    Transcoded,
+    /// Outputs are in object storage
+    Uploaded,
+    /// `media.ready` was published
+    Published,
+}
+
+/// Persisted state of one media job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub media_id: Uuid,
+    /// Event that started the job
+    pub payload: MediaUploadedPayload,
+    pub stage: JobStage,
+    /// Times the job was started, including resumes
+    pub attempts: u32,
+    /// Set once the job is `Probed`
+    #[serde(default)]
+    pub probe: Option<MediaInfo>,
+    /// Renditions transcoded so far, by id
+    #[serde(default)]
+    pub renditions: BTreeMap<String, Rendition>,
+    /// Set once the job is `Transcoded`
+    #[serde(default)]
+    pub result: Option<ProcessingResult>,
+    /// Set once the job is `Uploaded`
+    #[serde(default)]
+    pub uploaded: Option<UploadedMedia>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl JobRecord {
+    pub fn new(payload: MediaUploadedPayload) -> Self {
+        Self {
+            media_id: payload.media_id,
+            payload,
+            stage: JobStage::Received,
+            attempts: 0,
+            probe: None,
+            renditions: BTreeMap::new(),
+            result: None,
+            uploaded: None,
+            updated_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Directory of job records and working directories
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    root: PathBuf,
+}
+
+impl JobStore {
+    /// Open (creating it if needed) the store at `root`
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create job store: {}", root.display()))?;
+        Ok(Self { root })
+    }
+
+    /// Open the store at `MEDIA_JOB_STORE_PATH`
+    pub fn from_env() -> Result<Self> {
+        let root = std::env::var("MEDIA_JOB_STORE_PATH")
+            .unwrap_or_else(|_| DEFAULT_JOB_STORE_PATH.to_string());
+        Self::open(root)
+    }
+
+    /// Working directory of a job's source and outputs
+    pub fn work_dir(&self, media_id: &Uuid) -> PathBuf {
+        self.root.join(media_id.to_string())
+    }
+
+    fn record_path(&self, media_id: &Uuid) -> PathBuf {
+        self.root.join(format!("{}.json", media_id))
+    }
+
+    /// Record of a job, if one is stored
+    pub fn load(&self, media_id: &Uuid) -> Result<Option<JobRecord>> {
+        Self::read(&self.record_path(media_id))
+    }
+
+    fn read(path: &Path) -> Result<Option<JobRecord>> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(
+                serde_json::from_slice(&data)
+                    .with_context(|| format!("Invalid job record: {}", path.display()))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write a record, replacing the previous one atomically
+    pub fn save(&self, record: &JobRecord) -> Result<()> {
+        let path = self.record_path(&record.media_id);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(record)?)
+            .with_context(|| format!("Failed to write job record: {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Delete a job's record and working directory
+    pub fn remove(&self, media_id: &Uuid) -> Result<()> {
+        let work_dir = self.work_dir(media_id);
+        if work_dir.exists() {
+            std::fs::remove_dir_all(&work_dir)?;
+        }
+        match std::fs::remove_file(self.record_path(media_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records of the jobs that did not finish, oldest first
+    ///
+    /// Unreadable records are skipped with a warning.
+    pub fn pending(&self) -> Result<Vec<JobRecord>> {
+        let mut records = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match Self::read(&path) {
+                Ok(Some(record)) => records.push(record),
+                Ok(None) => {}
+                Err(e) => warn!(path = %path.display(), error = %e, "Skipping job record"),
+            }
+        }
+        records.sort_by_key(|record| record.updated_at);
+        Ok(records)
+    }
+}
+
+/// Shared handle to a running job's record
+///
+/// Every update is written to the store before it returns.
+#[derive(Debug, Clone)]
+pub struct JobJournal {
+    store: JobStore,
+    record: Arc<Mutex<JobRecord>>,
+}
+
+impl JobJournal {
+    pub fn new(store: JobStore, record: JobRecord) -> Self {
+        Self {
+            store,
+            record: Arc::new(Mutex::new(record)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JobRecord> {
+        self.record.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Snapshot of the record
+    pub fn record(&self) -> JobRecord {
+        self.lock().clone()
+    }
+
+    pub fn stage(&self) -> JobStage {
+        self.lock().stage
+    }
+
+    /// Apply `change` to the record and persist it
+    pub fn update(&self, change: impl FnOnce(&mut JobRecord)) -> Result<()> {
+        let mut record = self.lock();
+        change(&mut record);
+        record.updated_at = chrono::Utc::now();
+        self.store.save(&record)
+    }
+
+    /// Mark `stage` completed; stages never move backwards
+    pub fn advance(&self, stage: JobStage) -> Result<()> {
+        self.update(|record| record.stage = record.stage.max(stage))
+    }
+
+    pub fn probe(&self) -> Option<MediaInfo> {
+        self.lock().probe.clone()
+    }
+
+    /// Store the source metadata and mark the job `Probed`
+    pub fn set_probe(&self, probe: &MediaInfo) -> Result<()> {
+        self.update(|record| {
+            record.probe = Some(probe.clone());
+            record.stage = record.stage.max(JobStage::Probed);
+        })
+    }
+
+    /// A rendition transcoded by an earlier attempt
+    pub fn rendition(&self, id: &str) -> Option<Rendition> {
+        self.lock().renditions.get(id).cloned()
+    }
+
+    pub fn record_rendition(&self, rendition: &Rendition) -> Result<()> {
+        self.update(|record| {
+            record.renditions.insert(rendition.id.clone(), rendition.clone());
+        })
+    }
+
+    /// Stop reusing transcoded renditions, e.g. before their files are
+    /// rewritten in place
+    pub fn forget_renditions(&self) -> Result<()> {
+        self.update(|record| record.renditions.clear())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> MediaUploadedPayload {
+        MediaUploadedPayload {
+            media_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            file_path: "s3://bucket/source.mp4".to_string(),
+            content_type: "video/mp4".to_string(),
+            file_size: 1024,
+            metadata: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_journal_persists_stages_and_renditions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = JobStore::open(dir.path()).unwrap();
+        let record = JobRecord::new(payload());
+        let media_id = record.media_id;
+        let journal = JobJournal::new(store.clone(), record);
+
+        journal.advance(JobStage::Downloaded).unwrap();
+        journal
+            .set_probe(
+                &MediaInfo::from_ffprobe_json(r#"{"format": {"format_name": "mp4", "duration": "60.0"}}"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        journal
+            .record_rendition(&Rendition {
+                id: "720p".to_string(),
+                kind: crate::dash::RenditionKind::Video {
+                    width: 1280,
+                    height: 720,
+                    audio: vec!["audio_aac".to_string()],
+                    range: crate::dash::VideoRange::Sdr,
+                },
+                codecs: "avc1.64001f".to_string(),
+                bandwidth: 2_500_000,
+                playlist: store.work_dir(&media_id).join("720p/playlist.m3u8"),
+            })
+            .unwrap();
+        // Completed stages are never undone
+        journal.advance(JobStage::Downloaded).unwrap();
+
+        let loaded = store.load(&media_id).unwrap().unwrap();
+        assert_eq!(loaded.stage, JobStage::Probed);
+        assert_eq!(loaded.probe.unwrap().duration_secs(), 60);
+        assert_eq!(loaded.renditions["720p"].bandwidth, 2_500_000);
+
+        journal.forget_renditions().unwrap();
+        assert!(store.load(&media_id).unwrap().unwrap().renditions.is_empty());
+    }
+
+    #[test]
+    fn test_pending_lists_records_until_removed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = JobStore::open(dir.path()).unwrap();
+        let first = JobRecord::new(payload());
+        let mut second = JobRecord::new(payload());
+        second.updated_at = first.updated_at + chrono::Duration::seconds(1);
+        store.save(&first).unwrap();
+        store.save(&second).unwrap();
+        std::fs::create_dir_all(store.work_dir(&first.media_id)).unwrap();
+        std::fs::write(dir.path().join("garbage.json"), b"{").unwrap();
+
+        let pending: Vec<Uuid> = store.pending().unwrap().iter().map(|r| r.media_id).collect();
+        assert_eq!(pending, vec![first.media_id, second.media_id]);
+
+        store.remove(&first.media_id).unwrap();
+        assert!(!store.work_dir(&first.media_id).exists());
+        assert!(store.load(&first.media_id).unwrap().is_none());
+        assert_eq!(store.pending().unwrap().len(), 1);
+        // Removing twice is fine
+        store.remove(&first.media_id).unwrap();
+    }
+}
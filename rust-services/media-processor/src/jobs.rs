@@ -0,0 +1,377 @@
+//! In-flight processing jobs, cancellation and timeouts
+//!
+//! Every media job gets a [`CancellationToken`] registered under its media
+//! ID while it runs. A `media.cancel_requested` event or the job's
+//! wall-clock timeout fires the token; [`JobContext::run`] then kills the
+//! FFmpeg child it is waiting on and the job fails with [`JobCancelled`].
+//! Each child also runs under the job's [`SandboxConfig`] limits.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::dash::Rendition;
+use crate::job_store::JobJournal;
+use crate::progress::ProgressReporter;
+use crate::sandbox::{ProcessStatus, SandboxConfig, SandboxedChild, StderrTail};
+use std::collections::HashMap;
+use std::future::Future;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Wall-clock limit of one job when `MEDIA_JOB_TIMEOUT_SECS` is not set
+pub const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// Read `MEDIA_JOB_TIMEOUT_SECS`; 0 disables the timeout
+pub fn job_timeout_from_env() -> Option<Duration> {
+    match std::env::var("MEDIA_JOB_TIMEOUT_SECS").ok().and_then(|s| s.parse().ok()) {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(DEFAULT_JOB_TIMEOUT),
+    }
+}
+
+/// Error returned by work interrupted through its cancellation token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobCancelled;
+
+impl std::fmt::Display for JobCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Job cancelled")
+    }
+}
+
+impl std::error::Error for JobCancelled {}
+
+/// Whether `error` was caused by a cancelled job
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<JobCancelled>().is_some()
+}
+
+/// Per-job state handed down to every FFmpeg invocation
+#[derive(Debug, Clone, Default)]
+pub struct JobContext {
+    pub cancel: CancellationToken,
+    pub progress: Option<ProgressReporter>,
+    /// Durable record of the job, when it can be resumed
+    pub journal: Option<JobJournal>,
+    /// FFmpeg audio filter applied to every audio encode (loudness normalization)
+    pub audio_filter: Option<String>,
+    /// Limits every FFmpeg process of the job runs under
+    pub sandbox: SandboxConfig,
+}
+
+impl JobContext {
+    pub fn new(cancel: CancellationToken, progress: Option<ProgressReporter>) -> Self {
+        Self {
+            cancel,
+            progress,
+            journal: None,
+            audio_filter: None,
+            sandbox: SandboxConfig::default(),
+        }
+    }
+
+    /// Record completed stages and renditions in `journal`
+    pub fn with_journal(mut self, journal: JobJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// A rendition transcoded by an earlier attempt whose files still exist
+    pub fn completed_rendition(&self, id: &str) -> Option<Rendition> {
+        self.journal
+            .as_ref()
+            .and_then(|journal| journal.rendition(id))
+            .filter(|rendition| rendition.playlist.exists())
+    }
+
+    /// Record a transcoded rendition so a resumed job can reuse it
+    pub fn record_rendition(&self, rendition: &Rendition) -> anyhow::Result<()> {
+        match &self.journal {
+            Some(journal) => journal.record_rendition(rendition),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail with [`JobCancelled`] if the job was cancelled
+    pub fn check_cancelled(&self) -> anyhow::Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(JobCancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Run `command` to completion, passing each stdout line to `on_line`
+    ///
+    /// The child runs under the job's [`SandboxConfig`] and is killed if the
+    /// job is cancelled (or the returned future is dropped) before it exits.
+    /// Running into a timeout or resource limit fails with a [`ProcessError`].
+    pub async fn run(&self, command: &mut Command, mut on_line: impl FnMut(&str)) -> anyhow::Result<ProcessStatus> {
+        self.check_cancelled()?;
+        let mut process = self.sandbox.spawn(command.stdout(Stdio::piped()))?;
+        let stdout = process.child.stdout.take();
+
+        let read_stdout = async {
+            if let Some(stdout) = stdout {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Some(line) = lines.next_line().await? {
+                    on_line(&line);
+                }
+            }
+            Ok(())
+        };
+        self.supervise(process, read_stdout).await
+    }
+
+    /// Run `command` to completion, passing its raw stdout to `on_data` in chunks
+    ///
+    /// For binary output such as decoded PCM; limits and cancellation
+    /// behave as in [`JobContext::run`].
+    pub async fn run_binary(&self, command: &mut Command, mut on_data: impl FnMut(&[u8])) -> anyhow::Result<ProcessStatus> {
+        self.check_cancelled()?;
+        let mut process = self.sandbox.spawn(command.stdout(Stdio::piped()))?;
+        let stdout = process.child.stdout.take();
+
+        let read_stdout = async {
+            if let Some(mut stdout) = stdout {
+                let mut buffer = vec![0u8; 64 * 1024];
+                loop {
+                    let read = stdout.read(&mut buffer).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    on_data(&buffer[..read]);
+                }
+            }
+            Ok(())
+        };
+        self.supervise(process, read_stdout).await
+    }
+
+    /// Run `command` to completion, collecting its stdout
+    ///
+    /// Limits and cancellation behave as in [`JobContext::run`].
+    pub async fn output(&self, command: &mut Command) -> anyhow::Result<ProcessOutput> {
+        self.check_cancelled()?;
+        let mut process = self.sandbox.spawn(command.stdout(Stdio::piped()))?;
+        let stdout = process.child.stdout.take();
+
+        let mut collected = Vec::new();
+        let read_stdout = async {
+            if let Some(mut stdout) = stdout {
+                stdout.read_to_end(&mut collected).await?;
+            }
+            Ok(())
+        };
+        let status = self.supervise(process, read_stdout).await?;
+        Ok(ProcessOutput {
+            status,
+            stdout: collected,
+        })
+    }
+
+    /// Wait for `process` while `read_stdout` drains its stdout, keeping the
+    /// end of its stderr; kills it on cancellation or at its timeout
+    async fn supervise(
+        &self,
+        mut process: SandboxedChild,
+        read_stdout: impl Future<Output = std::io::Result<()>>,
+    ) -> anyhow::Result<ProcessStatus> {
+        let stderr_pipe = process.stderr();
+        let timeout = process.timeout();
+        let mut stderr = StderrTail::default();
+
+        let completion = async {
+            let (stdout, stderr) = tokio::join!(read_stdout, stderr.read_from(stderr_pipe));
+            stdout?;
+            stderr?;
+            process.wait().await
+        };
+        let deadline = async {
+            match timeout {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+        let status = tokio::select! {
+            status = completion => Some(status),
+            _ = self.cancel.cancelled() => None,
+            _ = deadline => None,
+        };
+
+        let Some(status) = status else {
+            let _ = process.child.kill().await;
+            if self.cancel.is_cancelled() {
+                return Err(JobCancelled.into());
+            }
+            return Err(process.timed_out(&stderr).into());
+        };
+        Ok(process.finish(status?, stderr)?)
+    }
+}
+
+/// Exit status and stdout of a process run by [`JobContext::output`]
+#[derive(Debug, Clone)]
+pub struct ProcessOutput {
+    pub status: ProcessStatus,
+    pub stdout: Vec<u8>,
+}
+
+/// Jobs currently being processed, by media ID
+#[derive(Debug, Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<HashMap<Uuid, (Uuid, CancellationToken)>>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a job; it stays in flight until the guard is dropped
+    pub fn start(&self, media_id: Uuid) -> JobGuard {
+        let job_id = Uuid::new_v4();
+        let cancel = CancellationToken::new();
+        self.lock().insert(media_id, (job_id, cancel.clone()));
+        JobGuard {
+            registry: self.clone(),
+            media_id,
+            job_id,
+            cancel,
+        }
+    }
+
+    /// Cancel the in-flight job of `media_id`; returns whether one was running
+    pub fn cancel(&self, media_id: &Uuid) -> bool {
+        match self.lock().get(media_id) {
+            Some((_, cancel)) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Media IDs of the in-flight jobs
+    pub fn in_flight(&self) -> Vec<Uuid> {
+        self.lock().keys().copied().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, (Uuid, CancellationToken)>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Registration of an in-flight job
+pub struct JobGuard {
+    registry: JobRegistry,
+    media_id: Uuid,
+    job_id: Uuid,
+    cancel: CancellationToken,
+}
+
+impl JobGuard {
+    /// Token fired when the job is cancelled or times out
+    pub fn token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        let mut jobs = self.registry.lock();
+        // A newer job for the same media may have replaced this one
+        if jobs.get(&self.media_id).is_some_and(|(job_id, _)| *job_id == self.job_id) {
+            jobs.remove(&self.media_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::{Failure, ProcessError};
+
+    #[test]
+    fn test_registry_tracks_and_cancels_jobs() {
+        let registry = JobRegistry::new();
+        let media_id = Uuid::new_v4();
+        assert!(!registry.cancel(&media_id));
+
+        let job = registry.start(media_id);
+        assert_eq!(registry.in_flight(), vec![media_id]);
+        assert!(registry.cancel(&media_id));
+        assert!(job.token().is_cancelled());
+
+        // A restarted job outlives the guard of the one it replaced
+        let restarted = registry.start(media_id);
+        drop(job);
+        assert_eq!(registry.in_flight(), vec![media_id]);
+        assert!(!restarted.token().is_cancelled());
+        drop(restarted);
+        assert!(registry.in_flight().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_kills_running_child() {
+        let job = JobContext::default();
+        let cancel = job.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        });
+
+        let started = std::time::Instant::now();
+        let error = job
+            .run(Command::new("sleep").arg("30"), |_| {})
+            .await
+            .unwrap_err();
+        assert!(is_cancelled(&error));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(is_cancelled(&job.run(&mut Command::new("true"), |_| {}).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_running_child() {
+        let mut job = JobContext::default();
+        job.sandbox.timeout = Some(Duration::from_millis(100));
+
+        let started = std::time::Instant::now();
+        let error = job
+            .run(Command::new("sh").args(["-c", "echo waiting >&2; exec sleep 30"]), |_| {})
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<ProcessError>().unwrap();
+        assert_eq!(error.failure, Failure::Timeout(Duration::from_millis(100)));
+        assert_eq!(error.stderr, "waiting");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(job.run(&mut Command::new("true"), |_| {}).await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_run_passes_stdout_lines() {
+        let mut lines = Vec::new();
+        let status = JobContext::default()
+            .run(Command::new("printf").arg("a=1\\nprogress=end\\n"), |line| lines.push(line.to_string()))
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(lines, vec!["a=1", "progress=end"]);
+    }
+}
@@ -0,0 +1,345 @@
+//! HLS key delivery and clear-key license endpoint
+//!
+//! Serves the raw 16-byte content keys referenced by `EXT-X-KEY` URIs at
+//! `/hls/keys/{media_id}/{key_index}`, and W3C Clear Key licenses for DRM
+//! packaged media at `POST /drm/clearkey/{media_id}/license`. Players must
+//! present a JWT, either as a `Bearer` token or a `token` query parameter,
+//! whose `media_id` claim (or `media_ids` array) names the requested media.
+//! Tokens are verified with the key store key named by
+//! `HLS_KEY_TOKEN_KEY_ID` (default `jwt.secret`).
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::encryption::ContentEncryption;
+use armoricore_keys::{JwtService, KeyError, KeyStore};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Route of the key delivery endpoint
+pub const KEY_PATH: &str = "/hls/keys/:media_id/:key_index";
+
+/// Route of the clear-key license endpoint
+pub const LICENSE_PATH: &str = "/drm/clearkey/:media_id/license";
+
+/// Key store key used to verify player tokens when `HLS_KEY_TOKEN_KEY_ID` is unset
+pub const DEFAULT_TOKEN_KEY_ID: &str = "jwt.secret";
+
+struct KeyServerState {
+    key_store: Arc<KeyStore>,
+    encryption: ContentEncryption,
+    jwt: JwtService,
+    token_key_id: String,
+}
+
+/// Build the key delivery and license routes for `key_store`
+pub fn key_router(key_store: Arc<KeyStore>, token_key_id: impl Into<String>) -> Router {
+    let state = KeyServerState {
+        encryption: ContentEncryption::new(Some((*key_store).clone())),
+        jwt: JwtService::new(key_store.clone()),
+        key_store,
+        token_key_id: token_key_id.into(),
+    };
+    Router::new()
+        .route(KEY_PATH, get(get_key))
+        .route(LICENSE_PATH, post(get_license))
+        .with_state(Arc::new(state))
+}
+
+/// Key verifying player tokens: `HLS_KEY_TOKEN_KEY_ID`, else [`DEFAULT_TOKEN_KEY_ID`]
+pub fn token_key_id_from_env() -> String {
+    std::env::var("HLS_KEY_TOKEN_KEY_ID").unwrap_or_else(|_| DEFAULT_TOKEN_KEY_ID.to_string())
+}
+
+/// Key delivery server
+pub struct KeyServer {
+    port: u16,
+    router: Router,
+}
+
+impl KeyServer {
+    /// Create a key server for `key_store`, reading `HLS_KEY_TOKEN_KEY_ID`
+    pub fn new(port: u16, key_store: Arc<KeyStore>) -> Self {
+        Self {
+            port,
+            router: key_router(key_store, token_key_id_from_env()),
+        }
+    }
+
+    /// Also serve `router` (e.g. [`crate::playback::playback_router`])
+    pub fn with_routes(mut self, router: Router) -> Self {
+        self.router = self.router.merge(router);
+        self
+    }
+
+    /// Start the key server
+    pub async fn start(self) -> anyhow::Result<()> {
+        let addr = format!("0.0.0.0:{}", self.port);
+        let listener = TcpListener::bind(&addr).await
+            .map_err(|e| anyhow::anyhow!("Failed to bind key server to {}: {}", addr, e))?;
+
+        info!(port = self.port, "HLS key server started");
+
+        axum::serve(listener, self.router.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(|e| anyhow::anyhow!("Key server error: {}", e))
+    }
+}
+
+async fn get_key(
+    State(state): State<Arc<KeyServerState>>,
+    Path((media_id, key_index)): Path<(Uuid, u32)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(status) = authorize(&state.jwt, &state.token_key_id, &media_id, &query, &headers).await {
+        return status.into_response();
+    }
+
+    let key_id = ContentEncryption::hls_key_id(&media_id, key_index);
+    match state.key_store.get_key(&key_id).await {
+        Ok(key) => (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            key,
+        )
+            .into_response(),
+        Err(KeyError::NotFound(_)) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            warn!(key_id = key_id, error = %e, "Failed to read content key");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Answer a W3C Clear Key license request (`{"kids": [...], "type": ...}`)
+/// with the content keys of `media_id` among the requested key IDs
+async fn get_license(
+    State(state): State<Arc<KeyServerState>>,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Json(request): Json<Value>,
+) -> Response {
+    if let Err(status) = authorize(&state.jwt, &state.token_key_id, &media_id, &query, &headers).await {
+        return status.into_response();
+    }
+    let Some(kids) = request.get("kids").and_then(Value::as_array) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let key = match state.encryption.drm_key(&media_id).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            warn!(media_id = %media_id, error = %e, "Failed to read DRM content key");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let kid = URL_SAFE_NO_PAD.encode(key.kid);
+    let keys: Vec<Value> = kids
+        .iter()
+        .filter(|requested| requested.as_str() == Some(kid.as_str()))
+        .map(|_| json!({ "kty": "oct", "kid": kid, "k": URL_SAFE_NO_PAD.encode(key.key) }))
+        .collect();
+    if keys.is_empty() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(json!({ "keys": keys, "type": "temporary" })),
+    )
+        .into_response()
+}
+
+/// Verify the request's player token and that it grants `media_id`,
+/// returning the token
+pub(crate) async fn authorize<'a>(
+    jwt: &JwtService,
+    token_key_id: &str,
+    media_id: &Uuid,
+    query: &'a HashMap<String, String>,
+    headers: &'a HeaderMap,
+) -> Result<&'a str, StatusCode> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| query.get("token").map(String::as_str))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let claims = match jwt.verify(&token_key_id.to_string(), token).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            warn!(media_id = %media_id, error = %e, "Rejected key request token");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
+    if !grants_media(&claims, media_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(token)
+}
+
+/// Whether token claims authorize playback of `media_id`
+fn grants_media(claims: &Map<String, Value>, media_id: &Uuid) -> bool {
+    let media_id = media_id.to_string();
+    claims.get("media_id").and_then(Value::as_str) == Some(media_id.as_str())
+        || claims
+            .get("media_ids")
+            .and_then(Value::as_array)
+            .is_some_and(|ids| ids.iter().any(|id| id.as_str() == Some(media_id.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn request(base: &str, path: &str, token: Option<&str>) -> (StatusCode, Vec<u8>) {
+        let mut request = reqwest::Client::new().get(format!("{}{}", base, path));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        (status, response.bytes().await.unwrap().to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_key_delivery_requires_matching_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(dir.path(), Some(&[5u8; 32]))
+            .await
+            .unwrap();
+        let key_store = Arc::new(KeyStore::new(Arc::new(backend)));
+        key_store
+            .store_jwt_secret(&DEFAULT_TOKEN_KEY_ID.to_string(), "player-secret")
+            .await
+            .unwrap();
+
+        let media_id = Uuid::new_v4();
+        let key = ContentEncryption::new(Some((*key_store).clone()))
+            .hls_content_key(&media_id, 0)
+            .await
+            .unwrap();
+
+        let jwt = JwtService::new(key_store.clone());
+        let token_key = DEFAULT_TOKEN_KEY_ID.to_string();
+        let allowed = jwt
+            .issue(&token_key, &json!({ "media_id": media_id.to_string() }), Duration::from_secs(300))
+            .await
+            .unwrap();
+        let other = jwt
+            .issue(&token_key, &json!({ "media_ids": [Uuid::new_v4().to_string()] }), Duration::from_secs(300))
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = key_router(key_store, DEFAULT_TOKEN_KEY_ID);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let uri = format!("/hls/keys/{}/0", media_id);
+
+        assert_eq!(request(&base, &uri, None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(request(&base, &uri, Some("not.a.jwt")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(request(&base, &uri, Some(&other)).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(request(&base, &uri, Some(&allowed)).await, (StatusCode::OK, key.to_vec()));
+
+        let missing = format!("/hls/keys/{}/7", media_id);
+        assert_eq!(request(&base, &missing, Some(&allowed)).await.0, StatusCode::NOT_FOUND);
+        let query = format!("{}?token={}", uri, allowed);
+        assert_eq!(request(&base, &query, None).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_clearkey_license_returns_requested_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(dir.path(), Some(&[6u8; 32]))
+            .await
+            .unwrap();
+        let key_store = Arc::new(KeyStore::new(Arc::new(backend)));
+        key_store
+            .store_jwt_secret(&DEFAULT_TOKEN_KEY_ID.to_string(), "player-secret")
+            .await
+            .unwrap();
+
+        let media_id = Uuid::new_v4();
+        let key = ContentEncryption::new(Some((*key_store).clone()))
+            .drm_content_key(&media_id)
+            .await
+            .unwrap();
+        let token = JwtService::new(key_store.clone())
+            .issue(
+                &DEFAULT_TOKEN_KEY_ID.to_string(),
+                &json!({ "media_id": media_id.to_string() }),
+                Duration::from_secs(300),
+            )
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/drm/clearkey/{}/license", listener.local_addr().unwrap(), media_id);
+        let app = key_router(key_store, DEFAULT_TOKEN_KEY_ID);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+        let kid = URL_SAFE_NO_PAD.encode(key.kid);
+
+        let response = client
+            .post(&url)
+            .json(&json!({ "kids": [kid], "type": "temporary" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let response = client
+            .post(&url)
+            .bearer_auth(&token)
+            .json(&json!({ "kids": [kid], "type": "temporary" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let license: Value = response.json().await.unwrap();
+        assert_eq!(license["keys"][0]["kid"], kid);
+        assert_eq!(license["keys"][0]["k"], URL_SAFE_NO_PAD.encode(key.key));
+        assert_eq!(license["keys"][0]["kty"], "oct");
+
+        let response = client
+            .post(&url)
+            .bearer_auth(&token)
+            .json(&json!({ "kids": [URL_SAFE_NO_PAD.encode([0u8; 16])] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
@@ -0,0 +1,311 @@
+//! Per-title encoding ladder
+//!
+//! The default ladder gives every rung a fixed bitrate for its resolution,
+//! tuned for typical content. With per-title encoding enabled, a few short
+//! windows of the source are encoded at a constant CRF before transcoding;
+//! the bitrate the encoder needed, relative to what typical content needs,
+//! is the title's complexity. Every rung's bitrate is scaled by it (within
+//! [`MIN_SCALE`]..=[`MAX_SCALE`]), so a static slideshow gets a fraction of
+//! the bitrate of a sports clip at the same resolution.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::info;
+
+/// CRF of the analysis encodes
+pub const ANALYSIS_CRF: u32 = 23;
+
+/// Height the analysis encodes are scaled to
+pub const ANALYSIS_HEIGHT: u32 = 720;
+
+/// Bitrate typical content needs at [`ANALYSIS_CRF`] and [`ANALYSIS_HEIGHT`];
+/// the default ladder's bitrates correspond to this complexity
+pub const REFERENCE_SAMPLE_KBPS: f64 = 2000.0;
+
+/// Lowest factor applied to the default bitrates
+pub const MIN_SCALE: f64 = 0.4;
+
+/// Highest factor applied to the default bitrates
+pub const MAX_SCALE: f64 = 1.5;
+
+/// Per-title encoding settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct LadderConfig {
+    /// Analyze the source and scale the ladder; `false` keeps the defaults
+    pub per_title: bool,
+    /// Number of windows encoded during analysis
+    pub sample_count: u32,
+    /// Length of each window in seconds
+    pub sample_seconds: u32,
+}
+
+impl Default for LadderConfig {
+    fn default() -> Self {
+        Self {
+            per_title: false,
+            sample_count: 3,
+            sample_seconds: 4,
+        }
+    }
+}
+
+impl LadderConfig {
+    /// Read `PER_TITLE_ENCODING`, `PER_TITLE_SAMPLE_COUNT` and `PER_TITLE_SAMPLE_SECONDS`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        Self {
+            per_title: var("PER_TITLE_ENCODING")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.per_title),
+            sample_count: var("PER_TITLE_SAMPLE_COUNT")
+                .and_then(|v| v.parse().ok())
+                .filter(|count| *count > 0)
+                .unwrap_or(defaults.sample_count),
+            sample_seconds: var("PER_TITLE_SAMPLE_SECONDS")
+                .and_then(|v| v.parse().ok())
+                .filter(|seconds| *seconds > 0)
+                .unwrap_or(defaults.sample_seconds),
+        }
+    }
+}
+
+/// One resolution of the ladder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LadderRung {
+    pub resolution: String,
+    pub width: u32,
+    pub height: u32,
+    /// Target bitrate in kbps
+    pub bitrate_kbps: u32,
+    /// Bitrate of the default ladder in kbps
+    pub default_bitrate_kbps: u32,
+}
+
+/// Outcome of the complexity analysis
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComplexityAnalysis {
+    /// Bitrate of each analysis encode in kbps
+    pub sample_kbps: Vec<f64>,
+    /// Mean sample bitrate relative to [`REFERENCE_SAMPLE_KBPS`]
+    pub complexity: f64,
+}
+
+impl ComplexityAnalysis {
+    pub fn from_samples(sample_kbps: Vec<f64>) -> Option<Self> {
+        if sample_kbps.is_empty() {
+            return None;
+        }
+        let mean = sample_kbps.iter().sum::<f64>() / sample_kbps.len() as f64;
+        Some(Self {
+            complexity: mean / REFERENCE_SAMPLE_KBPS,
+            sample_kbps,
+        })
+    }
+}
+
+/// Bitrate ladder chosen for a title
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncodingLadder {
+    pub rungs: Vec<LadderRung>,
+    /// Set when the ladder was built from a complexity analysis
+    pub analysis: Option<ComplexityAnalysis>,
+    /// Factor applied to the default bitrates
+    pub scale: f64,
+    /// Why these bitrates were chosen
+    pub reasoning: String,
+}
+
+impl EncodingLadder {
+    /// The default ladder, unchanged
+    pub fn fixed(defaults: Vec<LadderRung>, reasoning: impl Into<String>) -> Self {
+        Self {
+            rungs: defaults,
+            analysis: None,
+            scale: 1.0,
+            reasoning: reasoning.into(),
+        }
+    }
+
+    /// Scale the default ladder by the analyzed complexity
+    pub fn per_title(defaults: Vec<LadderRung>, analysis: ComplexityAnalysis) -> Self {
+        let scale = analysis.complexity.clamp(MIN_SCALE, MAX_SCALE);
+        let mean_kbps = analysis.complexity * REFERENCE_SAMPLE_KBPS;
+        let mut reasoning = format!(
+            "{} sample encode(s) at CRF {} and {}p averaged {:.0} kbps against {:.0} kbps for typical content \
+             (complexity {:.2}); default bitrates scaled by {:.2}",
+            analysis.sample_kbps.len(),
+            ANALYSIS_CRF,
+            ANALYSIS_HEIGHT,
+            mean_kbps,
+            REFERENCE_SAMPLE_KBPS,
+            analysis.complexity,
+            scale
+        );
+        if scale != analysis.complexity {
+            reasoning.push_str(&format!(" (limited to {}..={})", MIN_SCALE, MAX_SCALE));
+        }
+
+        let rungs = defaults
+            .into_iter()
+            .map(|rung| LadderRung {
+                bitrate_kbps: (rung.default_bitrate_kbps as f64 * scale).round() as u32,
+                ..rung
+            })
+            .collect();
+        Self {
+            rungs,
+            analysis: Some(analysis),
+            scale,
+            reasoning,
+        }
+    }
+
+    pub fn rung(&self, resolution: &str) -> Option<&LadderRung> {
+        self.rungs.iter().find(|rung| rung.resolution == resolution)
+    }
+}
+
+/// Start and length in seconds of each analysis window, spread evenly
+/// over the source and away from its start and end
+pub fn sample_windows(duration: f64, count: u32, seconds: u32) -> Vec<(f64, f64)> {
+    let seconds = seconds as f64;
+    if duration <= seconds * count as f64 {
+        return vec![(0.0, duration.max(seconds))];
+    }
+    (1..=count)
+        .map(|i| {
+            let center = duration * i as f64 / (count + 1) as f64;
+            ((center - seconds / 2.0).max(0.0), seconds)
+        })
+        .collect()
+}
+
+/// Encode the analysis windows of `input` and measure their bitrates
+///
+/// Sample files are written to `work_dir` and removed afterwards.
+pub async fn analyze(
+    input: &Path,
+    duration: u64,
+    work_dir: &Path,
+    config: &LadderConfig,
+    job: &JobContext,
+) -> anyhow::Result<ComplexityAnalysis> {
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let scale = format!("scale=-2:{}", ANALYSIS_HEIGHT);
+    let crf = ANALYSIS_CRF.to_string();
+
+    let mut sample_kbps = Vec::new();
+    for (index, (start, length)) in sample_windows(duration as f64, config.sample_count, config.sample_seconds)
+        .into_iter()
+        .enumerate()
+    {
+        let sample_path = work_dir.join(format!("complexity_sample_{}.mp4", index));
+        let sample = sample_path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Sample path contains invalid UTF-8: {:?}", sample_path))?;
+        let start_arg = format!("{:.3}", start);
+        let length_arg = format!("{:.3}", length);
+
+        let status = job
+            .run(
+                ingest::ffmpeg_command("ffmpeg").args([
+                    "-y", "-ss", &start_arg, "-t", &length_arg, "-i", input, "-map", "0:v:0", "-an", "-vf",
+                    &scale, "-c:v", "libx264", "-preset", "veryfast", "-crf", &crf, "-f", "mp4", sample,
+                ]),
+                |_| {},
+            )
+            .await?;
+        let size = tokio::fs::metadata(&sample_path).await.map(|m| m.len()).unwrap_or(0);
+        let _ = tokio::fs::remove_file(&sample_path).await;
+        if !status.success() || size == 0 {
+            continue;
+        }
+
+        // The last window may be cut short by the end of the source
+        let encoded_seconds = length.min(duration as f64 - start).max(1.0);
+        sample_kbps.push(size as f64 * 8.0 / 1000.0 / encoded_seconds);
+    }
+
+    let analysis = ComplexityAnalysis::from_samples(sample_kbps)
+        .ok_or_else(|| anyhow::anyhow!("No complexity sample could be encoded"))?;
+    info!(
+        samples = analysis.sample_kbps.len(),
+        complexity = analysis.complexity,
+        "Analyzed source complexity"
+    );
+    Ok(analysis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<LadderRung> {
+        [("1080p", 1920, 1080, 5000), ("720p", 1280, 720, 2500), ("360p", 640, 360, 600)]
+            .into_iter()
+            .map(|(resolution, width, height, bitrate)| LadderRung {
+                resolution: resolution.to_string(),
+                width,
+                height,
+                bitrate_kbps: bitrate,
+                default_bitrate_kbps: bitrate,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_windows() {
+        assert_eq!(
+            sample_windows(100.0, 3, 4),
+            vec![(23.0, 4.0), (48.0, 4.0), (73.0, 4.0)]
+        );
+        // Short sources are analyzed whole
+        assert_eq!(sample_windows(10.0, 3, 4), vec![(0.0, 10.0)]);
+    }
+
+    #[test]
+    fn test_per_title_ladder_scales_default_bitrates() {
+        let analysis = ComplexityAnalysis::from_samples(vec![600.0, 1000.0]).unwrap();
+        assert_eq!(analysis.complexity, 0.4);
+
+        let ladder = EncodingLadder::per_title(defaults(), analysis);
+        assert_eq!(ladder.scale, 0.4);
+        assert_eq!(ladder.rung("1080p").unwrap().bitrate_kbps, 2000);
+        assert_eq!(ladder.rung("360p").unwrap().bitrate_kbps, 240);
+        assert_eq!(ladder.rung("360p").unwrap().default_bitrate_kbps, 600);
+        assert!(ladder.reasoning.contains("averaged 800 kbps"));
+        assert!(!ladder.reasoning.contains("limited"));
+    }
+
+    #[test]
+    fn test_per_title_scale_is_limited() {
+        let busy = ComplexityAnalysis::from_samples(vec![8000.0]).unwrap();
+        let ladder = EncodingLadder::per_title(defaults(), busy);
+        assert_eq!(ladder.scale, MAX_SCALE);
+        assert_eq!(ladder.rung("720p").unwrap().bitrate_kbps, 3750);
+        assert!(ladder.reasoning.contains("limited"));
+
+        let still = ComplexityAnalysis::from_samples(vec![50.0]).unwrap();
+        assert_eq!(EncodingLadder::per_title(defaults(), still).scale, MIN_SCALE);
+        assert!(ComplexityAnalysis::from_samples(vec![]).is_none());
+    }
+}
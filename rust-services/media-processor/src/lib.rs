@@ -2,9 +2,26 @@
 //!
 //! This library provides media processing functionality including:
 //! - Video transcoding to multiple bitrates
-//! - HLS segmentation
-//! - Thumbnail generation
-//! - Remote file download
+//! - Per-title bitrate ladders from a source complexity analysis
+//! - HDR renditions with tone-mapped SDR fallbacks
+//! - CMAF segmentation with HLS playlists and DASH manifests
+//! - Alternative audio tracks per source language and segmented WebVTT subtitles
+//! - HLS segment encryption (AES-128, SAMPLE-AES) with key delivery
+//! - Common Encryption (cenc/cbcs) DRM packaging with a clear-key license endpoint
+//! - Transcoding progress reporting from FFmpeg `-progress` output
+//! - Job cancellation and timeouts
+//! - Sandboxed FFmpeg processes (rlimits, cgroup v2, network isolation, timeouts) with structured errors
+//! - Durable job records for resuming interrupted jobs
+//! - Source probing into a structured media description
+//! - EBU R128 two-pass loudness normalization of audio renditions
+//! - Thumbnail generation and scrubbing sprite sheets with a WebVTT track
+//! - Poster frame selection from scene changes and frame quality
+//! - Waveform peak data of audio-only media at several zoom levels
+//! - Image processing into oriented, metadata-free size variants with a BlurHash placeholder
+//! - Remote file download under an ingestion policy (SSRF-safe destinations, size and duration limits, content sniffing)
+//! - Pluggable object stores (S3-compatible, local filesystem, memory) with configurable public URLs
+//! - Streaming multipart uploads to object storage
+//! - Signed, expiring playback URLs (S3 presigned, CDN token auth) with HLS playlist rewriting
 // Copyright 2025 Francisco F. Pinochet
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -20,15 +37,44 @@
 // limitations under the License.
 
 
+pub mod blurhash;
+pub mod cenc;
+pub mod dash;
 pub mod downloader;
+pub mod drm;
 pub mod encryption;
+pub mod hdr;
+pub mod hls_encryption;
+pub mod ingest;
+pub mod images;
+pub mod job_store;
+pub mod jobs;
+pub mod key_server;
+pub mod ladder;
+pub mod local_store;
+pub mod loudness;
+pub mod media_info;
+pub mod multipart;
+pub mod object_store;
+pub mod playback;
+pub mod posters;
 pub mod processor;
+pub mod progress;
+pub mod sprites;
 pub mod storage;
+pub mod subtitles;
+pub mod waveform;
 pub mod worker;
 pub mod retry;
+pub mod s3_store;
+pub mod sandbox;
+pub mod url_signing;
+pub mod urls;
 
 // Re-export encryption types for convenience
+pub use drm::DrmConfig;
 pub use encryption::{ContentEncryption, EncryptionMetadata};
+pub use hls_encryption::{HlsEncryptionConfig, HlsEncryptionMethod};
 // Re-export codec types for convenience
-pub use processor::{VideoCodec, AudioCodec};
+pub use processor::{MediaProcessor, VideoCodec, AudioCodec};
 
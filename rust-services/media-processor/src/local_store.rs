@@ -0,0 +1,124 @@
+//! Local filesystem object store
+//!
+//! Objects are plain files under a root directory, laid out by key, so a
+//! web server pointed at the root can serve them. Meant for development and
+//! on-prem installs without object storage.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::object_store::{validate_key, ObjectStore};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Object store writing files under a root directory
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Store rooted at `LOCAL_STORAGE_PATH` (default `./media-store`)
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("LOCAL_STORAGE_PATH").unwrap_or_else(|_| "./media-store".to_string()))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// File holding the object under `key`
+    pub fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalStore {
+    async fn put_file(&self, local_path: &Path, key: &str, _content_type: &str) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Copy to a temporary name first so readers never see a partial file
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        tokio::fs::copy(local_path, &partial)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to copy {:?} to {:?}: {}", local_path, partial, e))?;
+        tokio::fs::rename(&partial, &path).await?;
+        info!(key = key, path = %path.display(), "Stored file in local object store");
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> anyhow::Result<u64> {
+        let path = self.path(key)?;
+        tokio::fs::copy(&path, destination)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read object {}: {}", key, e))
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn store_type(&self) -> &str {
+        "local"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("master.m3u8");
+        std::fs::write(&source, "#EXTM3U\n").unwrap();
+
+        let store = LocalStore::new(dir.path().join("store"));
+        store.put_file(&source, "media/1/master.m3u8", "application/vnd.apple.mpegurl").await.unwrap();
+        assert_eq!(std::fs::read_to_string(store.root().join("media/1/master.m3u8")).unwrap(), "#EXTM3U\n");
+        assert!(!store.root().join("media/1/master.m3u8.partial").exists());
+
+        let copy = dir.path().join("copy.m3u8");
+        assert_eq!(store.get_file("media/1/master.m3u8", &copy).await.unwrap(), 8);
+
+        store.delete("media/1/master.m3u8").await.unwrap();
+        store.delete("media/1/master.m3u8").await.unwrap();
+        assert!(store.get_file("media/1/master.m3u8", &copy).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_store_rejects_escaping_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("file");
+        std::fs::write(&source, "x").unwrap();
+
+        let store = LocalStore::new(dir.path().join("store"));
+        assert!(store.put_file(&source, "../outside", "text/plain").await.is_err());
+        assert!(!dir.path().join("outside").exists());
+    }
+}
@@ -0,0 +1,254 @@
+//! EBU R128 loudness normalization
+//!
+//! Two-pass `loudnorm`: the first pass decodes the source's primary audio
+//! stream and measures its integrated loudness, true peak and loudness
+//! range; the second pass, applied to every encode of that stream, feeds those
+//! measurements back to `loudnorm` so it can normalize with a single linear
+//! gain instead of dynamic compression whenever the target allows.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use armoricore_types::schemas::LoudnessInfo;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::info;
+
+/// Loudness normalization settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoudnessConfig {
+    pub enabled: bool,
+    /// Integrated loudness target in LUFS
+    pub target: f64,
+    /// Maximum true peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range target in LU
+    pub range: f64,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: -16.0,
+            true_peak: -1.5,
+            range: 11.0,
+        }
+    }
+}
+
+impl LoudnessConfig {
+    /// Read `LOUDNESS_NORMALIZATION`, `LOUDNESS_TARGET_LUFS`,
+    /// `LOUDNESS_TRUE_PEAK` and `LOUDNESS_RANGE`
+    ///
+    /// Values outside what `loudnorm` accepts fall back to the defaults.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let within = |name: &str, min: f64, max: f64, default: f64| {
+            var(name)
+                .and_then(|v| v.parse().ok())
+                .filter(|value: &f64| (min..=max).contains(value))
+                .unwrap_or(default)
+        };
+        Self {
+            enabled: var("LOUDNESS_NORMALIZATION")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.enabled),
+            target: within("LOUDNESS_TARGET_LUFS", -70.0, -5.0, defaults.target),
+            true_peak: within("LOUDNESS_TRUE_PEAK", -9.0, 0.0, defaults.true_peak),
+            range: within("LOUDNESS_RANGE", 1.0, 50.0, defaults.range),
+        }
+    }
+
+    fn targets(&self) -> String {
+        format!("I={}:TP={}:LRA={}", self.target, self.true_peak, self.range)
+    }
+}
+
+/// First-pass `loudnorm` measurement of a source
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoudnessMeasurement {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub range: f64,
+    /// Gating threshold in LUFS
+    pub threshold: f64,
+    /// Offset gain in LU
+    pub offset: f64,
+}
+
+/// The JSON block `loudnorm=print_format=json` logs; values are strings
+#[derive(Debug, Deserialize)]
+struct LoudnormReport {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+impl LoudnessMeasurement {
+    /// Parse the report `loudnorm` logs at the end of FFmpeg's stderr
+    ///
+    /// Fails for silent sources, whose loudness is reported as `-inf`.
+    pub fn from_loudnorm_log(log: &str) -> anyhow::Result<Self> {
+        let start = log
+            .rfind('{')
+            .ok_or_else(|| anyhow::anyhow!("No loudnorm report in FFmpeg output"))?;
+        let end = log[start..]
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("Truncated loudnorm report"))?;
+        let report: LoudnormReport = serde_json::from_str(&log[start..=start + end])?;
+
+        let value = |name: &str, value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| anyhow::anyhow!("Unusable loudnorm {}: {}", name, value))
+        };
+        Ok(Self {
+            integrated: value("input_i", &report.input_i)?,
+            true_peak: value("input_tp", &report.input_tp)?,
+            range: value("input_lra", &report.input_lra)?,
+            threshold: value("input_thresh", &report.input_thresh)?,
+            offset: value("target_offset", &report.target_offset)?,
+        })
+    }
+
+    /// Second-pass filter normalizing to `config`'s targets
+    ///
+    /// `loudnorm` upsamples to 192 kHz, so the output is resampled back to
+    /// `sample_rate`.
+    pub fn filter(&self, config: &LoudnessConfig, sample_rate: u32) -> String {
+        format!(
+            "loudnorm={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true,aresample={}",
+            config.targets(),
+            self.integrated,
+            self.true_peak,
+            self.range,
+            self.threshold,
+            self.offset,
+            sample_rate
+        )
+    }
+}
+
+/// A source's measured loudness and the target it was normalized to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    pub measured: LoudnessMeasurement,
+    /// Integrated loudness target in LUFS
+    pub target: f64,
+}
+
+impl Loudness {
+    /// Loudness as published with `media.ready`
+    pub fn summary(&self) -> LoudnessInfo {
+        LoudnessInfo {
+            integrated: self.measured.integrated,
+            true_peak: self.measured.true_peak,
+            range: self.measured.range,
+            target: self.target,
+        }
+    }
+}
+
+/// Measure the loudness of `input`'s audio stream `stream` (first pass)
+pub async fn measure(
+    input: &Path,
+    stream: u32,
+    config: &LoudnessConfig,
+    job: &JobContext,
+) -> anyhow::Result<LoudnessMeasurement> {
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let filter = format!("loudnorm={}:print_format=json", config.targets());
+    let stream_map = format!("0:{}", stream);
+
+    let output = job
+        .output(ingest::ffmpeg_command("ffmpeg").args([
+            "-hide_banner", "-nostats", "-i", input, "-map", &stream_map, "-vn", "-af", &filter, "-f", "null", "-",
+        ]))
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("Loudness measurement failed: {}", output.status.error()));
+    }
+
+    let measurement = LoudnessMeasurement::from_loudnorm_log(&output.status.stderr)?;
+    info!(
+        integrated = measurement.integrated,
+        true_peak = measurement.true_peak,
+        range = measurement.range,
+        target = config.target,
+        "Measured source loudness"
+    );
+    Ok(measurement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"[Parsed_loudnorm_0 @ 0x55d0c8a0f2c0]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-16.58",
+	"output_tp" : "-1.50",
+	"output_lra" : "14.78",
+	"output_thresh" : "-27.71",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.58"
+}
+"#;
+
+    #[test]
+    fn test_parse_loudnorm_report() {
+        let log = format!("Input #0, mov,mp4 {{...}}\nStream mapping:\n{}", REPORT);
+        let measurement = LoudnessMeasurement::from_loudnorm_log(&log).unwrap();
+        assert_eq!(measurement, LoudnessMeasurement {
+            integrated: -27.61,
+            true_peak: -4.47,
+            range: 18.06,
+            threshold: -39.2,
+            offset: 0.58,
+        });
+
+        // Digital silence has no measurable loudness
+        let silent = REPORT.replace("\"-27.61\"", "\"-inf\"");
+        assert!(LoudnessMeasurement::from_loudnorm_log(&silent).is_err());
+        assert!(LoudnessMeasurement::from_loudnorm_log("Conversion failed!").is_err());
+    }
+
+    #[test]
+    fn test_second_pass_filter() {
+        let measurement = LoudnessMeasurement::from_loudnorm_log(REPORT).unwrap();
+        assert_eq!(
+            measurement.filter(&LoudnessConfig::default(), 48000),
+            "loudnorm=I=-16:TP=-1.5:LRA=11:measured_I=-27.61:measured_TP=-4.47:\
+             measured_LRA=18.06:measured_thresh=-39.2:offset=0.58:linear=true,aresample=48000"
+        );
+    }
+}
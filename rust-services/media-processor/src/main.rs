@@ -2,9 +2,11 @@
 //!
 //! Consumes `media.uploaded` events from the message bus and processes media files:
 //! - Transcodes video to multiple bitrates
-//! - Creates HLS segments
+//! - Creates CMAF segments with HLS playlists and DASH manifests
+//! - Encrypts HLS segments and serves their content keys (optional)
 //! - Generates thumbnails
 //! - Uploads processed files to object storage
+//! - Resumes interrupted jobs from their last completed stage
 //! - Publishes `media.ready` events
 // Copyright 2025 Francisco F. Pinochet
 //
@@ -21,18 +23,19 @@
 // limitations under the License.
 
 
-mod downloader;
-mod encryption;
 mod health;
-mod processor;
-mod retry;
-mod storage;
-mod worker;
 
 use anyhow::Result;
 use armoricore_config::AppConfig;
-use armoricore_keys::{init_key_store, service_integration::*};
+use armoricore_keys::unseal_http::{serve_unseal, UnsealServerConfig};
+use armoricore_keys::{init_key_store_for, service_integration::*, KeyStore, ServiceIdentity, SEAL_KEY_ID};
 use armoricore_logging::init_console_logging;
+use media_processor::job_store::JobStore;
+use media_processor::key_server::{token_key_id_from_env, KeyServer};
+use media_processor::playback::{playback_router, Playback};
+use media_processor::storage::ObjectStorage;
+use media_processor::url_signing::{SigningConfig, UrlSigner};
+use media_processor::{worker, DrmConfig, HlsEncryptionConfig};
 use message_bus_client::nats::NatsClient;
 use std::sync::Arc;
 use tokio::signal;
@@ -49,42 +52,79 @@ async fn main() -> Result<()> {
     let config = AppConfig::from_env()
         .map_err(|e| anyhow::anyhow!("Failed to load configuration: {}", e))?;
 
+    // Start health check server in background
+    let health_port = std::env::var("HEALTH_CHECK_PORT")
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(8080);
+
+    let key_store = match init_key_store_for(ServiceIdentity::MEDIA_PROCESSOR, None).await {
+        Ok(key_store) => Some(key_store),
+        Err(e) => {
+            warn!(error = %e, "Key store unavailable");
+            None
+        }
+    };
+
+    let health_server = health::HealthServer::new(health_port);
+    let health_handle = tokio::spawn(async move {
+        if let Err(e) = health_server.start().await {
+            error!(error = %e, "Health check server error");
+        }
+    });
+
+    // A sealed store is unsealed through its own listener, which has to be up
+    // before credentials are read
+    let unseal_handle = match key_store {
+        Some(ref key_store) if is_sealable(key_store).await? => {
+            let unseal_config = UnsealServerConfig::from_env()?;
+            let key_store = key_store.clone();
+            Some(tokio::spawn(async move {
+                if let Err(e) = serve_unseal(key_store, unseal_config).await {
+                    error!(error = %e, "Unseal server error");
+                }
+            }))
+        }
+        _ => None,
+    };
+
     // Try to get object storage config from key store, fallback to environment
     let object_storage_config = if let Some(ref storage_config) = config.object_storage {
         Some(storage_config.clone())
-    } else {
-        // Try key store
-        if let Ok(key_store) = init_key_store(None).await {
-            if let (Some(access_key), Some(secret_key)) = (
-                get_object_storage_access_key(&key_store).await,
-                get_object_storage_secret_key(&key_store).await,
-            ) {
-                info!("Using object storage credentials from key store");
-                Some(armoricore_config::ObjectStorageConfig {
-                    endpoint: std::env::var("OBJECT_STORAGE_ENDPOINT")
-                        .unwrap_or_else(|_| "https://storage.akamai.com".to_string()),
-                    access_key,
-                    secret_key,
-                    bucket: std::env::var("OBJECT_STORAGE_BUCKET")
-                        .unwrap_or_else(|_| "armoricore-media".to_string()),
-                    region: std::env::var("OBJECT_STORAGE_REGION").ok(),
-                })
-            } else {
-                warn!("Object storage keys not found in key store, checking environment variables");
-                None
-            }
+    } else if let Some(ref key_store) = key_store {
+        // A sealed store has to be unsealed before credentials can be read
+        key_store
+            .wait_until_unsealed(std::time::Duration::from_secs(1))
+            .await;
+
+        if let (Some(access_key), Some(secret_key)) = (
+            get_object_storage_access_key(key_store).await,
+            get_object_storage_secret_key(key_store).await,
+        ) {
+            info!("Using object storage credentials from key store");
+            Some(armoricore_config::ObjectStorageConfig {
+                endpoint: std::env::var("OBJECT_STORAGE_ENDPOINT")
+                    .unwrap_or_else(|_| "https://storage.akamai.com".to_string()),
+                access_key,
+                secret_key,
+                bucket: std::env::var("OBJECT_STORAGE_BUCKET")
+                    .unwrap_or_else(|_| "armoricore-media".to_string()),
+                region: std::env::var("OBJECT_STORAGE_REGION").ok(),
+            })
         } else {
+            warn!("Object storage keys not found in key store, checking environment variables");
             None
         }
+    } else {
+        None
     };
 
-    let object_storage_config = match object_storage_config {
-        Some(config) => config,
-        None => {
+    // The S3 backend needs the configuration; the local backend does not
+    let storage = match ObjectStorage::from_env(object_storage_config.clone()) {
+        Ok(storage) => storage,
+        Err(e) => {
             error!("Object storage configuration is required for media processing");
-            return Err(anyhow::anyhow!(
-                "Missing OBJECT_STORAGE_* environment variables or key store keys"
-            ));
+            return Err(e);
         }
     };
 
@@ -93,19 +133,6 @@ async fn main() -> Result<()> {
             "Configuration loaded"
         );
 
-        // Start health check server in background
-        let health_port = std::env::var("HEALTH_CHECK_PORT")
-            .ok()
-            .and_then(|p| p.parse::<u16>().ok())
-            .unwrap_or(8080);
-        
-        let health_server = health::HealthServer::new(health_port);
-        let health_handle = tokio::spawn(async move {
-            if let Err(e) = health_server.start().await {
-                error!(error = %e, "Health check server error");
-            }
-        });
-
         // Connect to message bus
         let message_bus = NatsClient::new(
             config.message_bus_url(),
@@ -116,11 +143,68 @@ async fn main() -> Result<()> {
 
         info!("Connected to message bus");
 
+    // HLS encryption and DRM need the key store for content keys and their delivery
+    let hls_encryption = HlsEncryptionConfig::from_env()?;
+    let drm = DrmConfig::from_env()?;
+    if hls_encryption.is_enabled() && drm.is_enabled() {
+        return Err(anyhow::anyhow!("HLS_ENCRYPTION and MEDIA_DRM cannot be enabled together"));
+    }
+    // Signed playback URLs are served by the key server as well
+    let signing = SigningConfig::from_env()?;
+    let signer = match key_store {
+        Some(ref key_store) => {
+            UrlSigner::from_key_store(&signing, key_store, object_storage_config.as_ref()).await?
+        }
+        None if signing.is_enabled() => {
+            return Err(anyhow::anyhow!("PLAYBACK_URL_SIGNING requires a key store"));
+        }
+        None => None,
+    };
+    let key_server_handle = if hls_encryption.is_enabled() || drm.is_enabled() || signer.is_some() {
+        let key_store = key_store.clone().ok_or_else(|| {
+            anyhow::anyhow!("HLS_ENCRYPTION and MEDIA_DRM require a key store")
+        })?;
+        let key_server_port = std::env::var("HLS_KEY_SERVER_PORT")
+            .ok()
+            .and_then(|p| p.parse::<u16>().ok())
+            .unwrap_or(8081);
+        let mut key_server = KeyServer::new(key_server_port, key_store.clone());
+        if let Some(signer) = signer {
+            let store = storage
+                .store()
+                .ok_or_else(|| anyhow::anyhow!("PLAYBACK_URL_SIGNING requires an object store"))?;
+            let playback = Playback {
+                store,
+                urls: storage.urls().clone(),
+                signer,
+                trusted_proxies: signing.trusted_proxies.clone(),
+            };
+            key_server = key_server.with_routes(playback_router(key_store, token_key_id_from_env(), playback));
+            info!(method = ?signing.method, "Signing playback URLs");
+        }
+        Some(tokio::spawn(async move {
+            if let Err(e) = key_server.start().await {
+                error!(error = %e, "HLS key server error");
+            }
+        }))
+    } else {
+        None
+    };
+
     // Create worker
     let worker = worker::MediaWorker::new(
         Arc::new(message_bus),
+        storage,
         object_storage_config,
+        key_store.as_deref().cloned(),
+        hls_encryption,
+        drm,
+        JobStore::from_env()?,
     );
+    let worker = match signing.playback_base_url {
+        Some(ref base_url) if signing.is_enabled() => worker.with_playback_base_url(base_url),
+        _ => worker,
+    };
 
     // Start processing events
     info!("Starting event processing");
@@ -152,9 +236,31 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Cancel health check server
+    // Cancel health check, unseal and key servers
     health_handle.abort();
+    if let Some(handle) = unseal_handle {
+        handle.abort();
+    }
+    if let Some(handle) = key_server_handle {
+        handle.abort();
+    }
     info!("Media Processor stopped");
 
     Ok(())
 }
+
+/// Whether `key_store` runs in sealed mode and this service may unseal it
+///
+/// A store that is sealed but not unsealable under the access policy could
+/// never start, so that is an error.
+async fn is_sealable(key_store: &KeyStore) -> Result<bool> {
+    match key_store.seal_status().await {
+        Ok(status) => Ok(status.is_some()),
+        Err(e) if key_store.is_sealed().await => Err(anyhow::anyhow!(
+            "Key store is sealed but the access policy does not grant seal on {}: {}",
+            SEAL_KEY_ID,
+            e
+        )),
+        Err(_) => Ok(false),
+    }
+}
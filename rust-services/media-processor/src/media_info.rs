@@ -0,0 +1,569 @@
+//! Source media probing
+//!
+//! One `ffprobe -show_format -show_streams -show_chapters -of json` pass is
+//! parsed into a [`MediaInfo`]: every stream with its codec, profile and
+//! level, video frame rate, rotation, color description and HDR metadata,
+//! audio channel layout, language and title tags, plus chapters and
+//! container tags. FFprobe prints most numbers as strings; values that are
+//! missing or can't be parsed are left as `None`.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use anyhow::{Context, Result};
+use armoricore_types::schemas::{AudioTrackSummary, MediaInfoSummary, VideoSummary};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Properties of a media file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub format: FormatInfo,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+}
+
+/// Container properties
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormatInfo {
+    /// FFmpeg demuxer names (e.g. "mov,mp4,m4a,3gp,3g2,mj2")
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Overall bitrate in bits per second
+    pub bit_rate: Option<u64>,
+    /// File size in bytes
+    pub size: Option<u64>,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Other,
+}
+
+/// One stream of the container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    /// Codec level as reported by FFmpeg (e.g. 41 for H.264 level 4.1)
+    pub level: Option<i64>,
+    pub bit_rate: Option<u64>,
+    pub duration: Option<f64>,
+    /// ISO 639-2 language tag; `None` when missing or "und"
+    pub language: Option<String>,
+    pub title: Option<String>,
+    /// Marked as the default stream of its kind
+    pub default: bool,
+    pub forced: bool,
+    pub tags: BTreeMap<String, String>,
+    pub video: Option<VideoStreamInfo>,
+    pub audio: Option<AudioStreamInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoStreamInfo {
+    /// Coded width and height, before rotation
+    pub width: u32,
+    pub height: u32,
+    pub pix_fmt: Option<String>,
+    pub frame_rate: Option<f64>,
+    /// Clockwise rotation in degrees (0, 90, 180 or 270)
+    pub rotation: u32,
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_space: Option<String>,
+    pub color_range: Option<String>,
+    pub hdr: Option<HdrFormat>,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light_level: Option<ContentLightLevel>,
+}
+
+impl VideoStreamInfo {
+    /// Width and height as displayed, with rotation applied
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.rotation % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HdrFormat {
+    /// PQ transfer with static metadata
+    Hdr10,
+    /// PQ transfer with SMPTE 2094-40 dynamic metadata
+    Hdr10Plus,
+    Hlg,
+    DolbyVision,
+}
+
+impl HdrFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HdrFormat::Hdr10 => "hdr10",
+            HdrFormat::Hdr10Plus => "hdr10_plus",
+            HdrFormat::Hlg => "hlg",
+            HdrFormat::DolbyVision => "dolby_vision",
+        }
+    }
+}
+
+/// SMPTE ST 2086 mastering display color volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MasteringDisplay {
+    /// CIE 1931 xy chromaticity of the red, green and blue primaries
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white_point: (f64, f64),
+    /// Luminance in cd/m²
+    pub min_luminance: f64,
+    pub max_luminance: f64,
+}
+
+/// CTA-861.3 content light level, in cd/m²
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentLightLevel {
+    pub max_content: u32,
+    pub max_average: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioStreamInfo {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    /// FFmpeg channel layout name (e.g. "stereo", "5.1(side)")
+    pub channel_layout: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChapterInfo {
+    pub id: i64,
+    /// Start and end in seconds
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+impl MediaInfo {
+    /// Parse the JSON output of ffprobe
+    pub fn from_ffprobe_json(json: &str) -> Result<Self> {
+        let root: Value = serde_json::from_str(json).context("Invalid ffprobe output")?;
+        let format = root
+            .get("format")
+            .ok_or_else(|| anyhow::anyhow!("ffprobe output has no format section"))?;
+        let streams = root
+            .get("streams")
+            .and_then(Value::as_array)
+            .map(|streams| streams.iter().map(parse_stream).collect())
+            .unwrap_or_default();
+        let chapters = root
+            .get("chapters")
+            .and_then(Value::as_array)
+            .map(|chapters| chapters.iter().map(parse_chapter).collect())
+            .unwrap_or_default();
+
+        Ok(Self {
+            format: FormatInfo {
+                format_name: string(format, "format_name").unwrap_or_default(),
+                format_long_name: string(format, "format_long_name"),
+                duration: number(format, "duration"),
+                bit_rate: number(format, "bit_rate"),
+                size: number(format, "size"),
+                tags: tags(format),
+            },
+            streams,
+            chapters,
+        })
+    }
+
+    /// Duration in whole seconds, from the container or the longest stream
+    pub fn duration_secs(&self) -> u64 {
+        self.format
+            .duration
+            .or_else(|| self.streams.iter().filter_map(|s| s.duration).reduce(f64::max))
+            .unwrap_or(0.0) as u64
+    }
+
+    /// First stream of `kind` marked default, or else the first of `kind`
+    fn primary(&self, kind: StreamKind) -> Option<&StreamInfo> {
+        let mut streams = self.streams.iter().filter(|s| s.kind == kind);
+        let first = streams.clone().next();
+        streams.find(|s| s.default).or(first)
+    }
+
+    /// Primary video stream; attached cover art is not video
+    pub fn video_stream(&self) -> Option<&StreamInfo> {
+        self.primary(StreamKind::Video)
+    }
+
+    pub fn audio_stream(&self) -> Option<&StreamInfo> {
+        self.primary(StreamKind::Audio)
+    }
+
+    pub fn streams_of(&self, kind: StreamKind) -> impl Iterator<Item = &StreamInfo> {
+        self.streams.iter().filter(move |s| s.kind == kind)
+    }
+
+    /// Summary published with `media.ready`
+    pub fn summary(&self) -> MediaInfoSummary {
+        let video = self.video_stream().and_then(|stream| {
+            let video = stream.video.as_ref()?;
+            let (width, height) = video.display_size();
+            Some(VideoSummary {
+                codec: stream.codec_name.clone().unwrap_or_default(),
+                profile: stream.profile.clone(),
+                width,
+                height,
+                frame_rate: video.frame_rate.map(|fps| (fps * 1000.0).round() / 1000.0),
+                rotation: video.rotation,
+                hdr: video.hdr.map(|hdr| hdr.as_str().to_string()),
+            })
+        });
+        let audio_tracks = self
+            .streams_of(StreamKind::Audio)
+            .map(|stream| AudioTrackSummary {
+                codec: stream.codec_name.clone().unwrap_or_default(),
+                channels: stream.audio.as_ref().and_then(|a| a.channels),
+                channel_layout: stream.audio.as_ref().and_then(|a| a.channel_layout.clone()),
+                sample_rate: stream.audio.as_ref().and_then(|a| a.sample_rate),
+                language: stream.language.clone(),
+            })
+            .collect();
+        let subtitle_languages = self
+            .streams_of(StreamKind::Subtitle)
+            .map(|stream| stream.language.clone().unwrap_or_else(|| "und".to_string()))
+            .collect();
+
+        MediaInfoSummary {
+            container: self.format.format_name.clone(),
+            duration: self.format.duration.unwrap_or(self.duration_secs() as f64),
+            bit_rate: self.format.bit_rate,
+            video,
+            audio_tracks,
+            subtitle_languages,
+            chapters: self.chapters.len(),
+        }
+    }
+}
+
+/// Probe `input` with a single ffprobe call
+pub async fn probe(input: &Path, job: &JobContext) -> Result<MediaInfo> {
+    let output = job
+        .output(
+            ingest::ffmpeg_command("ffprobe")
+                .args(["-v", "error", "-show_format", "-show_streams", "-show_chapters", "-of", "json"])
+                .arg(input),
+        )
+        .await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("ffprobe failed: {}", output.status.error()));
+    }
+    MediaInfo::from_ffprobe_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn string(value: &Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(str::to_string)
+}
+
+/// A number that ffprobe may print as a JSON number or a string
+fn number<T: std::str::FromStr>(value: &Value, key: &str) -> Option<T> {
+    match value.get(key)? {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A rational such as "30000/1001" or "35400/50000"
+fn rational(value: &Value, key: &str) -> Option<f64> {
+    let text = match value.get(key)? {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+    match text.split_once('/') {
+        Some((num, den)) => {
+            let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+            (den != 0.0).then(|| num / den)
+        }
+        None => text.parse().ok(),
+    }
+}
+
+fn tags(value: &Value) -> BTreeMap<String, String> {
+    value
+        .get("tags")
+        .and_then(Value::as_object)
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn side_data<'a>(stream: &'a Value, side_data_type: &str) -> Option<&'a Value> {
+    stream
+        .get("side_data_list")?
+        .as_array()?
+        .iter()
+        .find(|data| data.get("side_data_type").and_then(Value::as_str) == Some(side_data_type))
+}
+
+fn parse_stream(stream: &Value) -> StreamInfo {
+    let tags = tags(stream);
+    let disposition = |key: &str| {
+        stream
+            .get("disposition")
+            .and_then(|d| d.get(key))
+            .and_then(Value::as_i64)
+            == Some(1)
+    };
+    let kind = match stream.get("codec_type").and_then(Value::as_str) {
+        // Cover art is reported as a video stream
+        Some("video") if disposition("attached_pic") => StreamKind::Attachment,
+        Some("video") => StreamKind::Video,
+        Some("audio") => StreamKind::Audio,
+        Some("subtitle") => StreamKind::Subtitle,
+        Some("data") => StreamKind::Data,
+        Some("attachment") => StreamKind::Attachment,
+        _ => StreamKind::Other,
+    };
+
+    StreamInfo {
+        index: number(stream, "index").unwrap_or_default(),
+        kind,
+        codec_name: string(stream, "codec_name"),
+        codec_long_name: string(stream, "codec_long_name"),
+        profile: string(stream, "profile"),
+        level: number(stream, "level").filter(|level: &i64| *level >= 0),
+        bit_rate: number(stream, "bit_rate"),
+        duration: number(stream, "duration"),
+        language: tags.get("language").filter(|lang| *lang != "und").cloned(),
+        title: tags.get("title").cloned(),
+        default: disposition("default"),
+        forced: disposition("forced"),
+        video: (kind == StreamKind::Video).then(|| parse_video(stream, &tags)),
+        audio: (kind == StreamKind::Audio).then(|| AudioStreamInfo {
+            sample_rate: number(stream, "sample_rate"),
+            channels: number(stream, "channels"),
+            channel_layout: string(stream, "channel_layout"),
+        }),
+        tags,
+    }
+}
+
+fn parse_video(stream: &Value, tags: &BTreeMap<String, String>) -> VideoStreamInfo {
+    // Older FFmpeg reports a clockwise "rotate" tag, newer ones a display
+    // matrix rotation, which is counterclockwise
+    let rotation = tags
+        .get("rotate")
+        .and_then(|r| r.parse::<f64>().ok())
+        .or_else(|| side_data(stream, "Display Matrix").and_then(|d| number::<f64>(d, "rotation")).map(|r| -r))
+        .unwrap_or(0.0);
+    let rotation = ((rotation.round() as i64).rem_euclid(360) as u32 + 45) / 90 * 90 % 360;
+
+    let color_transfer = string(stream, "color_transfer");
+    let hdr = if side_data(stream, "DOVI configuration record").is_some() {
+        Some(HdrFormat::DolbyVision)
+    } else {
+        match color_transfer.as_deref() {
+            Some("smpte2084") if side_data(stream, "HDR Dynamic Metadata SMPTE2094-40 (HDR10+)").is_some() => {
+                Some(HdrFormat::Hdr10Plus)
+            }
+            Some("smpte2084") => Some(HdrFormat::Hdr10),
+            Some("arib-std-b67") => Some(HdrFormat::Hlg),
+            _ => None,
+        }
+    };
+
+    let mastering_display = side_data(stream, "Mastering display metadata").and_then(|data| {
+        let xy = |x: &str, y: &str| Some((rational(data, x)?, rational(data, y)?));
+        Some(MasteringDisplay {
+            red: xy("red_x", "red_y")?,
+            green: xy("green_x", "green_y")?,
+            blue: xy("blue_x", "blue_y")?,
+            white_point: xy("white_point_x", "white_point_y")?,
+            min_luminance: rational(data, "min_luminance")?,
+            max_luminance: rational(data, "max_luminance")?,
+        })
+    });
+    let content_light_level = side_data(stream, "Content light level metadata").and_then(|data| {
+        Some(ContentLightLevel {
+            max_content: number(data, "max_content")?,
+            max_average: number(data, "max_average")?,
+        })
+    });
+
+    VideoStreamInfo {
+        width: number(stream, "width").unwrap_or_default(),
+        height: number(stream, "height").unwrap_or_default(),
+        pix_fmt: string(stream, "pix_fmt"),
+        frame_rate: rational(stream, "avg_frame_rate")
+            .filter(|fps| *fps > 0.0)
+            .or_else(|| rational(stream, "r_frame_rate").filter(|fps| *fps > 0.0)),
+        rotation,
+        color_primaries: string(stream, "color_primaries"),
+        color_transfer,
+        color_space: string(stream, "color_space"),
+        color_range: string(stream, "color_range"),
+        hdr,
+        mastering_display,
+        content_light_level,
+    }
+}
+
+fn parse_chapter(chapter: &Value) -> ChapterInfo {
+    ChapterInfo {
+        id: number(chapter, "id").unwrap_or_default(),
+        start: number(chapter, "start_time").unwrap_or_default(),
+        end: number(chapter, "end_time").unwrap_or_default(),
+        title: tags(chapter).get("title").cloned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HDR_MKV: &str = r#"{
+        "streams": [
+            {
+                "index": 0, "codec_name": "hevc", "codec_long_name": "H.265 / HEVC", "profile": "Main 10",
+                "codec_type": "video", "width": 3840, "height": 2160, "pix_fmt": "yuv420p10le", "level": 153,
+                "color_range": "tv", "color_space": "bt2020nc", "color_transfer": "smpte2084",
+                "color_primaries": "bt2020", "r_frame_rate": "24000/1001", "avg_frame_rate": "24000/1001",
+                "disposition": {"default": 1, "forced": 0, "attached_pic": 0},
+                "tags": {"language": "und"},
+                "side_data_list": [
+                    {"side_data_type": "Mastering display metadata",
+                     "red_x": "34000/50000", "red_y": "16000/50000", "green_x": "13250/50000", "green_y": "34500/50000",
+                     "blue_x": "7500/50000", "blue_y": "3000/50000", "white_point_x": "15635/50000",
+                     "white_point_y": "16450/50000", "min_luminance": "50/10000", "max_luminance": "10000000/10000"},
+                    {"side_data_type": "Content light level metadata", "max_content": 1000, "max_average": 400},
+                    {"side_data_type": "Display Matrix", "rotation": -90}
+                ]
+            },
+            {
+                "index": 1, "codec_name": "eac3", "codec_type": "audio", "sample_rate": "48000", "channels": 6,
+                "channel_layout": "5.1(side)", "bit_rate": "640000",
+                "disposition": {"default": 1, "forced": 0}, "tags": {"language": "eng", "title": "Surround"}
+            },
+            {
+                "index": 2, "codec_name": "aac", "codec_type": "audio", "sample_rate": "48000", "channels": 2,
+                "channel_layout": "stereo", "disposition": {"default": 0}, "tags": {"language": "spa"}
+            },
+            {
+                "index": 3, "codec_name": "subrip", "codec_type": "subtitle",
+                "disposition": {"default": 0, "forced": 1}, "tags": {"language": "fre"}
+            },
+            {
+                "index": 4, "codec_name": "mjpeg", "codec_type": "video", "width": 600, "height": 600,
+                "disposition": {"default": 0, "attached_pic": 1}
+            }
+        ],
+        "chapters": [
+            {"id": 1, "start_time": "0.000000", "end_time": "300.000000", "tags": {"title": "Opening"}},
+            {"id": 2, "start_time": "300.000000", "end_time": "5400.500000", "tags": {}}
+        ],
+        "format": {
+            "filename": "movie.mkv", "nb_streams": 5, "format_name": "matroska,webm",
+            "format_long_name": "Matroska / WebM", "duration": "5400.500000", "size": "4000000000",
+            "bit_rate": "5925000", "tags": {"title": "Movie", "encoder": "libebml"}
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_streams_hdr_and_chapters() {
+        let info = MediaInfo::from_ffprobe_json(HDR_MKV).unwrap();
+        assert_eq!(info.format.format_name, "matroska,webm");
+        assert_eq!(info.format.bit_rate, Some(5_925_000));
+        assert_eq!(info.format.tags["title"], "Movie");
+        assert_eq!(info.duration_secs(), 5400);
+
+        let video_stream = info.video_stream().unwrap();
+        assert_eq!(video_stream.profile.as_deref(), Some("Main 10"));
+        assert_eq!(video_stream.level, Some(153));
+        assert_eq!(video_stream.language, None);
+        let video = video_stream.video.as_ref().unwrap();
+        assert_eq!(video.rotation, 90);
+        assert_eq!(video.display_size(), (2160, 3840));
+        assert_eq!(video.hdr, Some(HdrFormat::Hdr10));
+        assert!((video.frame_rate.unwrap() - 23.976).abs() < 0.001);
+        let mastering = video.mastering_display.as_ref().unwrap();
+        assert_eq!(mastering.red, (0.68, 0.32));
+        assert_eq!((mastering.min_luminance, mastering.max_luminance), (0.005, 1000.0));
+        assert_eq!(video.content_light_level.as_ref().unwrap().max_content, 1000);
+
+        let audio = info.audio_stream().unwrap();
+        assert_eq!(audio.language.as_deref(), Some("eng"));
+        assert_eq!(audio.title.as_deref(), Some("Surround"));
+        assert_eq!(audio.audio.as_ref().unwrap().channel_layout.as_deref(), Some("5.1(side)"));
+
+        assert_eq!(info.streams[3].kind, StreamKind::Subtitle);
+        assert!(info.streams[3].forced);
+        // Cover art is not a video stream
+        assert_eq!(info.streams[4].kind, StreamKind::Attachment);
+        assert_eq!(info.chapters[0].title.as_deref(), Some("Opening"));
+        assert_eq!(info.chapters[1].end, 5400.5);
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = MediaInfo::from_ffprobe_json(HDR_MKV).unwrap().summary();
+        let video = summary.video.unwrap();
+        assert_eq!((video.codec.as_str(), video.width, video.height), ("hevc", 2160, 3840));
+        assert_eq!(video.frame_rate, Some(23.976));
+        assert_eq!(video.hdr.as_deref(), Some("hdr10"));
+        assert_eq!(summary.audio_tracks.len(), 2);
+        assert_eq!(summary.audio_tracks[1].language.as_deref(), Some("spa"));
+        assert_eq!(summary.subtitle_languages, vec!["fre".to_string()]);
+        assert_eq!(summary.chapters, 2);
+    }
+
+    #[test]
+    fn test_minimal_audio_file() {
+        let info = MediaInfo::from_ffprobe_json(
+            r#"{"streams": [{"index": 0, "codec_type": "audio", "codec_name": "mp3",
+                "sample_rate": "44100", "channels": 2, "bit_rate": "320000", "duration": "61.2"}],
+                "format": {"format_name": "mp3"}}"#,
+        )
+        .unwrap();
+        assert_eq!(info.duration_secs(), 61);
+        assert!(info.video_stream().is_none());
+        assert_eq!(info.audio_stream().unwrap().bit_rate, Some(320_000));
+        assert!(info.chapters.is_empty());
+        assert!(MediaInfo::from_ffprobe_json("{}").is_err());
+    }
+}
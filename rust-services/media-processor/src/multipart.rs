@@ -0,0 +1,312 @@
+//! Multipart uploads to S3-compatible object storage
+//!
+//! Files at or above a size threshold are streamed from disk in parts
+//! instead of being read into memory for a single `PutObject`. Parts are
+//! uploaded concurrently, each with its own Content-MD5 and retries; a
+//! failed upload is aborted so the store does not keep orphaned parts.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::retry::{retry_with_backoff, RetryConfig};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use md5::{Digest, Md5};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, S3Client, UploadPartRequest, S3,
+};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{info, warn};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Smallest part S3 accepts (except for the last one)
+pub const MIN_PART_SIZE: u64 = 5 * MIB;
+
+/// Most parts a multipart upload may have
+pub const MAX_PARTS: u64 = 10_000;
+
+/// Upload settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadConfig {
+    /// Files of at least this many bytes are uploaded in parts
+    pub multipart_threshold: u64,
+    /// Part size in bytes
+    pub part_size: u64,
+    /// Parts uploaded at the same time
+    pub concurrency: usize,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            multipart_threshold: 64 * MIB,
+            part_size: 16 * MIB,
+            concurrency: 4,
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Read `S3_MULTIPART_THRESHOLD_MB`, `S3_PART_SIZE_MB` and
+    /// `S3_UPLOAD_CONCURRENCY`
+    ///
+    /// Part sizes below S3's 5 MiB minimum are raised to it.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        Self {
+            multipart_threshold: var("S3_MULTIPART_THRESHOLD_MB")
+                .map(|mb| mb * MIB)
+                .unwrap_or(defaults.multipart_threshold),
+            part_size: var("S3_PART_SIZE_MB")
+                .map(|mb| (mb * MIB).max(MIN_PART_SIZE))
+                .unwrap_or(defaults.part_size),
+            concurrency: var("S3_UPLOAD_CONCURRENCY")
+                .filter(|n| *n > 0)
+                .map(|n| n as usize)
+                .unwrap_or(defaults.concurrency),
+        }
+    }
+}
+
+/// A byte range of the file uploaded as one part
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    /// 1-based part number
+    pub number: i64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Split `size` bytes into parts of `part_size`
+///
+/// The part size grows when needed to stay within [`MAX_PARTS`].
+pub fn plan_parts(size: u64, part_size: u64) -> Vec<Part> {
+    let part_size = part_size.max(MIN_PART_SIZE).max(size.div_ceil(MAX_PARTS));
+    (0..size.div_ceil(part_size).max(1))
+        .map(|index| {
+            let offset = index * part_size;
+            Part {
+                number: index as i64 + 1,
+                offset,
+                len: part_size.min(size - offset),
+            }
+        })
+        .collect()
+}
+
+/// Base64 MD5 digest, as sent in `Content-MD5`
+pub fn content_md5(data: &[u8]) -> String {
+    STANDARD.encode(Md5::digest(data))
+}
+
+/// Destination and headers of an upload
+#[derive(Debug, Clone)]
+pub struct UploadTarget {
+    pub bucket: String,
+    pub key: String,
+    pub content_type: String,
+    pub cache_control: String,
+}
+
+/// Upload `local_path` to `target` as a multipart upload
+pub async fn upload(
+    client: &Arc<S3Client>,
+    local_path: &Path,
+    target: &UploadTarget,
+    config: &UploadConfig,
+    retry_config: &RetryConfig,
+) -> anyhow::Result<()> {
+    let size = tokio::fs::metadata(local_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?
+        .len();
+    let parts = plan_parts(size, config.part_size);
+
+    let created = retry_with_backoff(retry_config, || {
+        let client = Arc::clone(client);
+        let request = CreateMultipartUploadRequest {
+            bucket: target.bucket.clone(),
+            key: target.key.clone(),
+            content_type: Some(target.content_type.clone()),
+            cache_control: Some(target.cache_control.clone()),
+            ..Default::default()
+        };
+        Box::pin(async move {
+            client
+                .create_multipart_upload(request)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to create multipart upload: {}", e))
+        })
+    })
+    .await?;
+    let upload_id = created
+        .upload_id
+        .ok_or_else(|| anyhow::anyhow!("Multipart upload created without an upload id"))?;
+
+    info!(
+        s3_key = target.key,
+        size = size,
+        parts = parts.len(),
+        "Started multipart upload"
+    );
+
+    let result = async {
+        let completed = upload_parts(client, local_path, target, &upload_id, &parts, config.concurrency, retry_config).await?;
+        complete(client, target, &upload_id, completed, retry_config).await
+    }
+    .await;
+
+    if let Err(e) = &result {
+        warn!(error = %e, s3_key = target.key, "Multipart upload failed, aborting");
+        let request = AbortMultipartUploadRequest {
+            bucket: target.bucket.clone(),
+            key: target.key.clone(),
+            upload_id,
+            ..Default::default()
+        };
+        if let Err(abort_error) = client.abort_multipart_upload(request).await {
+            warn!(error = %abort_error, s3_key = target.key, "Failed to abort multipart upload");
+        }
+    }
+    result
+}
+
+/// Upload `parts` with at most `concurrency` in flight, in part order
+async fn upload_parts(
+    client: &Arc<S3Client>,
+    local_path: &Path,
+    target: &UploadTarget,
+    upload_id: &str,
+    parts: &[Part],
+    concurrency: usize,
+    retry_config: &RetryConfig,
+) -> anyhow::Result<Vec<CompletedPart>> {
+    let mut completed: Vec<CompletedPart> = stream::iter(parts.iter().copied())
+        .map(|part| upload_part(client, local_path, target, upload_id, part, retry_config))
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    completed.sort_by_key(|part| part.part_number);
+    Ok(completed)
+}
+
+async fn upload_part(
+    client: &Arc<S3Client>,
+    local_path: &Path,
+    target: &UploadTarget,
+    upload_id: &str,
+    part: Part,
+    retry_config: &RetryConfig,
+) -> anyhow::Result<CompletedPart> {
+    let mut file = tokio::fs::File::open(local_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?;
+    file.seek(SeekFrom::Start(part.offset)).await?;
+    let mut data = vec![0u8; part.len as usize];
+    file.read_exact(&mut data).await?;
+    let md5 = content_md5(&data);
+
+    let output = retry_with_backoff(retry_config, || {
+        let client = Arc::clone(client);
+        let request = UploadPartRequest {
+            bucket: target.bucket.clone(),
+            key: target.key.clone(),
+            upload_id: upload_id.to_string(),
+            part_number: part.number,
+            body: Some(data.clone().into()),
+            content_length: Some(part.len as i64),
+            content_md5: Some(md5.clone()),
+            ..Default::default()
+        };
+        Box::pin(async move {
+            client
+                .upload_part(request)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to upload part {}: {}", part.number, e))
+        })
+    })
+    .await?;
+
+    Ok(CompletedPart {
+        e_tag: output.e_tag,
+        part_number: Some(part.number),
+    })
+}
+
+async fn complete(
+    client: &Arc<S3Client>,
+    target: &UploadTarget,
+    upload_id: &str,
+    parts: Vec<CompletedPart>,
+    retry_config: &RetryConfig,
+) -> anyhow::Result<()> {
+    retry_with_backoff(retry_config, || {
+        let client = Arc::clone(client);
+        let request = CompleteMultipartUploadRequest {
+            bucket: target.bucket.clone(),
+            key: target.key.clone(),
+            upload_id: upload_id.to_string(),
+            multipart_upload: Some(CompletedMultipartUpload {
+                parts: Some(parts.clone()),
+            }),
+            ..Default::default()
+        };
+        Box::pin(async move {
+            client
+                .complete_multipart_upload(request)
+                .await
+                .map(|_| ())
+                .map_err(|e| anyhow::anyhow!("Failed to complete multipart upload: {}", e))
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_parts() {
+        let parts = plan_parts(12 * MIB + 3, 5 * MIB);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], Part { number: 1, offset: 0, len: 5 * MIB });
+        assert_eq!(parts[2], Part { number: 3, offset: 10 * MIB, len: 2 * MIB + 3 });
+
+        // Undersized parts are raised to the S3 minimum
+        assert_eq!(plan_parts(12 * MIB, MIB).len(), 3);
+        // An empty file is still one (empty) part
+        assert_eq!(plan_parts(0, 5 * MIB), vec![Part { number: 1, offset: 0, len: 0 }]);
+    }
+
+    #[test]
+    fn test_plan_parts_stays_within_part_limit() {
+        let size = 100_000 * MIB;
+        let parts = plan_parts(size, 5 * MIB);
+        assert!(parts.len() as u64 <= MAX_PARTS);
+        assert_eq!(parts.iter().map(|p| p.len).sum::<u64>(), size);
+    }
+
+    #[test]
+    fn test_content_md5() {
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+        assert_eq!(content_md5(b"hello world"), "XrY7u+Ae7tCTyyK7j1rNww==");
+    }
+}
@@ -0,0 +1,181 @@
+//! Object store interface for processed media
+//!
+//! Outputs are written through an [`ObjectStore`] so the processor is not
+//! tied to one storage service. Backends:
+//! - S3-compatible object storage ([`crate::s3_store::S3Store`])
+//! - The local filesystem, for development and on-prem ([`crate::local_store::LocalStore`])
+//! - Memory, for tests ([`MemoryStore`])
+//!
+//! Public URLs of stored objects are built separately by
+//! [`crate::urls::UrlBuilder`].
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::sync::Mutex;
+
+/// Trait for object storage backends
+///
+/// Keys are `/`-separated relative paths such as `media/{id}/master.m3u8`.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Store the file at `local_path` under `key`
+    async fn put_file(&self, local_path: &Path, key: &str, content_type: &str) -> anyhow::Result<()>;
+
+    /// Write the object under `key` to `destination`, returning its size
+    async fn get_file(&self, key: &str, destination: &Path) -> anyhow::Result<u64>;
+
+    /// Delete the object under `key`; deleting a missing object succeeds
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+
+    /// Get the store type name
+    fn store_type(&self) -> &str;
+}
+
+/// Which backend `OBJECT_STORAGE_BACKEND` selects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    S3,
+    Local,
+}
+
+impl StorageBackend {
+    /// Read `OBJECT_STORAGE_BACKEND` (`s3` or `local`, default `s3`)
+    pub fn from_env() -> Self {
+        match std::env::var("OBJECT_STORAGE_BACKEND").ok().as_deref() {
+            Some("local") => Self::Local,
+            _ => Self::S3,
+        }
+    }
+}
+
+/// Reject keys that are empty, absolute or step outside the store
+pub fn validate_key(key: &str) -> anyhow::Result<()> {
+    let valid = !key.is_empty()
+        && Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(anyhow::anyhow!("Invalid object key: {:?}", key));
+    }
+    Ok(())
+}
+
+/// An object held by a [`MemoryStore`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredObject {
+    pub data: Vec<u8>,
+    pub content_type: String,
+}
+
+/// Object store keeping objects in memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    objects: Mutex<HashMap<String, StoredObject>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The object under `key`
+    pub fn get(&self, key: &str) -> Option<StoredObject> {
+        self.objects().get(key).cloned()
+    }
+
+    /// All keys, sorted
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.objects().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn objects(&self) -> std::sync::MutexGuard<'_, HashMap<String, StoredObject>> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryStore {
+    async fn put_file(&self, local_path: &Path, key: &str, content_type: &str) -> anyhow::Result<()> {
+        validate_key(key)?;
+        let data = tokio::fs::read(local_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?;
+        self.objects().insert(
+            key.to_string(),
+            StoredObject {
+                data,
+                content_type: content_type.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> anyhow::Result<u64> {
+        let object = self
+            .get(key)
+            .ok_or_else(|| anyhow::anyhow!("Object not found: {}", key))?;
+        tokio::fs::write(destination, &object.data)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create destination file: {}", e))?;
+        Ok(object.data.len() as u64)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.objects().remove(key);
+        Ok(())
+    }
+
+    fn store_type(&self) -> &str {
+        "memory"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("media/123/master.m3u8").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("/etc/passwd").is_err());
+        assert!(validate_key("media/../../etc/passwd").is_err());
+        assert!(validate_key("./media").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("segment.m4s");
+        std::fs::write(&source, b"segment").unwrap();
+
+        let store = MemoryStore::new();
+        store.put_file(&source, "media/1/720p/segment.m4s", "video/iso.segment").await.unwrap();
+        assert_eq!(store.keys(), vec!["media/1/720p/segment.m4s"]);
+        assert_eq!(store.get("media/1/720p/segment.m4s").unwrap().content_type, "video/iso.segment");
+
+        let copy = dir.path().join("copy.m4s");
+        assert_eq!(store.get_file("media/1/720p/segment.m4s", &copy).await.unwrap(), 7);
+        assert_eq!(std::fs::read(&copy).unwrap(), b"segment");
+
+        store.delete("media/1/720p/segment.m4s").await.unwrap();
+        assert!(store.get_file("media/1/720p/segment.m4s", &copy).await.is_err());
+    }
+}
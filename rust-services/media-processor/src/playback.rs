@@ -0,0 +1,304 @@
+//! Signed playback of protected media
+//!
+//! `GET /playback/{media_id}/{path}` checks the player's JWT like the key
+//! endpoints do, then:
+//! - for HLS playlists, returns the stored playlist with its segment URIs
+//!   signed and, when the player passed its token in the query, its nested
+//!   playlist URIs carrying that token, so every rendition playlist passes
+//!   through here as well
+//! - for the DASH manifest, returns it with its segment URLs signed
+//! - for any other object (MP4s, thumbnails), redirects to a signed URL
+//!
+//! With signing enabled, `media.ready` publishes these routes instead of the
+//! stored objects' public URLs.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::key_server::authorize;
+use crate::object_store::{validate_key, ObjectStore};
+use crate::url_signing::UrlSigner;
+use crate::urls::UrlBuilder;
+use armoricore_keys::{JwtService, KeyStore};
+use armoricore_types::schemas::PlaybackUrls;
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Route of the playback endpoint
+pub const PLAYBACK_PATH: &str = "/playback/:media_id/*path";
+
+/// Where protected media are read and how their URLs are signed
+pub struct Playback {
+    pub store: Arc<dyn ObjectStore>,
+    pub urls: UrlBuilder,
+    pub signer: UrlSigner,
+    /// Reverse proxies allowed to report the viewer's address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+struct PlaybackState {
+    playback: Playback,
+    jwt: JwtService,
+    token_key_id: String,
+}
+
+/// Build the playback route, verifying player tokens with `token_key_id`
+pub fn playback_router(key_store: Arc<KeyStore>, token_key_id: impl Into<String>, playback: Playback) -> Router {
+    let state = PlaybackState {
+        playback,
+        jwt: JwtService::new(key_store),
+        token_key_id: token_key_id.into(),
+    };
+    Router::new()
+        .route(PLAYBACK_PATH, get(get_playback))
+        .with_state(Arc::new(state))
+}
+
+/// `urls` with every stored object replaced by its playback route under
+/// `playback_base_url`
+pub fn playback_urls(urls: &PlaybackUrls, storage_urls: &UrlBuilder, playback_base_url: &str, media_id: &Uuid) -> PlaybackUrls {
+    let prefix = format!("media/{}/", media_id);
+    let route = |url: &String| match storage_urls.key(url).and_then(|key| key.strip_prefix(&prefix)) {
+        Some(path) => format!("{}/playback/{}/{}", playback_base_url.trim_end_matches('/'), media_id, path),
+        None => url.clone(),
+    };
+    PlaybackUrls {
+        hls: urls.hls.as_ref().map(route),
+        dash: urls.dash.as_ref().map(route),
+        mp4: urls.mp4.iter().map(|(resolution, url)| (resolution.clone(), route(url))).collect(),
+    }
+}
+
+async fn get_playback(
+    State(state): State<Arc<PlaybackState>>,
+    Path((media_id, path)): Path<(Uuid, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Response {
+    let token = match authorize(&state.jwt, &state.token_key_id, &media_id, &query, &headers).await {
+        Ok(token) => token,
+        Err(status) => return status.into_response(),
+    };
+    let key = format!("media/{}/{}", media_id, path);
+    if validate_key(&key).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let playback = &state.playback;
+    let client_ip = client_ip(&headers, connect_info.map(|ConnectInfo(addr)| addr), &playback.trusted_proxies);
+
+    let is_playlist = path.ends_with(".m3u8");
+    if !is_playlist && !path.ends_with(".mpd") {
+        let url = playback.signer.sign(&playback.urls, &key, &media_id, client_ip);
+        return ([(header::CACHE_CONTROL, "no-store")], Redirect::temporary(&url)).into_response();
+    }
+
+    let document = match read_document(playback.store.as_ref(), &key).await {
+        Ok(document) => document,
+        Err(e) => {
+            warn!(media_id = %media_id, key = key, error = %e, "Failed to read playlist");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let (content_type, signed) = if is_playlist {
+        // A Bearer token stays out of URLs; the player sends it again
+        let query = if query.get("token").map(String::as_str) == Some(token) {
+            format!("token={}", token)
+        } else {
+            String::new()
+        };
+        let signed = playback
+            .signer
+            .sign_playlist(&document, &key, &playback.urls, &media_id, client_ip, &query);
+        ("application/vnd.apple.mpegurl", signed)
+    } else {
+        let signed = playback
+            .signer
+            .sign_manifest(&document, &key, &playback.urls, &media_id, client_ip);
+        ("application/dash+xml", signed)
+    };
+    (
+        [(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "no-store")],
+        signed,
+    )
+        .into_response()
+}
+
+async fn read_document(store: &dyn ObjectStore, key: &str) -> anyhow::Result<String> {
+    let file = tempfile::NamedTempFile::new()?;
+    store.get_file(key, file.path()).await?;
+    Ok(tokio::fs::read_to_string(file.path()).await?)
+}
+
+/// Viewer address: the peer, or when the peer is one of `trusted_proxies`,
+/// the last `X-Forwarded-For` entry that no trusted proxy added
+///
+/// Entries left of it are supplied by the client and are never used.
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for entry in forwarded.into_iter().rev() {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) if trusted_proxies.contains(&ip) => continue,
+            Ok(ip) => return Some(ip),
+            Err(_) => break,
+        }
+    }
+    Some(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_server::DEFAULT_TOKEN_KEY_ID;
+    use crate::object_store::MemoryStore;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_playback_urls() {
+        let media_id = Uuid::new_v4();
+        let storage_urls = UrlBuilder::new("https://cdn.example.com");
+        let urls = PlaybackUrls {
+            hls: Some(storage_urls.url(&format!("media/{}/master.m3u8", media_id))),
+            dash: None,
+            mp4: HashMap::from([("720p".to_string(), storage_urls.url(&format!("media/{}/720p.mp4", media_id)))]),
+        };
+        let routed = playback_urls(&urls, &storage_urls, "https://play.example.com/", &media_id);
+        assert_eq!(routed.hls.unwrap(), format!("https://play.example.com/playback/{}/master.m3u8", media_id));
+        assert_eq!(routed.mp4["720p"], format!("https://play.example.com/playback/{}/720p.mp4", media_id));
+    }
+
+    #[test]
+    fn test_client_ip_trusts_forwarded_for_only_from_proxies() {
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.3".parse().unwrap());
+        let from = |ip: IpAddr| Some(SocketAddr::new(ip, 443));
+
+        // Direct clients can't claim another address
+        let viewer: IpAddr = "192.0.2.10".parse().unwrap();
+        assert_eq!(client_ip(&headers, from(viewer), &[proxy]), Some(viewer));
+        // Behind the proxies, the spoofable leftmost entry is skipped
+        let proxies = [proxy, "10.0.0.3".parse().unwrap()];
+        assert_eq!(client_ip(&headers, from(proxy), &proxies), Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(client_ip(&HeaderMap::new(), from(proxy), &proxies), Some(proxy));
+    }
+
+    #[tokio::test]
+    async fn test_playback_signs_playlists_and_redirects() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = armoricore_keys::local_store::LocalKeyStore::new(dir.path(), Some(&[7u8; 32]))
+            .await
+            .unwrap();
+        let key_store = Arc::new(KeyStore::new(Arc::new(backend)));
+        key_store
+            .store_jwt_secret(&DEFAULT_TOKEN_KEY_ID.to_string(), "player-secret")
+            .await
+            .unwrap();
+
+        let media_id = Uuid::new_v4();
+        let store = Arc::new(MemoryStore::new());
+        let master = dir.path().join("master.m3u8");
+        std::fs::write(&master, "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1\n720p/playlist.m3u8\n").unwrap();
+        store
+            .put_file(&master, &format!("media/{}/master.m3u8", media_id), "application/vnd.apple.mpegurl")
+            .await
+            .unwrap();
+        let manifest = dir.path().join("manifest.mpd");
+        std::fs::write(
+            &manifest,
+            "<SegmentTemplate timescale=\"1000\" initialization=\"720p/init.mp4\" media=\"720p/segment_$Number%03d$.m4s\" startNumber=\"0\">\n",
+        )
+        .unwrap();
+        store
+            .put_file(&manifest, &format!("media/{}/manifest.mpd", media_id), "application/dash+xml")
+            .await
+            .unwrap();
+
+        let token = JwtService::new(key_store.clone())
+            .issue(
+                &DEFAULT_TOKEN_KEY_ID.to_string(),
+                &json!({ "media_id": media_id.to_string() }),
+                Duration::from_secs(300),
+            )
+            .await
+            .unwrap();
+
+        let playback = Playback {
+            store,
+            urls: UrlBuilder::new("https://cdn.example.com"),
+            signer: UrlSigner::cdn(b"secret".to_vec(), "hdnts", true, Duration::from_secs(60)),
+            trusted_proxies: Vec::new(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/playback/{}", listener.local_addr().unwrap(), media_id);
+        let app = playback_router(key_store, DEFAULT_TOKEN_KEY_ID, playback);
+        tokio::spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await });
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let response = client.get(format!("{}/master.m3u8", base)).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+
+        let response = client.get(format!("{}/master.m3u8?token={}", base, token)).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let playlist = response.text().await.unwrap();
+        assert!(playlist.contains(&format!("720p/playlist.m3u8?token={}\n", token)));
+
+        let response = client.get(format!("{}/master.m3u8", base)).bearer_auth(&token).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let playlist = response.text().await.unwrap();
+        assert!(playlist.contains("\n720p/playlist.m3u8\n"));
+        assert!(!playlist.contains(&token));
+
+        let response = client.get(format!("{}/manifest.mpd", base)).bearer_auth(&token).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "application/dash+xml");
+        let manifest = response.text().await.unwrap();
+        assert!(manifest.contains(&format!(
+            r#"media="https://cdn.example.com/media/{}/720p/segment_$Number%03d$.m4s?hdnts=ip=127.0.0.1~"#,
+            media_id
+        )));
+
+        let response = client.get(format!("{}/720p.mp4", base)).bearer_auth(&token).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 307);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(location.starts_with(&format!("https://cdn.example.com/media/{}/720p.mp4?hdnts=ip=127.0.0.1~", media_id)));
+
+        let response = client.get(format!("{}/missing.m3u8", base)).bearer_auth(&token).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
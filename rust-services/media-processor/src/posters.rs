@@ -0,0 +1,371 @@
+//! Poster frame selection
+//!
+//! Instead of grabbing frames at fixed fractions of the duration, the source
+//! is sampled at a low frame rate and every sampled frame is measured in one
+//! FFmpeg pass: scene-change score (`select`), mean luma (`signalstats`),
+//! share of black pixels (`blackframe`) and blur (`blurdetect`). Frames are
+//! scored for exposure, non-blackness and sharpness, grouped into scenes at
+//! scene cuts, and the best frame of the most significant scenes become the
+//! posters. Scores are kept so alternates can be offered to users.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Width frames are scaled to before they are measured
+pub const ANALYSIS_WIDTH: u32 = 320;
+
+/// Mean luma (8-bit) below which a frame counts as a fade to black
+pub const MIN_BRIGHTNESS: f64 = 20.0;
+
+/// Mean luma (8-bit) above which a frame counts as washed out
+pub const MAX_BRIGHTNESS: f64 = 235.0;
+
+/// Poster selection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct PosterConfig {
+    /// Select posters by analysis; `false` takes frames at fixed fractions
+    pub enabled: bool,
+    /// Number of posters (thumbnails) per media
+    pub count: usize,
+    /// Seconds between sampled frames
+    pub sample_interval: f64,
+    /// Scene-change score starting a new scene
+    pub scene_threshold: f64,
+}
+
+impl Default for PosterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            count: 3,
+            sample_interval: 1.0,
+            scene_threshold: 0.3,
+        }
+    }
+}
+
+impl PosterConfig {
+    /// Read `POSTER_SELECTION`, `POSTER_COUNT`, `POSTER_SAMPLE_INTERVAL_SECS`
+    /// and `POSTER_SCENE_THRESHOLD`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        Self {
+            enabled: var("POSTER_SELECTION")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.enabled),
+            count: var("POSTER_COUNT")
+                .and_then(|v| v.parse().ok())
+                .filter(|count| *count > 0)
+                .unwrap_or(defaults.count),
+            sample_interval: var("POSTER_SAMPLE_INTERVAL_SECS")
+                .and_then(|v| v.parse().ok())
+                .filter(|interval: &f64| *interval > 0.0)
+                .unwrap_or(defaults.sample_interval),
+            scene_threshold: var("POSTER_SCENE_THRESHOLD")
+                .and_then(|v| v.parse().ok())
+                .filter(|threshold: &f64| (0.0..=1.0).contains(threshold))
+                .unwrap_or(defaults.scene_threshold),
+        }
+    }
+
+    /// Timestamps at fixed fractions of the duration, used without analysis
+    pub fn fixed_timestamps(&self, duration: f64) -> Vec<f64> {
+        (1..=self.count)
+            .map(|i| duration / (self.count + 1) as f64 * i as f64)
+            .collect()
+    }
+}
+
+/// Measurements of one sampled frame
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrameStats {
+    /// Presentation time in seconds
+    pub time: f64,
+    /// Difference from the previous sampled frame (0-1)
+    pub scene_score: f64,
+    /// Mean luma (0-255)
+    pub brightness: f64,
+    /// Share of black pixels (0-1)
+    pub black_ratio: f64,
+    /// `blurdetect` blur; higher is blurrier
+    pub blur: f64,
+}
+
+impl FrameStats {
+    /// Quality of the frame as a poster (0-1); black, washed out and blurry
+    /// frames score low
+    pub fn quality(&self) -> f64 {
+        if !(MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(&self.brightness) {
+            return 0.0;
+        }
+        let exposure = 1.0 - (self.brightness - 128.0).abs() / 128.0;
+        let coverage = 1.0 - self.black_ratio.clamp(0.0, 1.0);
+        let sharpness = 1.0 / (1.0 + self.blur.max(0.0) / 4.0);
+        exposure * coverage * sharpness
+    }
+}
+
+/// A frame chosen as a poster, with its score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PosterCandidate {
+    pub frame: FrameStats,
+    /// [`FrameStats::quality`] of the frame
+    pub score: f64,
+}
+
+/// An extracted poster image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Poster {
+    pub path: PathBuf,
+    pub candidate: PosterCandidate,
+}
+
+/// Collects per-frame metadata printed by FFmpeg's `metadata=mode=print` filter
+#[derive(Debug, Default)]
+pub struct FrameStatsParser {
+    frames: Vec<FrameStats>,
+}
+
+impl FrameStatsParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        let line = line.trim();
+        if line.starts_with("frame:") {
+            let time = line
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("pts_time:"))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.0);
+            self.frames.push(FrameStats {
+                time,
+                ..FrameStats::default()
+            });
+            return;
+        }
+        let (Some(frame), Some((key, value))) = (self.frames.last_mut(), line.split_once('=')) else {
+            return;
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            return;
+        };
+        match key {
+            "lavfi.scene_score" => frame.scene_score = value,
+            "lavfi.signalstats.YAVG" => frame.brightness = value,
+            "lavfi.blackframe.pblack" => frame.black_ratio = value / 100.0,
+            "lavfi.blur" => frame.blur = value,
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> Vec<FrameStats> {
+        self.frames
+    }
+}
+
+/// Pick up to `count` posters from the sampled `frames`, best first
+///
+/// Frames are split into scenes where the scene score reaches
+/// `scene_threshold`. The best frame of each scene is ranked by its quality
+/// weighted by the square root of the scene's length, so long shots win
+/// over flashes; when there are fewer usable scenes than `count`, the best
+/// remaining frames fill in. Posters are at least `min_gap` seconds apart.
+pub fn select_posters(
+    frames: &[FrameStats],
+    count: usize,
+    scene_threshold: f64,
+    min_gap: f64,
+) -> Vec<PosterCandidate> {
+    let candidate = |frame: &FrameStats| PosterCandidate {
+        frame: frame.clone(),
+        score: frame.quality(),
+    };
+
+    let mut scenes: Vec<&[FrameStats]> = Vec::new();
+    let mut start = 0;
+    for (index, frame) in frames.iter().enumerate().skip(1) {
+        if frame.scene_score >= scene_threshold {
+            scenes.push(&frames[start..index]);
+            start = index;
+        }
+    }
+    if start < frames.len() {
+        scenes.push(&frames[start..]);
+    }
+
+    let mut ranked: Vec<(f64, PosterCandidate)> = scenes
+        .iter()
+        .filter_map(|scene| {
+            let best = scene.iter().map(candidate).max_by(|a, b| a.score.total_cmp(&b.score))?;
+            Some(((scene.len() as f64).sqrt() * best.score, best))
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut by_quality: Vec<PosterCandidate> = frames.iter().map(candidate).collect();
+    by_quality.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut selected: Vec<PosterCandidate> = Vec::new();
+    for poster in ranked.into_iter().map(|(_, poster)| poster).chain(by_quality) {
+        if selected.len() == count {
+            break;
+        }
+        let far_enough = selected.iter().all(|chosen| {
+            let gap = (chosen.frame.time - poster.frame.time).abs();
+            gap > 0.0 && gap >= min_gap
+        });
+        if poster.score > 0.0 && far_enough {
+            selected.push(poster);
+        }
+    }
+    selected.sort_by(|a, b| b.score.total_cmp(&a.score));
+    selected
+}
+
+/// Sample and measure the frames of `input`, then select posters
+///
+/// `sdr_filter`, when set, is prepended to tone-map HDR sources so their
+/// brightness is measured as displayed.
+pub async fn analyze(
+    input: &Path,
+    duration: f64,
+    sdr_filter: Option<&str>,
+    config: &PosterConfig,
+    job: &JobContext,
+) -> anyhow::Result<Vec<PosterCandidate>> {
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let mut filter = format!(
+        "fps=1/{},scale={}:-2,format=yuv420p,select='gte(scene,0)',signalstats,\
+         blackframe=amount=0:threshold=32,blurdetect,metadata=mode=print:file=-",
+        config.sample_interval, ANALYSIS_WIDTH
+    );
+    if let Some(sdr_filter) = sdr_filter {
+        filter = format!("{},{}", sdr_filter, filter);
+    }
+
+    let mut parser = FrameStatsParser::new();
+    let status = job
+        .run(
+            ingest::ffmpeg_command("ffmpeg").args([
+                "-nostats", "-i", input, "-an", "-vf", &filter, "-f", "null", "-",
+            ]),
+            |line| parser.push_line(line),
+        )
+        .await?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("Frame analysis failed: {}", status.error()));
+    }
+
+    let frames = parser.finish();
+    let min_gap = (duration / (config.count * 4) as f64).max(config.sample_interval * 2.0);
+    let posters = select_posters(&frames, config.count, config.scene_threshold, min_gap);
+    if posters.is_empty() {
+        return Err(anyhow::anyhow!("No usable poster frame among {} sampled", frames.len()));
+    }
+    info!(
+        frames = frames.len(),
+        posters = posters.len(),
+        best_score = posters[0].score,
+        "Selected poster frames"
+    );
+    Ok(posters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time: f64, scene_score: f64, brightness: f64, blur: f64) -> FrameStats {
+        FrameStats {
+            time,
+            scene_score,
+            brightness,
+            black_ratio: 0.0,
+            blur,
+        }
+    }
+
+    #[test]
+    fn test_parse_metadata_output() {
+        let mut parser = FrameStatsParser::new();
+        for line in [
+            "frame:0    pts:0       pts_time:0",
+            "lavfi.scene_score=0.000000",
+            "lavfi.signalstats.YAVG=12.5",
+            "lavfi.blackframe.pblack=98",
+            "frame:1    pts:1       pts_time:1.5",
+            "lavfi.scene_score=0.420000",
+            "lavfi.signalstats.YAVG=110.25",
+            "lavfi.blackframe.pblack=3",
+            "lavfi.blur=2.5",
+        ] {
+            parser.push_line(line);
+        }
+        let frames = parser.finish();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].black_ratio, 0.98);
+        assert_eq!(frames[0].quality(), 0.0);
+        assert_eq!(frames[1], FrameStats {
+            time: 1.5,
+            scene_score: 0.42,
+            brightness: 110.25,
+            black_ratio: 0.03,
+            blur: 2.5,
+        });
+    }
+
+    #[test]
+    fn test_select_best_frame_of_each_scene() {
+        let frames = vec![
+            // Fade in from black
+            frame(0.0, 0.0, 5.0, 1.0),
+            frame(1.0, 0.1, 90.0, 6.0),
+            frame(2.0, 0.05, 120.0, 1.0),
+            frame(3.0, 0.1, 125.0, 3.0),
+            // Cut to a short, blurry shot
+            frame(4.0, 0.6, 130.0, 12.0),
+            // Cut to a long shot
+            frame(5.0, 0.5, 100.0, 2.0),
+            frame(6.0, 0.02, 128.0, 0.5),
+            frame(7.0, 0.01, 127.0, 0.8),
+            frame(8.0, 0.02, 240.0, 0.5),
+        ];
+        let posters = select_posters(&frames, 2, 0.3, 2.0);
+        let times: Vec<f64> = posters.iter().map(|p| p.frame.time).collect();
+        assert_eq!(times, vec![6.0, 2.0]);
+        assert!(posters[0].score > posters[1].score);
+
+        // Fewer scenes than requested: the best remaining frames fill in
+        let filled = select_posters(&frames, 4, 0.3, 1.0);
+        assert_eq!(filled.len(), 4);
+        assert!(filled.iter().all(|p| p.frame.time != 0.0 && p.frame.time != 8.0));
+    }
+
+    #[test]
+    fn test_fixed_timestamps() {
+        let config = PosterConfig::default();
+        assert_eq!(config.fixed_timestamps(100.0), vec![25.0, 50.0, 75.0]);
+    }
+}
@@ -14,30 +14,158 @@
 // limitations under the License.
 
 
+use crate::dash::{self, Rendition, RenditionKind, TrackInfo, VideoRange};
 use crate::downloader::FileDownloader;
+use crate::encryption::EncryptionMetadata;
+use crate::drm::{self, DrmConfig, DrmPackage};
+use crate::hls_encryption::{self, HlsEncryptionConfig};
+use crate::images::{self, ImageConfig, ImageVariant, ProcessedImage};
+use crate::ingest::{self, IngestPolicy};
+use crate::job_store::JobStage;
+use crate::jobs::JobContext;
+use crate::hdr::{HdrConfig, HdrSource, VideoColor};
+use crate::ladder::{self, EncodingLadder, LadderConfig, LadderRung};
+use crate::loudness::{self, Loudness, LoudnessConfig};
+use crate::media_info::{self, MediaInfo, StreamInfo, StreamKind};
+use crate::posters::{self, Poster, PosterConfig};
+use crate::sandbox::SandboxConfig;
+use crate::sprites::{self, SpriteConfig, SpriteSheets};
+use crate::subtitles::{self, SubtitleSource};
+use crate::progress::{FfmpegProgressParser, ProgressReporter, ProgressUpdate};
+use crate::waveform::{self, Waveform, WaveformConfig};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tempfile::TempDir;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 use uuid::Uuid;
 use futures::future::join_all;
 
 /// Result of media processing
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessingResult {
     pub output_dir: PathBuf,
-    pub thumbnail_urls: Vec<String>,
+    #[serde(default)]
+    pub thumbnails: Vec<PathBuf>, // Poster frames (video only)
+    #[serde(default)]
+    pub sprites: Option<SpriteSheets>, // Scrubbing preview sprites and WebVTT track (video only)
+    #[serde(default)]
+    pub posters: Vec<Poster>, // Scored poster frames, best first (video only)
+    #[serde(default)]
+    pub loudness: Option<Loudness>, // Source loudness, when the audio was normalized
+    #[serde(default)]
+    pub waveform: Option<Waveform>, // Waveform peak files (audio only)
+    #[serde(default)]
+    pub image: Option<ProcessedImage>, // Size variants and placeholder (images only)
     pub duration: u64, // Duration in seconds
     pub resolutions: Vec<String>, // Video resolutions or audio bitrates
     pub hls_playlist_path: Option<PathBuf>,
     pub mp4_files: Vec<PathBuf>, // MP4 files for each resolution/bitrate
-    pub dash_manifest_path: Option<PathBuf>, // DASH manifest (future)
+    pub dash_manifest_path: Option<PathBuf>, // DASH manifest over the HLS CMAF segments
     pub output_files: Vec<PathBuf>, // All generated files
     pub encryption_metadata: Option<crate::encryption::EncryptionMetadata>, // Encryption metadata if enabled
+    pub drm: Option<armoricore_types::schemas::DrmInfo>, // Common Encryption details if DRM packaged
+    #[serde(default)]
+    pub ladder: Option<EncodingLadder>, // Bitrate ladder and why it was chosen (video only)
+    #[serde(default)]
+    pub media_info: Option<MediaInfo>, // Properties of the source file
     pub is_audio_only: bool, // True if this is audio-only content
     pub audio_bitrate: Option<u32>, // Audio bitrate in kbps (for audio-only)
     pub sample_rate: Option<u32>, // Sample rate in Hz (for audio-only)
 }
 
+/// HLS master playlist and the CMAF renditions it references
+struct HlsPackage {
+    master_playlist: PathBuf,
+    renditions: Vec<Rendition>,
+    /// Set when the renditions' segments are encrypted
+    encryption: Option<EncryptionMetadata>,
+    /// Set when the renditions are DRM protected
+    drm: Option<DrmPackage>,
+}
+
+/// What a video's HLS package is made of
+struct HlsPlan<'a> {
+    ladder: &'a EncodingLadder,
+    /// Color variants of every ladder rung
+    colors: &'a [VideoColor],
+    /// Source audio streams, each encoded once per audio codec
+    audio: &'a [AudioSource],
+    /// Segmented WebVTT renditions of sidecar subtitles
+    subtitles: Vec<Rendition>,
+}
+
+/// A source audio stream encoded into audio renditions
+#[derive(Debug, Clone)]
+struct AudioSource {
+    /// Absolute stream index in the source
+    stream: u32,
+    sample_rate: Option<u32>,
+    /// Name and language, when the source has several audio streams
+    track: Option<TrackInfo>,
+}
+
+impl AudioSource {
+    /// Every audio stream of `media_info`, the primary one first
+    ///
+    /// Tracks are only labelled when there is more than one; the primary
+    /// stream is the default track.
+    fn all(media_info: &MediaInfo) -> Vec<Self> {
+        let Some(primary) = media_info.audio_stream() else {
+            return vec![];
+        };
+        let streams: Vec<&StreamInfo> = media_info.streams_of(StreamKind::Audio).collect();
+        let labelled = streams.len() > 1;
+        let ordered = std::iter::once(primary).chain(streams.iter().copied().filter(|s| s.index != primary.index));
+
+        ordered
+            .map(|stream| {
+                let track = labelled.then(|| {
+                    let position = streams.iter().position(|s| s.index == stream.index).unwrap_or(0);
+                    let language = stream.language.clone().filter(|l| l != "und");
+                    TrackInfo {
+                        name: stream
+                            .title
+                            .clone()
+                            .or_else(|| language.clone())
+                            .unwrap_or_else(|| format!("Track {}", position + 1)),
+                        language,
+                        default: stream.index == primary.index,
+                        forced: false,
+                    }
+                });
+                Self {
+                    stream: stream.index,
+                    sample_rate: stream.audio.as_ref().and_then(|a| a.sample_rate),
+                    track,
+                }
+            })
+            .collect()
+    }
+
+    /// The primary stream, which loudness normalization is measured on
+    fn is_primary(&self) -> bool {
+        self.track.as_ref().is_none_or(|track| track.default)
+    }
+
+    /// Id of this stream's rendition for a codec whose group is `group`;
+    /// the primary stream's rendition is the group id itself
+    fn rendition_id(&self, group: &str) -> String {
+        if self.is_primary() {
+            group.to_string()
+        } else {
+            format!("{}_{}", group, self.stream)
+        }
+    }
+}
+
+impl HlsPackage {
+    fn renditions(package: &Option<HlsPackage>) -> &[Rendition] {
+        package.as_ref().map(|p| p.renditions.as_slice()).unwrap_or_default()
+    }
+}
+
 /// Video codec configuration
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VideoCodec {
@@ -95,6 +223,53 @@ impl VideoCodec {
         }
     }
 
+    /// RFC 6381 codec string for CMAF manifests (profile/level picked by height)
+    pub fn rfc6381_codec(&self, height: u32) -> String {
+        match self {
+            // High profile; level 3.0 / 3.1 / 4.0 / 5.0 / 5.1 / 6.0
+            VideoCodec::H264 => {
+                let level = match height {
+                    0..=480 => 0x1e,
+                    481..=720 => 0x1f,
+                    721..=1080 => 0x28,
+                    1081..=1440 => 0x32,
+                    1441..=2160 => 0x33,
+                    _ => 0x3c,
+                };
+                format!("avc1.6400{:02x}", level)
+            }
+            // Profile 0, 8-bit
+            VideoCodec::VP9 => {
+                let level = match height {
+                    0..=720 => 31,
+                    721..=1080 => 40,
+                    1081..=2160 => 51,
+                    _ => 61,
+                };
+                format!("vp09.00.{}.08", level)
+            }
+            // Main profile, 8-bit; seq_level_idx 3.1 / 4.0 / 5.1 / 6.1
+            VideoCodec::AV1 => {
+                let level = match height {
+                    0..=720 => 5,
+                    721..=1080 => 8,
+                    1081..=2160 => 13,
+                    _ => 17,
+                };
+                format!("av01.0.{:02}M.08", level)
+            }
+            // Main 10 profile; general_level_idc 4.1 / 5.1 / 6.1
+            VideoCodec::VVC => {
+                let level = match height {
+                    0..=1080 => 67,
+                    1081..=2160 => 83,
+                    _ => 99,
+                };
+                format!("vvc1.1.L{}.CQA", level)
+            }
+        }
+    }
+
     /// Get container format for this codec
     #[allow(dead_code)] // Method is part of public API, may be used by external code
     fn container_format(&self) -> &'static str {
@@ -142,6 +317,36 @@ impl AudioCodec {
     fn is_lossless(&self) -> bool {
         matches!(self, AudioCodec::Flac)
     }
+
+    /// Short name used for rendition directories and playlist labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Vorbis => "vorbis",
+            AudioCodec::Flac => "flac",
+        }
+    }
+
+    /// Codec used for CMAF renditions (Vorbis cannot be carried in fragmented MP4)
+    pub fn cmaf_codec(&self) -> AudioCodec {
+        match self {
+            AudioCodec::Vorbis => AudioCodec::Opus,
+            codec => *codec,
+        }
+    }
+
+    /// RFC 6381 codec string for CMAF manifests
+    pub fn rfc6381_codec(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "mp4a.40.2",
+            AudioCodec::Opus => "Opus",
+            AudioCodec::Mp3 => "mp4a.40.34",
+            AudioCodec::Vorbis => "vorbis",
+            AudioCodec::Flac => "fLaC",
+        }
+    }
 }
 
 /// Hardware acceleration backend
@@ -164,78 +369,58 @@ pub struct MediaProcessor {
     video_codec: VideoCodec,
     audio_codec: AudioCodec,
     encryption: Option<crate::encryption::ContentEncryption>,
+    hls_encryption: HlsEncryptionConfig,
+    drm: DrmConfig,
+    ladder: LadderConfig,
+    hdr: HdrConfig,
+    sprites: SpriteConfig,
+    posters: PosterConfig,
+    loudness: LoudnessConfig,
+    waveform: WaveformConfig,
+    image: ImageConfig,
+    ingest: IngestPolicy,
+    /// Limits every FFmpeg process runs under
+    sandbox: SandboxConfig,
+    /// Receives transcoding progress when set
+    progress: Option<tokio::sync::mpsc::UnboundedSender<ProgressUpdate>>,
     hardware_backend: Option<HardwareBackend>,
 }
 
 impl MediaProcessor {
     pub fn new() -> Self {
-        let ffmpeg_available = Self::check_ffmpeg_available();
-        if !ffmpeg_available {
-            warn!("FFmpeg is not available - will use mock processing");
-        }
-        let hardware_backend = Self::detect_hardware_backend();
-        if let Some(backend) = hardware_backend {
-            info!(backend = ?backend, "Hardware acceleration detected");
-        }
-        Self {
-            encryption: None, // Encryption disabled by default
-            ffmpeg_available,
-            downloader: None,
-            video_codec: Self::get_video_codec_from_env(),
-            audio_codec: Self::get_audio_codec_from_env(),
-            hardware_backend,
-        }
+        Self::base(None, None)
     }
 
     /// Create with object storage config for S3 downloads
     pub fn with_storage_config(
         s3_config: Option<armoricore_config::ObjectStorageConfig>,
     ) -> Self {
-        let ffmpeg_available = Self::check_ffmpeg_available();
-        if !ffmpeg_available {
-            warn!("FFmpeg is not available - will use mock processing");
-        }
-        let hardware_backend = Self::detect_hardware_backend();
-        if let Some(backend) = hardware_backend {
-            info!(backend = ?backend, "Hardware acceleration detected");
-        }
-        Self {
-            ffmpeg_available,
-            downloader: Some(FileDownloader::new(s3_config)),
-            video_codec: Self::get_video_codec_from_env(),
-            hardware_backend,
-            audio_codec: Self::get_audio_codec_from_env(),
-            encryption: None, // Encryption disabled by default
-        }
+        Self::base(Some(FileDownloader::new(s3_config)), None)
     }
 
     /// Create with encryption enabled
     pub fn with_encryption(
         key_store: Option<armoricore_keys::key_store::KeyStore>,
     ) -> Self {
-        let ffmpeg_available = Self::check_ffmpeg_available();
-        if !ffmpeg_available {
-            warn!("FFmpeg is not available - will use mock processing");
-        }
-        let hardware_backend = Self::detect_hardware_backend();
-        if let Some(backend) = hardware_backend {
-            info!(backend = ?backend, "Hardware acceleration detected");
-        }
-        Self {
-            ffmpeg_available,
-            downloader: None,
-            video_codec: Self::get_video_codec_from_env(),
-            audio_codec: Self::get_audio_codec_from_env(),
-            encryption: Some(crate::encryption::ContentEncryption::new(key_store)),
-            hardware_backend,
-        }
+        Self::base(None, Some(crate::encryption::ContentEncryption::new(key_store)))
     }
 
     /// Create with both storage config and encryption
-    #[allow(dead_code)] // Method is part of public API
     pub fn with_storage_and_encryption(
         s3_config: Option<armoricore_config::ObjectStorageConfig>,
         key_store: Option<armoricore_keys::key_store::KeyStore>,
+    ) -> Self {
+        Self::base(
+            Some(FileDownloader::new(s3_config)),
+            Some(crate::encryption::ContentEncryption::new(key_store)),
+        )
+    }
+
+    /// Processor with the detected FFmpeg setup and the environment's
+    /// configuration; HLS encryption and DRM start disabled
+    fn base(
+        downloader: Option<FileDownloader>,
+        encryption: Option<crate::encryption::ContentEncryption>,
     ) -> Self {
         let ffmpeg_available = Self::check_ffmpeg_available();
         if !ffmpeg_available {
@@ -247,14 +432,45 @@ impl MediaProcessor {
         }
         Self {
             ffmpeg_available,
-            downloader: Some(FileDownloader::new(s3_config)),
-            hardware_backend,
+            downloader,
             video_codec: Self::get_video_codec_from_env(),
             audio_codec: Self::get_audio_codec_from_env(),
-            encryption: Some(crate::encryption::ContentEncryption::new(key_store)),
+            encryption,
+            hls_encryption: HlsEncryptionConfig::default(),
+            drm: DrmConfig::default(),
+            ladder: LadderConfig::from_env(),
+            hdr: HdrConfig::from_env(),
+            sprites: SpriteConfig::from_env(),
+            posters: PosterConfig::from_env(),
+            loudness: LoudnessConfig::from_env(),
+            waveform: WaveformConfig::from_env(),
+            image: ImageConfig::from_env(),
+            ingest: IngestPolicy::from_env(),
+            sandbox: SandboxConfig::from_env(),
+            progress: None,
+            hardware_backend,
         }
     }
 
+    /// Encrypt HLS segments as configured (requires an encryption-enabled processor)
+    pub fn with_hls_encryption(mut self, config: HlsEncryptionConfig) -> Self {
+        self.hls_encryption = config;
+        self
+    }
+
+    /// Package renditions with Common Encryption as configured (requires an
+    /// encryption-enabled processor with a key store)
+    pub fn with_drm(mut self, config: DrmConfig) -> Self {
+        self.drm = config;
+        self
+    }
+
+    /// Send per-rendition transcoding progress to `sender`
+    pub fn with_progress(mut self, sender: tokio::sync::mpsc::UnboundedSender<ProgressUpdate>) -> Self {
+        self.progress = Some(sender);
+        self
+    }
+
     /// Get video codec from environment variable
     fn get_video_codec_from_env() -> VideoCodec {
         use std::env;
@@ -363,13 +579,22 @@ impl MediaProcessor {
         file_path: &str,
         content_type: &str,
     ) -> anyhow::Result<ProcessingResult> {
-        info!(
-            media_id = %media_id,
-            file_path = file_path,
-            content_type = content_type,
-            "Starting media processing"
-        );
+        self.process_media_cancellable(media_id, file_path, content_type, CancellationToken::new())
+            .await
+    }
 
+    /// Process a media file until done or until `cancel` fires
+    ///
+    /// Cancellation kills the running FFmpeg processes and fails with
+    /// [`crate::jobs::JobCancelled`]; partial outputs are removed with the
+    /// temporary directory.
+    pub async fn process_media_cancellable(
+        &self,
+        media_id: &Uuid,
+        file_path: &str,
+        content_type: &str,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<ProcessingResult> {
         // Create temporary directory for processing
         let temp_dir = TempDir::new()?;
         let output_dir = temp_dir.path().to_path_buf();
@@ -380,24 +605,58 @@ impl MediaProcessor {
             "Created temporary processing directory"
         );
 
+        self.process_media_job(media_id, file_path, content_type, &output_dir, &[], JobContext::new(cancel, None))
+            .await
+    }
+
+    /// Process a media file into `output_dir`
+    ///
+    /// With a journal in `job`, completed stages are recorded as they finish
+    /// and skipped when the journal says an earlier attempt got past them:
+    /// the downloaded source and probe results are reused, and so are the
+    /// renditions whose playlists are still in `output_dir`.
+    pub async fn process_media_job(
+        &self,
+        media_id: &Uuid,
+        file_path: &str,
+        content_type: &str,
+        output_dir: &Path,
+        subtitles: &[SubtitleSource],
+        mut job: JobContext,
+    ) -> anyhow::Result<ProcessingResult> {
+        info!(
+            media_id = %media_id,
+            file_path = file_path,
+            content_type = content_type,
+            "Starting media processing"
+        );
+        let output_dir = output_dir.to_path_buf();
+        let resumed_stage = job.journal.as_ref().map(|journal| journal.stage());
+        job.sandbox = self.sandbox.clone();
+
         // Check if this is a video or audio file
         let is_video = content_type.starts_with("video/");
         let is_audio = content_type.starts_with("audio/");
+        let is_image = images::is_supported(content_type);
 
-        if !is_video && !is_audio {
+        if !is_video && !is_audio && !is_image {
             return Err(anyhow::anyhow!("Unsupported content type: {}", content_type));
         }
 
+        let is_remote = file_path.starts_with("s3://")
+            || file_path.starts_with("http://")
+            || file_path.starts_with("https://");
+        if !is_remote && !Path::new(file_path).exists() {
+            return Err(anyhow::anyhow!("Input file does not exist: {}", file_path));
+        }
+
         if !self.ffmpeg_available {
             warn!("FFmpeg not available - using mock processing");
-            return self.mock_processing(media_id, output_dir).await;
+            return self.mock_processing(media_id, output_dir, is_image, &job).await;
         }
 
         // Download source file from S3/HTTP if needed
-        let input_path = if file_path.starts_with("s3://")
-            || file_path.starts_with("http://")
-            || file_path.starts_with("https://")
-        {
+        let input_path = if is_remote {
             // Download from remote source
             let downloader = self
                 .downloader
@@ -411,9 +670,13 @@ impl MediaProcessor {
 
             // Create temporary file for download
             let temp_file = output_dir.join(format!("source_{}.tmp", media_id));
-            downloader
-                .download_file(file_path, &temp_file, media_id)
-                .await?;
+            if resumed_stage >= Some(JobStage::Downloaded) && temp_file.exists() {
+                info!(media_id = %media_id, "Reusing downloaded source file");
+            } else {
+                downloader
+                    .download_file(file_path, &temp_file, media_id)
+                    .await?;
+            }
 
             temp_file
         } else {
@@ -425,12 +688,56 @@ impl MediaProcessor {
             return Err(anyhow::anyhow!("Input file does not exist: {}", file_path));
         }
 
-        // Extract metadata based on content type
-        let duration = self.extract_duration(&input_path).await?;
+        // The source is untrusted: bound its size and make sure it is the
+        // media it claims to be before FFmpeg opens it
+        self.ingest.check_file(&input_path, content_type)?;
+
+        if let Some(journal) = &job.journal {
+            journal.advance(JobStage::Downloaded)?;
+        }
+        job.check_cancelled()?;
+
+        // Probe the source once for every stage
+        let media_info = match job.journal.as_ref().and_then(|journal| journal.probe()) {
+            Some(media_info) => media_info,
+            None => {
+                let media_info = media_info::probe(&input_path, &job).await?;
+                if let Some(journal) = &job.journal {
+                    journal.set_probe(&media_info)?;
+                }
+                media_info
+            }
+        };
+        let duration = media_info.duration_secs();
+        self.ingest.check_duration(duration)?;
+        job.progress = self
+            .progress
+            .clone()
+            .map(|sender| ProgressReporter::new(*media_id, duration, sender));
+
+        if is_image {
+            return self.process_image(&input_path, output_dir, media_info, media_id, &job).await;
+        }
+
+        // Measure the source loudness so every audio encode is normalized
+        let loudness = self.measure_loudness(&input_path, &media_info, media_id, &job).await?;
+        if let Some(ref loudness) = loudness {
+            let sample_rate = media_info
+                .audio_stream()
+                .and_then(|stream| stream.audio.as_ref())
+                .and_then(|audio| audio.sample_rate)
+                .unwrap_or(48000);
+            job.audio_filter = Some(loudness.measured.filter(&self.loudness, sample_rate));
+        }
 
         if is_audio {
             // Audio-only processing
-            let (audio_bitrate, sample_rate) = self.extract_audio_metadata(&input_path).await?;
+            let audio = media_info
+                .audio_stream()
+                .ok_or_else(|| anyhow::anyhow!("Source has no audio stream"))?;
+            // Defaults to 128 kbps and 44.1 kHz when not reported
+            let audio_bitrate = audio.bit_rate.or(media_info.format.bit_rate).unwrap_or(128_000) as u32 / 1000;
+            let sample_rate = audio.audio.as_ref().and_then(|a| a.sample_rate).unwrap_or(44100);
             
             info!(
                 media_id = %media_id,
@@ -443,22 +750,51 @@ impl MediaProcessor {
             // Determine target audio bitrates (for adaptive streaming)
             let target_bitrates = self.determine_audio_bitrates(audio_bitrate);
 
-            // Transcode audio to HLS with multiple bitrates
-            let hls_playlist_path = self
-                .transcode_audio_to_hls(&input_path, &output_dir, &target_bitrates, media_id)
+            // Transcode audio to CMAF renditions with multiple bitrates
+            let hls_package = self
+                .transcode_audio_to_hls(&input_path, &output_dir, &target_bitrates, &AudioSource {
+                    stream: audio.index,
+                    sample_rate: Some(sample_rate),
+                    track: None,
+                }, media_id, &job)
                 .await?;
+            let hls_playlist_path = hls_package.as_ref().map(|p| p.master_playlist.clone());
 
-            // Generate audio-only MP4 files for each bitrate
-            let mp4_files = self
-                .transcode_audio_to_mp4(&input_path, &output_dir, &target_bitrates, media_id)
-                .await?;
+            let encryption_metadata = hls_package.as_ref().and_then(|p| p.encryption.clone());
+            let drm_package = hls_package.as_ref().and_then(|p| p.drm.clone());
 
-            // Generate DASH manifest (future implementation)
-            let dash_manifest_path = self
-                .generate_dash_manifest(&output_dir, &target_bitrates, media_id)
-                .await?;
+            // Generate audio-only MP4 files for each bitrate (clear files are
+            // not produced for encrypted output)
+            job.check_cancelled()?;
+            let mp4_files = if encryption_metadata.is_some() || drm_package.is_some() {
+                vec![]
+            } else {
+                self.transcode_audio_to_mp4(&input_path, &output_dir, &target_bitrates, media_id, &job)
+                    .await?
+            };
+
+            // Generate DASH manifest over the same CMAF segments
+            let dash_manifest_path = if encryption_metadata.is_some() {
+                None
+            } else {
+                let protection = drm_package.as_ref().map(|p| &p.protection);
+                self.generate_dash_manifest(&output_dir, HlsPackage::renditions(&hls_package), protection, media_id)
+                    .await?
+            };
 
-            // No thumbnails for audio-only
+            // No thumbnails for audio-only; waveform peaks instead
+            let waveform = if self.waveform.enabled {
+                match waveform::generate(&input_path, &output_dir, sample_rate, &self.waveform, &job).await {
+                    Ok(waveform) => waveform,
+                    Err(e) if crate::jobs::is_cancelled(&e) => return Err(e),
+                    Err(e) => {
+                        warn!(media_id = %media_id, error = %e, "Failed to generate waveform");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
 
             // Collect all output files
             let mut output_files = vec![];
@@ -469,9 +805,9 @@ impl MediaProcessor {
             if let Some(ref dash_manifest) = dash_manifest_path {
                 output_files.push(dash_manifest.clone());
             }
-
-            // Generate thumbnail URLs (empty for audio-only)
-            let thumbnail_urls: Vec<String> = vec![];
+            if let Some(ref waveform) = waveform {
+                output_files.extend(waveform.levels.iter().map(|level| level.path.clone()));
+            }
 
             info!(
                 media_id = %media_id,
@@ -484,21 +820,33 @@ impl MediaProcessor {
 
             Ok(ProcessingResult {
                 output_dir,
-                thumbnail_urls,
+                thumbnails: vec![],
+                sprites: None,
+                posters: vec![],
+                loudness,
+                waveform,
+                image: None,
                 duration,
                 resolutions: target_bitrates,
                 hls_playlist_path,
                 mp4_files,
                 dash_manifest_path,
                 output_files,
-                encryption_metadata: None,
+                encryption_metadata,
+                drm: drm_package.map(|p| p.info),
+                ladder: None,
+                media_info: Some(media_info),
                 is_audio_only: true,
                 audio_bitrate: Some(audio_bitrate),
                 sample_rate: Some(sample_rate),
             })
         } else {
             // Video processing (existing logic)
-            let (width, height) = self.extract_resolution(&input_path).await?;
+            let (width, height) = media_info
+                .video_stream()
+                .and_then(|stream| stream.video.as_ref())
+                .map(|video| video.display_size())
+                .ok_or_else(|| anyhow::anyhow!("Source has no video stream"))?;
 
             info!(
                 media_id = %media_id,
@@ -507,28 +855,118 @@ impl MediaProcessor {
                 "Extracted video metadata"
             );
 
+            // HDR sources get HDR renditions and tone-mapped SDR renditions
+            let hdr_source = HdrSource::detect(&media_info);
+            if let Some(ref source) = hdr_source {
+                info!(media_id = %media_id, format = source.format.as_str(), "Source is HDR");
+            }
+            let colors = self.hdr.targets(hdr_source.as_ref());
+            let sdr_filter = self.hdr.sdr_filter(hdr_source.as_ref());
+
             // Determine target resolutions based on source
             let target_resolutions = self.determine_resolutions(width, height);
-
-            // Transcode to multiple bitrates and create HLS segments
-            let hls_playlist_path = self
-                .transcode_to_hls(&input_path, &output_dir, &target_resolutions, media_id)
+            let ladder = self
+                .build_ladder(&input_path, &output_dir, duration, &target_resolutions, &job)
                 .await?;
 
-            // Generate MP4 files for each resolution
-            let mp4_files = self
-                .transcode_to_mp4(&input_path, &output_dir, &target_resolutions, media_id)
+            // Every source audio stream and sidecar subtitle file becomes an
+            // alternative rendition
+            let exact_duration = media_info.format.duration.unwrap_or(duration as f64);
+            let audio_sources = AudioSource::all(&media_info);
+            let subtitle_renditions = self
+                .package_subtitles(subtitles, &output_dir, exact_duration, media_id, &job)
                 .await?;
 
-            // Generate DASH manifest (future implementation)
-            let dash_manifest_path = self
-                .generate_dash_manifest(&output_dir, &target_resolutions, media_id)
+            // Transcode to multiple bitrates and create CMAF segments with HLS playlists
+            let plan = HlsPlan {
+                ladder: &ladder,
+                colors: &colors,
+                audio: &audio_sources,
+                subtitles: subtitle_renditions,
+            };
+            let hls_package = self
+                .transcode_to_hls(&input_path, &output_dir, plan, media_id, &job)
                 .await?;
+            let hls_playlist_path = hls_package.as_ref().map(|p| p.master_playlist.clone());
+
+            let encryption_metadata = hls_package.as_ref().and_then(|p| p.encryption.clone());
+            let drm_package = hls_package.as_ref().and_then(|p| p.drm.clone());
+
+            // Generate MP4 files for each resolution (clear files are not
+            // produced for encrypted output)
+            job.check_cancelled()?;
+            let mp4_files = if encryption_metadata.is_some() || drm_package.is_some() {
+                vec![]
+            } else {
+                self.transcode_to_mp4(&input_path, &output_dir, &ladder, sdr_filter.as_deref(), media_id, &job)
+                    .await?
+            };
+
+            // Generate DASH manifest over the same CMAF segments
+            let dash_manifest_path = if encryption_metadata.is_some() {
+                None
+            } else {
+                let protection = drm_package.as_ref().map(|p| &p.protection);
+                self.generate_dash_manifest(&output_dir, HlsPackage::renditions(&hls_package), protection, media_id)
+                    .await?
+            };
+
+            // Pick poster frames by scene changes and frame quality, falling
+            // back to evenly spaced frames
+            let candidates = if self.posters.enabled {
+                match posters::analyze(&input_path, exact_duration, sdr_filter.as_deref(), &self.posters, &job).await {
+                    Ok(candidates) => candidates,
+                    Err(e) if crate::jobs::is_cancelled(&e) => return Err(e),
+                    Err(e) => {
+                        warn!(media_id = %media_id, error = %e, "Poster selection failed, using fixed timestamps");
+                        vec![]
+                    }
+                }
+            } else {
+                vec![]
+            };
+            let timestamps: Vec<f64> = if candidates.is_empty() {
+                self.posters.fixed_timestamps(exact_duration)
+            } else {
+                candidates.iter().map(|c| c.frame.time).collect()
+            };
 
             // Generate thumbnails
-            let thumbnail_paths = self
-                .generate_thumbnails(&input_path, &output_dir, media_id, 3)
+            let thumbnails = self
+                .generate_thumbnails(&input_path, &output_dir, sdr_filter.as_deref(), &timestamps, &job)
                 .await?;
+            let posters: Vec<Poster> = thumbnails
+                .iter()
+                .filter_map(|(index, path)| {
+                    candidates.get(*index).map(|candidate| Poster {
+                        path: path.clone(),
+                        candidate: candidate.clone(),
+                    })
+                })
+                .collect();
+            let thumbnail_paths: Vec<PathBuf> = thumbnails.into_iter().map(|(_, path)| path).collect();
+            let sprites = if self.sprites.enabled {
+                match sprites::generate(
+                    &input_path,
+                    &output_dir,
+                    exact_duration,
+                    (width, height),
+                    sdr_filter.as_deref(),
+                    &self.sprites,
+                    &job,
+                )
+                .await
+                {
+                    Ok(sprites) => sprites,
+                    Err(e) if crate::jobs::is_cancelled(&e) => return Err(e),
+                    Err(e) => {
+                        warn!(media_id = %media_id, error = %e, "Failed to generate sprite sheets");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
 
             // Collect all output files
             let mut output_files = vec![];
@@ -540,19 +978,10 @@ impl MediaProcessor {
                 output_files.push(dash_manifest.clone());
             }
             output_files.extend(thumbnail_paths.iter().cloned());
-
-            // Generate thumbnail URLs (will be uploaded to S3)
-            let thumbnail_urls: Vec<String> = thumbnail_paths
-                .iter()
-                .enumerate()
-                .map(|(i, _)| {
-                    format!(
-                        "https://cdn.example.com/media/{}/thumb_{}.jpg",
-                        media_id,
-                        i + 1
-                    )
-                })
-                .collect();
+            if let Some(ref sprites) = sprites {
+                output_files.extend(sprites.sheets.iter().cloned());
+                output_files.push(sprites.vtt.clone());
+            }
 
             info!(
                 media_id = %media_id,
@@ -566,14 +995,22 @@ impl MediaProcessor {
 
             Ok(ProcessingResult {
                 output_dir,
-                thumbnail_urls,
+                thumbnails: thumbnail_paths,
+                sprites,
+                posters,
+                loudness,
+                waveform: None,
+                image: None,
                 duration,
                 resolutions: target_resolutions,
                 hls_playlist_path,
                 mp4_files,
                 dash_manifest_path,
                 output_files,
-                encryption_metadata: None,
+                encryption_metadata,
+                drm: drm_package.map(|p| p.info),
+                ladder: Some(ladder),
+                media_info: Some(media_info),
                 is_audio_only: false,
                 audio_bitrate: None,
                 sample_rate: None,
@@ -581,114 +1018,6 @@ impl MediaProcessor {
         }
     }
 
-    /// Extract video duration using FFprobe
-    async fn extract_duration(&self, input_path: &Path) -> anyhow::Result<u64> {
-        let output = Command::new("ffprobe")
-            .args(&[
-                "-v",
-                "error",
-                "-show_entries",
-                "format=duration",
-                "-of",
-                "default=noprint_wrappers=1:nokey=1",
-                input_path.to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
-            ])
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to extract duration"));
-        }
-
-        let duration_str = String::from_utf8(output.stdout)?;
-        let duration_f64: f64 = duration_str.trim().parse()?;
-        Ok(duration_f64 as u64)
-    }
-
-    /// Extract video resolution
-    async fn extract_resolution(&self, input_path: &Path) -> anyhow::Result<(u32, u32)> {
-        let output = Command::new("ffprobe")
-            .args(&[
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=width,height",
-                "-of",
-                "csv=s=x:p=0",
-                input_path.to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
-            ])
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("Failed to extract resolution"));
-        }
-
-        let resolution_str = String::from_utf8(output.stdout)?;
-        let parts: Vec<&str> = resolution_str.trim().split('x').collect();
-        if parts.len() != 2 {
-            return Err(anyhow::anyhow!("Invalid resolution format"));
-        }
-
-        let width: u32 = parts[0].parse()?;
-        let height: u32 = parts[1].parse()?;
-
-        Ok((width, height))
-    }
-
-    /// Extract audio metadata (bitrate and sample rate)
-    async fn extract_audio_metadata(&self, input_path: &Path) -> anyhow::Result<(u32, u32)> {
-        // Extract bitrate
-        let bitrate_output = Command::new("ffprobe")
-            .args(&[
-                "-v",
-                "error",
-                "-select_streams",
-                "a:0",
-                "-show_entries",
-                "stream=bit_rate",
-                "-of",
-                "default=noprint_wrappers=1:nokey=1",
-                input_path.to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
-            ])
-            .output()?;
-
-        let bitrate = if bitrate_output.status.success() {
-            let bitrate_str = String::from_utf8(bitrate_output.stdout)?;
-            bitrate_str.trim().parse::<u32>().unwrap_or(128000) / 1000 // Convert to kbps
-        } else {
-            128 // Default to 128 kbps if extraction fails
-        };
-
-        // Extract sample rate
-        let sample_rate_output = Command::new("ffprobe")
-            .args(&[
-                "-v",
-                "error",
-                "-select_streams",
-                "a:0",
-                "-show_entries",
-                "stream=sample_rate",
-                "-of",
-                "default=noprint_wrappers=1:nokey=1",
-                input_path.to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
-            ])
-            .output()?;
-
-        let sample_rate = if sample_rate_output.status.success() {
-            let sample_rate_str = String::from_utf8(sample_rate_output.stdout)?;
-            sample_rate_str.trim().parse::<u32>().unwrap_or(44100) // Default to 44.1 kHz
-        } else {
-            44100 // Default to 44.1 kHz if extraction fails
-        };
-
-        Ok((bitrate, sample_rate))
-    }
-
     /// Determine target resolutions based on source resolution
     fn determine_resolutions(&self, _width: u32, height: u32) -> Vec<String> {
         let mut resolutions = Vec::new();
@@ -765,59 +1094,116 @@ impl MediaProcessor {
         bitrates
     }
 
-    /// Transcode video to HLS with multiple bitrates
+    /// Transcode video to CMAF renditions with an HLS master playlist
     /// Uses parallel processing for faster encoding of multiple resolutions
+    ///
+    /// Video and audio are packaged as separate fragmented MP4 renditions so
+    /// the same segments can be referenced from the DASH manifest. Each
+    /// resolution is paired with the audio codecs returned by
+    /// `get_audio_codecs_for_resolution` (two for dual-track resolutions).
+    /// Every rung of `ladder` is encoded once per entry of `colors`.
     async fn transcode_to_hls(
         &self,
         input_path: &Path,
         output_dir: &Path,
-        resolutions: &[String],
+        plan: HlsPlan<'_>,
         media_id: &Uuid,
-    ) -> anyhow::Result<Option<PathBuf>> {
+        job: &JobContext,
+    ) -> anyhow::Result<Option<HlsPackage>> {
+        let HlsPlan { ladder, colors, audio: audio_sources, subtitles } = plan;
+        let resolutions: Vec<&str> = ladder.rungs.iter().map(|rung| rung.resolution.as_str()).collect();
         info!(
             "Transcoding to HLS with {} resolution(s): {:?}",
             resolutions.len(),
             resolutions
         );
 
-        // Process resolutions in parallel for better performance
-        let tasks: Vec<_> = resolutions.iter()
-            .map(|resolution| {
+        // Collect the audio renditions needed by any resolution
+        let high_res = resolutions.iter().any(|r| Self::is_high_resolution(r));
+        let mut audio_codecs: Vec<AudioCodec> = Vec::new();
+        let mut pairings = Vec::with_capacity(resolutions.len());
+        for resolution in &resolutions {
+            let (primary, secondary) = self.get_audio_codecs_for_resolution(resolution);
+            let codecs: Vec<AudioCodec> = std::iter::once(primary)
+                .chain(secondary)
+                .map(|codec| codec.cmaf_codec())
+                .collect();
+            for codec in &codecs {
+                if !audio_codecs.contains(codec) {
+                    audio_codecs.push(*codec);
+                }
+            }
+            pairings.push(codecs);
+        }
+
+        // Process renditions in parallel for better performance
+        let video_tasks: Vec<_> = colors.iter()
+            .flat_map(|color| ladder.rungs.iter().zip(&pairings).map(move |(rung, audio)| (color, rung, audio)))
+            .map(|(color, rung, audio)| {
                 let input = input_path.to_path_buf();
                 let output = output_dir.to_path_buf();
-                let res = resolution.clone();
-                let media_id = *media_id;
-                let video_codec = self.video_codec;
-                let audio_codec = self.audio_codec;
-                
+                let rung = rung.clone();
+                let color = color.clone();
+                let audio_ids: Vec<String> = audio.iter().map(|c| Self::audio_rendition_id(*c)).collect();
+                let hardware_backend = self.hardware_backend;
+                let job = job.clone();
+
                 tokio::spawn(async move {
                     Self::transcode_single_resolution_hls(
                         &input,
                         &output,
-                        &res,
-                        media_id,
-                        video_codec,
-                        audio_codec,
+                        &rung,
+                        &color,
+                        audio_ids,
+                        hardware_backend,
+                        &job,
                     ).await
                 })
             })
             .collect();
-        
+
+        // One rendition per audio codec and source audio stream; the streams
+        // of a codec form its audio group
+        let audio_tasks: Vec<_> = audio_codecs.iter()
+            .flat_map(|&codec| audio_sources.iter().map(move |source| (codec, source)))
+            .map(|(codec, source)| {
+                let input = input_path.to_path_buf();
+                let output = output_dir.to_path_buf();
+                let bitrate = Self::audio_bitrate_for(codec, high_res);
+                let rendition_id = source.rendition_id(&Self::audio_rendition_id(codec));
+                let source = source.clone();
+                let job = job.clone();
+
+                tokio::spawn(async move {
+                    Self::transcode_audio_rendition_hls(
+                        &input,
+                        &output,
+                        &rendition_id,
+                        codec,
+                        bitrate,
+                        &source,
+                        &job,
+                    ).await
+                })
+            })
+            .collect();
+
         // Wait for all tasks to complete
-        let results: Vec<_> = join_all(tasks).await;
-        
-        let mut variant_playlists = Vec::new();
-        for result in results {
+        let (video_results, audio_results) = futures::join!(join_all(video_tasks), join_all(audio_tasks));
+        job.check_cancelled()?;
+
+        let mut renditions = Vec::new();
+        for result in video_results.into_iter().chain(audio_results) {
             match result {
-                Ok(Ok(Some((resolution, playlist_path)))) => {
-                    variant_playlists.push((resolution, playlist_path));
+                Ok(Ok(Some(rendition))) => {
+                    renditions.push(rendition);
                 }
                 Ok(Ok(None)) => {
-                    // Resolution skipped (e.g., codec not available)
+                    // Rendition skipped (e.g., codec not available)
                     continue;
                 }
                 Ok(Err(e)) => {
-                    warn!("Failed to transcode resolution: {}", e);
+                    warn!("Failed to transcode rendition: {}", e);
                     continue;
                 }
                 Err(e) => {
@@ -827,58 +1213,83 @@ impl MediaProcessor {
             }
         }
 
-        if variant_playlists.is_empty() {
+        if !renditions.iter().any(Rendition::is_video) {
             return Err(anyhow::anyhow!("Failed to transcode any variants"));
         }
 
+        // Encrypt segments before the master playlist lists the renditions
+        self.forget_renditions_before_encryption(job)?;
+        let encryption = self.encrypt_hls_renditions(&mut renditions, media_id).await?;
+        let drm = self.protect_drm_renditions(&mut renditions, media_id).await?;
+        if !renditions.iter().any(Rendition::is_video) {
+            return Err(anyhow::anyhow!("No video rendition could be encrypted"));
+        }
+        // WebVTT subtitles are left in the clear
+        renditions.extend(subtitles);
+
         // Create master playlist
         let master_playlist_path = output_dir.join("master.m3u8");
-        Self::create_master_playlist(&master_playlist_path, &variant_playlists, media_id)?;
+        Self::create_master_playlist(&master_playlist_path, &renditions, media_id)?;
 
         info!(
-            variants = variant_playlists.len(),
+            renditions = renditions.len(),
             "HLS transcoding completed with multiple bitrates (parallel processing)"
         );
 
-        Ok(Some(master_playlist_path))
+        Ok(Some(HlsPackage {
+            master_playlist: master_playlist_path,
+            renditions,
+            encryption,
+            drm,
+        }))
     }
-    
-    /// Transcode a single resolution to HLS (used for parallel processing)
+
+    /// Transcode a single resolution to a video-only CMAF rendition (used for parallel processing)
+    ///
+    /// Tone-mapped and HDR renditions are always encoded in software.
     async fn transcode_single_resolution_hls(
         input_path: &Path,
         output_dir: &Path,
-        resolution: &str,
-        _media_id: Uuid,
-        _video_codec: VideoCodec,
-        audio_codec: AudioCodec,
-    ) -> anyhow::Result<Option<(String, PathBuf)>> {
-        let (width, height, bitrate) = Self::get_resolution_params(resolution);
-        
-        // Get audio codec(s) for this resolution
-        let (primary_audio_codec, _secondary_audio_codec) = Self::get_audio_codecs_for_resolution_static(resolution, audio_codec);
+        rung: &LadderRung,
+        color: &VideoColor,
+        audio_rendition_ids: Vec<String>,
+        hardware_backend: Option<HardwareBackend>,
+        job: &JobContext,
+    ) -> anyhow::Result<Option<Rendition>> {
+        let resolution = rung.resolution.as_str();
+        let rendition_id = color.rendition_id(resolution);
+        if let Some(rendition) = job.completed_rendition(&rendition_id) {
+            info!(rendition = rendition_id, "Reusing transcoded variant");
+            return Ok(Some(rendition));
+        }
+        let (width, height, bitrate) = (rung.width, rung.height, rung.bitrate_kbps);
         
-        let variant_dir = output_dir.join(resolution);
+        let variant_dir = output_dir.join(&rendition_id);
         std::fs::create_dir_all(&variant_dir)?;
         
         let variant_playlist = variant_dir.join("playlist.m3u8");
-        let segment_pattern = variant_dir.join("segment_%03d.ts");
 
         info!(
-            resolution = resolution,
+            rendition = rendition_id,
             width = width,
             height = height,
             bitrate = bitrate,
-            primary_audio = ?primary_audio_codec,
+            range = color.range().hls_value(),
             "Transcoding variant (parallel)"
         );
 
-        // Select optimal codec for this resolution
-        let codec_to_use = VideoCodec::recommended_for_resolution(resolution);
+        // Select optimal codec for this resolution; tone-mapped SDR renditions
+        // are H.264 for the widest compatibility
+        let codec_to_use = match color {
+            VideoColor::Sdr => VideoCodec::recommended_for_resolution(resolution),
+            _ => VideoCodec::H264,
+        };
+        let hardware_backend = hardware_backend.filter(|_| matches!(color, VideoColor::Sdr));
         
         // Try to use hardware acceleration if available
-        // Note: For static function, we detect hardware on the fly
-        let hardware_backend = Self::detect_hardware_backend();
-        let video_codec_name = if let Some(backend) = hardware_backend {
+        let video_codec_name = if let VideoColor::Hdr(_, hdr_codec) = color {
+            hdr_codec.ffmpeg_codec()
+        } else if let Some(backend) = hardware_backend {
             if let Some(hw_codec) = codec_to_use.ffmpeg_hw_codec(backend) {
                 info!(resolution = resolution, hw_codec = hw_codec, "Using hardware acceleration");
                 hw_codec
@@ -894,22 +1305,33 @@ impl MediaProcessor {
         let bufsize_str = format!("{}k", bitrate * 2);
         
         // Use high-quality Lanczos downscaling for 8K→4K/5K conversions
-        let vf_filter = if resolution == "4K" || resolution == "2160p" {
+        let mut vf_filter = if resolution == "4K" || resolution == "2160p" {
             format!("scale={}:{}:flags=lanczos+accurate_rnd+full_chroma_int:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2", width, height, width, height)
         } else {
             format!("scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2", width, height, width, height)
         };
+        if let VideoColor::ToneMapped(tonemap) = color {
+            vf_filter = format!("{},{}", tonemap, vf_filter);
+        }
+        let hdr_args = match color {
+            VideoColor::Hdr(source, hdr_codec) => hdr_codec.encode_args(source),
+            _ => Vec::new(),
+        };
         
         let mut ffmpeg_args = vec![
             "-i",
             input_path.to_str()
                 .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
+            "-map",
+            "0:v:0",
             "-c:v",
             video_codec_name,
         ];
 
         // Add codec-specific arguments (hardware encoders may have different args)
-        if !video_codec_name.contains("nvenc") && !video_codec_name.contains("videotoolbox") && !video_codec_name.contains("vaapi") {
+        if !hdr_args.is_empty() {
+            ffmpeg_args.extend(hdr_args.iter().map(String::as_str));
+        } else if !video_codec_name.contains("nvenc") && !video_codec_name.contains("videotoolbox") && !video_codec_name.contains("vaapi") {
             // Only add software codec args for software encoders
             ffmpeg_args.extend_from_slice(&codec_to_use.ffmpeg_args());
         } else {
@@ -924,6 +1346,9 @@ impl MediaProcessor {
             }
         }
 
+        // Keyframes on the segment grid keep segments aligned across renditions
+        let keyframe_expr = format!("expr:gte(t,n_forced*{})", dash::SEGMENT_DURATION_SECS);
+
         // Add bitrate control
         ffmpeg_args.extend_from_slice(&[
             "-maxrate",
@@ -932,33 +1357,148 @@ impl MediaProcessor {
             &bufsize_str,
             "-vf",
             &vf_filter,
-            "-c:a",
-            primary_audio_codec.ffmpeg_codec(),
+            "-force_key_frames",
+            &keyframe_expr,
+            "-an", // Audio is packaged as separate renditions
         ]);
 
-        // Add bitrate for lossy codecs
-        if let Some(audio_bitrate) = primary_audio_codec.bitrate() {
-            let bitrate_to_use = if matches!(resolution, "8K" | "4320p" | "5K" | "2880p" | "4K" | "2160p") 
-                && primary_audio_codec == AudioCodec::Opus {
-                "192k"
-            } else {
-                audio_bitrate
-            };
+        if !Self::run_cmaf_hls(ffmpeg_args, &variant_dir, &variant_playlist, &rendition_id, job).await? {
+            warn!(rendition = rendition_id, "Failed to transcode variant, skipping");
+            return Ok(None);
+        }
+
+        info!(rendition = rendition_id, "Variant transcoding completed (parallel)");
+        let codecs = match color {
+            VideoColor::Hdr(_, hdr_codec) => hdr_codec.rfc6381_codec(height, color.range()),
+            _ => codec_to_use.rfc6381_codec(height),
+        };
+        let rendition = Rendition {
+            id: rendition_id,
+            kind: RenditionKind::Video {
+                width,
+                height,
+                audio: audio_rendition_ids,
+                range: color.range(),
+            },
+            codecs,
+            bandwidth: bitrate as u64 * 1000,
+            playlist: variant_playlist,
+        };
+        job.record_rendition(&rendition)?;
+        Ok(Some(rendition))
+    }
+
+    /// Transcode the audio track to an audio-only CMAF rendition
+    async fn transcode_audio_rendition_hls(
+        input_path: &Path,
+        output_dir: &Path,
+        rendition_id: &str,
+        codec: AudioCodec,
+        bitrate: Option<&str>,
+        source: &AudioSource,
+        job: &JobContext,
+    ) -> anyhow::Result<Option<Rendition>> {
+        if let Some(rendition) = job.completed_rendition(rendition_id) {
+            info!(rendition = rendition_id, "Reusing transcoded audio rendition");
+            return Ok(Some(rendition));
+        }
+        let rendition_dir = output_dir.join(rendition_id);
+        std::fs::create_dir_all(&rendition_dir)?;
+
+        let rendition_playlist = rendition_dir.join("playlist.m3u8");
+
+        info!(
+            rendition = rendition_id,
+            codec = ?codec,
+            bitrate = ?bitrate,
+            "Transcoding audio rendition"
+        );
+
+        let stream_map = format!("0:{}", source.stream);
+        let mut ffmpeg_args = vec![
+            "-i",
+            input_path.to_str()
+                .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
+            "-map",
+            &stream_map,
+            "-vn", // No video
+            "-c:a",
+            codec.ffmpeg_codec(),
+        ];
+
+        // Add bitrate for lossy codecs (FLAC is lossless, no bitrate)
+        if let Some(bitrate) = bitrate {
             ffmpeg_args.push("-b:a");
-            ffmpeg_args.push(bitrate_to_use);
+            ffmpeg_args.push(bitrate);
+        }
+        // Loudness is measured on the primary stream only
+        if let Some(audio_filter) = job.audio_filter.as_ref().filter(|_| source.is_primary()) {
+            ffmpeg_args.extend_from_slice(&["-af", audio_filter]);
+        }
+
+        if !Self::run_cmaf_hls(ffmpeg_args, &rendition_dir, &rendition_playlist, rendition_id, job).await? {
+            warn!(rendition = rendition_id, "Failed to transcode audio rendition, skipping");
+            return Ok(None);
         }
 
-        // Add HLS-specific options
+        // Lossless audio has no target bitrate; advertise a typical FLAC peak
+        let bandwidth = bitrate
+            .and_then(|b| b.trim_end_matches('k').parse::<u64>().ok())
+            .map(|kbps| kbps * 1000)
+            .unwrap_or(1_000_000);
+
+        info!(rendition = rendition_id, "Audio rendition transcoding completed");
+        let rendition = Rendition {
+            id: rendition_id.to_string(),
+            kind: RenditionKind::Audio {
+                // Opus always decodes at 48 kHz
+                sample_rate: if codec == AudioCodec::Opus { Some(48000) } else { source.sample_rate },
+                group: source.track.as_ref().map(|_| Self::audio_rendition_id(codec)),
+                track: source.track.clone(),
+            },
+            codecs: codec.rfc6381_codec().to_string(),
+            bandwidth,
+            playlist: rendition_playlist,
+        };
+        job.record_rendition(&rendition)?;
+        Ok(Some(rendition))
+    }
+
+    /// Run FFmpeg with the HLS muxer writing CMAF (fragmented MP4) segments
+    ///
+    /// `ffmpeg_args` holds the input and encoding options; returns whether
+    /// FFmpeg succeeded. FFmpeg's `-progress` output is reported for
+    /// `rendition_id` when the job has a progress reporter.
+    async fn run_cmaf_hls(
+        ffmpeg_args: Vec<&str>,
+        rendition_dir: &Path,
+        playlist: &Path,
+        rendition_id: &str,
+        job: &JobContext,
+    ) -> anyhow::Result<bool> {
+        let segment_pattern = rendition_dir.join(dash::SEGMENT_FILE_PATTERN);
         let segment_filename = segment_pattern.to_str()
             .ok_or_else(|| anyhow::anyhow!("Segment pattern contains invalid UTF-8: {:?}", segment_pattern))?;
-        let playlist_path = variant_playlist.to_str()
-            .ok_or_else(|| anyhow::anyhow!("Playlist path contains invalid UTF-8: {:?}", variant_playlist))?;
-        
-        ffmpeg_args.extend_from_slice(&[
+        let playlist_path = playlist.to_str()
+            .ok_or_else(|| anyhow::anyhow!("Playlist path contains invalid UTF-8: {:?}", playlist))?;
+        let segment_duration = dash::SEGMENT_DURATION_SECS.to_string();
+
+        // Overwrite the leftovers of an interrupted attempt
+        let mut args: Vec<&str> = vec!["-y", "-progress", "pipe:1", "-nostats"];
+        args.extend(ffmpeg_args);
+        args.extend_from_slice(&[
             "-hls_time",
-            "10",
+            &segment_duration,
             "-hls_list_size",
             "0",
+            "-hls_playlist_type",
+            "vod",
+            "-hls_segment_type",
+            "fmp4",
+            "-hls_fmp4_init_filename",
+            dash::INIT_SEGMENT_NAME,
+            "-hls_flags",
+            "independent_segments",
             "-hls_segment_filename",
             segment_filename,
             "-f",
@@ -966,29 +1506,35 @@ impl MediaProcessor {
             playlist_path,
         ]);
 
-        let status = Command::new("ffmpeg")
-            .args(&ffmpeg_args)
-            .status()?;
-
-        if !status.success() {
-            warn!(resolution = resolution, "Failed to transcode variant, skipping");
-            return Ok(None);
-        }
+        let mut parser = FfmpegProgressParser::new();
+        let status = job
+            .run(ingest::ffmpeg_command("ffmpeg").args(&args), |line| {
+                if let (Some(block), Some(progress)) = (parser.push_line(line), &job.progress) {
+                    progress.report(rendition_id, &block);
+                }
+            })
+            .await?;
+        Ok(status.success())
+    }
 
-        info!(resolution = resolution, "Variant transcoding completed (parallel)");
-        Ok(Some((resolution.to_string(), variant_playlist)))
+    /// Rendition id (and directory) of the audio track for `codec` in video outputs
+    fn audio_rendition_id(codec: AudioCodec) -> String {
+        format!("audio_{}", codec.name())
     }
-    
-    /// Get audio codecs for resolution (static version for parallel processing)
-    fn get_audio_codecs_for_resolution_static(resolution: &str, default_audio_codec: AudioCodec) -> (AudioCodec, Option<AudioCodec>) {
-        // For high-res (8K, 5K, 4K), use Opus + FLAC dual tracks
-        if matches!(resolution, "8K" | "4320p" | "5K" | "2880p" | "4K" | "2160p") {
-            (AudioCodec::Opus, Some(AudioCodec::Flac))
-        } else {
-            (default_audio_codec, None)
+
+    /// Audio bitrate for a rendition; Opus gets 192 kbps for high-res sources
+    fn audio_bitrate_for(codec: AudioCodec, high_res: bool) -> Option<&'static str> {
+        match codec.bitrate() {
+            Some(_) if high_res && codec == AudioCodec::Opus => Some("192k"),
+            bitrate => bitrate,
         }
     }
 
+    /// Whether `resolution` is 4K or above
+    fn is_high_resolution(resolution: &str) -> bool {
+        matches!(resolution, "8K" | "4320p" | "5K" | "2880p" | "4K" | "2160p")
+    }
+
 
     /// Get resolution parameters (width, height, bitrate) for a resolution string
     fn get_resolution_params(resolution: &str) -> (u32, u32, u32) {
@@ -1022,10 +1568,64 @@ impl MediaProcessor {
         }
     }
 
+    /// Width, height and bitrate of a resolution in `ladder`
+    fn ladder_params(ladder: &EncodingLadder, resolution: &str) -> (u32, u32, u32) {
+        match ladder.rung(resolution) {
+            Some(rung) => (rung.width, rung.height, rung.bitrate_kbps),
+            None => Self::get_resolution_params(resolution),
+        }
+    }
+
+    /// Build the bitrate ladder of `resolutions`
+    ///
+    /// With per-title encoding, the default bitrates are scaled by the
+    /// source's complexity; if the analysis fails the defaults are kept.
+    async fn build_ladder(
+        &self,
+        input_path: &Path,
+        output_dir: &Path,
+        duration: u64,
+        resolutions: &[String],
+        job: &JobContext,
+    ) -> anyhow::Result<EncodingLadder> {
+        let defaults: Vec<LadderRung> = resolutions
+            .iter()
+            .map(|resolution| {
+                let (width, height, bitrate) = Self::get_resolution_params(resolution);
+                LadderRung {
+                    resolution: resolution.clone(),
+                    width,
+                    height,
+                    bitrate_kbps: bitrate,
+                    default_bitrate_kbps: bitrate,
+                }
+            })
+            .collect();
+
+        if !self.ladder.per_title {
+            return Ok(EncodingLadder::fixed(defaults, "Per-title encoding disabled; default ladder"));
+        }
+
+        let ladder = match ladder::analyze(input_path, duration, output_dir, &self.ladder, job).await {
+            Ok(analysis) => EncodingLadder::per_title(defaults, analysis),
+            Err(e) if crate::jobs::is_cancelled(&e) => return Err(e),
+            Err(e) => {
+                warn!(error = %e, "Complexity analysis failed, using the default ladder");
+                EncodingLadder::fixed(defaults, format!("Complexity analysis failed ({}); default ladder", e))
+            }
+        };
+        info!(scale = ladder.scale, reasoning = ladder.reasoning, "Built encoding ladder");
+        Ok(ladder)
+    }
+
     /// Create master HLS playlist referencing all variants
+    ///
+    /// Each audio rendition gets its own `EXT-X-MEDIA` group; video variants
+    /// are listed once per audio group they are paired with. When any variant
+    /// is HDR, every variant carries a `VIDEO-RANGE` attribute.
     fn create_master_playlist(
         master_path: &Path,
-        variant_playlists: &[(String, PathBuf)],
+        renditions: &[Rendition],
         _media_id: &Uuid,
     ) -> anyhow::Result<()> {
         use std::fs::File;
@@ -1033,151 +1633,276 @@ impl MediaProcessor {
 
         let mut file = File::create(master_path)?;
         
-        // Write HLS master playlist header
+        // Write HLS master playlist header (version 7 for fMP4 segments)
         writeln!(file, "#EXTM3U")?;
-        writeln!(file, "#EXT-X-VERSION:3")?;
+        writeln!(file, "#EXT-X-VERSION:7")?;
+        writeln!(file, "#EXT-X-INDEPENDENT-SEGMENTS")?;
 
-        // Add each variant
         let master_parent = master_path.parent()
             .ok_or_else(|| anyhow::anyhow!("Master playlist path has no parent directory: {:?}", master_path))?;
-        for (resolution, variant_playlist) in variant_playlists {
-            // Get relative path from master to variant
-            let relative_path = variant_playlist
+        let relative_uri = |rendition: &Rendition| -> anyhow::Result<String> {
+            let relative_path = rendition.playlist
                 .strip_prefix(master_parent)
-                .unwrap_or(variant_playlist);
-            
-            let (_, _, bitrate) = Self::get_resolution_params(resolution);
-            
-            // Write variant entry
-            writeln!(file, "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}", 
-                bitrate * 1000, // Convert to bps
-                Self::get_resolution_string(resolution)
+                .unwrap_or(&rendition.playlist);
+            relative_path.to_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Relative path contains invalid UTF-8: {:?}", relative_path))
+        };
+
+        // Audio groups, one alternative per source audio stream
+        for audio in renditions.iter().filter(|r| r.is_audio()) {
+            let group = audio.audio_group().unwrap_or(&audio.id);
+            match audio.track() {
+                Some(track) => writeln!(
+                    file,
+                    "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"{}\",NAME=\"{}\",{}DEFAULT={},AUTOSELECT=YES,URI=\"{}\"",
+                    group,
+                    track.quoted_name(),
+                    Self::language_attribute(track),
+                    if track.default { "YES" } else { "NO" },
+                    relative_uri(audio)?
+                )?,
+                None => writeln!(
+                    file,
+                    "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"{}\",NAME=\"{}\",DEFAULT=YES,AUTOSELECT=YES,URI=\"{}\"",
+                    group,
+                    audio.id,
+                    relative_uri(audio)?
+                )?,
+            }
+        }
+
+        // Subtitle group
+        for subtitles in renditions.iter().filter(|r| r.is_subtitles()) {
+            let Some(track) = subtitles.track() else {
+                continue;
+            };
+            writeln!(
+                file,
+                "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"{}\",NAME=\"{}\",{}DEFAULT={},AUTOSELECT=YES,FORCED={},URI=\"{}\"",
+                subtitles::SUBTITLE_GROUP,
+                track.quoted_name(),
+                Self::language_attribute(track),
+                if track.default { "YES" } else { "NO" },
+                if track.forced { "YES" } else { "NO" },
+                relative_uri(subtitles)?
             )?;
-            writeln!(file, "{}", relative_path.to_str()
-                .ok_or_else(|| anyhow::anyhow!("Relative path contains invalid UTF-8: {:?}", relative_path))?)?;
+        }
+        let subtitle_group = if renditions.iter().any(Rendition::is_subtitles) {
+            format!(",SUBTITLES=\"{}\"", subtitles::SUBTITLE_GROUP)
+        } else {
+            String::new()
+        };
+
+        // Add each variant
+        let signal_range = renditions.iter().any(|r| r.range() != VideoRange::Sdr);
+        for video in renditions.iter().filter(|r| r.is_video()) {
+            let RenditionKind::Video { width, height, audio, range } = &video.kind else {
+                continue;
+            };
+            let uri = relative_uri(video)?;
+            let video_range = if signal_range {
+                format!(",VIDEO-RANGE={}", range.hls_value())
+            } else {
+                String::new()
+            };
+            // The largest alternative of each paired audio group
+            let paired: Vec<(&str, &Rendition)> = audio
+                .iter()
+                .filter_map(|group| {
+                    renditions
+                        .iter()
+                        .filter(|r| r.audio_group() == Some(group.as_str()))
+                        .max_by_key(|r| r.bandwidth)
+                        .map(|r| (group.as_str(), r))
+                })
+                .collect();
+
+            if paired.is_empty() {
+                writeln!(file, "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},CODECS=\"{}\"{}{}",
+                    video.bandwidth, width, height, video.codecs, video_range, subtitle_group)?;
+                writeln!(file, "{}", uri)?;
+            }
+            for (group, audio) in paired {
+                writeln!(file, "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},CODECS=\"{},{}\",AUDIO=\"{}\"{}{}",
+                    video.bandwidth + audio.bandwidth,
+                    width,
+                    height,
+                    video.codecs,
+                    audio.codecs,
+                    group,
+                    video_range,
+                    subtitle_group
+                )?;
+                writeln!(file, "{}", uri)?;
+            }
         }
 
         info!(
             master_playlist = %master_path.display(),
-            variants = variant_playlists.len(),
+            renditions = renditions.len(),
             "Created master HLS playlist"
         );
 
         Ok(())
     }
 
-    /// Get resolution string for HLS (e.g., "1920x1080")
-    fn get_resolution_string(resolution: &str) -> String {
-        let (width, height, _) = Self::get_resolution_params(resolution);
-        format!("{}x{}", width, height)
-    }
-
-    /// Transcode audio to HLS with multiple bitrates (audio-only)
+    /// Transcode audio to CMAF renditions with multiple bitrates (audio-only)
     async fn transcode_audio_to_hls(
         &self,
         input_path: &Path,
         output_dir: &Path,
         bitrates: &[String],
+        source: &AudioSource,
         media_id: &Uuid,
-    ) -> anyhow::Result<Option<PathBuf>> {
+        job: &JobContext,
+    ) -> anyhow::Result<Option<HlsPackage>> {
         info!(
             "Transcoding audio to HLS with {} bitrate(s): {:?}",
             bitrates.len(),
             bitrates
         );
 
-        let mut variant_playlists = Vec::new();
+        // Lossless sources are offered as lossy AAC variants at the target bitrates
+        let codec = if self.audio_codec.is_lossless() {
+            AudioCodec::Aac
+        } else {
+            self.audio_codec.cmaf_codec()
+        };
+
+        let mut renditions = Vec::new();
 
         // Transcode each bitrate variant
         for bitrate_str in bitrates {
-            let variant_dir = output_dir.join(bitrate_str);
-            std::fs::create_dir_all(&variant_dir)?;
-            
-            let variant_playlist = variant_dir.join("playlist.m3u8");
-            let segment_pattern = variant_dir.join("segment_%03d.ts");
-
-            info!(
-                bitrate = bitrate_str,
-                "Transcoding audio variant"
-            );
-
-            // Build FFmpeg command for audio-only HLS transcoding
-            let mut ffmpeg_args = vec![
-                "-i",
-                input_path.to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
-                "-vn", // No video
-                "-c:a",
-                self.audio_codec.ffmpeg_codec(),
-            ];
-
-            // Add bitrate for lossy codecs (FLAC is lossless, no bitrate)
-            if let Some(_audio_bitrate) = self.audio_codec.bitrate() {
-                // Use the target bitrate from the bitrate list
-                ffmpeg_args.push("-b:a");
-                ffmpeg_args.push(bitrate_str);
-            } else if self.audio_codec.is_lossless() {
-                // For FLAC, we might transcode to lossy variants
-                // Use the bitrate from the list (e.g., "320k")
-                ffmpeg_args.push("-b:a");
-                ffmpeg_args.push(bitrate_str);
-                // Override codec to AAC for lossy variants from FLAC source
-                if let Some(pos) = ffmpeg_args.iter().position(|x| *x == "-c:a") {
-                    ffmpeg_args[pos + 1] = "aac"; // Use AAC for lossy variants
-                }
-            }
-
-            // Add HLS-specific options
-            let segment_filename = segment_pattern.to_str()
-                .ok_or_else(|| anyhow::anyhow!("Segment pattern contains invalid UTF-8: {:?}", segment_pattern))?;
-            let playlist_path = variant_playlist.to_str()
-                .ok_or_else(|| anyhow::anyhow!("Playlist path contains invalid UTF-8: {:?}", variant_playlist))?;
-            
-            ffmpeg_args.extend_from_slice(&[
-                "-hls_time",
-                "10", // 10 second segments
-                "-hls_list_size",
-                "0", // Keep all segments
-                "-hls_segment_filename",
-                segment_filename,
-                "-f",
-                "hls",
-                playlist_path,
-            ]);
-
-            let status = Command::new("ffmpeg")
-                .args(&ffmpeg_args)
-                .status()?;
-
-            if !status.success() {
-                warn!(bitrate = bitrate_str, "Failed to transcode audio variant, skipping");
-                continue;
+            if let Some(rendition) = Self::transcode_audio_rendition_hls(
+                input_path,
+                output_dir,
+                bitrate_str,
+                codec,
+                Some(bitrate_str),
+                source,
+                job,
+            )
+            .await?
+            {
+                renditions.push(rendition);
             }
-
-            variant_playlists.push((bitrate_str.clone(), variant_playlist));
-            info!(bitrate = bitrate_str, "Audio variant transcoding completed");
         }
 
-        if variant_playlists.is_empty() {
+        if renditions.is_empty() {
             return Err(anyhow::anyhow!("Failed to transcode any audio variants"));
         }
 
+        self.forget_renditions_before_encryption(job)?;
+        let encryption = self.encrypt_hls_renditions(&mut renditions, media_id).await?;
+        let drm = self.protect_drm_renditions(&mut renditions, media_id).await?;
+        if renditions.is_empty() {
+            return Err(anyhow::anyhow!("No audio rendition could be encrypted"));
+        }
+
         // Create master playlist for audio
         let master_playlist_path = output_dir.join("master.m3u8");
-        Self::create_audio_master_playlist(&master_playlist_path, &variant_playlists, media_id)?;
+        Self::create_audio_master_playlist(&master_playlist_path, &renditions, media_id)?;
 
         info!(
-            variants = variant_playlists.len(),
+            variants = renditions.len(),
             "Audio HLS transcoding completed with multiple bitrates"
         );
 
-        Ok(Some(master_playlist_path))
+        Ok(Some(HlsPackage {
+            master_playlist: master_playlist_path,
+            renditions,
+            encryption,
+            drm,
+        }))
+    }
+
+    /// `LANGUAGE` attribute (with its trailing comma) of an `EXT-X-MEDIA` tag
+    fn language_attribute(track: &TrackInfo) -> String {
+        match track.language_tag() {
+            Some(language) => format!("LANGUAGE=\"{}\",", language),
+            None => String::new(),
+        }
+    }
+
+    /// Fetch sidecar subtitle files and segment them into WebVTT renditions
+    ///
+    /// Files that can't be fetched or parsed are skipped with a warning.
+    async fn package_subtitles(
+        &self,
+        sources: &[SubtitleSource],
+        output_dir: &Path,
+        duration: f64,
+        media_id: &Uuid,
+        job: &JobContext,
+    ) -> anyhow::Result<Vec<Rendition>> {
+        let mut renditions = Vec::new();
+        for (index, source) in sources.iter().enumerate() {
+            job.check_cancelled()?;
+            let id = format!("subtitles_{}", index);
+            let rendition_dir = output_dir.join(&id);
+            let rendition = match self.fetch_subtitles(source, output_dir, index, media_id).await {
+                Ok(document) => subtitles::parse_cues(&document).and_then(|cues| {
+                    subtitles::write_rendition(&cues, &rendition_dir, duration, dash::SEGMENT_DURATION_SECS as f64)
+                }),
+                Err(e) => Err(e),
+            };
+            match rendition {
+                Ok(playlist) => renditions.push(Rendition {
+                    id,
+                    kind: RenditionKind::Subtitles {
+                        track: TrackInfo {
+                            name: source.display_name(index),
+                            language: source.language.clone(),
+                            default: source.default,
+                            forced: source.forced,
+                        },
+                    },
+                    codecs: "wvtt".to_string(),
+                    bandwidth: 0,
+                    playlist,
+                }),
+                Err(e) => {
+                    warn!(media_id = %media_id, file = source.file_path, error = %e, "Skipping subtitle file");
+                }
+            }
+        }
+        if !renditions.is_empty() {
+            info!(media_id = %media_id, subtitles = renditions.len(), "Packaged subtitle renditions");
+        }
+        Ok(renditions)
+    }
+
+    /// Contents of a remote subtitle file, downloaded under the ingestion
+    /// policy and read up to the subtitle size limit
+    async fn fetch_subtitles(
+        &self,
+        source: &SubtitleSource,
+        output_dir: &Path,
+        index: usize,
+        media_id: &Uuid,
+    ) -> anyhow::Result<String> {
+        let path = &source.file_path;
+        if !source.is_remote() {
+            return Err(anyhow::anyhow!("Subtitle file must be an S3 or HTTP(S) URL: {}", path));
+        }
+        let downloader = self
+            .downloader
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Downloader not configured for remote subtitle files"))?;
+        let local = output_dir.join(format!("subtitles_{}.src", index));
+        downloader
+            .download_file_limited(path, &local, media_id, subtitles::MAX_SUBTITLE_SIZE)
+            .await?;
+        let document = subtitles::read_document(&local, self.ingest.max_size.min(subtitles::MAX_SUBTITLE_SIZE));
+        std::fs::remove_file(&local)?;
+        document
     }
 
     /// Create master HLS playlist for audio-only content
     fn create_audio_master_playlist(
         master_path: &Path,
-        variant_playlists: &[(String, PathBuf)],
+        renditions: &[Rendition],
         _media_id: &Uuid,
     ) -> anyhow::Result<()> {
         use std::fs::File;
@@ -1185,33 +1910,27 @@ impl MediaProcessor {
 
         let mut file = File::create(master_path)?;
         
-        // Write HLS master playlist header
+        // Write HLS master playlist header (version 7 for fMP4 segments)
         writeln!(file, "#EXTM3U")?;
-        writeln!(file, "#EXT-X-VERSION:3")?;
+        writeln!(file, "#EXT-X-VERSION:7")?;
+        writeln!(file, "#EXT-X-INDEPENDENT-SEGMENTS")?;
 
         // Write variant entries
         let master_parent = master_path.parent()
             .ok_or_else(|| anyhow::anyhow!("Master playlist path has no parent directory: {:?}", master_path))?;
-        for (bitrate_str, variant_path) in variant_playlists {
-            let relative_path = variant_path
+        for rendition in renditions {
+            let relative_path = rendition.playlist
                 .strip_prefix(master_parent)
-                .unwrap_or(variant_path);
-            
-            // Parse bitrate (e.g., "320k" -> 320000)
-            let bitrate_value = bitrate_str
-                .trim_end_matches('k')
-                .parse::<u32>()
-                .unwrap_or(128)
-                * 1000;
-
-            writeln!(file, "#EXT-X-STREAM-INF:BANDWIDTH={}", bitrate_value)?;
+                .unwrap_or(&rendition.playlist);
+
+            writeln!(file, "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"", rendition.bandwidth, rendition.codecs)?;
             writeln!(file, "{}", relative_path.to_str()
                 .ok_or_else(|| anyhow::anyhow!("Relative path contains invalid UTF-8: {:?}", relative_path))?)?;
         }
 
         info!(
             master_playlist = %master_path.display(),
-            variants = variant_playlists.len(),
+            variants = renditions.len(),
             "Created master audio HLS playlist"
         );
 
@@ -1225,6 +1944,7 @@ impl MediaProcessor {
         output_dir: &Path,
         bitrates: &[String],
         _media_id: &Uuid,
+        job: &JobContext,
     ) -> anyhow::Result<Vec<PathBuf>> {
         if !self.ffmpeg_available {
             warn!("FFmpeg not available - skipping audio MP4 generation");
@@ -1274,6 +1994,9 @@ impl MediaProcessor {
                     ffmpeg_args[pos + 1] = "aac"; // Use AAC for lossy variants
                 }
             }
+            if let Some(ref audio_filter) = job.audio_filter {
+                ffmpeg_args.extend_from_slice(&["-af", audio_filter]);
+            }
 
             // Add MP4-specific options for progressive download
             let mp4_path_str = mp4_path.to_str().unwrap();
@@ -1282,15 +2005,16 @@ impl MediaProcessor {
                 "+faststart", // Enable progressive download
                 "-f",
                 "mp4",
+                "-y", // Overwrite output file
                 mp4_path_str,
             ]);
 
-            let status = Command::new("ffmpeg")
-                .args(&ffmpeg_args)
-                .status()?;
+            let status = job
+                .run(ingest::ffmpeg_command("ffmpeg").args(&ffmpeg_args), |_| {})
+                .await?;
 
             if !status.success() {
-                warn!(bitrate = bitrate_str, "Failed to transcode audio variant to MP4, skipping");
+                warn!(bitrate = bitrate_str, error = %status.error(), "Failed to transcode audio variant to MP4, skipping");
                 continue;
             }
 
@@ -1313,17 +2037,22 @@ impl MediaProcessor {
     /// - Selected audio codec (AAC, Opus, MP3, Vorbis, FLAC)
     /// - Progressive download support (faststart for MP4)
     /// - Optimized for streaming
+    ///
+    /// `sdr_filter` tone-maps HDR sources; MP4 files are always SDR.
     async fn transcode_to_mp4(
         &self,
         input_path: &Path,
         output_dir: &Path,
-        resolutions: &[String],
+        ladder: &EncodingLadder,
+        sdr_filter: Option<&str>,
         _media_id: &Uuid,
+        job: &JobContext,
     ) -> anyhow::Result<Vec<PathBuf>> {
         if !self.ffmpeg_available {
             warn!("FFmpeg not available - skipping MP4 generation");
             return Ok(vec![]);
         }
+        let resolutions: Vec<&String> = ladder.rungs.iter().map(|rung| &rung.resolution).collect();
 
         info!(
             "Transcoding to MP4 with {} resolution(s): {:?}",
@@ -1335,7 +2064,7 @@ impl MediaProcessor {
 
         // Transcode each resolution variant to MP4
         for resolution in resolutions {
-            let (width, height, bitrate) = Self::get_resolution_params(resolution);
+            let (width, height, bitrate) = Self::ladder_params(ladder, resolution);
             
             // Get audio codec(s) for this resolution
             let (primary_audio_codec, secondary_audio_codec) = self.get_audio_codecs_for_resolution(resolution);
@@ -1355,10 +2084,13 @@ impl MediaProcessor {
             // Build FFmpeg command for MP4 transcoding
             let maxrate_str = format!("{}k", bitrate);
             let bufsize_str = format!("{}k", bitrate * 2);
-            let vf_filter = format!(
+            let mut vf_filter = format!(
                 "scale={}:{}:force_original_aspect_ratio=decrease,pad={}:{}:(ow-iw)/2:(oh-ih)/2",
                 width, height, width, height
             );
+            if let Some(tonemap) = sdr_filter {
+                vf_filter = format!("{},{}", tonemap, vf_filter);
+            }
             
             let mut ffmpeg_args = vec![
                 "-i",
@@ -1396,6 +2128,9 @@ impl MediaProcessor {
                 ffmpeg_args.push("-b:a");
                 ffmpeg_args.push(bitrate_to_use);
             }
+            if let Some(ref audio_filter) = job.audio_filter {
+                ffmpeg_args.extend_from_slice(&["-af", audio_filter]);
+            }
 
             // Add container-specific options
             let mp4_path_str = mp4_path.to_str().unwrap();
@@ -1423,12 +2158,12 @@ impl MediaProcessor {
                 ]);
             }
 
-            let status = Command::new("ffmpeg")
-                .args(&ffmpeg_args)
-                .status()?;
+            let status = job
+                .run(ingest::ffmpeg_command("ffmpeg").args(&ffmpeg_args), |_| {})
+                .await?;
 
             if !status.success() {
-                warn!(resolution = resolution, "Failed to transcode variant to MP4, skipping");
+                warn!(resolution = resolution, error = %status.error(), "Failed to transcode variant to MP4, skipping");
                 continue;
             }
 
@@ -1487,6 +2222,9 @@ impl MediaProcessor {
                     secondary_ffmpeg_args.push("-b:a");
                     secondary_ffmpeg_args.push(audio_bitrate);
                 }
+                if let Some(ref audio_filter) = job.audio_filter {
+                    secondary_ffmpeg_args.extend_from_slice(&["-af", audio_filter]);
+                }
 
                 // Add container-specific options
                 let secondary_mp4_path_str = secondary_mp4_path.to_str().unwrap();
@@ -1513,9 +2251,9 @@ impl MediaProcessor {
                     ]);
                 }
 
-                let secondary_status = Command::new("ffmpeg")
-                    .args(&secondary_ffmpeg_args)
-                    .status()?;
+                let secondary_status = job
+                    .run(ingest::ffmpeg_command("ffmpeg").args(&secondary_ffmpeg_args), |_| {})
+                    .await?;
 
                 if secondary_status.success() && secondary_mp4_path.exists() {
                     mp4_files.push(secondary_mp4_path.clone());
@@ -1543,60 +2281,156 @@ impl MediaProcessor {
         Ok(mp4_files)
     }
 
-    /// Generate DASH manifest for adaptive streaming (Future Implementation)
-    /// 
-    /// This function will generate a DASH manifest (.mpd file) for adaptive streaming.
-    /// Currently returns None as placeholder for future implementation.
-    /// 
-    /// Future implementation will:
-    /// - Generate DASH manifest (.mpd) file
-    /// - Reference all resolution variants
-    /// - Support both HLS and DASH formats
-    /// - Include period and adaptation set definitions
+    /// Encryption rewrites segments in place, so a resumed job can't reuse
+    /// renditions once it has started
+    fn forget_renditions_before_encryption(&self, job: &JobContext) -> anyhow::Result<()> {
+        match &job.journal {
+            Some(journal) if self.hls_encryption.is_enabled() || self.drm.is_enabled() => {
+                journal.forget_renditions()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Encrypt the segments of every rendition according to `hls_encryption`
+    ///
+    /// Renditions whose codec can't be sample encrypted are removed (and their
+    /// clear files deleted so they are never uploaded). Returns the metadata
+    /// of the first content key, or `None` when HLS encryption is disabled.
+    async fn encrypt_hls_renditions(
+        &self,
+        renditions: &mut Vec<Rendition>,
+        media_id: &Uuid,
+    ) -> anyhow::Result<Option<EncryptionMetadata>> {
+        if !self.hls_encryption.is_enabled() {
+            return Ok(None);
+        }
+        let encryption = self.encryption.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("HLS encryption is enabled but the processor has no content encryption")
+        })?;
+
+        let iv: [u8; 16] = rand::random();
+        let mut encrypted = Vec::with_capacity(renditions.len());
+        for rendition in renditions.drain(..) {
+            match hls_encryption::encrypt_rendition(&rendition, &self.hls_encryption, encryption, media_id, &iv).await {
+                Ok(()) => encrypted.push(rendition),
+                Err(e) if self.hls_encryption.method == hls_encryption::HlsEncryptionMethod::SampleAes => {
+                    warn!(rendition = rendition.id, error = %e, "Dropping rendition that cannot be sample encrypted");
+                    if let Some(dir) = rendition.playlist.parent() {
+                        std::fs::remove_dir_all(dir)?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        *renditions = encrypted;
+        info!(
+            media_id = %media_id,
+            method = self.hls_encryption.method.as_str(),
+            "HLS segments encrypted; clear MP4 and DASH outputs are skipped"
+        );
+
+        Ok(Some(EncryptionMetadata {
+            media_id: *media_id,
+            encryption_key_id: crate::encryption::ContentEncryption::hls_key_id(media_id, 0),
+            iv: iv.to_vec(),
+            algorithm: self.hls_encryption.method.as_str().to_string(),
+        }))
+    }
+
+    /// Protect every rendition with Common Encryption according to `drm`
+    ///
+    /// All renditions share the media's content key, each with its own IV.
+    /// Renditions whose codec can't be sample encrypted are removed (and
+    /// their clear files deleted). Returns `None` when DRM is disabled.
+    async fn protect_drm_renditions(
+        &self,
+        renditions: &mut Vec<Rendition>,
+        media_id: &Uuid,
+    ) -> anyhow::Result<Option<DrmPackage>> {
+        let Some(scheme) = self.drm.scheme else {
+            return Ok(None);
+        };
+        let encryption = self.encryption.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("DRM is enabled but the processor has no content encryption")
+        })?;
+
+        let key = encryption.drm_content_key(media_id).await?;
+        let mut protected = Vec::with_capacity(renditions.len());
+        for rendition in renditions.drain(..) {
+            let iv: [u8; 16] = rand::random();
+            match drm::protect_rendition(&rendition, scheme, &key, &iv).await {
+                Ok(()) => protected.push(rendition),
+                Err(e) => {
+                    warn!(rendition = rendition.id, error = %e, "Dropping rendition that cannot be DRM protected");
+                    if let Some(dir) = rendition.playlist.parent() {
+                        std::fs::remove_dir_all(dir)?;
+                    }
+                }
+            }
+        }
+        *renditions = protected;
+        info!(
+            media_id = %media_id,
+            scheme = scheme.as_str(),
+            key_id = %Uuid::from_bytes(key.kid),
+            "Renditions DRM protected; clear MP4 outputs are skipped"
+        );
+
+        Ok(Some(DrmPackage::new(scheme, &key, self.drm.license_url(media_id))))
+    }
+
+    /// Generate DASH manifest for adaptive streaming
+    ///
+    /// Writes `manifest.mpd` referencing the CMAF renditions produced for HLS,
+    /// with one adaptation set per video codec and per audio codec. A failure
+    /// here is logged and does not fail the job, since HLS is still usable.
     async fn generate_dash_manifest(
         &self,
-        _output_dir: &Path,
-        _resolutions: &[String],
-        _media_id: &Uuid,
+        output_dir: &Path,
+        renditions: &[Rendition],
+        protection: Option<&dash::ContentProtection>,
+        media_id: &Uuid,
     ) -> anyhow::Result<Option<PathBuf>> {
-        // TODO: Implement DASH manifest generation
-        // This will generate a DASH manifest (.mpd) file:
-        // - Create MPD (Media Presentation Description) XML
-        // - Define periods and adaptation sets
-        // - Reference video and audio representations
-        // - Support multi-period for live streaming
-        
-        info!("DASH manifest generation not yet implemented - returning None");
-        Ok(None)
+        if renditions.is_empty() {
+            return Ok(None);
+        }
+
+        match dash::write_mpd(output_dir, renditions, protection) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(e) => {
+                warn!(media_id = %media_id, error = %e, "Failed to generate DASH manifest");
+                Ok(None)
+            }
+        }
     }
 
-    /// Generate thumbnails from video
+    /// Generate thumbnails from video; `sdr_filter` tone-maps HDR sources
     async fn generate_thumbnails(
         &self,
         input_path: &Path,
         output_dir: &Path,
-        _media_id: &Uuid,
-        count: usize,
-    ) -> anyhow::Result<Vec<PathBuf>> {
-        info!("Generating {} thumbnails", count);
-
-        // First, get video duration
-        let duration = self.extract_duration(input_path).await?;
+        sdr_filter: Option<&str>,
+        timestamps: &[f64],
+        job: &JobContext,
+    ) -> anyhow::Result<Vec<(usize, PathBuf)>> {
+        info!("Generating {} thumbnails", timestamps.len());
 
         let mut thumbnail_paths = Vec::new();
 
-        // Generate thumbnails at evenly spaced intervals
-        for i in 0..count {
-            let timestamp = (duration as f64 / (count + 1) as f64) * (i + 1) as f64;
+        // One thumbnail per timestamp, returned with the timestamp's index
+        for (i, timestamp) in timestamps.iter().enumerate() {
             let thumbnail_path = output_dir.join(format!("thumb_{}.jpg", i + 1));
 
-            let status = Command::new("ffmpeg")
-                .args(&[
+            let mut command = ingest::ffmpeg_command("ffmpeg");
+            command.args([
                     "-i",
                     input_path.to_str()
                     .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input_path))?,
                     "-ss",
                     &timestamp.to_string(),
+                    "-vf",
+                    sdr_filter.unwrap_or("null"),
                     "-vframes",
                     "1",
                     "-q:v",
@@ -1604,13 +2438,13 @@ impl MediaProcessor {
                     "-y", // Overwrite
                     thumbnail_path.to_str()
                         .ok_or_else(|| anyhow::anyhow!("Thumbnail path contains invalid UTF-8: {:?}", thumbnail_path))?,
-                ])
-                .status()?;
+                ]);
+            let status = job.run(&mut command, |_| {}).await?;
 
             if status.success() {
-                thumbnail_paths.push(thumbnail_path);
+                thumbnail_paths.push((i, thumbnail_path));
             } else {
-                warn!("Failed to generate thumbnail {}", i + 1);
+                warn!(error = %status.error(), "Failed to generate thumbnail {}", i + 1);
             }
         }
 
@@ -1618,25 +2452,83 @@ impl MediaProcessor {
         Ok(thumbnail_paths)
     }
 
+    /// First loudnorm pass over the source's audio, when normalization is enabled
+    ///
+    /// Sources without audio, or whose loudness cannot be measured (e.g.
+    /// silence), are left as they are.
+    async fn measure_loudness(
+        &self,
+        input_path: &Path,
+        media_info: &MediaInfo,
+        media_id: &Uuid,
+        job: &JobContext,
+    ) -> anyhow::Result<Option<Loudness>> {
+        let Some(stream) = media_info.audio_stream().filter(|_| self.loudness.enabled) else {
+            return Ok(None);
+        };
+        match loudness::measure(input_path, stream.index, &self.loudness, job).await {
+            Ok(measured) => Ok(Some(Loudness {
+                measured,
+                target: self.loudness.target,
+            })),
+            Err(e) if crate::jobs::is_cancelled(&e) => Err(e),
+            Err(e) => {
+                warn!(media_id = %media_id, error = %e, "Loudness measurement failed, audio is not normalized");
+                Ok(None)
+            }
+        }
+    }
+
     /// Mock processing (fallback when FFmpeg is not available)
     async fn mock_processing(
         &self,
-        media_id: &Uuid,
+        _media_id: &Uuid,
         output_dir: PathBuf,
+        is_image: bool,
+        job: &JobContext,
     ) -> anyhow::Result<ProcessingResult> {
         warn!("Using mock processing");
 
         // Simulate processing time
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-        let thumbnail_urls = vec![
-            format!("https://cdn.example.com/media/{}/thumb_1.jpg", media_id),
-            format!("https://cdn.example.com/media/{}/thumb_2.jpg", media_id),
-        ];
+        tokio::select! {
+            _ = tokio::time::sleep(tokio::time::Duration::from_millis(500)) => {}
+            _ = job.cancel.cancelled() => {}
+        }
+        job.check_cancelled()?;
+
+        if is_image {
+            let (width, height) = (1920, 1080);
+            let dir = output_dir.as_path();
+            let variants = self
+                .image
+                .widths(width)
+                .into_iter()
+                .flat_map(|variant_width| {
+                    self.image.formats.iter().map(move |&format| ImageVariant {
+                        path: images::variant_path(dir, variant_width, format),
+                        width: variant_width,
+                        height: images::variant_height(variant_width, (width, height)),
+                        format,
+                    })
+                })
+                .collect();
+            let image = ProcessedImage {
+                width,
+                height,
+                blurhash: None,
+                variants,
+            };
+            return Ok(Self::image_result(output_dir, image, None));
+        }
 
         Ok(ProcessingResult {
             output_dir,
-            thumbnail_urls,
+            thumbnails: vec![],
+            sprites: None,
+            posters: vec![],
+            loudness: None,
+            waveform: None,
+            image: None,
             duration: 3600,
             resolutions: vec!["1080p".to_string(), "720p".to_string(), "480p".to_string()],
             hls_playlist_path: None,
@@ -1644,12 +2536,74 @@ impl MediaProcessor {
             dash_manifest_path: None,
             output_files: vec![],
             encryption_metadata: None,
+            drm: None,
+            ladder: None,
+            media_info: None,
             is_audio_only: false,
             audio_bitrate: None,
             sample_rate: None,
         })
     }
 
+    /// Encode an image's size variants and BlurHash placeholder
+    async fn process_image(
+        &self,
+        input_path: &Path,
+        output_dir: PathBuf,
+        media_info: MediaInfo,
+        media_id: &Uuid,
+        job: &JobContext,
+    ) -> anyhow::Result<ProcessingResult> {
+        // Stored size: the EXIF orientation is applied by the image pipeline
+        let size = media_info
+            .video_stream()
+            .and_then(|stream| stream.video.as_ref())
+            .map(|video| (video.width, video.height))
+            .ok_or_else(|| anyhow::anyhow!("Source has no image stream"))?;
+        let image = images::process(input_path, &output_dir, size, &self.image, job).await?;
+
+        info!(
+            media_id = %media_id,
+            resolution = format!("{}x{}", image.width, image.height),
+            variants = image.variants.len(),
+            blurhash = image.blurhash.is_some(),
+            "Image processing completed"
+        );
+        Ok(Self::image_result(output_dir, image, Some(media_info)))
+    }
+
+    /// Processing result of an image: its variants and no stream outputs
+    fn image_result(output_dir: PathBuf, image: ProcessedImage, media_info: Option<MediaInfo>) -> ProcessingResult {
+        let mut resolutions: Vec<String> = image
+            .variants
+            .iter()
+            .map(|variant| format!("{}x{}", variant.width, variant.height))
+            .collect();
+        resolutions.dedup();
+        ProcessingResult {
+            output_dir,
+            thumbnails: vec![],
+            sprites: None,
+            posters: vec![],
+            loudness: None,
+            waveform: None,
+            duration: 0,
+            resolutions,
+            hls_playlist_path: None,
+            mp4_files: vec![],
+            dash_manifest_path: None,
+            output_files: image.variants.iter().map(|variant| variant.path.clone()).collect(),
+            encryption_metadata: None,
+            drm: None,
+            ladder: None,
+            media_info,
+            is_audio_only: false,
+            audio_bitrate: None,
+            sample_rate: None,
+            image: Some(image),
+        }
+    }
+
     /// Check if FFmpeg is available
     fn check_ffmpeg_available() -> bool {
         match Command::new("ffmpeg").arg("-version").output() {
@@ -1688,7 +2642,7 @@ impl MediaProcessor {
     fn check_nvenc_available() -> bool {
         // Check if FFmpeg has NVENC support
         match Command::new("ffmpeg")
-            .args(&["-hide_banner", "-encoders"])
+            .args(["-hide_banner", "-encoders"])
             .output()
         {
             Ok(output) => {
@@ -1703,7 +2657,7 @@ impl MediaProcessor {
     #[cfg(target_os = "macos")]
     fn check_videotoolbox_available() -> bool {
         match Command::new("ffmpeg")
-            .args(&["-hide_banner", "-encoders"])
+            .args(["-hide_banner", "-encoders"])
             .output()
         {
             Ok(output) => {
@@ -1718,7 +2672,7 @@ impl MediaProcessor {
     #[cfg(target_os = "linux")]
     fn check_vaapi_available() -> bool {
         match Command::new("ffmpeg")
-            .args(&["-hide_banner", "-encoders"])
+            .args(["-hide_banner", "-encoders"])
             .output()
         {
             Ok(output) => {
@@ -1728,16 +2682,6 @@ impl MediaProcessor {
             Err(_) => false,
         }
     }
-    
-    #[cfg(not(target_os = "macos"))]
-    fn check_videotoolbox_available() -> bool {
-        false
-    }
-    
-    #[cfg(not(target_os = "linux"))]
-    fn check_vaapi_available() -> bool {
-        false
-    }
 }
 
 impl Default for MediaProcessor {
@@ -1745,3 +2689,51 @@ impl Default for MediaProcessor {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_master_playlist_sanitizes_track_tags() {
+        let dir = TempDir::new().unwrap();
+        let track = |name: &str, language: &str| TrackInfo {
+            name: name.to_string(),
+            language: Some(language.to_string()),
+            default: false,
+            forced: false,
+        };
+        let renditions = vec![
+            Rendition {
+                id: "audio_aac".to_string(),
+                kind: RenditionKind::Audio {
+                    sample_rate: Some(48000),
+                    group: None,
+                    track: Some(track("Evil\",URI=\"https://attacker.example/x.m3u8\"\n#EXT-X-ENDLIST", "en\"\r\n#X")),
+                },
+                codecs: "mp4a.40.2".to_string(),
+                bandwidth: 128_000,
+                playlist: dir.path().join("audio_aac/playlist.m3u8"),
+            },
+            Rendition {
+                id: "subtitles_0".to_string(),
+                kind: RenditionKind::Subtitles { track: track("Français", "fr-CA") },
+                codecs: "wvtt".to_string(),
+                bandwidth: 0,
+                playlist: dir.path().join("subtitles_0/playlist.m3u8"),
+            },
+        ];
+        let master = dir.path().join("master.m3u8");
+        MediaProcessor::create_master_playlist(&master, &renditions, &Uuid::new_v4()).unwrap();
+
+        let playlist = std::fs::read_to_string(&master).unwrap();
+        let lines: Vec<&str> = playlist.lines().collect();
+        assert_eq!(
+            lines[3],
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio_aac\",NAME=\"Evil',URI='https://attacker.example/x.m3u8' #EXT-X-ENDLIST\",DEFAULT=NO,AUTOSELECT=YES,URI=\"audio_aac/playlist.m3u8\""
+        );
+        assert!(lines[4].contains("NAME=\"Français\",LANGUAGE=\"fr-CA\","));
+        assert!(!playlist.contains("#EXT-X-ENDLIST\n"));
+        assert!(!playlist.contains("#X"));
+    }
+}
@@ -0,0 +1,254 @@
+//! Transcoding progress reporting
+//!
+//! FFmpeg is run with `-progress pipe:1`, which prints blocks of `key=value`
+//! lines ending in `progress=continue` (or `progress=end`). Each block is
+//! turned into a per-rendition [`ProgressUpdate`] with a percentage of the
+//! source duration, the encoding fps and an ETA. Updates are sent to the
+//! worker, which throttles them per rendition with [`ProgressThrottle`] and
+//! publishes `media.processing_progress` events.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+/// Minimum time between two published updates of a rendition when
+/// `MEDIA_PROGRESS_INTERVAL_MS` is not set
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Progress of one rendition's transcode
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressUpdate {
+    pub media_id: Uuid,
+    pub rendition: String,
+    /// Percentage of the source duration transcoded (0-100)
+    pub percent: f64,
+    /// Encoding frames per second (video only)
+    pub fps: Option<f64>,
+    /// Estimated seconds until the rendition is done
+    pub eta_seconds: Option<u64>,
+    /// Set on the final update of the rendition
+    pub done: bool,
+}
+
+/// Sends the progress of one media file's transcodes
+#[derive(Debug, Clone)]
+pub struct ProgressReporter {
+    media_id: Uuid,
+    /// Source duration in seconds
+    duration: u64,
+    sender: UnboundedSender<ProgressUpdate>,
+}
+
+impl ProgressReporter {
+    pub fn new(media_id: Uuid, duration: u64, sender: UnboundedSender<ProgressUpdate>) -> Self {
+        Self {
+            media_id,
+            duration,
+            sender,
+        }
+    }
+
+    /// Convert an FFmpeg progress block into an update for `rendition`
+    pub fn report(&self, rendition: &str, progress: &FfmpegProgress) {
+        let duration_us = self.duration.saturating_mul(1_000_000);
+        let percent = match (progress.end, duration_us) {
+            (true, _) => 100.0,
+            (false, 0) => 0.0,
+            (false, total) => (progress.out_time_us as f64 / total as f64 * 100.0).min(100.0),
+        };
+        let eta_seconds = match progress.speed {
+            Some(speed) if speed > 0.0 && !progress.end => {
+                let remaining_us = duration_us.saturating_sub(progress.out_time_us);
+                Some((remaining_us as f64 / 1_000_000.0 / speed).round() as u64)
+            }
+            _ if progress.end => Some(0),
+            _ => None,
+        };
+
+        // The receiver is gone once the job's progress is no longer wanted
+        let _ = self.sender.send(ProgressUpdate {
+            media_id: self.media_id,
+            rendition: rendition.to_string(),
+            percent,
+            fps: progress.fps,
+            eta_seconds,
+            done: progress.end,
+        });
+    }
+}
+
+/// One block of `ffmpeg -progress` output
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfmpegProgress {
+    /// Output timestamp in microseconds
+    pub out_time_us: u64,
+    pub fps: Option<f64>,
+    /// Encoding speed relative to real time
+    pub speed: Option<f64>,
+    /// `progress=end` was reached
+    pub end: bool,
+}
+
+/// Incremental parser of `ffmpeg -progress` output
+#[derive(Debug, Default)]
+pub struct FfmpegProgressParser {
+    current: FfmpegProgress,
+}
+
+impl FfmpegProgressParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one output line; returns the block it completes, if any
+    pub fn push_line(&mut self, line: &str) -> Option<FfmpegProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            // out_time_ms is in microseconds too, despite its name
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse() {
+                    self.current.out_time_us = us;
+                }
+            }
+            "fps" => self.current.fps = value.parse().ok().filter(|fps: &f64| *fps > 0.0),
+            "speed" => self.current.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => {
+                self.current.end = value == "end";
+                return Some(self.current.clone());
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Limits published updates to one per rendition per interval
+///
+/// Final updates always pass so consumers see every rendition complete.
+#[derive(Debug)]
+pub struct ProgressThrottle {
+    interval: Duration,
+    last_published: HashMap<(Uuid, String), Instant>,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_published: HashMap::new(),
+        }
+    }
+
+    /// Read `MEDIA_PROGRESS_INTERVAL_MS`
+    pub fn from_env() -> Self {
+        let interval = std::env::var("MEDIA_PROGRESS_INTERVAL_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_PROGRESS_INTERVAL);
+        Self::new(interval)
+    }
+
+    /// Whether `update` should be published now
+    pub fn should_publish(&mut self, update: &ProgressUpdate, now: Instant) -> bool {
+        let key = (update.media_id, update.rendition.clone());
+        if update.done {
+            self.last_published.remove(&key);
+            return true;
+        }
+        match self.last_published.get(&key) {
+            Some(last) if now.duration_since(*last) < self.interval => false,
+            _ => {
+                self.last_published.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = "frame=240\nfps=48.00\nbitrate=1200.0kbits/s\nout_time_us=10000000\n\
+        out_time_ms=10000000\nout_time=00:00:10.000000\nspeed=2.5x\nprogress=continue\n";
+
+    #[test]
+    fn test_parse_progress_block() {
+        let mut parser = FfmpegProgressParser::new();
+        let blocks: Vec<_> = BLOCK.lines().filter_map(|line| parser.push_line(line)).collect();
+        assert_eq!(
+            blocks,
+            vec![FfmpegProgress {
+                out_time_us: 10_000_000,
+                fps: Some(48.0),
+                speed: Some(2.5),
+                end: false,
+            }]
+        );
+
+        assert!(parser.push_line("speed=N/A").is_none());
+        let end = parser.push_line("progress=end").unwrap();
+        assert!(end.end);
+        assert_eq!(end.speed, None);
+    }
+
+    #[test]
+    fn test_report_percent_and_eta() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let media_id = Uuid::new_v4();
+        let reporter = ProgressReporter::new(media_id, 40, sender);
+
+        let mut parser = FfmpegProgressParser::new();
+        let block = BLOCK.lines().find_map(|line| parser.push_line(line)).unwrap();
+        reporter.report("720p", &block);
+        let update = receiver.try_recv().unwrap();
+        assert_eq!(update.rendition, "720p");
+        assert_eq!(update.percent, 25.0);
+        assert_eq!(update.fps, Some(48.0));
+        // 30 s of source left at 2.5x
+        assert_eq!(update.eta_seconds, Some(12));
+        assert!(!update.done);
+
+        reporter.report("720p", &FfmpegProgress { end: true, ..block });
+        let update = receiver.try_recv().unwrap();
+        assert_eq!((update.percent, update.eta_seconds, update.done), (100.0, Some(0), true));
+    }
+
+    #[test]
+    fn test_throttle_per_rendition() {
+        let mut throttle = ProgressThrottle::new(Duration::from_secs(2));
+        let media_id = Uuid::new_v4();
+        let update = |rendition: &str, done| ProgressUpdate {
+            media_id,
+            rendition: rendition.to_string(),
+            percent: 50.0,
+            fps: None,
+            eta_seconds: None,
+            done,
+        };
+        let start = Instant::now();
+
+        assert!(throttle.should_publish(&update("720p", false), start));
+        assert!(!throttle.should_publish(&update("720p", false), start + Duration::from_secs(1)));
+        assert!(throttle.should_publish(&update("480p", false), start + Duration::from_secs(1)));
+        assert!(throttle.should_publish(&update("720p", true), start + Duration::from_secs(1)));
+        assert!(throttle.should_publish(&update("720p", false), start + Duration::from_secs(3)));
+    }
+}
@@ -0,0 +1,250 @@
+//! S3-compatible object store (Akamai, MinIO, AWS)
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use armoricore_config::ObjectStorageConfig;
+use async_trait::async_trait;
+use rusoto_core::{credential::StaticProvider, request::HttpClient, Region};
+use rusoto_s3::{DeleteObjectRequest, GetObjectRequest, HeadObjectRequest, PutObjectRequest, S3Client, S3};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::multipart::{self, UploadConfig, UploadTarget};
+use crate::object_store::ObjectStore;
+use crate::retry::{RetryConfig, is_retryable_upload_error, retry_with_backoff};
+
+/// `Cache-Control` of uploaded objects (1 year)
+const CACHE_CONTROL: &str = "public, max-age=31536000";
+
+/// Object store backed by an S3-compatible bucket
+#[derive(Clone)]
+pub struct S3Store {
+    client: Arc<S3Client>,
+    bucket: String,
+    retry_config: RetryConfig,
+    upload_config: UploadConfig,
+}
+
+impl S3Store {
+    /// Create a store for `config`'s bucket
+    pub fn new(config: &ObjectStorageConfig) -> anyhow::Result<Self> {
+        let credentials = StaticProvider::new_minimal(
+            config.access_key.clone(),
+            config.secret_key.clone(),
+        );
+
+        let region = Self::region(config);
+
+        info!(
+            endpoint = Self::extract_endpoint(&config.endpoint),
+            bucket = config.bucket,
+            "Creating S3 client for object storage"
+        );
+
+        let http_client = HttpClient::new()
+            .map_err(|e| anyhow::anyhow!("Failed to create HTTP client: {}", e))?;
+
+        Ok(Self {
+            client: Arc::new(S3Client::new_with(http_client, credentials, region)),
+            bucket: config.bucket.clone(),
+            retry_config: RetryConfig::from_env(),
+            upload_config: UploadConfig::from_env(),
+        })
+    }
+
+    /// Use `upload_config` instead of the settings from the environment
+    pub fn with_upload_config(mut self, upload_config: UploadConfig) -> Self {
+        self.upload_config = upload_config;
+        self
+    }
+
+    /// The same store for another bucket, sharing the client
+    pub fn with_bucket(&self, bucket: &str) -> Self {
+        Self {
+            bucket: bucket.to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Size of the object `key`, from a HEAD request
+    pub async fn content_length(&self, key: &str) -> anyhow::Result<u64> {
+        let head = self
+            .client
+            .head_object(HeadObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read S3 object metadata: {}", e))?;
+        head.content_length
+            .and_then(|length| u64::try_from(length).ok())
+            .ok_or_else(|| anyhow::anyhow!("S3 object {} has no content length", key))
+    }
+
+    /// Region and endpoint of `config`'s storage service
+    pub fn region(config: &ObjectStorageConfig) -> Region {
+        // Akamai typically uses us-east-1 or a custom region
+        Region::Custom {
+            name: config.region.clone().unwrap_or_else(|| "akamai".to_string()),
+            endpoint: Self::extract_endpoint(&config.endpoint),
+        }
+    }
+
+    /// Extract endpoint URL from configuration
+    fn extract_endpoint(endpoint: &str) -> String {
+        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            endpoint.to_string()
+        } else if endpoint.starts_with("s3://") {
+            // Convert s3://bucket to https://bucket.akamai.com
+            let bucket = endpoint.strip_prefix("s3://").unwrap_or(endpoint);
+            format!("https://{}.akamai.com", bucket)
+        } else {
+            format!("https://{}.akamai.com", endpoint)
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    /// Upload with retries; files from the multipart threshold on are
+    /// streamed in parts
+    async fn put_file(&self, local_path: &Path, key: &str, content_type: &str) -> anyhow::Result<()> {
+        let size = std::fs::metadata(local_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?
+            .len();
+        if size >= self.upload_config.multipart_threshold {
+            let target = UploadTarget {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                content_type: content_type.to_string(),
+                cache_control: CACHE_CONTROL.to_string(),
+            };
+            return multipart::upload(&self.client, local_path, &target, &self.upload_config, &self.retry_config)
+                .await;
+        }
+
+        // Read file content once (before retries)
+        let file_content = std::fs::read(local_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?;
+        let content_md5 = multipart::content_md5(&file_content);
+
+        retry_with_backoff(&self.retry_config, || {
+            let client = Arc::clone(&self.client);
+            let s3_key = key.to_string();
+            let put_request = PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: s3_key.clone(),
+                body: Some(file_content.clone().into()),
+                content_type: Some(content_type.to_string()),
+                content_md5: Some(content_md5.clone()),
+                cache_control: Some(CACHE_CONTROL.to_string()),
+                ..Default::default()
+            };
+
+            Box::pin(async move {
+                client
+                    .put_object(put_request)
+                    .await
+                    .map_err(|e| {
+                        let error = anyhow::anyhow!("Failed to upload file: {}", e);
+                        // Only retry if error is retryable
+                        if !is_retryable_upload_error(&error) {
+                            warn!(
+                                error = %error,
+                                s3_key = s3_key,
+                                "Non-retryable upload error, will not retry"
+                            );
+                        }
+                        error
+                    })?;
+                Ok::<(), anyhow::Error>(())
+            })
+        })
+        .await
+    }
+
+    async fn get_file(&self, key: &str, destination: &Path) -> anyhow::Result<u64> {
+        let get_request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..Default::default()
+        };
+
+        let result = self
+            .client
+            .get_object(get_request)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to download from S3: {}", e))?;
+
+        let body = result.body.ok_or_else(|| anyhow::anyhow!("S3 response has no body"))?;
+
+        // Read the stream into bytes (rusoto ByteStream is blocking)
+        // Use spawn_blocking to avoid blocking the async runtime
+        let destination = destination.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            use std::io::Read;
+            use std::fs::File;
+
+            let mut file = File::create(&destination)
+                .map_err(|e| anyhow::anyhow!("Failed to create destination file: {}", e))?;
+
+            // ByteStream implements Read trait
+            let mut reader = body.into_blocking_read();
+            let mut buffer = [0u8; 8192]; // 8KB buffer
+            let mut total_bytes = 0u64;
+
+            loop {
+                let bytes_read = reader.read(&mut buffer)
+                    .map_err(|e| anyhow::anyhow!("Failed to read S3 stream: {}", e))?;
+
+                if bytes_read == 0 {
+                    break; // EOF
+                }
+
+                file.write_all(&buffer[..bytes_read])
+                    .map_err(|e| anyhow::anyhow!("Failed to write chunk: {}", e))?;
+
+                total_bytes += bytes_read as u64;
+            }
+
+            Ok::<u64, anyhow::Error>(total_bytes)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to delete object: {}", e))?;
+        Ok(())
+    }
+
+    fn store_type(&self) -> &str {
+        "s3"
+    }
+}
@@ -0,0 +1,701 @@
+//! Resource limits and isolation for FFmpeg processes
+//!
+//! Every FFmpeg and FFprobe process is started by [`JobContext`], which
+//! spawns it through [`SandboxConfig::spawn`]. Where the platform allows,
+//! the child:
+//!
+//! - runs under rlimits on CPU time, data size and written file size, and
+//!   never dumps core
+//! - gets its own cgroup v2 below a delegated `FFMPEG_CGROUP`, capped on
+//!   memory, CPU and processes
+//! - is cut off the network: new user and network namespaces on Linux,
+//!   plus a seccomp filter refusing IPv4/IPv6 sockets
+//!
+//! A wall-clock timeout kills processes that hang, and the end of stderr is
+//! kept so a failure becomes a [`ProcessError`] saying what FFmpeg
+//! complained about. Limits that cannot be applied (no cgroup delegation,
+//! user namespaces disabled) are skipped.
+//!
+//! [`JobContext`]: crate::jobs::JobContext
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::{Child, ChildStderr, Command};
+use tracing::{debug, warn};
+
+/// Bytes of stderr kept per process
+pub const STDERR_TAIL_BYTES: usize = 64 * 1024;
+
+/// Lines of stderr quoted in a [`ProcessError`]
+const ERROR_STDERR_LINES: usize = 20;
+
+/// Grace period between the soft (SIGXCPU) and hard (SIGKILL) CPU limits
+const CPU_GRACE_SECS: u64 = 5;
+
+/// How far below the hard CPU limit the reported CPU time of a process
+/// killed there may be; `/proc` accounts in clock ticks
+const CPU_ACCOUNTING_SLACK: Duration = Duration::from_millis(500);
+
+/// Limits applied to every FFmpeg process
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxConfig {
+    /// Wall-clock limit of one process
+    pub timeout: Option<Duration>,
+    /// CPU seconds a process may use (RLIMIT_CPU)
+    pub cpu_time: Option<u64>,
+    /// Bytes of heap a process may allocate (RLIMIT_DATA), also its cgroup's `memory.max`
+    pub memory: Option<u64>,
+    /// Largest file a process may write (RLIMIT_FSIZE)
+    pub max_file_size: Option<u64>,
+    /// Delegated cgroup v2 directory under which each process gets its own cgroup
+    pub cgroup: Option<PathBuf>,
+    /// CPUs a process may use (cgroup `cpu.max`)
+    pub cpu_quota: Option<f64>,
+    /// Processes and threads a process may have (cgroup `pids.max`)
+    pub max_pids: Option<u64>,
+    /// Deny network access (Linux only)
+    pub isolate_network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            cpu_time: None,
+            memory: None,
+            max_file_size: None,
+            cgroup: None,
+            cpu_quota: None,
+            max_pids: None,
+            isolate_network: true,
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Read `FFMPEG_*` limits; unset or 0 leaves a limit off
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let positive = |name: &str| var(name).and_then(|v| v.parse::<u64>().ok()).filter(|value| *value > 0);
+        Self {
+            timeout: positive("FFMPEG_TIMEOUT_SECS").map(Duration::from_secs),
+            cpu_time: positive("FFMPEG_CPU_SECS"),
+            memory: positive("FFMPEG_MEMORY_MB").map(|mb| mb * 1024 * 1024),
+            max_file_size: positive("FFMPEG_MAX_FILE_MB").map(|mb| mb * 1024 * 1024),
+            cgroup: var("FFMPEG_CGROUP").filter(|v| !v.is_empty()).map(PathBuf::from),
+            cpu_quota: var("FFMPEG_CPU_QUOTA")
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|cpus| *cpus > 0.0),
+            max_pids: positive("FFMPEG_MAX_PIDS"),
+            isolate_network: var("FFMPEG_ISOLATE_NETWORK")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.isolate_network),
+        }
+    }
+
+    /// Spawn `command` under the limits, with stderr piped
+    ///
+    /// The child is killed when the returned process is dropped.
+    pub fn spawn(&self, command: &mut Command) -> anyhow::Result<SandboxedChild> {
+        let program = Path::new(command.as_std().get_program())
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let cgroup = self.cgroup.as_deref().and_then(|parent| match Cgroup::create(parent, self) {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                warn!(cgroup = %parent.display(), error = %e, "Running FFmpeg without a cgroup");
+                None
+            }
+        });
+
+        #[cfg(unix)]
+        {
+            let setup = ChildSetup::new(self, cgroup.as_ref());
+            // SAFETY: the hook only makes async-signal-safe system calls on
+            // memory prepared before the fork.
+            unsafe {
+                command.pre_exec(move || setup.apply());
+            }
+        }
+
+        let child = command.stderr(Stdio::piped()).kill_on_drop(true).spawn()?;
+        Ok(SandboxedChild {
+            child,
+            program,
+            config: self.clone(),
+            cgroup,
+            cpu_used: None,
+        })
+    }
+}
+
+/// A process started by [`SandboxConfig::spawn`]
+#[derive(Debug)]
+pub struct SandboxedChild {
+    pub child: Child,
+    program: String,
+    config: SandboxConfig,
+    cgroup: Option<Cgroup>,
+    /// CPU time of the exited process, where the platform reports it
+    cpu_used: Option<Duration>,
+}
+
+impl SandboxedChild {
+    /// Wall-clock limit of the process
+    pub fn timeout(&self) -> Option<Duration> {
+        self.config.timeout
+    }
+
+    /// Wait for the process to exit, noting the CPU time it used
+    pub async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        #[cfg(target_os = "linux")]
+        if let Some(pid) = self.child.id() {
+            self.cpu_used = tokio::task::spawn_blocking(move || cpu_time_at_exit(pid))
+                .await
+                .ok()
+                .flatten();
+        }
+        self.child.wait().await
+    }
+
+    /// Take the process's stderr, to be drained into a [`StderrTail`]
+    pub fn stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    /// Error for a process killed at its wall-clock limit
+    pub fn timed_out(&self, stderr: &StderrTail) -> ProcessError {
+        ProcessError::new(
+            &self.program,
+            Failure::Timeout(self.config.timeout.unwrap_or_default()),
+            &stderr.to_string(),
+        )
+    }
+
+    /// Status of the exited process; a process killed by one of its
+    /// limits is an error
+    pub fn finish(&self, status: ExitStatus, stderr: StderrTail) -> Result<ProcessStatus, ProcessError> {
+        let stderr = stderr.to_string();
+        if let Some(limit) = self.exceeded_limit(&status, &stderr) {
+            let error = ProcessError::new(&self.program, Failure::ResourceLimit(limit), &stderr);
+            warn!(program = self.program, limit = %limit, "FFmpeg process exceeded a resource limit");
+            return Err(error);
+        }
+        Ok(ProcessStatus {
+            program: self.program.clone(),
+            status,
+            stderr,
+        })
+    }
+
+    fn exceeded_limit(&self, status: &ExitStatus, stderr: &str) -> Option<Limit> {
+        if status.success() {
+            return None;
+        }
+        if self.cgroup.as_ref().is_some_and(Cgroup::oom_killed)
+            || (self.config.memory.is_some() && stderr.contains("Cannot allocate memory"))
+        {
+            return Some(Limit::Memory);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            let signal = status.signal();
+            // The kernel sends SIGKILL at the hard CPU limit, but so does
+            // anything else; only count it when the CPU time got there
+            let hard_cpu_limit = self.config.cpu_time.is_some_and(|secs| {
+                self.cpu_used
+                    .is_some_and(|used| used + CPU_ACCOUNTING_SLACK >= Duration::from_secs(secs + CPU_GRACE_SECS))
+            });
+            // FFmpeg catches SIGXCPU and exits on its own
+            if signal == Some(libc::SIGXCPU)
+                || (signal == Some(libc::SIGKILL) && hard_cpu_limit)
+                || (self.config.cpu_time.is_some()
+                    && stderr.contains(&format!("received signal {}", libc::SIGXCPU)))
+            {
+                return Some(Limit::CpuTime);
+            }
+            if signal == Some(libc::SIGXFSZ) || (self.config.max_file_size.is_some() && stderr.contains("File too large")) {
+                return Some(Limit::FileSize);
+            }
+        }
+        None
+    }
+}
+
+/// Exit status of a sandboxed process and the end of its stderr
+#[derive(Debug, Clone)]
+pub struct ProcessStatus {
+    pub program: String,
+    pub status: ExitStatus,
+    /// Last [`STDERR_TAIL_BYTES`] of stderr
+    pub stderr: String,
+}
+
+impl ProcessStatus {
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// The failure as an error quoting stderr
+    pub fn error(&self) -> ProcessError {
+        let failure = match self.status.code() {
+            Some(code) => Failure::Exit(code),
+            None => Failure::Signal(signal(&self.status)),
+        };
+        ProcessError::new(&self.program, failure, &self.stderr)
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> i32 {
+    0
+}
+
+/// Resource limit a process ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    CpuTime,
+    Memory,
+    FileSize,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Limit::CpuTime => "CPU time",
+            Limit::Memory => "memory",
+            Limit::FileSize => "file size",
+        })
+    }
+}
+
+/// How a process failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Exited with a non-zero code
+    Exit(i32),
+    /// Killed by a signal
+    Signal(i32),
+    /// Killed at its wall-clock limit
+    Timeout(Duration),
+    /// Stopped by a resource limit
+    ResourceLimit(Limit),
+}
+
+/// A failed FFmpeg or FFprobe process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessError {
+    pub program: String,
+    pub failure: Failure,
+    /// Last lines of stderr
+    pub stderr: String,
+}
+
+impl ProcessError {
+    fn new(program: &str, failure: Failure, stderr: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            failure,
+            stderr: last_lines(stderr, ERROR_STDERR_LINES),
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.failure {
+            Failure::Exit(code) => write!(f, "{} exited with code {}", self.program, code)?,
+            Failure::Signal(signal) => write!(f, "{} was killed by signal {}", self.program, signal)?,
+            Failure::Timeout(limit) => write!(f, "{} timed out after {} s", self.program, limit.as_secs())?,
+            Failure::ResourceLimit(limit) => write!(f, "{} exceeded its {} limit", self.program, limit)?,
+        }
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// Last `count` non-empty lines of `text`; FFmpeg's `\r` progress updates count as lines
+fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text
+        .rsplit(['\n', '\r'])
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .take(count)
+        .collect();
+    lines.into_iter().rev().collect::<Vec<_>>().join("\n")
+}
+
+/// The last [`STDERR_TAIL_BYTES`] a process wrote to stderr
+#[derive(Debug, Default)]
+pub struct StderrTail {
+    buffer: Vec<u8>,
+}
+
+impl StderrTail {
+    /// Read `stderr` to its end
+    pub async fn read_from(&mut self, stderr: Option<ChildStderr>) -> std::io::Result<()> {
+        let Some(mut stderr) = stderr else {
+            return Ok(());
+        };
+        let mut chunk = vec![0u8; 8 * 1024];
+        loop {
+            let read = stderr.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.push(&chunk[..read]);
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() > 2 * STDERR_TAIL_BYTES {
+            self.buffer.drain(..self.buffer.len() - STDERR_TAIL_BYTES);
+        }
+    }
+}
+
+impl std::fmt::Display for StderrTail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let start = self.buffer.len().saturating_sub(STDERR_TAIL_BYTES);
+        f.write_str(&String::from_utf8_lossy(&self.buffer[start..]))
+    }
+}
+
+/// cgroup v2 of one process, removed when dropped
+#[derive(Debug)]
+struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    fn create(parent: &Path, config: &SandboxConfig) -> std::io::Result<Self> {
+        let path = parent.join(format!("ffmpeg-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&path)?;
+        let cgroup = Self { path };
+        if let Some(memory) = config.memory {
+            cgroup.write("memory.max", &memory.to_string())?;
+            // Without swap the OOM killer stops the process instead of the host paging
+            let _ = cgroup.write("memory.swap.max", "0");
+        }
+        if let Some(cpus) = config.cpu_quota {
+            let period = 100_000;
+            let quota = ((cpus * period as f64) as u64).max(1000);
+            cgroup.write("cpu.max", &format!("{} {}", quota, period))?;
+        }
+        if let Some(pids) = config.max_pids {
+            cgroup.write("pids.max", &pids.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, value: &str) -> std::io::Result<()> {
+        std::fs::write(self.path.join(file), value)
+    }
+
+    /// Whether the OOM killer stopped a process of the cgroup
+    fn oom_killed(&self) -> bool {
+        std::fs::read_to_string(self.path.join("memory.events"))
+            .map(|events| {
+                events.lines().any(|line| {
+                    line.strip_prefix("oom_kill ")
+                        .and_then(|count| count.trim().parse::<u64>().ok())
+                        .is_some_and(|count| count > 0)
+                })
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            debug!(cgroup = %self.path.display(), error = %e, "Failed to remove FFmpeg cgroup");
+        }
+    }
+}
+
+/// CPU time of process `pid` once it has exited
+///
+/// Waits for the exit without reaping the process, so its accounting can
+/// still be read from `/proc`; the caller reaps it afterwards.
+#[cfg(target_os = "linux")]
+fn cpu_time_at_exit(pid: u32) -> Option<Duration> {
+    // SAFETY: siginfo_t is plain data and waitid only writes into it
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    loop {
+        let result = unsafe {
+            libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT)
+        };
+        if result == 0 {
+            break;
+        }
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+
+    // utime and stime are fields 14 and 15, counted from the state after the command name
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (ticks_per_sec > 0).then(|| Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64))
+}
+
+/// Everything the forked child applies before exec, prepared in the parent
+/// so that the child does not allocate
+#[cfg(unix)]
+struct ChildSetup {
+    rlimits: Vec<(i32, libc::rlim_t, libc::rlim_t)>,
+    cgroup_procs: Option<std::ffi::CString>,
+    #[cfg(target_os = "linux")]
+    seccomp: Option<Vec<libc::sock_filter>>,
+}
+
+#[cfg(unix)]
+impl ChildSetup {
+    fn new(config: &SandboxConfig, cgroup: Option<&Cgroup>) -> Self {
+        let mut rlimits = vec![(libc::RLIMIT_CORE as i32, 0, 0)];
+        if let Some(secs) = config.cpu_time {
+            rlimits.push((libc::RLIMIT_CPU as i32, secs as _, (secs + CPU_GRACE_SECS) as _));
+        }
+        if let Some(bytes) = config.memory {
+            rlimits.push((libc::RLIMIT_DATA as i32, bytes as _, bytes as _));
+        }
+        if let Some(bytes) = config.max_file_size {
+            rlimits.push((libc::RLIMIT_FSIZE as i32, bytes as _, bytes as _));
+        }
+        let cgroup_procs = cgroup.and_then(|cgroup| {
+            use std::os::unix::ffi::OsStrExt;
+            std::ffi::CString::new(cgroup.path.join("cgroup.procs").as_os_str().as_bytes()).ok()
+        });
+        Self {
+            rlimits,
+            cgroup_procs,
+            #[cfg(target_os = "linux")]
+            seccomp: config.isolate_network.then(seccomp::deny_inet_sockets).flatten(),
+        }
+    }
+
+    /// Runs in the child between fork and exec
+    fn apply(&self) -> std::io::Result<()> {
+        // SAFETY: plain system calls on pointers to live, initialized memory
+        unsafe {
+            if let Some(procs) = &self.cgroup_procs {
+                let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                // "0" moves the writing process
+                let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                libc::close(fd);
+                if written != 1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            for &(resource, soft, hard) in &self.rlimits {
+                let limit = libc::rlimit {
+                    rlim_cur: soft,
+                    rlim_max: hard,
+                };
+                if libc::setrlimit(resource as _, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(filter) = &self.seccomp {
+                // An unprivileged user namespace makes the network namespace
+                // possible without CAP_SYS_ADMIN; either may be unavailable
+                if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                    libc::unshare(libc::CLONE_NEWNET);
+                }
+                seccomp::install(filter);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// seccomp filter refusing IPv4 and IPv6 sockets
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use libc::{sock_filter, BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    /// x32 system calls, which would bypass the syscall number check
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    // Offsets into struct seccomp_data
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    const ARG0_OFFSET: u32 = 16;
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: (BPF_JMP | BPF_JEQ | BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// Filter program, on architectures it is written for
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn deny_inet_sockets() -> Option<Vec<sock_filter>> {
+        let deny = libc::SECCOMP_RET_ERRNO | libc::EACCES as u32;
+        Some(vec![
+            statement(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+            jump(AUDIT_ARCH, 0, 7),
+            statement(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET),
+            sock_filter {
+                code: (BPF_JMP | BPF_JGE | BPF_K) as u16,
+                jt: 5,
+                jf: 0,
+                k: X32_SYSCALL_BIT,
+            },
+            jump(libc::SYS_socket as u32, 0, 3),
+            statement(BPF_LD | BPF_W | BPF_ABS, ARG0_OFFSET),
+            jump(libc::AF_INET as u32, 2, 0),
+            jump(libc::AF_INET6 as u32, 1, 0),
+            statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+            statement(BPF_RET | BPF_K, deny),
+        ])
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn deny_inet_sockets() -> Option<Vec<sock_filter>> {
+        None
+    }
+
+    /// Install `filter` in the calling process; failures leave it unfiltered
+    ///
+    /// # Safety
+    ///
+    /// Must only be called in a child about to exec.
+    pub(super) unsafe fn install(filter: &[sock_filter]) {
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr() as *mut sock_filter,
+        };
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0 {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(config: &SandboxConfig, script: &str) -> Result<ProcessStatus, ProcessError> {
+        let mut process = config.spawn(Command::new("sh").args(["-c", script])).unwrap();
+        let mut stderr = StderrTail::default();
+        stderr.read_from(process.stderr()).await.unwrap();
+        let status = process.wait().await.unwrap();
+        process.finish(status, stderr)
+    }
+
+    #[test]
+    fn test_last_lines() {
+        assert_eq!(last_lines("a\nb\r\nframe=1\rframe=2\n\n", 2), "frame=1\nframe=2");
+        assert_eq!(last_lines("", 5), "");
+
+        let mut tail = StderrTail::default();
+        tail.push(&vec![b'x'; 3 * STDERR_TAIL_BYTES]);
+        tail.push(b"end");
+        let text = tail.to_string();
+        assert_eq!(text.len(), STDERR_TAIL_BYTES);
+        assert!(text.ends_with("xend"));
+    }
+
+    #[tokio::test]
+    async fn test_failure_quotes_stderr() {
+        let status = run(&SandboxConfig::default(), "echo starting >&2; echo 'Invalid data found' >&2; exit 3")
+            .await
+            .unwrap();
+        assert!(!status.success());
+        let error = status.error();
+        assert_eq!(error.failure, Failure::Exit(3));
+        assert_eq!(error.to_string(), "sh exited with code 3: starting\nInvalid data found");
+
+        let status = run(&SandboxConfig::default(), "echo ok").await.unwrap();
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_file_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SandboxConfig {
+            max_file_size: Some(4096),
+            ..SandboxConfig::default()
+        };
+        let script = format!("exec head -c 100000 /dev/zero > {}", dir.path().join("out").display());
+        let error = run(&config, &script).await.unwrap_err();
+        assert_eq!(error.failure, Failure::ResourceLimit(Limit::FileSize));
+        assert!(std::fs::metadata(dir.path().join("out")).unwrap().len() <= 4096);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sigkill_is_a_cpu_limit_only_at_the_hard_limit() {
+        let config = SandboxConfig {
+            cpu_time: Some(1),
+            ..SandboxConfig::default()
+        };
+        let status = run(&config, "kill -9 $$").await.unwrap();
+        assert_eq!(status.error().failure, Failure::Signal(libc::SIGKILL));
+
+        // Ignoring SIGXCPU runs the process into the hard limit
+        let error = run(&config, "trap '' XCPU; while :; do :; done").await.unwrap_err();
+        assert_eq!(error.failure, Failure::ResourceLimit(Limit::CpuTime));
+    }
+}
@@ -0,0 +1,296 @@
+//! Thumbnail sprite sheets for scrubbing previews
+//!
+//! One frame is sampled every [`SpriteConfig::interval`] seconds, scaled to
+//! the tile size and laid out row by row on sheets of `columns` x `rows`
+//! tiles (FFmpeg's `fps`, `scale` and `tile` filters). A WebVTT track,
+//! written next to the sheets, has one cue per tile whose payload is the
+//! sheet file with a `#xywh=` media fragment, as players expect for
+//! thumbnail previews while seeking.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// File name of the WebVTT track in the output directory
+pub const VTT_FILE_NAME: &str = "thumbnails.vtt";
+
+/// Image format of the sprite sheets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpriteFormat {
+    Jpeg,
+    Webp,
+}
+
+impl SpriteFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpriteFormat::Jpeg => "jpg",
+            SpriteFormat::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SpriteFormat::Jpeg => "image/jpeg",
+            SpriteFormat::Webp => "image/webp",
+        }
+    }
+
+    fn ffmpeg_args(&self) -> &'static [&'static str] {
+        match self {
+            SpriteFormat::Jpeg => &["-q:v", "4"],
+            SpriteFormat::Webp => &["-c:v", "libwebp", "-quality", "75"],
+        }
+    }
+}
+
+/// Sprite sheet settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteConfig {
+    pub enabled: bool,
+    /// Seconds between sampled frames
+    pub interval: u32,
+    /// Tile width in pixels; the height follows the source aspect ratio
+    pub tile_width: u32,
+    pub columns: u32,
+    pub rows: u32,
+    pub format: SpriteFormat,
+}
+
+impl Default for SpriteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 5,
+            tile_width: 160,
+            columns: 10,
+            rows: 10,
+            format: SpriteFormat::Jpeg,
+        }
+    }
+}
+
+impl SpriteConfig {
+    /// Read `THUMBNAIL_SPRITES`, `SPRITE_INTERVAL_SECS`, `SPRITE_TILE_WIDTH`,
+    /// `SPRITE_COLUMNS`, `SPRITE_ROWS` and `SPRITE_FORMAT` (jpeg, webp)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let positive = |name: &str, default: u32| {
+            var(name)
+                .and_then(|v| v.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            enabled: var("THUMBNAIL_SPRITES")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.enabled),
+            interval: positive("SPRITE_INTERVAL_SECS", defaults.interval),
+            tile_width: positive("SPRITE_TILE_WIDTH", defaults.tile_width),
+            columns: positive("SPRITE_COLUMNS", defaults.columns),
+            rows: positive("SPRITE_ROWS", defaults.rows),
+            format: match var("SPRITE_FORMAT").map(|v| v.to_lowercase()).as_deref() {
+                Some("webp") => SpriteFormat::Webp,
+                _ => defaults.format,
+            },
+        }
+    }
+
+    /// Tile height for a source displayed at `width` x `height`, rounded to
+    /// an even number of pixels
+    pub fn tile_height(&self, (width, height): (u32, u32)) -> u32 {
+        if width == 0 {
+            return self.tile_width * 9 / 16 / 2 * 2;
+        }
+        let exact = self.tile_width as f64 * height as f64 / width as f64;
+        ((exact / 2.0).round() as u32 * 2).max(2)
+    }
+}
+
+/// Generated sprite sheets and their WebVTT track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpriteSheets {
+    pub sheets: Vec<PathBuf>,
+    pub vtt: PathBuf,
+    pub format: SpriteFormat,
+    pub interval: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+/// WebVTT track with one cue per tile over `duration` seconds
+///
+/// Tiles are numbered in sampling order across `sheet_names`; cues stop at
+/// the last tile the sheets hold.
+pub fn render_vtt(
+    duration: f64,
+    config: &SpriteConfig,
+    tile_height: u32,
+    sheet_names: &[String],
+) -> String {
+    let per_sheet = (config.columns * config.rows) as usize;
+    let tiles = ((duration / config.interval as f64).ceil() as usize).min(per_sheet * sheet_names.len());
+
+    let mut vtt = String::from("WEBVTT\n");
+    for tile in 0..tiles {
+        let start = (tile as u32 * config.interval) as f64;
+        let end = (start + config.interval as f64).min(duration);
+        let position = tile % per_sheet;
+        let x = (position as u32 % config.columns) * config.tile_width;
+        let y = (position as u32 / config.columns) * tile_height;
+        let _ = write!(
+            vtt,
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            vtt_timestamp(start),
+            vtt_timestamp(end),
+            sheet_names[tile / per_sheet],
+            x,
+            y,
+            config.tile_width,
+            tile_height
+        );
+    }
+    vtt
+}
+
+/// WebVTT timestamp (`HH:MM:SS.mmm`)
+pub(crate) fn vtt_timestamp(seconds: f64) -> String {
+    let ms = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Render the sprite sheets of `input` and their WebVTT track into `output_dir`
+///
+/// `display_size` is the source's displayed size and `sdr_filter`, when
+/// set, is prepended to tone-map HDR sources. Returns `None` when no sheet
+/// could be rendered.
+pub async fn generate(
+    input: &Path,
+    output_dir: &Path,
+    duration: f64,
+    display_size: (u32, u32),
+    sdr_filter: Option<&str>,
+    config: &SpriteConfig,
+    job: &JobContext,
+) -> anyhow::Result<Option<SpriteSheets>> {
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let extension = config.format.extension();
+    let pattern = output_dir.join(format!("sprite_%03d.{}", extension));
+    let pattern = pattern
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Sprite path contains invalid UTF-8: {:?}", pattern))?;
+
+    let tile_height = config.tile_height(display_size);
+    let mut filter = format!(
+        "fps=1/{},scale={}:{},tile={}x{}",
+        config.interval, config.tile_width, tile_height, config.columns, config.rows
+    );
+    if let Some(sdr_filter) = sdr_filter {
+        filter = format!("{},{}", sdr_filter, filter);
+    }
+
+    let mut args = vec!["-y", "-i", input, "-an", "-vf", &filter];
+    args.extend_from_slice(config.format.ffmpeg_args());
+    args.extend_from_slice(&["-f", "image2", pattern]);
+    let status = job
+        .run(ingest::ffmpeg_command("ffmpeg").args(&args), |_| {})
+        .await?;
+    if !status.success() {
+        warn!(error = %status.error(), "Failed to render sprite sheets");
+        return Ok(None);
+    }
+
+    // The image2 muxer numbers sheets from 1
+    let mut sheets = Vec::new();
+    let mut sheet_names = Vec::new();
+    for index in 1.. {
+        let name = format!("sprite_{:03}.{}", index, extension);
+        let path = output_dir.join(&name);
+        if !path.exists() {
+            break;
+        }
+        sheets.push(path);
+        sheet_names.push(name);
+    }
+    if sheets.is_empty() {
+        return Ok(None);
+    }
+
+    let vtt = output_dir.join(VTT_FILE_NAME);
+    std::fs::write(&vtt, render_vtt(duration, config, tile_height, &sheet_names))?;
+    info!(sheets = sheets.len(), interval = config.interval, "Generated sprite sheets");
+
+    Ok(Some(SpriteSheets {
+        sheets,
+        vtt,
+        format: config.format,
+        interval: config.interval,
+        tile_width: config.tile_width,
+        tile_height,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_height_follows_aspect_ratio() {
+        let config = SpriteConfig::default();
+        assert_eq!(config.tile_height((1920, 1080)), 90);
+        // Portrait phone video
+        assert_eq!(config.tile_height((1080, 1920)), 284);
+        assert_eq!(config.tile_height((0, 0)), 90);
+    }
+
+    #[test]
+    fn test_vtt_cues_walk_tiles_across_sheets() {
+        let config = SpriteConfig {
+            interval: 10,
+            columns: 2,
+            rows: 2,
+            ..SpriteConfig::default()
+        };
+        let sheets = vec!["sprite_001.jpg".to_string(), "sprite_002.jpg".to_string()];
+        let vtt = render_vtt(45.5, &config, 90, &sheets);
+        let cues: Vec<&str> = vtt.split("\n\n").skip(1).collect();
+
+        assert!(vtt.starts_with("WEBVTT\n"));
+        assert_eq!(cues.len(), 5);
+        assert_eq!(cues[0], "00:00:00.000 --> 00:00:10.000\nsprite_001.jpg#xywh=0,0,160,90");
+        assert_eq!(cues[3], "00:00:30.000 --> 00:00:40.000\nsprite_001.jpg#xywh=160,90,160,90");
+        assert_eq!(cues[4].trim_end(), "00:00:40.000 --> 00:00:45.500\nsprite_002.jpg#xywh=0,0,160,90");
+
+        // Cues stop where the sheets do
+        let one_sheet = render_vtt(45.5, &config, 90, &sheets[..1]);
+        assert_eq!(one_sheet.matches("-->").count(), 4);
+        assert_eq!(vtt_timestamp(3725.25), "01:02:05.250");
+    }
+}
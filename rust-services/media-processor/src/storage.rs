@@ -1,4 +1,4 @@
-//! Object Storage - S3-compatible storage operations (Akamai-compatible)
+//! Object Storage - uploads processed media through an [`ObjectStore`]
 // Copyright 2025 Francisco F. Pinochet
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -15,115 +15,104 @@
 
 
 use armoricore_config::ObjectStorageConfig;
-use rusoto_core::{credential::StaticProvider, request::HttpClient, Region};
-use rusoto_s3::{PutObjectRequest, S3Client, S3};
-use std::fs;
-use std::path::Path;
+use armoricore_types::schemas::{
+    ImageInfo, ImageVariantInfo, PlaybackUrls, PosterFrame, ThumbnailTrack, WaveformInfo, WaveformLevelInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::images::ProcessedImage;
+use crate::local_store::LocalStore;
+use crate::object_store::{ObjectStore, StorageBackend};
+use crate::posters::Poster;
 use crate::processor::ProcessingResult;
-use crate::retry::{RetryConfig, is_retryable_upload_error, retry_with_backoff};
+use crate::s3_store::S3Store;
+use crate::sprites::SpriteSheets;
+use crate::urls::UrlBuilder;
+use crate::waveform::Waveform;
+
+/// Public URLs of a media's uploaded outputs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedMedia {
+    pub playback_urls: PlaybackUrls,
+    pub thumbnail_urls: Vec<String>,
+    pub thumbnail_track: Option<ThumbnailTrack>,
+    #[serde(default)]
+    pub posters: Vec<PosterFrame>,
+    #[serde(default)]
+    pub waveform: Option<WaveformInfo>,
+    #[serde(default)]
+    pub image: Option<ImageInfo>,
+}
 
-/// Object storage client for S3-compatible storage (Akamai)
+/// Uploads processed media to an object store
 pub struct ObjectStorage {
-    client: Option<Arc<S3Client>>,
-    config: ObjectStorageConfig,
-    base_url: String,
-    retry_config: RetryConfig,
+    store: Option<Arc<dyn ObjectStore>>,
+    urls: UrlBuilder,
 }
 
 impl ObjectStorage {
-    /// Create a new object storage client
+    /// Create storage for an S3-compatible bucket
+    ///
+    /// Without a usable S3 client, uploads fall back to mock URLs.
     pub fn new(config: ObjectStorageConfig) -> Self {
         info!(
             endpoint = config.endpoint,
             bucket = config.bucket,
-            "Initializing object storage client for Akamai"
+            "Initializing object storage client"
         );
 
-        // Extract base URL from endpoint
-        // For Akamai, endpoint might be like: https://your-bucket.akamai.com
-        // or s3://bucket-name with a separate CDN URL
-        let base_url = if config.endpoint.starts_with("http") {
-            // If endpoint is a full URL, use it as base
-            let url = config.endpoint.trim_end_matches('/');
-            url.to_string()
-        } else if config.endpoint.starts_with("s3://") {
-            // If it's an s3:// URL, construct from bucket
-            format!("https://{}.akamai.com", config.bucket)
-        } else {
-            format!("https://{}.akamai.com", config.bucket)
+        let store = match S3Store::new(&config) {
+            Ok(store) => Some(Arc::new(store) as Arc<dyn ObjectStore>),
+            Err(e) => {
+                warn!(error = %e, "Failed to create S3 client");
+                None
+            }
         };
 
-        // Initialize S3 client for Akamai (S3-compatible)
-        let client = Self::create_s3_client(&config)
-            .map(Arc::new);
-
         Self {
-            client,
-            config,
-            base_url,
-            retry_config: RetryConfig::from_env(),
+            store,
+            urls: UrlBuilder::from_env(UrlBuilder::for_s3(&config)),
         }
     }
 
-    /// Create S3 client configured for Akamai Object Storage
-    fn create_s3_client(config: &ObjectStorageConfig) -> Option<S3Client> {
-        // Create credentials provider
-        let credentials = StaticProvider::new_minimal(
-            config.access_key.clone(),
-            config.secret_key.clone(),
-        );
-
-        // Determine region - Akamai typically uses us-east-1 or a custom region
-        let region = if let Some(ref region_str) = config.region {
-            Region::Custom {
-                name: region_str.clone(),
-                endpoint: Self::extract_endpoint(&config.endpoint),
+    /// Create storage for the backend selected by `OBJECT_STORAGE_BACKEND`
+    ///
+    /// The S3 backend requires `config`.
+    pub fn from_env(config: Option<ObjectStorageConfig>) -> anyhow::Result<Self> {
+        match (StorageBackend::from_env(), config) {
+            (StorageBackend::Local, _) => {
+                let store = LocalStore::from_env();
+                info!(root = %store.root().display(), "Using local object store");
+                let urls = UrlBuilder::from_env(UrlBuilder::for_local(store.root()));
+                Ok(Self::with_store(Arc::new(store), urls))
             }
-        } else {
-            // Default to custom region with Akamai endpoint
-            Region::Custom {
-                name: "akamai".to_string(),
-                endpoint: Self::extract_endpoint(&config.endpoint),
-            }
-        };
-
-        let endpoint_str = match &region {
-            Region::Custom { endpoint, .. } => endpoint.clone(),
-            _ => "default".to_string(),
-        };
-        
-        info!(
-            endpoint = endpoint_str,
-            "Creating S3 client for Akamai Object Storage"
-        );
+            (StorageBackend::S3, Some(config)) => Ok(Self::new(config)),
+            (StorageBackend::S3, None) => Err(anyhow::anyhow!(
+                "Missing OBJECT_STORAGE_* environment variables or key store keys"
+            )),
+        }
+    }
 
-        // Create HTTP client
-        let http_client = match HttpClient::new() {
-            Ok(client) => client,
-            Err(e) => {
-                warn!(error = %e, "Failed to create HTTP client");
-                return None;
-            }
-        };
+    /// Create storage over `store`, publishing its objects at `urls`
+    pub fn with_store(store: Arc<dyn ObjectStore>, urls: UrlBuilder) -> Self {
+        Self {
+            store: Some(store),
+            urls,
+        }
+    }
 
-        Some(S3Client::new_with(http_client, credentials, region))
+    /// The store outputs are written to, if any
+    pub fn store(&self) -> Option<Arc<dyn ObjectStore>> {
+        self.store.clone()
     }
 
-    /// Extract endpoint URL from configuration
-    fn extract_endpoint(endpoint: &str) -> String {
-        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
-            endpoint.to_string()
-        } else if endpoint.starts_with("s3://") {
-            // Convert s3://bucket to https://bucket.akamai.com
-            let bucket = endpoint.strip_prefix("s3://").unwrap_or(endpoint);
-            format!("https://{}.akamai.com", bucket)
-        } else {
-            format!("https://{}.akamai.com", endpoint)
-        }
+    /// Public URLs of stored objects
+    pub fn urls(&self) -> &UrlBuilder {
+        &self.urls
     }
 
     /// Upload processed files to object storage
@@ -131,20 +120,19 @@ impl ObjectStorage {
         &self,
         media_id: &Uuid,
         processing_result: &ProcessingResult,
-    ) -> anyhow::Result<armoricore_types::schemas::PlaybackUrls> {
+    ) -> anyhow::Result<UploadedMedia> {
         info!(
             media_id = %media_id,
-            bucket = self.config.bucket,
             files_count = processing_result.output_files.len(),
-            "Uploading processed files to Akamai Object Storage"
+            "Uploading processed files to object storage"
         );
 
-        // If client is not available, use mock
-        let client = match &self.client {
-            Some(c) => c,
+        // If no store is available, use mock
+        let store = match &self.store {
+            Some(store) => store.as_ref(),
             None => {
-                warn!("S3 client not available, using mock URLs");
-                return self.generate_mock_urls(media_id);
+                warn!("Object store not available, using mock URLs");
+                return self.generate_mock_urls(media_id, processing_result);
             }
         };
 
@@ -162,7 +150,7 @@ impl ObjectStorage {
                     // Upload all variant playlists and segments
                     let output_dir = playlist_path.parent()
                         .ok_or_else(|| anyhow::anyhow!("Playlist path has no parent directory: {:?}", playlist_path))?;
-                    self.upload_hls_variants(output_dir, media_id).await?;
+                    self.upload_hls_variants(output_dir, media_id, processing_result.is_audio_only).await?;
                     
                     Some(url)
                 }
@@ -210,67 +198,213 @@ impl ObjectStorage {
             }
         }
 
-        // Upload thumbnails
-        let thumbnail_urls = self.upload_thumbnails(client, media_id, processing_result).await?;
+        // Upload thumbnails and scrubbing sprites
+        let thumbnails = self.upload_thumbnails(store, media_id, processing_result).await?;
+        let posters = Self::poster_frames(&processing_result.posters, &thumbnails);
+        let mut thumbnail_urls: Vec<String> = thumbnails.into_iter().map(|(_, url)| url).collect();
+        let thumbnail_track = match processing_result.sprites {
+            Some(ref sprites) => self.upload_sprites(media_id, sprites).await,
+            None => None,
+        };
+        let waveform = match processing_result.waveform {
+            Some(ref waveform) => self.upload_waveform(media_id, waveform).await,
+            None => None,
+        };
+        let image = match processing_result.image {
+            Some(ref image) => Some(self.upload_image(media_id, image).await?),
+            None => None,
+        };
+        thumbnail_urls.extend(Self::image_thumbnail(image.as_ref()));
 
-        // For now, DASH is not generated, but structure is ready
-        let dash_url = None;
+        // Upload DASH manifest (segments are shared with HLS and already uploaded)
+        let dash_url = if hls_url.is_some() {
+            self.upload_dash_manifest(store, media_id, processing_result).await?
+        } else {
+            None
+        };
 
         info!(
             media_id = %media_id,
             hls_url = ?hls_url,
+            dash_url = ?dash_url,
             mp4_files = mp4_urls.len(),
             thumbnails = thumbnail_urls.len(),
-            "Files uploaded to Akamai Object Storage"
+            sprites = thumbnail_track.as_ref().map(|t| t.sprite_urls.len()).unwrap_or(0),
+            store = store.store_type(),
+            "Files uploaded to object storage"
         );
 
-        Ok(armoricore_types::schemas::PlaybackUrls {
-            hls: hls_url,
-            mp4: mp4_urls,
-            dash: dash_url,
+        Ok(UploadedMedia {
+            playback_urls: PlaybackUrls {
+                hls: hls_url,
+                mp4: mp4_urls,
+                dash: dash_url,
+            },
+            thumbnail_urls,
+            thumbnail_track,
+            posters,
+            waveform,
+            image,
         })
     }
 
     /// Upload thumbnails
     async fn upload_thumbnails(
         &self,
-        _client: &S3Client,
+        _store: &dyn ObjectStore,
         media_id: &Uuid,
         processing_result: &ProcessingResult,
-    ) -> anyhow::Result<Vec<String>> {
-        let _ = _client; // Suppress unused warning
-        
-        // Find thumbnail files in output directory
-        let mut thumbnail_paths = Vec::new();
-        if let Ok(entries) = std::fs::read_dir(&processing_result.output_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                    if file_name.starts_with("thumb_") && file_name.ends_with(".jpg") {
-                        let file_name_str = file_name.to_string();
-                        thumbnail_paths.push((path, file_name_str));
-                    }
+    ) -> anyhow::Result<Vec<(PathBuf, String)>> {
+        let _ = _store; // Suppress unused warning
+
+        // Upload each thumbnail; only uploaded ones are reported, with their
+        // local path
+        let mut uploaded_urls = Vec::new();
+        for thumb_path in &processing_result.thumbnails {
+            let Some(file_name) = thumb_path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let s3_key = format!("media/{}/{}", media_id, file_name);
+            match self.upload_file(thumb_path, &s3_key, "image/jpeg").await {
+                Ok(url) => uploaded_urls.push((thumb_path.clone(), url)),
+                Err(e) => {
+                    warn!(error = %e, file = file_name, "Failed to upload thumbnail");
                 }
             }
         }
 
-        // Upload each thumbnail
-        let mut uploaded_urls = Vec::new();
-        for (thumb_path, file_name) in thumbnail_paths {
+        Ok(uploaded_urls)
+    }
+
+    /// Upload sprite sheets and their WebVTT track
+    ///
+    /// The track is only reported when it and every sheet it points at were
+    /// uploaded, since its cues reference the sheets by relative URL.
+    async fn upload_sprites(&self, media_id: &Uuid, sprites: &SpriteSheets) -> Option<ThumbnailTrack> {
+        let mut sprite_urls = Vec::new();
+        for sheet in &sprites.sheets {
+            let file_name = sheet.file_name().and_then(|n| n.to_str())?;
             let s3_key = format!("media/{}/{}", media_id, file_name);
-            match self.upload_file(&thumb_path, &s3_key, "image/jpeg").await {
-                Ok(url) => uploaded_urls.push(url),
+            match self.upload_file(sheet, &s3_key, sprites.format.content_type()).await {
+                Ok(url) => sprite_urls.push(url),
                 Err(e) => {
-                    warn!(error = %e, file = file_name, "Failed to upload thumbnail");
+                    warn!(error = %e, file = file_name, "Failed to upload sprite sheet");
+                    return None;
                 }
             }
         }
 
-        // If no thumbnails were uploaded, use the URLs from processing result
-        if uploaded_urls.is_empty() {
-            Ok(processing_result.thumbnail_urls.clone())
-        } else {
-            Ok(uploaded_urls)
+        let vtt_key = format!("media/{}/{}", media_id, crate::sprites::VTT_FILE_NAME);
+        match self.upload_file(&sprites.vtt, &vtt_key, "text/vtt").await {
+            Ok(vtt_url) => Some(Self::thumbnail_track(vtt_url, sprite_urls, sprites)),
+            Err(e) => {
+                warn!(error = %e, "Failed to upload sprite WebVTT track");
+                None
+            }
+        }
+    }
+
+    /// Upload the waveform levels; levels that fail to upload are left out
+    async fn upload_waveform(&self, media_id: &Uuid, waveform: &Waveform) -> Option<WaveformInfo> {
+        let mut levels = Vec::new();
+        for level in &waveform.levels {
+            let Some(file_name) = level.path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let s3_key = format!("media/{}/{}", media_id, file_name);
+            match self.upload_file(&level.path, &s3_key, waveform.format.content_type()).await {
+                Ok(url) => levels.push(WaveformLevelInfo {
+                    samples_per_pixel: level.samples_per_pixel,
+                    url,
+                }),
+                Err(e) => warn!(error = %e, file = file_name, "Failed to upload waveform"),
+            }
+        }
+        if levels.is_empty() {
+            return None;
+        }
+        Some(Self::waveform_info(waveform, levels))
+    }
+
+    /// Upload the image variants; variants that fail to upload are left out,
+    /// but at least one must succeed
+    async fn upload_image(&self, media_id: &Uuid, image: &ProcessedImage) -> anyhow::Result<ImageInfo> {
+        let mut variants = Vec::new();
+        for variant in &image.variants {
+            let Some(file_name) = variant.path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let s3_key = format!("media/{}/{}", media_id, file_name);
+            match self.upload_file(&variant.path, &s3_key, variant.format.content_type()).await {
+                Ok(url) => variants.push(Self::image_variant(variant, url)),
+                Err(e) => warn!(error = %e, file = file_name, "Failed to upload image variant"),
+            }
+        }
+        if variants.is_empty() {
+            return Err(anyhow::anyhow!("Failed to upload any image variant"));
+        }
+        Ok(Self::image_info(image, variants))
+    }
+
+    fn image_variant(variant: &crate::images::ImageVariant, url: String) -> ImageVariantInfo {
+        ImageVariantInfo {
+            url,
+            width: variant.width,
+            height: variant.height,
+            format: variant.format.as_str().to_string(),
+        }
+    }
+
+    fn image_info(image: &ProcessedImage, variants: Vec<ImageVariantInfo>) -> ImageInfo {
+        ImageInfo {
+            width: image.width,
+            height: image.height,
+            blurhash: image.blurhash.clone(),
+            variants,
+        }
+    }
+
+    /// Images have no frames to grab: the smallest variant is the thumbnail
+    fn image_thumbnail(image: Option<&ImageInfo>) -> Option<String> {
+        image.and_then(|image| image.variants.first()).map(|variant| variant.url.clone())
+    }
+
+    fn waveform_info(waveform: &Waveform, levels: Vec<WaveformLevelInfo>) -> WaveformInfo {
+        WaveformInfo {
+            format: waveform.format.extension().to_string(),
+            sample_rate: waveform.sample_rate,
+            bits: waveform.bits,
+            levels,
+        }
+    }
+
+    /// Poster frames of the uploaded thumbnails, in poster order
+    fn poster_frames(posters: &[Poster], thumbnails: &[(PathBuf, String)]) -> Vec<PosterFrame> {
+        posters
+            .iter()
+            .filter_map(|poster| {
+                let (_, url) = thumbnails.iter().find(|(path, _)| *path == poster.path)?;
+                let frame = &poster.candidate.frame;
+                Some(PosterFrame {
+                    url: url.clone(),
+                    time: frame.time,
+                    score: poster.candidate.score,
+                    scene_score: frame.scene_score,
+                    brightness: frame.brightness,
+                    black_ratio: frame.black_ratio,
+                    blur: frame.blur,
+                })
+            })
+            .collect()
+    }
+
+    fn thumbnail_track(vtt_url: String, sprite_urls: Vec<String>, sprites: &SpriteSheets) -> ThumbnailTrack {
+        ThumbnailTrack {
+            vtt_url,
+            sprite_urls,
+            interval: sprites.interval,
+            tile_width: sprites.tile_width,
+            tile_height: sprites.tile_height,
         }
     }
 
@@ -278,45 +412,52 @@ impl ObjectStorage {
     #[allow(dead_code)] // Method is part of public API, may be used by external code
     async fn upload_hls_playlist(
         &self,
-        _client: &S3Client,
+        _store: &dyn ObjectStore,
         _media_id: &Uuid,
         _processing_result: &ProcessingResult,
     ) -> anyhow::Result<Option<String>> {
         // TODO: Upload actual HLS playlist when FFmpeg generates it
         // For now, generate URL
-        let _ = (_client, _processing_result); // Suppress unused warnings
+        let _ = (_store, _processing_result); // Suppress unused warnings
         let hls_key = format!("media/{}/playlist.m3u8", _media_id);
-        let hls_url = format!("{}/{}", self.base_url, hls_key);
+        let hls_url = self.urls.url(&hls_key);
         Ok(Some(hls_url))
     }
 
     /// Upload DASH manifest
-    #[allow(dead_code)] // Method is part of public API, may be used by external code
     async fn upload_dash_manifest(
         &self,
-        _client: &S3Client,
-        _media_id: &Uuid,
-        _processing_result: &ProcessingResult,
+        _store: &dyn ObjectStore,
+        media_id: &Uuid,
+        processing_result: &ProcessingResult,
     ) -> anyhow::Result<Option<String>> {
-        // TODO: Upload actual DASH manifest when FFmpeg generates it
-        // For now, generate URL
-        let _ = (_client, _processing_result); // Suppress unused warnings
-        let dash_key = format!("media/{}/manifest.mpd", _media_id);
-        let dash_url = format!("{}/{}", self.base_url, dash_key);
-        Ok(Some(dash_url))
+        let _ = _store; // Suppress unused warning
+
+        let Some(ref manifest_path) = processing_result.dash_manifest_path else {
+            return Ok(None);
+        };
+
+        let dash_key = format!("media/{}/{}", media_id, crate::dash::MPD_FILE_NAME);
+        match self.upload_file(manifest_path, &dash_key, crate::dash::MPD_CONTENT_TYPE).await {
+            Ok(url) => Ok(Some(url)),
+            Err(e) => {
+                error!(error = %e, "Failed to upload DASH manifest");
+                Ok(None)
+            }
+        }
     }
 
-    /// Upload a single file to S3-compatible storage with retry logic
+    /// Upload a single file to the object store, returning its public URL
     pub async fn upload_file(
         &self,
         local_path: &Path,
         s3_key: &str,
         content_type: &str,
     ) -> anyhow::Result<String> {
-        let client = match &self.client {
-            Some(c) => c,
+        let store = match &self.store {
+            Some(store) => store,
             None => {
-                return Err(anyhow::anyhow!("S3 client not available"));
+                return Err(anyhow::anyhow!("Object store not available"));
             }
         };
 
@@ -324,64 +465,12 @@ impl ObjectStorage {
             local_path = %local_path.display(),
             s3_key = s3_key,
             content_type = content_type,
-            "Uploading file to Akamai Object Storage"
+            store = store.store_type(),
+            "Uploading file to object storage"
         );
 
-        // Read file content once (before retries)
-        let file_content = fs::read(local_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read file: {}", e))?;
-
-        let bucket = self.config.bucket.clone();
-        let s3_key_owned = s3_key.to_string();
-        let content_type_owned = content_type.to_string();
-        let base_url = self.base_url.clone();
-        let retry_config = self.retry_config.clone();
-
-        // Upload file with retry logic
-        let client_arc = Arc::clone(client);
-        let url: String = retry_with_backoff(&retry_config, || {
-            let client = Arc::clone(&client_arc);
-            let bucket = bucket.clone();
-            let s3_key = s3_key_owned.clone();
-            let content_type = content_type_owned.clone();
-            let file_content = file_content.clone();
-            let base_url = base_url.clone();
-
-            Box::pin(async move {
-                // Create put object request
-                let put_request = PutObjectRequest {
-                    bucket: bucket.clone(),
-                    key: s3_key.clone(),
-                    body: Some(file_content.clone().into()),
-                    content_type: Some(content_type.clone()),
-                    cache_control: Some("public, max-age=31536000".to_string()), // 1 year cache
-                    ..Default::default()
-                };
-
-                // Upload file
-                client
-                    .put_object(put_request)
-                    .await
-                    .map_err(|e| {
-                        let error = anyhow::anyhow!("Failed to upload file: {}", e);
-                        // Only retry if error is retryable
-                        if !is_retryable_upload_error(&error) {
-                            warn!(
-                                error = %error,
-                                s3_key = s3_key,
-                                "Non-retryable upload error, will not retry"
-                            );
-                        }
-                        error
-                    })?;
-
-                // Generate public URL
-                let url = format!("{}/{}", base_url, s3_key);
-
-                Ok::<String, anyhow::Error>(url)
-            })
-        })
-        .await?;
+        store.put_file(local_path, s3_key, content_type).await?;
+        let url = self.urls.url(s3_key);
 
         info!(
             s3_key = s3_key,
@@ -397,6 +486,7 @@ impl ObjectStorage {
         &self,
         output_dir: &Path,
         media_id: &Uuid,
+        is_audio_only: bool,
     ) -> anyhow::Result<()> {
         // Find all resolution directories (1080p, 720p, 480p, etc.)
         if let Ok(entries) = std::fs::read_dir(output_dir) {
@@ -414,16 +504,18 @@ impl ObjectStorage {
                             }
                         }
 
-                        // Upload all segments in this variant directory
+                        // Upload all segments (and the CMAF init segment) in this variant directory
                         if let Ok(segment_entries) = std::fs::read_dir(&path) {
                             for segment_entry in segment_entries.flatten() {
                                 let segment_path = segment_entry.path();
-                                if segment_path.extension().and_then(|s| s.to_str()) == Some("ts") {
-                                    if let Some(segment_name) = segment_path.file_name().and_then(|n| n.to_str()) {
-                                        let s3_key = format!("media/{}/{}/{}", media_id, variant_name, segment_name);
-                                        if let Err(e) = self.upload_file(&segment_path, &s3_key, "video/mp2t").await {
-                                            warn!(error = %e, variant = variant_name, segment = segment_name, "Failed to upload segment");
-                                        }
+                                if let Some(segment_name) = segment_path.file_name().and_then(|n| n.to_str()) {
+                                    let is_audio = is_audio_only || variant_name.starts_with("audio_");
+                                    let Some(content_type) = Self::segment_content_type(segment_name, is_audio) else {
+                                        continue;
+                                    };
+                                    let s3_key = format!("media/{}/{}/{}", media_id, variant_name, segment_name);
+                                    if let Err(e) = self.upload_file(&segment_path, &s3_key, content_type).await {
+                                        warn!(error = %e, variant = variant_name, segment = segment_name, "Failed to upload segment");
                                     }
                                 }
                             }
@@ -436,18 +528,75 @@ impl ObjectStorage {
         Ok(())
     }
 
-    /// Generate mock URLs (fallback when S3 client is not available)
+    /// MIME type of a file in a rendition directory, or `None` if it is not a segment
+    fn segment_content_type(file_name: &str, is_audio: bool) -> Option<&'static str> {
+        match Path::new(file_name).extension().and_then(|s| s.to_str()) {
+            Some("ts") => Some("video/mp2t"),
+            Some("vtt") => Some("text/vtt"),
+            Some("m4s") => Some(if is_audio { "audio/iso.segment" } else { "video/iso.segment" }),
+            Some("mp4") if file_name == crate::dash::INIT_SEGMENT_NAME => {
+                Some(if is_audio { "audio/mp4" } else { "video/mp4" })
+            }
+            _ => None,
+        }
+    }
+
+    /// Generate mock URLs (fallback when no object store is available)
     fn generate_mock_urls(
         &self,
         media_id: &Uuid,
-    ) -> anyhow::Result<armoricore_types::schemas::PlaybackUrls> {
-        let hls_url = format!("{}/media/{}/master.m3u8", self.base_url, media_id);
-        let dash_url = format!("{}/media/{}/manifest.mpd", self.base_url, media_id);
-
-        Ok(armoricore_types::schemas::PlaybackUrls {
-            hls: Some(hls_url),
-            dash: Some(dash_url),
-            mp4: std::collections::HashMap::new(), // TODO: Generate MP4 URLs when implemented
+        processing_result: &ProcessingResult,
+    ) -> anyhow::Result<UploadedMedia> {
+        let url = |path: &Path| {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            self.urls.url(&format!("media/{}/{}", media_id, file_name))
+        };
+        let hls_url = self.urls.url(&format!("media/{}/master.m3u8", media_id));
+        let dash_url = self.urls.url(&format!("media/{}/{}", media_id, crate::dash::MPD_FILE_NAME));
+
+        let thumbnails: Vec<(PathBuf, String)> = processing_result
+            .thumbnails
+            .iter()
+            .map(|path| (path.clone(), url(path)))
+            .collect();
+
+        let image = processing_result.image.as_ref().map(|image| {
+            let variants = image
+                .variants
+                .iter()
+                .map(|variant| Self::image_variant(variant, url(&variant.path)))
+                .collect();
+            Self::image_info(image, variants)
+        });
+        let mut thumbnail_urls: Vec<String> = thumbnails.iter().map(|(_, url)| url.clone()).collect();
+        thumbnail_urls.extend(Self::image_thumbnail(image.as_ref()));
+
+        // Images have no streams to play
+        let is_image = image.is_some();
+        Ok(UploadedMedia {
+            playback_urls: PlaybackUrls {
+                hls: (!is_image).then_some(hls_url),
+                dash: (!is_image).then_some(dash_url),
+                mp4: std::collections::HashMap::new(), // TODO: Generate MP4 URLs when implemented
+            },
+            posters: Self::poster_frames(&processing_result.posters, &thumbnails),
+            thumbnail_urls,
+            thumbnail_track: processing_result.sprites.as_ref().map(|sprites| {
+                let sprite_urls = sprites.sheets.iter().map(|path| url(path)).collect();
+                Self::thumbnail_track(url(&sprites.vtt), sprite_urls, sprites)
+            }),
+            waveform: processing_result.waveform.as_ref().map(|waveform| {
+                let levels = waveform
+                    .levels
+                    .iter()
+                    .map(|level| WaveformLevelInfo {
+                        samples_per_pixel: level.samples_per_pixel,
+                        url: url(&level.path),
+                    })
+                    .collect();
+                Self::waveform_info(waveform, levels)
+            }),
+            image,
         })
     }
 }
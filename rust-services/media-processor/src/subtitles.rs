@@ -0,0 +1,288 @@
+//! Sidecar subtitles as segmented WebVTT renditions
+//!
+//! Subtitle files (SRT or WebVTT) listed in the upload metadata are parsed
+//! into cues and cut into WebVTT segments aligned with the CMAF segments,
+//! each with an HLS media playlist, so the master playlist can offer them
+//! as `TYPE=SUBTITLES` alternatives. A cue spanning a segment boundary is
+//! repeated in every segment it overlaps, as HLS clients expect.
+//!
+//! The metadata of `media.uploaded` lists them as:
+//!
+//! ```json
+//! {"subtitles": [{"file_path": "s3://bucket/subs/en.srt", "language": "en", "name": "English", "default": true}]}
+//! ```
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::sprites::vtt_timestamp;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// HLS `GROUP-ID` of the subtitle renditions
+pub const SUBTITLE_GROUP: &str = "subs";
+
+/// Largest subtitle file read, in bytes
+pub const MAX_SUBTITLE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// A sidecar subtitle file from the upload metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleSource {
+    /// S3 or HTTP(S) URL of an SRT or WebVTT file
+    pub file_path: String,
+    /// RFC 5646 language tag
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub forced: bool,
+}
+
+impl SubtitleSource {
+    /// Subtitle files listed under `subtitles` in `media.uploaded` metadata;
+    /// malformed entries are skipped
+    pub fn from_metadata(metadata: &serde_json::Value) -> Vec<Self> {
+        let Some(entries) = metadata.get("subtitles").and_then(|v| v.as_array()) else {
+            return vec![];
+        };
+        entries
+            .iter()
+            .filter_map(|entry| match serde_json::from_value(entry.clone()) {
+                Ok(source) => Some(source),
+                Err(e) => {
+                    warn!(error = %e, "Ignoring malformed subtitle entry in upload metadata");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the file is fetched from object storage or HTTP(S); local
+    /// paths from event payloads are never read
+    pub fn is_remote(&self) -> bool {
+        ["s3://", "http://", "https://"]
+            .iter()
+            .any(|scheme| self.file_path.starts_with(scheme))
+    }
+
+    /// `NAME` of the rendition: the given name, else the language
+    pub fn display_name(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.language.clone())
+            .unwrap_or_else(|| format!("Subtitles {}", index + 1))
+    }
+}
+
+/// A timed subtitle cue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    /// Start and end in seconds
+    pub start: f64,
+    pub end: f64,
+    /// WebVTT cue settings (e.g. "line:0 align:start"); empty for SRT
+    pub settings: String,
+    pub text: String,
+}
+
+/// Parse the cues of an SRT or WebVTT document
+///
+/// Cue numbers and ids, `NOTE`, `STYLE` and `REGION` blocks are dropped.
+pub fn parse_cues(document: &str) -> anyhow::Result<Vec<Cue>> {
+    let document = document.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in document.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let (start, rest) = timing
+            .split_once("-->")
+            .ok_or_else(|| anyhow::anyhow!("Invalid cue timing: {}", timing))?;
+        let rest = rest.trim();
+        let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let text = lines.collect::<Vec<_>>().join("\n");
+        if text.trim().is_empty() {
+            continue;
+        }
+        cues.push(Cue {
+            start: parse_timestamp(start.trim())?,
+            end: parse_timestamp(end)?,
+            settings: settings.trim().to_string(),
+            text,
+        });
+    }
+    if cues.is_empty() {
+        return Err(anyhow::anyhow!("Subtitle file has no cues"));
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+/// `HH:MM:SS,mmm` (SRT) or `[HH:]MM:SS.mmm` (WebVTT) in seconds
+fn parse_timestamp(value: &str) -> anyhow::Result<f64> {
+    let invalid = || anyhow::anyhow!("Invalid cue timestamp: {}", value);
+    let normalized = value.replace(',', ".");
+    let mut seconds = 0.0;
+    for part in normalized.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().map_err(|_| invalid())?;
+    }
+    if normalized.split(':').count() < 2 {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
+/// WebVTT segments of `segment_duration` seconds covering `duration`
+///
+/// Every segment is written, even without cues, so the subtitle playlist
+/// spans the whole presentation.
+pub fn segment_cues(cues: &[Cue], duration: f64, segment_duration: f64) -> Vec<String> {
+    let count = ((duration / segment_duration).ceil() as usize).max(1);
+    (0..count)
+        .map(|index| {
+            let start = index as f64 * segment_duration;
+            let end = start + segment_duration;
+            let mut segment = String::from("WEBVTT\n");
+            for cue in cues.iter().filter(|cue| cue.start < end && cue.end > start) {
+                let _ = write!(
+                    segment,
+                    "\n{} --> {}{}{}\n{}\n",
+                    vtt_timestamp(cue.start),
+                    vtt_timestamp(cue.end),
+                    if cue.settings.is_empty() { "" } else { " " },
+                    cue.settings,
+                    cue.text
+                );
+            }
+            segment
+        })
+        .collect()
+}
+
+/// Read a subtitle document, failing if it is larger than `limit` bytes
+pub fn read_document(path: &Path, limit: u64) -> anyhow::Result<String> {
+    let mut document = String::new();
+    std::fs::File::open(path)?
+        .take(limit + 1)
+        .read_to_string(&mut document)?;
+    if document.len() as u64 > limit {
+        return Err(anyhow::anyhow!("Subtitle file is over the {} byte limit", limit));
+    }
+    Ok(document)
+}
+
+/// Write the segments of `cues` and their media playlist into `rendition_dir`
+pub fn write_rendition(
+    cues: &[Cue],
+    rendition_dir: &Path,
+    duration: f64,
+    segment_duration: f64,
+) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(rendition_dir)?;
+    let segments = segment_cues(cues, duration, segment_duration);
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        segment_duration.ceil() as u64
+    );
+    for (index, segment) in segments.iter().enumerate() {
+        let name = format!("segment_{:03}.vtt", index);
+        std::fs::write(rendition_dir.join(&name), segment)?;
+        let length = (duration - index as f64 * segment_duration).min(segment_duration);
+        let _ = write!(playlist, "#EXTINF:{:.3},\n{}\n", length.max(0.0), name);
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+
+    let playlist_path = rendition_dir.join("playlist.m3u8");
+    std::fs::write(&playlist_path, playlist)?;
+    Ok(playlist_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srt_and_webvtt() {
+        let srt = "\u{feff}1\r\n00:00:01,500 --> 00:00:04,000\r\nHello\r\nworld\r\n\r\n\
+                   2\r\n00:01:02,250 --> 00:01:03,000\r\n<i>Bye</i>\r\n";
+        let cues = parse_cues(srt).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0], Cue {
+            start: 1.5,
+            end: 4.0,
+            settings: String::new(),
+            text: "Hello\nworld".to_string(),
+        });
+        assert_eq!(cues[1].start, 62.25);
+
+        let vtt = "WEBVTT\n\nNOTE a comment\n\nintro\n00:05.000 --> 00:07.000 line:0 align:start\nTop\n";
+        let cues = parse_cues(vtt).unwrap();
+        assert_eq!(cues.len(), 1);
+        assert_eq!((cues[0].start, cues[0].end), (5.0, 7.0));
+        assert_eq!(cues[0].settings, "line:0 align:start");
+
+        assert!(parse_cues("WEBVTT\n").is_err());
+    }
+
+    #[test]
+    fn test_cues_repeat_across_segment_boundaries() {
+        let cues = parse_cues("1\n00:00:08,000 --> 00:00:12,000\nAcross\n\n2\n00:00:25,000 --> 00:00:26,000\nLate\n")
+            .unwrap();
+        let segments = segment_cues(&cues, 25.5, 10.0);
+
+        assert_eq!(segments.len(), 3);
+        assert!(segments[0].contains("00:00:08.000 --> 00:00:12.000\nAcross"));
+        assert!(segments[1].contains("Across"));
+        assert!(!segments[1].contains("Late"));
+        assert!(segments[2].ends_with("00:00:25.000 --> 00:00:26.000\nLate\n"));
+    }
+
+    #[test]
+    fn test_sources_from_metadata() {
+        let metadata = serde_json::json!({
+            "subtitles": [
+                {"file_path": "/subs/en.srt", "language": "en", "default": true},
+                {"language": "fr"},
+                {"file_path": "s3://bucket/es.vtt", "name": "Español"}
+            ]
+        });
+        let sources = SubtitleSource::from_metadata(&metadata);
+        assert_eq!(sources.len(), 2);
+        assert!(sources[0].default);
+        assert_eq!(sources[0].display_name(0), "en");
+        assert_eq!(sources[1].display_name(1), "Español");
+        assert!(!sources[0].is_remote());
+        assert!(sources[1].is_remote());
+        assert!(SubtitleSource::from_metadata(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_read_document_is_size_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("en.vtt");
+        std::fs::write(&path, "WEBVTT\n").unwrap();
+
+        assert_eq!(read_document(&path, 7).unwrap(), "WEBVTT\n");
+        assert!(read_document(&path, 6).is_err());
+        assert!(read_document(Path::new("/dev/zero"), 1024).is_err());
+    }
+}
@@ -0,0 +1,720 @@
+//! Signed, expiring playback URLs
+//!
+//! Protected media are not fetched from their public URLs but from URLs that
+//! grant access for a limited time:
+//! - S3 SigV4 presigned GETs, signed with the object storage credentials
+//! - CDN token-auth URLs: an Akamai-style `ip=…~st=…~exp=…~acl=…~hmac=…`
+//!   token (HMAC-SHA256) in a query parameter, whose ACL covers every
+//!   object of the media so one token serves all its segments
+//!
+//! Segment URIs in HLS playlists are relative, so a signed playlist URL
+//! alone does not reach the segments. [`UrlSigner::sign_playlist`] rewrites
+//! a playlist instead: segments, init segments and subtitle files become
+//! signed absolute URLs, while nested playlists stay relative and inherit
+//! the query string of the request, so each is rewritten in turn.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::s3_store::S3Store;
+use crate::urls::UrlBuilder;
+use armoricore_config::ObjectStorageConfig;
+use armoricore_keys::service_integration::{get_object_storage_access_key, get_object_storage_secret_key};
+use armoricore_keys::KeyStore;
+use hmac::{Hmac, Mac};
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::Region;
+use rusoto_s3::util::{PreSignedRequest, PreSignedRequestOption};
+use rusoto_s3::GetObjectRequest;
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Key store key holding the hex CDN token secret when `CDN_TOKEN_KEY_ID` is unset
+pub const DEFAULT_CDN_TOKEN_KEY_ID: &str = "cdn.token_key";
+
+/// How playback URLs are signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningMethod {
+    None,
+    /// S3 SigV4 presigned URLs
+    S3Presigned,
+    /// CDN token authentication
+    CdnToken,
+}
+
+/// Playback URL signing settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningConfig {
+    pub method: SigningMethod,
+    /// Lifetime of signed URLs
+    pub ttl: Duration,
+    /// Query parameter carrying the CDN token
+    pub token_name: String,
+    /// Key store key of the CDN token secret
+    pub token_key_id: String,
+    /// Bind CDN tokens to the viewer's IP address
+    pub ip_binding: bool,
+    /// Public URL of the key server's playback routes
+    pub playback_base_url: Option<String>,
+    /// Proxies whose `X-Forwarded-For` entries are trusted for the viewer's
+    /// address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            method: SigningMethod::None,
+            ttl: Duration::from_secs(3600),
+            token_name: "hdnts".to_string(),
+            token_key_id: DEFAULT_CDN_TOKEN_KEY_ID.to_string(),
+            ip_binding: false,
+            playback_base_url: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl SigningConfig {
+    /// Read `PLAYBACK_URL_SIGNING` (`none`, `s3` or `cdn`),
+    /// `PLAYBACK_URL_TTL_SECS`, `CDN_TOKEN_NAME`, `CDN_TOKEN_KEY_ID`,
+    /// `CDN_TOKEN_IP_BINDING`, `PLAYBACK_BASE_URL` and
+    /// `PLAYBACK_TRUSTED_PROXIES` (comma-separated IP addresses)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let method = match var("PLAYBACK_URL_SIGNING").as_deref() {
+            None | Some("none") => SigningMethod::None,
+            Some("s3") => SigningMethod::S3Presigned,
+            Some("cdn") => SigningMethod::CdnToken,
+            Some(other) => return Err(anyhow::anyhow!("Unknown PLAYBACK_URL_SIGNING: {}", other)),
+        };
+        let config = Self {
+            method,
+            ttl: var("PLAYBACK_URL_TTL_SECS")
+                .and_then(|v| v.parse().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ttl),
+            token_name: var("CDN_TOKEN_NAME").unwrap_or(defaults.token_name),
+            token_key_id: var("CDN_TOKEN_KEY_ID").unwrap_or(defaults.token_key_id),
+            ip_binding: var("CDN_TOKEN_IP_BINDING")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.ip_binding),
+            playback_base_url: var("PLAYBACK_BASE_URL"),
+            trusted_proxies: var("PLAYBACK_TRUSTED_PROXIES")
+                .map(|proxies| {
+                    proxies
+                        .split(',')
+                        .map(|proxy| {
+                            proxy.trim().parse().map_err(|_| {
+                                anyhow::anyhow!("Invalid PLAYBACK_TRUSTED_PROXIES address: {}", proxy.trim())
+                            })
+                        })
+                        .collect::<anyhow::Result<Vec<IpAddr>>>()
+                })
+                .transpose()?
+                .unwrap_or_default(),
+        };
+        if config.is_enabled() && config.playback_base_url.is_none() {
+            return Err(anyhow::anyhow!("PLAYBACK_URL_SIGNING requires PLAYBACK_BASE_URL"));
+        }
+        Ok(config)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.method != SigningMethod::None
+    }
+}
+
+/// A CDN access token
+#[derive(Debug, Clone, PartialEq)]
+pub struct CdnToken {
+    pub ip: Option<IpAddr>,
+    /// Validity window in Unix seconds
+    pub start: u64,
+    pub expires: u64,
+    /// Path pattern the token grants; a trailing `*` matches any suffix
+    pub acl: String,
+}
+
+impl CdnToken {
+    fn fields(&self) -> String {
+        let ip = self.ip.map(|ip| format!("ip={}~", ip)).unwrap_or_default();
+        format!("{}st={}~exp={}~acl={}", ip, self.start, self.expires, self.acl)
+    }
+
+    /// The token string, authenticated with `key`
+    pub fn sign(&self, key: &[u8]) -> String {
+        let fields = self.fields();
+        format!("{}~hmac={}", fields, hex::encode(hmac_sha256(key, &fields)))
+    }
+
+    /// Verify `token` for a request of `path` from `ip` at `now`
+    pub fn verify(token: &str, key: &[u8], path: &str, ip: Option<IpAddr>, now: u64) -> bool {
+        let Some((fields, signature)) = token.rsplit_once("~hmac=") else {
+            return false;
+        };
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(key) else {
+            return false;
+        };
+        mac.update(fields.as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return false;
+        }
+
+        let field = |name: &str| {
+            fields
+                .split('~')
+                .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+        };
+        let number = |name: &str| field(name).and_then(|v| v.parse::<u64>().ok());
+        let (Some(start), Some(expires), Some(acl)) = (number("st"), number("exp"), field("acl")) else {
+            return false;
+        };
+        let ip_matches = match field("ip") {
+            Some(bound) => ip.is_some_and(|ip| bound.parse::<IpAddr>().ok() == Some(ip)),
+            None => true,
+        };
+        let path_matches = match acl.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == acl,
+        };
+        (start..expires).contains(&now) && ip_matches && path_matches
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC key of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+enum Method {
+    S3 {
+        region: Region,
+        credentials: AwsCredentials,
+        bucket: String,
+    },
+    Cdn {
+        key: Vec<u8>,
+        token_name: String,
+        ip_binding: bool,
+    },
+}
+
+/// Signs the URLs of stored objects
+pub struct UrlSigner {
+    method: Method,
+    ttl: Duration,
+}
+
+impl UrlSigner {
+    /// Presign S3 GETs with `config`'s credentials
+    pub fn s3(config: &ObjectStorageConfig, ttl: Duration) -> Self {
+        Self {
+            method: Method::S3 {
+                region: S3Store::region(config),
+                credentials: AwsCredentials::new(config.access_key.clone(), config.secret_key.clone(), None, None),
+                bucket: config.bucket.clone(),
+            },
+            ttl,
+        }
+    }
+
+    /// Append CDN tokens authenticated with `key` as `token_name`
+    pub fn cdn(key: Vec<u8>, token_name: impl Into<String>, ip_binding: bool, ttl: Duration) -> Self {
+        Self {
+            method: Method::Cdn {
+                key,
+                token_name: token_name.into(),
+                ip_binding,
+            },
+            ttl,
+        }
+    }
+
+    /// Signer for `config`, with its secrets from `key_store`
+    ///
+    /// S3 credentials fall back to `storage`'s; the CDN secret is the hex
+    /// string stored under `config.token_key_id`.
+    pub async fn from_key_store(
+        config: &SigningConfig,
+        key_store: &KeyStore,
+        storage: Option<&ObjectStorageConfig>,
+    ) -> anyhow::Result<Option<Self>> {
+        match config.method {
+            SigningMethod::None => Ok(None),
+            SigningMethod::S3Presigned => {
+                let mut storage = storage
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("S3 URL signing requires object storage configuration"))?;
+                if let Some(access_key) = get_object_storage_access_key(key_store).await {
+                    storage.access_key = access_key;
+                }
+                if let Some(secret_key) = get_object_storage_secret_key(key_store).await {
+                    storage.secret_key = secret_key;
+                }
+                Ok(Some(Self::s3(&storage, config.ttl)))
+            }
+            SigningMethod::CdnToken => {
+                let secret = key_store
+                    .get_api_key(&config.token_key_id)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to read CDN token key {}: {}", config.token_key_id, e))?;
+                let key = hex::decode(secret.trim())
+                    .map_err(|e| anyhow::anyhow!("CDN token key {} is not hex: {}", config.token_key_id, e))?;
+                Ok(Some(Self::cdn(key, config.token_name.clone(), config.ip_binding, config.ttl)))
+            }
+        }
+    }
+
+    /// Signed URL of the object `key` of `media_id`, published at `urls`
+    pub fn sign(&self, urls: &UrlBuilder, key: &str, media_id: &Uuid, client_ip: Option<IpAddr>) -> String {
+        match &self.method {
+            Method::S3 { region, credentials, bucket } => {
+                let request = GetObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.to_string(),
+                    ..Default::default()
+                };
+                let option = PreSignedRequestOption { expires_in: self.ttl };
+                request.get_presigned_url(region, credentials, &option)
+            }
+            Method::Cdn { key: secret, token_name, ip_binding } => {
+                let now = unix_now();
+                let token = CdnToken {
+                    ip: client_ip.filter(|_| *ip_binding),
+                    start: now,
+                    expires: now + self.ttl.as_secs(),
+                    acl: format!("{}*", url_path(&urls.url(&format!("media/{}/", media_id)))),
+                };
+                let url = urls.url(key);
+                let separator = if url.contains('?') { '&' } else { '?' };
+                format!("{}{}{}={}", url, separator, token_name, token.sign(secret))
+            }
+        }
+    }
+
+    /// Rewrite the HLS playlist stored at `playlist_key`
+    ///
+    /// Nested playlists stay relative with `query` appended; every other
+    /// relative URI becomes a signed URL.
+    pub fn sign_playlist(
+        &self,
+        playlist: &str,
+        playlist_key: &str,
+        urls: &UrlBuilder,
+        media_id: &Uuid,
+        client_ip: Option<IpAddr>,
+        query: &str,
+    ) -> String {
+        let dir = playlist_key.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        rewrite_playlist(playlist, |uri| {
+            if is_playlist(uri) {
+                if query.is_empty() {
+                    return uri.to_string();
+                }
+                let separator = if uri.contains('?') { '&' } else { '?' };
+                format!("{}{}{}", uri, separator, query)
+            } else {
+                self.sign(urls, &resolve(dir, uri), media_id, client_ip)
+            }
+        })
+    }
+
+    /// Rewrite the DASH manifest stored at `manifest_key`
+    ///
+    /// A CDN token covers the whole media directory, so each
+    /// `SegmentTemplate` keeps its `$Number$` template with the token
+    /// appended. A presigned S3 URL covers a single object, so templates
+    /// become `SegmentList`s with one signed URL per segment of their
+    /// timeline.
+    pub fn sign_manifest(
+        &self,
+        manifest: &str,
+        manifest_key: &str,
+        urls: &UrlBuilder,
+        media_id: &Uuid,
+        client_ip: Option<IpAddr>,
+    ) -> String {
+        let dir = manifest_key.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let sign = |uri: &str| escape_xml(&self.sign(urls, &resolve(dir, uri), media_id, client_ip));
+        let per_segment = matches!(self.method, Method::S3 { .. });
+
+        let mut output = String::with_capacity(manifest.len());
+        // Media template, first segment number, segment count and indentation
+        // of an open SegmentList
+        let mut list: Option<(&str, u64, u64, &str)> = None;
+        for line in manifest.lines() {
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+            if trimmed.starts_with("<SegmentTemplate ") {
+                let initialization = xml_attribute(trimmed, "initialization").filter(|uri| is_relative(uri));
+                let media = xml_attribute(trimmed, "media").filter(|uri| is_relative(uri));
+                if per_segment {
+                    if let Some(media) = media {
+                        let timescale = xml_attribute(trimmed, "timescale").unwrap_or("1");
+                        let start = xml_attribute(trimmed, "startNumber").and_then(|n| n.parse().ok()).unwrap_or(1);
+                        output.push_str(&format!("{}<SegmentList timescale=\"{}\">\n", indent, timescale));
+                        if let Some(initialization) = initialization {
+                            output.push_str(&format!("{}  <Initialization sourceURL=\"{}\"/>\n", indent, sign(initialization)));
+                        }
+                        list = Some((media, start, 0, indent));
+                        continue;
+                    }
+                }
+                let mut signed = line.to_string();
+                for uri in [initialization, media].into_iter().flatten() {
+                    signed = signed.replacen(&format!("\"{}\"", uri), &format!("\"{}\"", sign(uri)), 1);
+                }
+                output.push_str(&signed);
+            } else if let Some((media, start, count, list_indent)) = list.as_mut() {
+                if trimmed.starts_with("<S ") {
+                    let repeats: u64 = xml_attribute(trimmed, "r").and_then(|r| r.parse().ok()).unwrap_or(0);
+                    *count += 1 + repeats;
+                    output.push_str(line);
+                } else if trimmed.starts_with("</SegmentTimeline>") {
+                    output.push_str(line);
+                    for number in *start..*start + *count {
+                        output.push_str(&format!(
+                            "\n{}  <SegmentURL media=\"{}\"/>",
+                            list_indent,
+                            sign(&segment_uri(media, number))
+                        ));
+                    }
+                } else if trimmed.starts_with("</SegmentTemplate>") {
+                    output.push_str(&format!("{}</SegmentList>", list_indent));
+                    list = None;
+                } else {
+                    output.push_str(line);
+                }
+            } else {
+                output.push_str(line);
+            }
+            output.push('\n');
+        }
+        output
+    }
+}
+
+/// Value of the attribute `name` of the XML element `element`
+fn xml_attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let start = element.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `template` with its `$Number$` or `$Number%0<width>d$` identifier
+/// replaced by `number`
+fn segment_uri(template: &str, number: u64) -> String {
+    let Some(start) = template.find("$Number") else {
+        return template.to_string();
+    };
+    let rest = &template[start + "$Number".len()..];
+    let Some(end) = rest.find('$') else {
+        return template.to_string();
+    };
+    let width = rest[..end]
+        .strip_prefix("%0")
+        .and_then(|format| format.strip_suffix('d'))
+        .and_then(|width| width.parse().ok())
+        .unwrap_or(0);
+    format!("{}{:0width$}{}", &template[..start], number, &rest[end + 1..], width = width)
+}
+
+/// Path component of `url`
+fn url_path(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .map(|url| url.path().to_string())
+        .unwrap_or_else(|_| url.to_string())
+}
+
+fn is_playlist(uri: &str) -> bool {
+    uri.split(['?', '#']).next().is_some_and(|path| path.ends_with(".m3u8"))
+}
+
+/// Whether `uri` is relative to the document's location: neither absolute
+/// (key server, `skd://` and `data:` URIs) nor root-relative
+fn is_relative(uri: &str) -> bool {
+    !uri.contains(':') && !uri.starts_with('/')
+}
+
+/// Key of `uri` relative to the key directory `dir`
+fn resolve(dir: &str, uri: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+    for part in uri.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Apply `rewrite` to every relative URI of an HLS playlist: URI lines and
+/// `URI="…"` attributes
+///
+/// Absolute URIs (key server, `skd://` and `data:` URIs) and root-relative
+/// ones such as the default key delivery path are left alone.
+pub fn rewrite_playlist(playlist: &str, mut rewrite: impl FnMut(&str) -> String) -> String {
+    let mut rewrite_relative = |uri: &str| {
+        if is_relative(uri) {
+            rewrite(uri)
+        } else {
+            uri.to_string()
+        }
+    };
+    let mut output = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            output.push_str(line);
+        } else if !trimmed.starts_with('#') {
+            output.push_str(&rewrite_relative(trimmed));
+        } else if let Some(start) = line.find("URI=\"") {
+            let value_start = start + "URI=\"".len();
+            match line[value_start..].find('"') {
+                Some(len) => {
+                    output.push_str(&line[..value_start]);
+                    output.push_str(&rewrite_relative(&line[value_start..value_start + len]));
+                    output.push_str(&line[value_start + len..]);
+                }
+                None => output.push_str(line),
+            }
+        } else {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    fn test_cdn_token_round_trip() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let token = CdnToken {
+            ip: Some(ip),
+            start: 1_000,
+            expires: 1_600,
+            acl: "/media/42/*".to_string(),
+        }
+        .sign(KEY);
+        assert!(token.starts_with("ip=203.0.113.7~st=1000~exp=1600~acl=/media/42/*~hmac="));
+
+        assert!(CdnToken::verify(&token, KEY, "/media/42/720p/segment_001.m4s", Some(ip), 1_200));
+        // Expired, other media, other viewer, other key
+        assert!(!CdnToken::verify(&token, KEY, "/media/42/master.m3u8", Some(ip), 1_600));
+        assert!(!CdnToken::verify(&token, KEY, "/media/43/master.m3u8", Some(ip), 1_200));
+        assert!(!CdnToken::verify(&token, KEY, "/media/42/master.m3u8", Some("203.0.113.8".parse().unwrap()), 1_200));
+        assert!(!CdnToken::verify(&token, b"other", "/media/42/master.m3u8", Some(ip), 1_200));
+        // Tampered expiry
+        let tampered = token.replace("exp=1600", "exp=9600");
+        assert!(!CdnToken::verify(&tampered, KEY, "/media/42/master.m3u8", Some(ip), 1_200));
+    }
+
+    #[test]
+    fn test_sign_playlist_with_cdn_tokens() {
+        let media_id = Uuid::new_v4();
+        let urls = UrlBuilder::new("https://cdn.example.com/vod");
+        let signer = UrlSigner::cdn(KEY.to_vec(), "hdnts", false, Duration::from_secs(600));
+        let playlist = "#EXTM3U\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/hls/keys/1/0\"\n\
+            #EXTINF:6.000,\n\
+            segment_000.m4s\n\
+            #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",URI=\"../subs_en/playlist.m3u8\"\n";
+
+        let signed = signer.sign_playlist(
+            playlist,
+            &format!("media/{}/720p/playlist.m3u8", media_id),
+            &urls,
+            &media_id,
+            None,
+            "token=abc",
+        );
+        let lines: Vec<&str> = signed.lines().collect();
+        let prefix = format!("https://cdn.example.com/vod/media/{}/720p/", media_id);
+
+        assert!(lines[1].starts_with(&format!("#EXT-X-MAP:URI=\"{}init.mp4?hdnts=st=", prefix)));
+        assert!(lines[1].contains(&format!("~acl=/vod/media/{}/*~hmac=", media_id)));
+        assert_eq!(lines[2], "#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/hls/keys/1/0\"");
+        assert!(lines[4].starts_with(&format!("{}segment_000.m4s?hdnts=", prefix)));
+        assert!(lines[5].ends_with("URI=\"../subs_en/playlist.m3u8?token=abc\""));
+
+        let token = lines[4].split_once("hdnts=").unwrap().1;
+        let path = format!("/vod/media/{}/720p/segment_000.m4s", media_id);
+        assert!(CdnToken::verify(token, KEY, &path, None, unix_now()));
+    }
+
+    #[test]
+    fn test_sign_playlist_keeps_root_relative_key_uri() {
+        let media_id = Uuid::new_v4();
+        let key_uri = crate::hls_encryption::HlsEncryptionConfig::default().key_uri(&media_id, 0);
+        let urls = UrlBuilder::new("https://cdn.example.com/vod");
+        let signer = UrlSigner::cdn(KEY.to_vec(), "hdnts", false, Duration::from_secs(600));
+        let playlist = format!(
+            "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"{}\"\n#EXTINF:6.000,\nsegment_000.m4s\n",
+            key_uri
+        );
+
+        let signed = signer.sign_playlist(
+            &playlist,
+            &format!("media/{}/720p/playlist.m3u8", media_id),
+            &urls,
+            &media_id,
+            None,
+            "",
+        );
+        let lines: Vec<&str> = signed.lines().collect();
+        assert_eq!(lines[1], format!("#EXT-X-KEY:METHOD=AES-128,URI=\"/hls/keys/{}/0\"", media_id));
+        assert!(lines[3].contains("segment_000.m4s?hdnts="));
+    }
+
+    const MANIFEST: &str = r#"    <AdaptationSet id="0" contentType="video" mimeType="video/mp4">
+      <Representation id="720p" bandwidth="5000000" codecs="avc1.64001f">
+        <SegmentTemplate timescale="1000" initialization="720p/init.mp4" media="720p/segment_$Number%03d$.m4s" startNumber="0">
+          <SegmentTimeline>
+            <S t="0" d="6000" r="1"/>
+            <S d="2500"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+"#;
+
+    #[test]
+    fn test_sign_manifest_with_cdn_tokens() {
+        let media_id = Uuid::new_v4();
+        let urls = UrlBuilder::new("https://cdn.example.com");
+        let signer = UrlSigner::cdn(KEY.to_vec(), "hdnts", false, Duration::from_secs(600));
+        let signed = signer.sign_manifest(MANIFEST, &format!("media/{}/manifest.mpd", media_id), &urls, &media_id, None);
+
+        let prefix = format!("https://cdn.example.com/media/{}/720p/", media_id);
+        assert!(signed.contains(&format!(r#"initialization="{}init.mp4?hdnts=st="#, prefix)));
+        assert!(signed.contains(&format!(r#"media="{}segment_$Number%03d$.m4s?hdnts=st="#, prefix)));
+        assert!(signed.contains(r#"<S t="0" d="6000" r="1"/>"#));
+        assert_eq!(signed.lines().count(), MANIFEST.lines().count());
+    }
+
+    #[test]
+    fn test_sign_manifest_lists_presigned_segments() {
+        let config = ObjectStorageConfig {
+            endpoint: "https://storage.example.com".to_string(),
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "secret".to_string(),
+            bucket: "media".to_string(),
+            region: Some("us-east-1".to_string()),
+        };
+        let media_id = Uuid::new_v4();
+        let signer = UrlSigner::s3(&config, Duration::from_secs(300));
+        let signed = signer.sign_manifest(
+            MANIFEST,
+            &format!("media/{}/manifest.mpd", media_id),
+            &UrlBuilder::for_s3(&config),
+            &media_id,
+            None,
+        );
+
+        assert!(!signed.contains("SegmentTemplate"));
+        assert!(signed.contains(r#"<SegmentList timescale="1000">"#));
+        assert!(signed.contains(&format!(
+            r#"<Initialization sourceURL="https://storage.example.com/media/media/{}/720p/init.mp4?"#,
+            media_id
+        )));
+        let segments: Vec<&str> = signed.lines().filter(|line| line.contains("<SegmentURL ")).collect();
+        assert_eq!(segments.len(), 3);
+        for (number, segment) in segments.iter().enumerate() {
+            assert!(segment.contains(&format!("/720p/segment_{:03}.m4s?", number)));
+            assert!(segment.contains("&amp;X-Amz-Signature="));
+        }
+        assert!(signed.contains("</SegmentTimeline>\n          <SegmentURL"));
+        assert!(signed.contains("</SegmentList>\n      </Representation>"));
+    }
+
+    #[tokio::test]
+    async fn test_example_policy_grants_cdn_token_key() {
+        use armoricore_keys::local_store::LocalKeyStore;
+        use armoricore_keys::{AccessPolicy, ServiceIdentity};
+        use std::sync::Arc;
+
+        let policy = AccessPolicy::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../armoricore-keys/key-policy.example.toml"
+        ))
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let admin = KeyStore::new(Arc::new(LocalKeyStore::new(dir.path(), Some(&[5u8; 32])).await.unwrap()));
+        admin
+            .store_api_key(&DEFAULT_CDN_TOKEN_KEY_ID.to_string(), &hex::encode(KEY), None)
+            .await
+            .unwrap();
+        let key_store = admin.as_identity(Arc::new(policy), ServiceIdentity::new(ServiceIdentity::MEDIA_PROCESSOR));
+
+        let config = SigningConfig {
+            method: SigningMethod::CdnToken,
+            ..SigningConfig::default()
+        };
+        let signer = UrlSigner::from_key_store(&config, &key_store, None).await.unwrap();
+        assert!(signer.is_some());
+    }
+
+    #[test]
+    fn test_s3_presigned_url() {
+        let config = ObjectStorageConfig {
+            endpoint: "https://storage.example.com".to_string(),
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "secret".to_string(),
+            bucket: "media".to_string(),
+            region: Some("us-east-1".to_string()),
+        };
+        let signer = UrlSigner::s3(&config, Duration::from_secs(300));
+        let url = signer.sign(&UrlBuilder::for_s3(&config), "media/1/720p.mp4", &Uuid::new_v4(), None);
+
+        assert!(url.starts_with("https://storage.example.com/media/media/1/720p.mp4?"));
+        assert!(url.contains("X-Amz-Algorithm=AWS4-HMAC-SHA256"));
+        assert!(url.contains("X-Amz-Credential=AKIDEXAMPLE%2F"));
+        assert!(url.contains("X-Amz-Expires=300"));
+        assert!(url.contains("X-Amz-Signature="));
+    }
+}
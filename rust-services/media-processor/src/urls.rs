@@ -0,0 +1,118 @@
+//! Public URLs of stored objects
+//!
+//! Playback clients fetch outputs from wherever the store is published —
+//! often a CDN in front of the bucket rather than the storage endpoint — so
+//! the base URL is configured apart from the store with
+//! `MEDIA_PUBLIC_BASE_URL`.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use armoricore_config::ObjectStorageConfig;
+use std::path::Path;
+
+/// Builds the public URL of an object key
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlBuilder {
+    base_url: String,
+}
+
+impl UrlBuilder {
+    /// URLs under `base_url`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Base URL from `MEDIA_PUBLIC_BASE_URL`, else `fallback`
+    pub fn from_env(fallback: Self) -> Self {
+        std::env::var("MEDIA_PUBLIC_BASE_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+            .map(Self::new)
+            .unwrap_or(fallback)
+    }
+
+    /// Default for an S3 bucket: the endpoint when it is an HTTP(S) URL,
+    /// else the bucket's Akamai hostname
+    pub fn for_s3(config: &ObjectStorageConfig) -> Self {
+        if config.endpoint.starts_with("http://") || config.endpoint.starts_with("https://") {
+            Self::new(config.endpoint.as_str())
+        } else {
+            Self::new(format!("https://{}.akamai.com", config.bucket))
+        }
+    }
+
+    /// Default for a local store: `file://` URLs under its root
+    pub fn for_local(root: &Path) -> Self {
+        let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        Self::new(format!("file://{}", root.display()))
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Public URL of `key`
+    pub fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key.trim_start_matches('/'))
+    }
+
+    /// Key of a URL built by [`UrlBuilder::url`]
+    pub fn key<'a>(&self, url: &'a str) -> Option<&'a str> {
+        url.strip_prefix(&self.base_url)?.strip_prefix('/')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s3_config(endpoint: &str) -> ObjectStorageConfig {
+        ObjectStorageConfig {
+            endpoint: endpoint.to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            bucket: "armoricore-media".to_string(),
+            region: None,
+        }
+    }
+
+    #[test]
+    fn test_url() {
+        let urls = UrlBuilder::new("https://cdn.example.com/vod/");
+        assert_eq!(urls.base_url(), "https://cdn.example.com/vod");
+        assert_eq!(urls.url("media/1/master.m3u8"), "https://cdn.example.com/vod/media/1/master.m3u8");
+        assert_eq!(urls.url("/media/1/dash.mpd"), "https://cdn.example.com/vod/media/1/dash.mpd");
+        assert_eq!(urls.key("https://cdn.example.com/vod/media/1/dash.mpd"), Some("media/1/dash.mpd"));
+        assert_eq!(urls.key("https://other.example.com/media/1/dash.mpd"), None);
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(
+            UrlBuilder::for_s3(&s3_config("https://storage.example.com/")).base_url(),
+            "https://storage.example.com"
+        );
+        assert_eq!(
+            UrlBuilder::for_s3(&s3_config("s3://armoricore-media")).base_url(),
+            "https://armoricore-media.akamai.com"
+        );
+        assert_eq!(
+            UrlBuilder::for_local(Path::new("/srv/media")).url("media/1/master.m3u8"),
+            "file:///srv/media/media/1/master.m3u8"
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Audio waveform peak data
+//!
+//! The source's primary audio stream is decoded to mono 16-bit PCM and
+//! reduced to one min/max pair per `samples_per_pixel` samples. Coarser
+//! zoom levels are derived by merging adjacent pairs, each level doubling
+//! the samples per pixel. Levels are written in the audiowaveform formats
+//! (version 2 `.dat` binary or `.json`) that waveform players such as
+//! peaks.js load directly.
+// Copyright 2025 Francisco F. Pinochet
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::ingest;
+use crate::jobs::JobContext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// audiowaveform data format version written
+const FORMAT_VERSION: i32 = 2;
+
+/// File format of the peak data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaveformFormat {
+    /// audiowaveform binary (`.dat`)
+    Dat,
+    Json,
+}
+
+impl WaveformFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            WaveformFormat::Dat => "dat",
+            WaveformFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            WaveformFormat::Dat => "application/octet-stream",
+            WaveformFormat::Json => "application/json",
+        }
+    }
+}
+
+/// Waveform settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveformConfig {
+    pub enabled: bool,
+    /// Samples per pixel of the most detailed level
+    pub samples_per_pixel: u32,
+    /// Number of zoom levels
+    pub levels: u32,
+    /// Peak resolution, 8 or 16 bits
+    pub bits: u8,
+    pub format: WaveformFormat,
+}
+
+impl Default for WaveformConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            samples_per_pixel: 256,
+            levels: 4,
+            bits: 8,
+            format: WaveformFormat::Dat,
+        }
+    }
+}
+
+impl WaveformConfig {
+    /// Read `AUDIO_WAVEFORM`, `WAVEFORM_SAMPLES_PER_PIXEL`,
+    /// `WAVEFORM_ZOOM_LEVELS`, `WAVEFORM_BITS` (8, 16) and `WAVEFORM_FORMAT`
+    /// (dat, json)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok();
+        let positive = |name: &str, default: u32| {
+            var(name)
+                .and_then(|v| v.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            enabled: var("AUDIO_WAVEFORM")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.enabled),
+            samples_per_pixel: positive("WAVEFORM_SAMPLES_PER_PIXEL", defaults.samples_per_pixel),
+            levels: positive("WAVEFORM_ZOOM_LEVELS", defaults.levels),
+            bits: match var("WAVEFORM_BITS").as_deref() {
+                Some("16") => 16,
+                _ => defaults.bits,
+            },
+            format: match var("WAVEFORM_FORMAT").map(|v| v.to_lowercase()).as_deref() {
+                Some("json") => WaveformFormat::Json,
+                _ => defaults.format,
+            },
+        }
+    }
+}
+
+/// One zoom level of a generated waveform
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformLevel {
+    pub samples_per_pixel: u32,
+    pub path: PathBuf,
+}
+
+/// Generated waveform peak files, most detailed level first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub bits: u8,
+    pub format: WaveformFormat,
+    pub levels: Vec<WaveformLevel>,
+}
+
+/// Reduces little-endian 16-bit mono PCM to min/max pairs
+#[derive(Debug)]
+pub struct PeakBuilder {
+    samples_per_pixel: u32,
+    /// Low byte of a sample split across chunks
+    carry: Option<u8>,
+    count: u32,
+    min: i16,
+    max: i16,
+    peaks: Vec<(i16, i16)>,
+}
+
+impl PeakBuilder {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        Self {
+            samples_per_pixel,
+            carry: None,
+            count: 0,
+            min: i16::MAX,
+            max: i16::MIN,
+            peaks: Vec::new(),
+        }
+    }
+
+    pub fn push_pcm(&mut self, mut bytes: &[u8]) {
+        if let Some(low) = self.carry.take() {
+            let Some((&high, rest)) = bytes.split_first() else {
+                self.carry = Some(low);
+                return;
+            };
+            self.push_sample(i16::from_le_bytes([low, high]));
+            bytes = rest;
+        }
+        let mut samples = bytes.chunks_exact(2);
+        for sample in &mut samples {
+            self.push_sample(i16::from_le_bytes([sample[0], sample[1]]));
+        }
+        self.carry = samples.remainder().first().copied();
+    }
+
+    fn push_sample(&mut self, sample: i16) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.count += 1;
+        if self.count == self.samples_per_pixel {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.peaks.push((self.min, self.max));
+        self.count = 0;
+        self.min = i16::MAX;
+        self.max = i16::MIN;
+    }
+
+    /// Peaks including the partial last pixel
+    pub fn finish(mut self) -> Vec<(i16, i16)> {
+        if self.count > 0 {
+            self.flush();
+        }
+        self.peaks
+    }
+}
+
+/// The next zoom level: adjacent pairs merged, halving the resolution
+pub fn zoom_out(peaks: &[(i16, i16)]) -> Vec<(i16, i16)> {
+    peaks
+        .chunks(2)
+        .map(|pair| {
+            pair.iter()
+                .fold((i16::MAX, i16::MIN), |(min, max), &(lo, hi)| (min.min(lo), max.max(hi)))
+        })
+        .collect()
+}
+
+/// Peak values at `bits` resolution (8-bit values are scaled down)
+fn scaled(peaks: &[(i16, i16)], bits: u8) -> Vec<i16> {
+    let scale = |value: i16| if bits == 8 { value / 256 } else { value };
+    peaks.iter().flat_map(|&(min, max)| [scale(min), scale(max)]).collect()
+}
+
+/// audiowaveform binary data (version 2, one channel)
+pub fn encode_dat(peaks: &[(i16, i16)], sample_rate: u32, samples_per_pixel: u32, bits: u8) -> Vec<u8> {
+    let flags: u32 = if bits == 8 { 1 } else { 0 };
+    let mut data = Vec::with_capacity(24 + peaks.len() * 4);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&(sample_rate as i32).to_le_bytes());
+    data.extend_from_slice(&(samples_per_pixel as i32).to_le_bytes());
+    data.extend_from_slice(&(peaks.len() as u32).to_le_bytes());
+    data.extend_from_slice(&1i32.to_le_bytes());
+    for value in scaled(peaks, bits) {
+        if bits == 8 {
+            data.push(value as i8 as u8);
+        } else {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    data
+}
+
+/// audiowaveform JSON data (version 2, one channel)
+pub fn encode_json(peaks: &[(i16, i16)], sample_rate: u32, samples_per_pixel: u32, bits: u8) -> String {
+    serde_json::json!({
+        "version": FORMAT_VERSION,
+        "channels": 1,
+        "sample_rate": sample_rate,
+        "samples_per_pixel": samples_per_pixel,
+        "bits": bits,
+        "length": peaks.len(),
+        "data": scaled(peaks, bits),
+    })
+    .to_string()
+}
+
+/// Decode `input`'s audio at `sample_rate` and write the waveform levels
+/// into `output_dir` (`waveform_256.dat`, `waveform_512.dat`, ...)
+///
+/// Returns `None` when the audio could not be decoded.
+pub async fn generate(
+    input: &Path,
+    output_dir: &Path,
+    sample_rate: u32,
+    config: &WaveformConfig,
+    job: &JobContext,
+) -> anyhow::Result<Option<Waveform>> {
+    let input = input
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Input path contains invalid UTF-8: {:?}", input))?;
+    let rate = sample_rate.to_string();
+
+    let mut builder = PeakBuilder::new(config.samples_per_pixel);
+    let status = job
+        .run_binary(
+            ingest::ffmpeg_command("ffmpeg").args([
+                "-v", "error", "-i", input, "-map", "0:a:0", "-vn", "-ac", "1", "-ar", &rate, "-f", "s16le",
+                "-acodec", "pcm_s16le", "-",
+            ]),
+            |data| builder.push_pcm(data),
+        )
+        .await?;
+    if !status.success() {
+        warn!(error = %status.error(), "Failed to decode audio for the waveform");
+        return Ok(None);
+    }
+    let mut peaks = builder.finish();
+    if peaks.is_empty() {
+        return Ok(None);
+    }
+
+    let mut levels = Vec::new();
+    let mut samples_per_pixel = config.samples_per_pixel;
+    for level in 0..config.levels {
+        if level > 0 {
+            // Stop once a level would be a single pixel
+            if peaks.len() < 2 {
+                break;
+            }
+            peaks = zoom_out(&peaks);
+            samples_per_pixel *= 2;
+        }
+        let path = output_dir.join(format!("waveform_{}.{}", samples_per_pixel, config.format.extension()));
+        match config.format {
+            WaveformFormat::Dat => {
+                std::fs::write(&path, encode_dat(&peaks, sample_rate, samples_per_pixel, config.bits))?
+            }
+            WaveformFormat::Json => {
+                std::fs::write(&path, encode_json(&peaks, sample_rate, samples_per_pixel, config.bits))?
+            }
+        }
+        levels.push(WaveformLevel { samples_per_pixel, path });
+    }
+    info!(levels = levels.len(), sample_rate, "Generated audio waveform");
+
+    Ok(Some(Waveform {
+        sample_rate,
+        bits: config.bits,
+        format: config.format,
+        levels,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_peaks_across_split_chunks() {
+        let data = pcm(&[100, -200, 300, 50, -1000, 20, 7]);
+        let mut builder = PeakBuilder::new(3);
+        // Split mid-sample
+        builder.push_pcm(&data[..3]);
+        builder.push_pcm(&data[3..9]);
+        builder.push_pcm(&data[9..]);
+        let peaks = builder.finish();
+
+        assert_eq!(peaks, vec![(-200, 300), (-1000, 50), (7, 7)]);
+        assert_eq!(zoom_out(&peaks), vec![(-1000, 300), (7, 7)]);
+    }
+
+    #[test]
+    fn test_audiowaveform_encodings() {
+        let peaks = vec![(-32768, 32767), (-256, 512)];
+        let dat = encode_dat(&peaks, 44100, 256, 8);
+        assert_eq!(&dat[..4], &2i32.to_le_bytes());
+        assert_eq!(&dat[4..8], &1u32.to_le_bytes());
+        assert_eq!(&dat[8..12], &44100i32.to_le_bytes());
+        assert_eq!(&dat[12..16], &256i32.to_le_bytes());
+        assert_eq!(&dat[16..20], &2u32.to_le_bytes());
+        assert_eq!(&dat[20..24], &1i32.to_le_bytes());
+        assert_eq!(&dat[24..], &[0x80, 0x7f, 0xff, 0x02]);
+        assert_eq!(encode_dat(&peaks, 44100, 256, 16).len(), 24 + 8);
+
+        let json: serde_json::Value = serde_json::from_str(&encode_json(&peaks, 44100, 512, 16)).unwrap();
+        assert_eq!(json["samples_per_pixel"], 512);
+        assert_eq!(json["length"], 2);
+        assert_eq!(json["data"], serde_json::json!([-32768, 32767, -256, 512]));
+    }
+}
@@ -14,14 +14,31 @@
 // limitations under the License.
 
 
+use crate::drm::DrmConfig;
+use crate::hls_encryption::HlsEncryptionConfig;
+use crate::job_store::{JobJournal, JobRecord, JobStage, JobStore, MAX_JOB_ATTEMPTS};
+use crate::jobs::{self, JobContext, JobRegistry};
+use crate::loudness::Loudness;
+use crate::media_info::MediaInfo;
 use crate::processor::MediaProcessor;
+use crate::progress::{ProgressThrottle, ProgressUpdate};
+use crate::playback::playback_urls;
+use crate::sandbox::{Failure, ProcessError};
 use crate::storage::ObjectStorage;
+use crate::subtitles::SubtitleSource;
+use armoricore_keys::KeyStore;
 use armoricore_types::{
-    schemas::{MediaReadyPayload, MediaUploadedPayload, PlaybackUrls},
+    schemas::{
+        MediaCancelRequestedPayload, MediaFailedPayload, MediaFailureReason,
+        MediaProcessingProgressPayload, MediaReadyPayload, MediaUploadedPayload,
+    },
     Event, EventType,
 };
 use message_bus_client::traits::MessageBusClient;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
 use tokio_stream::StreamExt;
 use tracing::{error, info, warn};
 use uuid::Uuid;
@@ -31,24 +48,69 @@ pub struct MediaWorker {
     message_bus: Arc<dyn MessageBusClient>,
     processor: MediaProcessor,
     storage: ObjectStorage,
+    /// Transcoding progress from the processor, taken by `run`
+    progress: Mutex<Option<UnboundedReceiver<ProgressUpdate>>>,
+    /// In-flight jobs, cancelled by `media.cancel_requested` events
+    jobs: JobRegistry,
+    /// Wall-clock limit of a job; `None` disables it
+    job_timeout: Option<Duration>,
+    /// Durable job records, used to resume interrupted jobs
+    job_store: JobStore,
+    /// Base URL of the signed playback routes, when playback URLs are signed
+    playback_base_url: Option<String>,
 }
 
 impl MediaWorker {
     /// Create a new media worker
+    ///
+    /// `storage_config` is used to download `s3://` sources; `key_store`
+    /// holds the content keys when `hls_encryption` or `drm` is enabled.
     pub fn new(
         message_bus: Arc<dyn MessageBusClient>,
-        storage_config: armoricore_config::ObjectStorageConfig,
+        storage: ObjectStorage,
+        storage_config: Option<armoricore_config::ObjectStorageConfig>,
+        key_store: Option<KeyStore>,
+        hls_encryption: HlsEncryptionConfig,
+        drm: DrmConfig,
+        job_store: JobStore,
     ) -> Self {
-        let storage_config_clone = storage_config.clone();
+        let (progress_sender, progress_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let processor = MediaProcessor::with_storage_and_encryption(storage_config, key_store)
+            .with_hls_encryption(hls_encryption)
+            .with_drm(drm)
+            .with_progress(progress_sender);
         Self {
             message_bus,
-            processor: MediaProcessor::with_storage_config(Some(storage_config_clone)),
-            storage: ObjectStorage::new(storage_config),
+            processor,
+            storage,
+            progress: Mutex::new(Some(progress_receiver)),
+            jobs: JobRegistry::new(),
+            job_timeout: jobs::job_timeout_from_env(),
+            job_store,
+            playback_base_url: None,
         }
     }
 
+    /// Publish playback routes under `playback_base_url` instead of the
+    /// stored objects' public URLs
+    pub fn with_playback_base_url(mut self, playback_base_url: impl Into<String>) -> Self {
+        self.playback_base_url = Some(playback_base_url.into());
+        self
+    }
+
     /// Run the worker - consume events and process them
     pub async fn run(&self) -> anyhow::Result<()> {
+        let progress = self.progress.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(progress) = progress {
+            tokio::spawn(Self::publish_progress(
+                self.message_bus.clone(),
+                progress,
+                ProgressThrottle::from_env(),
+            ));
+        }
+        tokio::spawn(Self::handle_cancel_requests(self.message_bus.clone(), self.jobs.clone()));
+        self.resume_pending_jobs().await;
+
         info!("Subscribing to media.uploaded events");
 
         let mut event_stream = self.message_bus.subscribe("media.uploaded");
@@ -83,6 +145,29 @@ impl MediaWorker {
         Ok(())
     }
 
+    /// Cancel in-flight jobs on media.cancel_requested events
+    async fn handle_cancel_requests(message_bus: Arc<dyn MessageBusClient>, jobs: JobRegistry) {
+        let mut requests = message_bus.subscribe("media.cancel_requested");
+        while let Some(request) = requests.next().await {
+            let payload: MediaCancelRequestedPayload = match request
+                .map_err(|e| anyhow::anyhow!("{}", e))
+                .and_then(|event| event.payload_as().map_err(|e| anyhow::anyhow!("Invalid payload: {}", e)))
+            {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(error = %e, "Ignoring cancel request");
+                    continue;
+                }
+            };
+
+            if jobs.cancel(&payload.media_id) {
+                info!(media_id = %payload.media_id, reason = ?payload.reason, "Cancelling media job");
+            } else {
+                info!(media_id = %payload.media_id, "Cancel requested for media with no running job");
+            }
+        }
+    }
+
     /// Process a single media upload event
     async fn process_media_upload(&self, event: &Event) -> anyhow::Result<()> {
         // Deserialize the payload
@@ -98,83 +183,263 @@ impl MediaWorker {
             "Processing media upload"
         );
 
+        // A redelivered event continues the job it started; a new upload
+        // of the same media starts over
+        let record = match self.job_store.load(&payload.media_id)? {
+            Some(record) if record.payload.file_path == payload.file_path => record,
+            Some(_) => {
+                self.job_store.remove(&payload.media_id)?;
+                JobRecord::new(payload)
+            }
+            None => JobRecord::new(payload),
+        };
+        self.process_job(record).await
+    }
+
+    /// Resume the jobs left unfinished by a previous run
+    async fn resume_pending_jobs(&self) {
+        let records = match self.job_store.pending() {
+            Ok(records) => records,
+            Err(e) => {
+                error!(error = %e, "Failed to read pending media jobs");
+                return;
+            }
+        };
+
+        for record in records {
+            info!(
+                media_id = %record.media_id,
+                stage = ?record.stage,
+                attempts = record.attempts,
+                "Resuming media job"
+            );
+            if let Err(e) = self.process_job(record).await {
+                error!(error = %e, "Failed to resume media job");
+            }
+        }
+    }
+
+    /// Run a job from its last completed stage
+    ///
+    /// The job runs until it finishes, is cancelled or exceeds
+    /// `job_timeout`; the two latter publish `media.failed`, as do errors.
+    /// Its record is kept only while it can still be resumed.
+    async fn process_job(&self, mut record: JobRecord) -> anyhow::Result<()> {
+        let media_id = record.media_id;
+        record.attempts += 1;
+        if record.attempts > MAX_JOB_ATTEMPTS {
+            let error = anyhow::anyhow!("Job gave up after {} attempts", MAX_JOB_ATTEMPTS);
+            self.fail_job(media_id, MediaFailureReason::ProcessingError, &error).await;
+            self.job_store.remove(&media_id)?;
+            return Err(error);
+        }
+        self.job_store.save(&record)?;
+        let journal = JobJournal::new(self.job_store.clone(), record);
+
+        let job = self.jobs.start(media_id);
+        let cancel = job.token().clone();
+        let timeout = async {
+            match self.job_timeout {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        // Dropping the job future kills its FFmpeg processes
+        let (result, reason) = tokio::select! {
+            result = self.run_job(&journal, &cancel) => {
+                let reason = match &result {
+                    Err(e) if jobs::is_cancelled(e) => Some(MediaFailureReason::Cancelled),
+                    Err(e) => Some(match e.downcast_ref::<ProcessError>().map(|e| e.failure) {
+                        Some(Failure::Timeout(_)) => MediaFailureReason::Timeout,
+                        Some(Failure::ResourceLimit(_)) => MediaFailureReason::ResourceLimit,
+                        _ => MediaFailureReason::ProcessingError,
+                    }),
+                    Ok(()) => None,
+                };
+                (result, reason)
+            }
+            _ = cancel.cancelled() => {
+                (Err(jobs::JobCancelled.into()), Some(MediaFailureReason::Cancelled))
+            }
+            _ = timeout => {
+                cancel.cancel();
+                let limit = self.job_timeout.unwrap_or_default();
+                (
+                    Err(anyhow::anyhow!("Job exceeded its {} s timeout", limit.as_secs())),
+                    Some(MediaFailureReason::Timeout),
+                )
+            }
+        };
+        drop(job);
+
+        if let (Err(e), Some(reason)) = (&result, reason) {
+            self.fail_job(media_id, reason, e).await;
+        }
+        // Finished and failed jobs are not resumed; their outputs go too
+        if let Err(e) = self.job_store.remove(&media_id) {
+            warn!(media_id = %media_id, error = %e, "Failed to remove job record");
+        }
+        result
+    }
+
+    /// Log a failed job and publish media.failed
+    async fn fail_job(&self, media_id: Uuid, reason: MediaFailureReason, error: &anyhow::Error) {
+        warn!(media_id = %media_id, reason = ?reason, error = %error, "Media job failed");
+        if let Err(publish_error) = self.publish_media_failed(media_id, reason, error).await {
+            warn!(media_id = %media_id, error = %publish_error, "Failed to publish media.failed");
+        }
+    }
+
+    /// Process, upload and announce one media file, skipping the stages
+    /// `journal` records as completed
+    async fn run_job(&self, journal: &JobJournal, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let record = journal.record();
+        let payload = &record.payload;
+
         // Process the media file
-        match self
-            .processor
-            .process_media(
-                &payload.media_id,
-                &payload.file_path,
-                &payload.content_type,
-            )
-            .await
-        {
-            Ok(processing_result) => {
-                // Upload processed files to object storage
-                let upload_result = self
-                    .storage
-                    .upload_processed_files(
+        let processing_result = match record.result {
+            Some(result) if record.stage >= JobStage::Transcoded => {
+                info!(media_id = %payload.media_id, "Reusing transcoded outputs");
+                result
+            }
+            _ => {
+                let work_dir = self.job_store.work_dir(&payload.media_id);
+                std::fs::create_dir_all(&work_dir)?;
+                let job = JobContext::new(cancel.clone(), None).with_journal(journal.clone());
+                let result = self
+                    .processor
+                    .process_media_job(
                         &payload.media_id,
-                        &processing_result,
+                        &payload.file_path,
+                        &payload.content_type,
+                        &work_dir,
+                        &SubtitleSource::from_metadata(&payload.metadata),
+                        job,
                     )
-                    .await;
-
-                match upload_result {
-                    Ok(playback_urls) => {
-                        // Publish media.ready event
-                        self.publish_media_ready(
-                            payload.media_id,
-                            playback_urls,
-                            processing_result.thumbnail_urls,
-                            processing_result.duration,
-                            processing_result.resolutions,
-                        )
-                        .await?;
-
-                        info!(
-                            media_id = %payload.media_id,
-                            "Media processing completed successfully"
-                        );
-                    }
-                    Err(e) => {
-                        error!(
-                            media_id = %payload.media_id,
-                            error = %e,
-                            "Failed to upload processed files"
-                        );
-                        return Err(e);
-                    }
-                }
+                    .await
+                    .inspect_err(|e| {
+                        error!(media_id = %payload.media_id, error = %e, "Failed to process media");
+                    })?;
+                journal.update(|record| {
+                    record.result = Some(result.clone());
+                    record.stage = JobStage::Transcoded;
+                })?;
+                result
             }
-            Err(e) => {
-                error!(
-                    media_id = %payload.media_id,
-                    error = %e,
-                    "Failed to process media"
-                );
-                return Err(e);
+        };
+
+        // Upload processed files to object storage
+        let uploaded = match record.uploaded {
+            Some(uploaded) if record.stage >= JobStage::Uploaded => uploaded,
+            _ => {
+                let uploaded = self
+                    .storage
+                    .upload_processed_files(&payload.media_id, &processing_result)
+                    .await
+                    .inspect_err(|e| {
+                        error!(media_id = %payload.media_id, error = %e, "Failed to upload processed files");
+                    })?;
+                journal.update(|record| {
+                    record.uploaded = Some(uploaded.clone());
+                    record.stage = JobStage::Uploaded;
+                })?;
+                uploaded
             }
+        };
+
+        // Publish media.ready event
+        if journal.stage() < JobStage::Published {
+            let playback_urls = match self.playback_base_url {
+                Some(ref base_url) => {
+                    playback_urls(&uploaded.playback_urls, self.storage.urls(), base_url, &payload.media_id)
+                }
+                None => uploaded.playback_urls,
+            };
+            self.publish_media_ready(MediaReadyPayload {
+                media_id: payload.media_id,
+                playback_urls,
+                thumbnail_urls: uploaded.thumbnail_urls,
+                duration: processing_result.duration,
+                resolutions: processing_result.resolutions,
+                drm: processing_result.drm,
+                media_info: processing_result.media_info.as_ref().map(MediaInfo::summary),
+                thumbnail_track: uploaded.thumbnail_track,
+                posters: uploaded.posters,
+                loudness: processing_result.loudness.as_ref().map(Loudness::summary),
+                waveform: uploaded.waveform,
+                image: uploaded.image,
+            })
+            .await?;
+            journal.advance(JobStage::Published)?;
         }
 
+        info!(
+            media_id = %payload.media_id,
+            "Media processing completed successfully"
+        );
         Ok(())
     }
 
-    /// Publish a media.ready event
-    async fn publish_media_ready(
+    /// Publish throttled media.processing_progress events until the processor is dropped
+    async fn publish_progress(
+        message_bus: Arc<dyn MessageBusClient>,
+        mut updates: UnboundedReceiver<ProgressUpdate>,
+        mut throttle: ProgressThrottle,
+    ) {
+        while let Some(update) = updates.recv().await {
+            if !throttle.should_publish(&update, std::time::Instant::now()) {
+                continue;
+            }
+            let payload = MediaProcessingProgressPayload {
+                media_id: update.media_id,
+                rendition: update.rendition,
+                percent: (update.percent * 10.0).round() / 10.0,
+                fps: update.fps,
+                eta_seconds: update.eta_seconds,
+                done: update.done,
+            };
+            let event = match Event::new(EventType::MediaProcessingProgress, "media-processor", payload) {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!(error = %e, "Failed to create progress event");
+                    continue;
+                }
+            };
+            // Progress is best effort; a lost update is replaced by the next one
+            if let Err(e) = message_bus.publish(&event).await {
+                warn!(media_id = %update.media_id, error = %e, "Failed to publish progress event");
+            }
+        }
+    }
+
+    /// Publish a media.failed event
+    async fn publish_media_failed(
         &self,
         media_id: Uuid,
-        playback_urls: PlaybackUrls,
-        thumbnail_urls: Vec<String>,
-        duration: u64,
-        resolutions: Vec<String>,
+        reason: MediaFailureReason,
+        error: &anyhow::Error,
     ) -> anyhow::Result<()> {
-        let payload = MediaReadyPayload {
+        let payload = MediaFailedPayload {
             media_id,
-            playback_urls,
-            thumbnail_urls,
-            duration,
-            resolutions,
+            reason,
+            error: error.to_string(),
+            failed_at: chrono::Utc::now(),
         };
 
+        let event = Event::new(EventType::MediaFailed, "media-processor", payload)
+            .map_err(|e| anyhow::anyhow!("Failed to create event: {}", e))?;
+
+        self.message_bus
+            .publish(&event)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to publish event: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Publish a media.ready event
+    async fn publish_media_ready(&self, payload: MediaReadyPayload) -> anyhow::Result<()> {
         let event = Event::new(EventType::MediaReady, "media-processor", payload)
             .map_err(|e| anyhow::anyhow!("Failed to create event: {}", e))?;
 
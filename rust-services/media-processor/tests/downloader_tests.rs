@@ -1,6 +1,7 @@
 //! File Downloader Unit Tests
 
 use media_processor::downloader::FileDownloader;
+use media_processor::ingest::IngestPolicy;
 use tempfile::TempDir;
 use uuid::Uuid;
 
@@ -48,3 +49,48 @@ async fn test_s3_download_without_config() {
     assert!(error.to_string().contains("S3 client not configured"));
 }
 
+
+#[tokio::test]
+async fn test_http_download_to_private_address_is_blocked() {
+    let downloader = FileDownloader::new(None).with_policy(IngestPolicy::default());
+    let media_id = Uuid::new_v4();
+    let temp_dir = TempDir::new().unwrap();
+    let dest = temp_dir.path().join("test_file");
+
+    for url in ["http://127.0.0.1:9/video.mp4", "http://169.254.169.254/latest/meta-data/"] {
+        let error = downloader.download_file(url, &dest, &media_id).await.unwrap_err();
+        assert!(error.to_string().contains("not a public address"), "{}", error);
+    }
+    assert!(!dest.exists());
+}
+
+#[tokio::test]
+async fn test_http_redirects_are_checked() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Serves a redirect to a host outside the allow list
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 1024];
+        let _ = socket.read(&mut request).await;
+        let response = "HTTP/1.1 302 Found\r\nLocation: http://metadata.internal/\r\nContent-Length: 0\r\n\r\n";
+        socket.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let policy = IngestPolicy {
+        allowed_hosts: vec!["127.0.0.1".to_string()],
+        allow_private_networks: true,
+        ..IngestPolicy::default()
+    };
+    let downloader = FileDownloader::new(None).with_policy(policy);
+    let temp_dir = TempDir::new().unwrap();
+    let dest = temp_dir.path().join("test_file");
+
+    let error = downloader
+        .download_file(&format!("http://127.0.0.1:{}/video.mp4", port), &dest, &Uuid::new_v4())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("metadata.internal is not allowed"), "{}", error);
+}
@@ -26,7 +26,7 @@ async fn test_mp4_generation_end_to_end() {
     // This creates a 10-second test pattern video
     println!("🎬 Creating test video file...");
     let ffmpeg_status = std::process::Command::new("ffmpeg")
-        .args(&[
+        .args([
             "-f", "lavfi",
             "-i", "testsrc=duration=10:size=1280x720:rate=30",
             "-c:v", "libx264",
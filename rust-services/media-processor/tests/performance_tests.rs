@@ -3,8 +3,7 @@
 //! These tests measure actual encoding performance and validate
 //! that optimizations are working correctly.
 
-use media_processor::MediaProcessor;
-use std::path::Path;
+use media_processor::{MediaProcessor, VideoCodec};
 use std::time::Instant;
 use tempfile::TempDir;
 use uuid::Uuid;
@@ -87,7 +86,7 @@ async fn test_parallel_processing_performance() {
 /// Test hardware acceleration detection
 #[test]
 fn test_hardware_acceleration_detection() {
-    let processor = MediaProcessor::new();
+    let _processor = MediaProcessor::new();
     
     // Hardware acceleration should be detected if available
     // This is a basic test - actual hardware usage is tested in benchmarks
@@ -97,10 +96,11 @@ fn test_hardware_acceleration_detection() {
 /// Test VVC codec selection for high-res
 #[test]
 fn test_vvc_codec_selection() {
-    // Test that codec selection works (would need to expose VideoCodec if needed)
-    // For now, just verify MediaProcessor can be created
-    let _processor = MediaProcessor::new();
-    assert!(true, "Codec selection test placeholder");
+    assert_eq!(VideoCodec::recommended_for_resolution("8K"), VideoCodec::VVC);
+    assert_eq!(VideoCodec::recommended_for_resolution("4K"), VideoCodec::VVC);
+    assert_eq!(VideoCodec::recommended_for_resolution("1080p"), VideoCodec::H264);
+    assert_eq!(VideoCodec::VVC.rfc6381_codec(2160), "vvc1.1.L83.CQA");
+    assert_eq!(VideoCodec::H264.rfc6381_codec(1080), "avc1.640028");
 }
 
 /// Test downscaling quality preservation
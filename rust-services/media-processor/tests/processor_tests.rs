@@ -79,3 +79,24 @@ async fn test_audio_only_content_type() {
     }
 }
 
+
+#[tokio::test]
+async fn test_image_content_types() {
+    let processor = MediaProcessor::new();
+    let media_id = Uuid::new_v4();
+
+    // Raster formats are routed to the image pipeline
+    let result = processor
+        .process_media(&media_id, "/path/to/photo.jpg", "image/jpeg")
+        .await;
+    if let Err(e) = result {
+        assert!(!e.to_string().contains("Unsupported content type"),
+            "JPEG content type should be supported");
+    }
+
+    // Vector images are not decoded
+    let result = processor
+        .process_media(&media_id, "/path/to/logo.svg", "image/svg+xml")
+        .await;
+    assert!(result.err().unwrap().to_string().contains("Unsupported content type"));
+}
@@ -0,0 +1,142 @@
+//! Object Storage Integration Tests
+//!
+//! The S3 tests run against a local S3-compatible server and are ignored by
+//! default:
+//!
+//! ```bash
+//! docker run -p 9000:9000 minio/minio server /data
+//! cargo test -p media-processor --test storage_tests -- --ignored
+//! ```
+//!
+//! `MINIO_ENDPOINT`, `MINIO_ACCESS_KEY` and `MINIO_SECRET_KEY` override the
+//! MinIO defaults.
+
+use armoricore_config::ObjectStorageConfig;
+use media_processor::multipart::UploadConfig;
+use media_processor::object_store::MemoryStore;
+use media_processor::s3_store::S3Store;
+use media_processor::storage::ObjectStorage;
+use media_processor::urls::UrlBuilder;
+use rusoto_core::{credential::StaticProvider, request::HttpClient, Region};
+use rusoto_s3::{CreateBucketRequest, GetObjectRequest, HeadObjectRequest, S3Client, S3};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+fn minio_config(bucket: &str) -> ObjectStorageConfig {
+    let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+    ObjectStorageConfig {
+        endpoint: var("MINIO_ENDPOINT", "http://127.0.0.1:9000"),
+        access_key: var("MINIO_ACCESS_KEY", "minioadmin"),
+        secret_key: var("MINIO_SECRET_KEY", "minioadmin"),
+        bucket: bucket.to_string(),
+        region: Some("us-east-1".to_string()),
+    }
+}
+
+fn minio_client(config: &ObjectStorageConfig) -> S3Client {
+    S3Client::new_with(
+        HttpClient::new().unwrap(),
+        StaticProvider::new_minimal(config.access_key.clone(), config.secret_key.clone()),
+        Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: config.endpoint.clone(),
+        },
+    )
+}
+
+async fn create_bucket(config: &ObjectStorageConfig) -> S3Client {
+    let client = minio_client(config);
+    client
+        .create_bucket(CreateBucketRequest {
+            bucket: config.bucket.clone(),
+            ..Default::default()
+        })
+        .await
+        .expect("MinIO not reachable");
+    client
+}
+
+async fn uploaded_bytes(client: &S3Client, bucket: &str, key: &str) -> Vec<u8> {
+    let object = client
+        .get_object(GetObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let mut body = Vec::new();
+    object.body.unwrap().into_async_read().read_to_end(&mut body).await.unwrap();
+    body
+}
+
+#[tokio::test]
+async fn test_upload_to_memory_store() {
+    let store = Arc::new(MemoryStore::new());
+    let storage = ObjectStorage::with_store(store.clone(), UrlBuilder::new("https://cdn.example.com/"));
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("720p.mp4");
+    std::fs::write(&path, b"mp4").unwrap();
+
+    let url = storage.upload_file(&path, "media/1/720p.mp4", "video/mp4").await.unwrap();
+    assert_eq!(url, "https://cdn.example.com/media/1/720p.mp4");
+    let object = store.get("media/1/720p.mp4").unwrap();
+    assert_eq!(object.data, b"mp4");
+    assert_eq!(object.content_type, "video/mp4");
+}
+
+#[tokio::test]
+#[ignore = "requires a local MinIO server"]
+async fn test_multipart_upload_to_minio() {
+    let config = minio_config(&format!("media-{}", Uuid::new_v4()));
+    let client = create_bucket(&config).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("rendition.mp4");
+    let content: Vec<u8> = (0..12 * 1024 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, &content).unwrap();
+
+    let store = S3Store::new(&config).unwrap().with_upload_config(UploadConfig {
+        multipart_threshold: 8 * 1024 * 1024,
+        part_size: 5 * 1024 * 1024,
+        concurrency: 2,
+    });
+    let storage = ObjectStorage::with_store(Arc::new(store), UrlBuilder::for_s3(&config));
+    let url = storage.upload_file(&path, "media/rendition.mp4", "video/mp4").await.unwrap();
+    assert!(url.ends_with("/media/rendition.mp4"));
+
+    let head = client
+        .head_object(HeadObjectRequest {
+            bucket: config.bucket.clone(),
+            key: "media/rendition.mp4".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(head.content_length, Some(content.len() as i64));
+    assert_eq!(head.content_type.as_deref(), Some("video/mp4"));
+    // Multipart ETags end with the part count
+    assert!(head.e_tag.unwrap().ends_with("-3\""));
+    assert_eq!(uploaded_bytes(&client, &config.bucket, "media/rendition.mp4").await, content);
+}
+
+#[tokio::test]
+#[ignore = "requires a local MinIO server"]
+async fn test_small_upload_to_minio() {
+    let config = minio_config(&format!("media-{}", Uuid::new_v4()));
+    let client = create_bucket(&config).await;
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("playlist.m3u8");
+    std::fs::write(&path, "#EXTM3U\n").unwrap();
+
+    let storage = ObjectStorage::new(config.clone());
+    storage
+        .upload_file(&path, "media/playlist.m3u8", "application/vnd.apple.mpegurl")
+        .await
+        .unwrap();
+    assert_eq!(uploaded_bytes(&client, &config.bucket, "media/playlist.m3u8").await, b"#EXTM3U\n");
+}
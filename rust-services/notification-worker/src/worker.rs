@@ -19,7 +19,7 @@ use crate::dead_letter_queue::DeadLetterQueue;
 use crate::rate_limiter::RateLimiter;
 use crate::retry::{is_retryable_error, RetryConfig};
 use crate::sender::NotificationSender;
-use armoricore_keys::init_key_store;
+use armoricore_keys::{init_key_store_for, ServiceIdentity};
 use armoricore_types::{
     schemas::{
         NotificationFailedPayload, NotificationRequestedPayload, NotificationSentPayload,
@@ -62,7 +62,7 @@ impl NotificationWorker {
         };
 
         // Initialize key store (optional - falls back to environment variables)
-        let key_store = init_key_store(None).await.ok();
+        let key_store = init_key_store_for(ServiceIdentity::NOTIFICATION_WORKER, None).await.ok();
         
         // Create sender with key store if available, otherwise use environment variables
         let mut sender = if let Some(ref ks) = key_store {
@@ -17,7 +17,7 @@
 use realtime_media_engine::{
     StreamManager, StreamConfig,
     AudioPipeline, AudioConfig, AudioFrame,
-    PacketRouter, RtpPacket, SrtpConfig,
+    PacketRouter, RtpPacket,
     key_integration::SrtpKeyManager,
     H264PayloadHandler, NalUnit,
     VvcPayloadHandler, VvcNalUnit,
@@ -27,7 +27,7 @@ use realtime_media_engine::{
 use bytes::Bytes;
 use crate::armoricore_media_engine::media_engine_server::MediaEngine;
 use crate::armoricore_media_engine::*;
-use armoricore_keys::{KeyStore, local_store::LocalKeyStore};
+use armoricore_keys::{init_key_store_for, ServiceIdentity};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
@@ -51,11 +51,8 @@ pub struct MediaEngineService {
 impl MediaEngineService {
     /// Create a new media engine service
     pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Initialize key store
-        let storage_path = std::env::var("KEY_STORAGE_PATH")
-            .unwrap_or_else(|_| "./keys".to_string());
-        let local_store = LocalKeyStore::new(&storage_path, None).await?;
-        let key_store = Arc::new(KeyStore::new(Arc::new(local_store)));
+        // Initialize key store (KEY_STORE_BACKEND, KEY_STORE_POLICY_FILE)
+        let key_store = init_key_store_for(ServiceIdentity::REALTIME_MEDIA_ENGINE, None).await?;
         let key_manager = Arc::new(SrtpKeyManager::new(key_store));
 
         Ok(MediaEngineService {
@@ -89,24 +86,18 @@ impl MediaEngine for MediaEngineService {
             crate::armoricore_media_engine::MediaType::Video => realtime_media_engine::MediaType::Video,
         };
 
-        // Create SRTP config if encryption enabled
-        let srtp_config = if config.encryption_enabled {
+        // Create SRTP pipeline if encryption enabled; session keys are derived
+        // by the key store so the master key never leaves it
+        let srtp_pipeline = if config.encryption_enabled {
             let session_id = Uuid::new_v4();
-            // Create keys first
             self.key_manager.create_session_keys(&session_id, config.ssrc).await
                 .map_err(|e| Status::internal(format!("Failed to create keys: {}", e)))?;
-            
-            // Retrieve keys
-            let (master_key, master_salt) = self.key_manager.get_session_keys(&session_id).await
-                .map_err(|e| Status::internal(format!("Failed to get keys: {}", e)))?;
-            
-            // SrtpConfig uses Vec<u8>, so we can use the vectors directly
-            Some(SrtpConfig {
-                master_key,
-                master_salt,
-                ssrc: config.ssrc,
-                roc: 0, // Initial ROC
-            })
+
+            let pipeline = self.key_manager
+                .create_srtp_pipeline(&session_id, config.ssrc, 0)
+                .await
+                .map_err(|e| Status::internal(format!("Failed to derive keys: {}", e)))?;
+            Some(Arc::new(pipeline))
         } else {
             None
         };
@@ -118,12 +109,15 @@ impl MediaEngine for MediaEngineService {
             payload_type: config.payload_type as u8,
             codec: config.codec.clone(),
             bitrate: config.bitrate,
-            srtp_config,
+            srtp_config: None,
         };
 
         let mut manager = self.stream_manager.write().await;
-        let stream_id = manager.create_stream(stream_config)
-            .map_err(|e| Status::internal(format!("Failed to create stream: {}", e)))?;
+        let stream_id = match srtp_pipeline {
+            Some(pipeline) => manager.create_stream_with_srtp(stream_config, pipeline),
+            None => manager.create_stream(stream_config),
+        }
+        .map_err(|e| Status::internal(format!("Failed to create stream: {}", e)))?;
 
         // Create audio pipeline if audio stream
         if media_type == realtime_media_engine::MediaType::Audio {
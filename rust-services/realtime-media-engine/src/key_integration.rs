@@ -17,12 +17,19 @@
 
 
 use crate::error::{MediaEngineError, MediaEngineResult};
-use crate::srtp_pipeline::{SrtpConfig, SrtpPipeline};
-use armoricore_keys::{KeyId, KeyStore};
+use crate::srtp_pipeline::{
+    SrtpPipeline, SrtpSessionKeys, SRTP_AUTH_KEY_LABEL, SRTP_ENCRYPTION_KEY_LABEL,
+};
+use armoricore_keys::{KeyId, KeyStore, KeyType};
 use std::sync::Arc;
 use uuid::Uuid;
 
 /// SRTP key manager that integrates with armoricore-keys
+///
+/// Next to each master key the store keeps its HKDF-Extract output (see
+/// [`SrtpPipeline::master_prk`]). Session keys are derived from that PRK in
+/// place with `KeyStore::derive_key`, so pipelines never read key material
+/// back and agree with [`SrtpPipeline::new`] on the raw master key.
 pub struct SrtpKeyManager {
     key_store: Arc<KeyStore>,
 }
@@ -33,6 +40,17 @@ impl SrtpKeyManager {
         SrtpKeyManager { key_store }
     }
 
+    fn key_ids(session_id: &Uuid) -> (KeyId, KeyId) {
+        (
+            KeyId::from(format!("srtp:master_key:{}", session_id)),
+            KeyId::from(format!("srtp:master_salt:{}", session_id)),
+        )
+    }
+
+    fn prk_id(session_id: &Uuid) -> KeyId {
+        KeyId::from(format!("srtp:master_prk:{}", session_id))
+    }
+
     /// Generate and store SRTP keys for a session
     pub async fn create_session_keys(
         &self,
@@ -41,6 +59,8 @@ impl SrtpKeyManager {
     ) -> MediaEngineResult<(KeyId, KeyId)> {
         use rand::RngCore;
 
+        let (key_id, salt_id) = Self::key_ids(session_id);
+
         // Generate master key (16 bytes for AES-128)
         let mut master_key = vec![0u8; 16];
         rand::thread_rng().fill_bytes(&mut master_key);
@@ -49,41 +69,46 @@ impl SrtpKeyManager {
         let mut master_salt = vec![0u8; 14];
         rand::thread_rng().fill_bytes(&mut master_salt);
 
-        // Create key IDs
-        let key_id = KeyId::from(format!("srtp:master_key:{}", session_id));
-        let salt_id = KeyId::from(format!("srtp:master_salt:{}", session_id));
+        self.key_store
+            .store_key(&key_id, KeyType::Secret, &master_key, None)
+            .await
+            .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
 
-        // Store keys
+        // Session keys are derived from the PRK
+        let prk = SrtpPipeline::master_prk(&master_key);
         self.key_store
-            .store_encryption_key(&key_id, &master_key)
+            .store_key(&Self::prk_id(session_id), KeyType::Secret, &prk, None)
             .await
             .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
 
         self.key_store
-            .store_encryption_key(&salt_id, &master_salt)
+            .store_key(&salt_id, KeyType::Secret, &master_salt, None)
             .await
             .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
 
         Ok((key_id, salt_id))
     }
 
-    /// Retrieve SRTP keys for a session
+    /// Retrieve the raw master key and salt of a session, e.g. for an
+    /// [`SrtpConfig`](crate::srtp_pipeline::SrtpConfig) handed to another component
+    ///
+    /// Pipelines built from these with [`SrtpPipeline::new`] use the same
+    /// SRTP keys as [`Self::create_srtp_pipeline`].
     pub async fn get_session_keys(
         &self,
         session_id: &Uuid,
     ) -> MediaEngineResult<(Vec<u8>, Vec<u8>)> {
-        let key_id = KeyId::from(format!("srtp:master_key:{}", session_id));
-        let salt_id = KeyId::from(format!("srtp:master_salt:{}", session_id));
+        let (key_id, salt_id) = Self::key_ids(session_id);
 
         let master_key = self
             .key_store
-            .get_encryption_key(&key_id)
+            .get_key(&key_id)
             .await
             .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
 
         let master_salt = self
             .key_store
-            .get_encryption_key(&salt_id)
+            .get_key(&salt_id)
             .await
             .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
 
@@ -102,6 +127,45 @@ impl SrtpKeyManager {
         Ok((master_key, master_salt))
     }
 
+    /// Derive SRTP session keys for a session without exposing the master key
+    pub async fn derive_session_keys(
+        &self,
+        session_id: &Uuid,
+    ) -> MediaEngineResult<SrtpSessionKeys> {
+        let (_, salt_id) = Self::key_ids(session_id);
+        let prk_id = Self::prk_id(session_id);
+
+        let encryption_key = self
+            .key_store
+            .derive_key(&prk_id, SRTP_ENCRYPTION_KEY_LABEL, 16)
+            .await
+            .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
+
+        let auth_key = self
+            .key_store
+            .derive_key(&prk_id, SRTP_AUTH_KEY_LABEL, 16)
+            .await
+            .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
+
+        let master_salt = self
+            .key_store
+            .get_key(&salt_id)
+            .await
+            .map_err(|e| MediaEngineError::KeyError(e.to_string()))?;
+
+        if master_salt.len() != 14 {
+            return Err(MediaEngineError::KeyError(
+                format!("Invalid master salt size: {} (expected 14)", master_salt.len())
+            ));
+        }
+
+        Ok(SrtpSessionKeys {
+            encryption_key,
+            auth_key,
+            salt_key: SrtpPipeline::derive_salt_key(&master_salt)?,
+        })
+    }
+
     /// Create SRTP pipeline from stored keys
     pub async fn create_srtp_pipeline(
         &self,
@@ -109,25 +173,17 @@ impl SrtpKeyManager {
         ssrc: u32,
         roc: u32,
     ) -> MediaEngineResult<SrtpPipeline> {
-        let (master_key, master_salt) = self.get_session_keys(session_id).await?;
-
-        let config = SrtpConfig {
-            master_key,
-            master_salt,
-            ssrc,
-            roc,
-        };
-
-        SrtpPipeline::new(config)
+        let keys = self.derive_session_keys(session_id).await?;
+        SrtpPipeline::from_session_keys(keys, ssrc, roc)
     }
 
     /// Delete session keys (cleanup)
     pub async fn delete_session_keys(&self, session_id: &Uuid) -> MediaEngineResult<()> {
-        let key_id = KeyId::from(format!("srtp:master_key:{}", session_id));
-        let salt_id = KeyId::from(format!("srtp:master_salt:{}", session_id));
+        let (key_id, salt_id) = Self::key_ids(session_id);
 
         // Delete keys (ignore errors if they don't exist)
         let _ = self.key_store.delete_key(&key_id).await;
+        let _ = self.key_store.delete_key(&Self::prk_id(session_id)).await;
         let _ = self.key_store.delete_key(&salt_id).await;
 
         Ok(())
@@ -137,6 +193,8 @@ impl SrtpKeyManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rtp_handler::{RtpHeader, RtpPacket};
+    use crate::srtp_pipeline::SrtpConfig;
     use armoricore_keys::{local_store::LocalKeyStore, KeyStore};
     use std::path::PathBuf;
     use tempfile::TempDir;
@@ -152,7 +210,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_create_and_retrieve_session_keys() {
+    async fn test_create_and_derive_session_keys() {
         let (key_store, _temp_dir) = create_test_key_store().await;
         let manager = SrtpKeyManager::new(key_store);
         let session_id = Uuid::new_v4();
@@ -162,10 +220,15 @@ mod tests {
         assert!(!key_id.to_string().is_empty());
         assert!(!salt_id.to_string().is_empty());
 
-        // Retrieve keys
-        let (master_key, master_salt) = manager.get_session_keys(&session_id).await.unwrap();
-        assert_eq!(master_key.len(), 16);
-        assert_eq!(master_salt.len(), 14);
+        // Derive session keys (deterministic for the same master key)
+        let keys = manager.derive_session_keys(&session_id).await.unwrap();
+        assert_eq!(keys.encryption_key.len(), 16);
+        assert_eq!(keys.auth_key.len(), 16);
+        assert_eq!(keys.salt_key.len(), 14);
+        assert_ne!(keys.encryption_key, keys.auth_key);
+
+        let again = manager.derive_session_keys(&session_id).await.unwrap();
+        assert_eq!(keys.encryption_key, again.encryption_key);
     }
 
     #[tokio::test]
@@ -181,5 +244,65 @@ mod tests {
         let pipeline = manager.create_srtp_pipeline(&session_id, 12345, 0).await.unwrap();
         assert_eq!(pipeline.current_roc(), 0);
     }
+
+    #[tokio::test]
+    async fn test_raw_and_derived_pipelines_agree() {
+        let (key_store, _temp_dir) = create_test_key_store().await;
+        let manager = SrtpKeyManager::new(key_store);
+        let session_id = Uuid::new_v4();
+        manager.create_session_keys(&session_id, 12345).await.unwrap();
+
+        let (master_key, master_salt) = manager.get_session_keys(&session_id).await.unwrap();
+        let raw = SrtpPipeline::new(SrtpConfig {
+            master_key,
+            master_salt,
+            ssrc: 12345,
+            roc: 0,
+        })
+        .unwrap();
+        let derived = manager.create_srtp_pipeline(&session_id, 12345, 0).await.unwrap();
+
+        let packet = RtpPacket {
+            header: RtpHeader {
+                version: 2,
+                padding: false,
+                extension: false,
+                csrc_count: 0,
+                marker: false,
+                payload_type: 96,
+                sequence_number: 1,
+                timestamp: 1000,
+                ssrc: 12345,
+                csrc: vec![],
+                extension_header: None,
+            },
+            payload: bytes::Bytes::from("test payload data"),
+        };
+        let encrypted = raw.encrypt(&packet).unwrap();
+        assert_eq!(derived.encrypt(&packet).unwrap(), encrypted);
+        assert_eq!(derived.decrypt(&encrypted).unwrap().payload, packet.payload);
+    }
+
+    #[tokio::test]
+    async fn test_derived_session_key_vectors() {
+        let (key_store, _temp_dir) = create_test_key_store().await;
+        let manager = SrtpKeyManager::new(key_store.clone());
+        let session_id = Uuid::new_v4();
+        let (_, salt_id) = SrtpKeyManager::key_ids(&session_id);
+        let master_key: Vec<u8> = (0x00..0x10).collect();
+        let master_salt: Vec<u8> = (0x10..0x1e).collect();
+        let prk = SrtpPipeline::master_prk(&master_key);
+        key_store
+            .store_key(&SrtpKeyManager::prk_id(&session_id), KeyType::Secret, &prk, None)
+            .await
+            .unwrap();
+        key_store.store_key(&salt_id, KeyType::Secret, &master_salt, None).await.unwrap();
+
+        // Same vectors as the raw master key in srtp_pipeline's tests
+        let keys = manager.derive_session_keys(&session_id).await.unwrap();
+        assert_eq!(hex::encode(&keys.encryption_key), "d7bd24f9ae51b1aecb35389faeaa4f80");
+        assert_eq!(hex::encode(&keys.auth_key), "f90524179138875b77d40578f337370d");
+        assert_eq!(hex::encode(&keys.salt_key), "8c5d379151aabd72e1aac820ffb7");
+    }
 }
 
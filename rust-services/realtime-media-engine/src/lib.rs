@@ -65,7 +65,7 @@ pub mod rtp_retransmission;
 // Re-export main types
 pub use error::{MediaEngineError, MediaEngineResult};
 pub use rtp_handler::{RtpPacket, RtpHeader};
-pub use srtp_pipeline::{SrtpPipeline, SrtpConfig};
+pub use srtp_pipeline::{SrtpPipeline, SrtpConfig, SrtpSessionKeys};
 pub use stream_manager::{StreamManager, StreamConfig, StreamState, MediaType};
 pub use key_integration::SrtpKeyManager;
 pub use audio_pipeline::{AudioPipeline, AudioConfig, AudioFrame};
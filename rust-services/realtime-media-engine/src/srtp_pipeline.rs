@@ -40,6 +40,28 @@ pub struct SrtpConfig {
     pub roc: u32,
 }
 
+/// SRTP session keys derived from a master key and salt
+#[derive(Clone)]
+pub struct SrtpSessionKeys {
+    /// Encryption key (16 bytes)
+    pub encryption_key: Vec<u8>,
+    /// Authentication key (16 bytes)
+    pub auth_key: Vec<u8>,
+    /// Salt key (14 bytes)
+    pub salt_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for SrtpSessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SrtpSessionKeys").finish_non_exhaustive()
+    }
+}
+
+/// HKDF info labels for SRTP session key derivation
+pub const SRTP_ENCRYPTION_KEY_LABEL: &[u8] = b"SRTP encryption key";
+pub const SRTP_AUTH_KEY_LABEL: &[u8] = b"SRTP authentication key";
+pub const SRTP_SALT_KEY_LABEL: &[u8] = b"SRTP salt key";
+
 /// SRTP pipeline for encrypting/decrypting RTP packets
 pub struct SrtpPipeline {
     /// Encryption key (derived from master key)
@@ -78,35 +100,79 @@ impl SrtpPipeline {
         // Derive encryption key using HKDF
         let hkdf = Hkdf::<Sha256>::new(None, &config.master_key);
         let mut encryption_key = vec![0u8; 16];
-        hkdf.expand(b"SRTP encryption key", &mut encryption_key)
+        hkdf.expand(SRTP_ENCRYPTION_KEY_LABEL, &mut encryption_key)
             .map_err(|e| MediaEngineError::SrtpError(format!("HKDF error: {}", e)))?;
 
         // Derive authentication key
         let mut auth_key = vec![0u8; 16];
-        hkdf.expand(b"SRTP authentication key", &mut auth_key)
+        hkdf.expand(SRTP_AUTH_KEY_LABEL, &mut auth_key)
             .map_err(|e| MediaEngineError::SrtpError(format!("HKDF error: {}", e)))?;
 
-        // Derive salt key
-        let mut salt_key = vec![0u8; 14];
-        let hkdf_salt = Hkdf::<Sha256>::new(None, &config.master_salt);
-        hkdf_salt.expand(b"SRTP salt key", &mut salt_key)
-            .map_err(|e| MediaEngineError::SrtpError(format!("HKDF error: {}", e)))?;
+        let salt_key = Self::derive_salt_key(&config.master_salt)?;
+
+        let keys = SrtpSessionKeys {
+            encryption_key,
+            auth_key,
+            salt_key,
+        };
+        Self::from_session_keys(keys, config.ssrc, config.roc)
+    }
+
+    /// Create a pipeline from already-derived session keys
+    ///
+    /// Used when the master key is held by a key management backend that
+    /// derives session keys in place instead of releasing the master key.
+    pub fn from_session_keys(
+        keys: SrtpSessionKeys,
+        ssrc: u32,
+        roc: u32,
+    ) -> MediaEngineResult<Self> {
+        if keys.encryption_key.len() != 16 {
+            return Err(MediaEngineError::SrtpError(format!(
+                "Encryption key must be 16 bytes, got {}",
+                keys.encryption_key.len()
+            )));
+        }
+        if keys.salt_key.len() != 14 {
+            return Err(MediaEngineError::SrtpError(format!(
+                "Salt key must be 14 bytes, got {}",
+                keys.salt_key.len()
+            )));
+        }
 
         // Create cipher
-        let cipher = Aes128Gcm::new_from_slice(&encryption_key)
+        let cipher = Aes128Gcm::new_from_slice(&keys.encryption_key)
             .map_err(|e| MediaEngineError::SrtpError(format!("Cipher init error: {}", e)))?;
 
         Ok(SrtpPipeline {
-            encryption_key,
-            auth_key,
-            salt_key,
-            ssrc: config.ssrc,
+            encryption_key: keys.encryption_key,
+            auth_key: keys.auth_key,
+            salt_key: keys.salt_key,
+            ssrc,
             sequence_number: AtomicU64::new(0),
-            roc: AtomicU64::new(config.roc as u64),
+            roc: AtomicU64::new(roc as u64),
             cipher,
         })
     }
 
+    /// HKDF-Extract of a master key
+    ///
+    /// The encryption and authentication keys are HKDF-Expand of this PRK,
+    /// which is what `KeyStore::derive_key` computes for a stored PRK.
+    pub fn master_prk(master_key: &[u8]) -> Vec<u8> {
+        let (prk, _) = Hkdf::<Sha256>::extract(None, master_key);
+        prk.to_vec()
+    }
+
+    /// Derive the salt key from the master salt
+    pub fn derive_salt_key(master_salt: &[u8]) -> MediaEngineResult<Vec<u8>> {
+        let mut salt_key = vec![0u8; 14];
+        let hkdf_salt = Hkdf::<Sha256>::new(None, master_salt);
+        hkdf_salt.expand(SRTP_SALT_KEY_LABEL, &mut salt_key)
+            .map_err(|e| MediaEngineError::SrtpError(format!("HKDF error: {}", e)))?;
+        Ok(salt_key)
+    }
+
     /// Encrypt RTP packet to SRTP
     pub fn encrypt(&self, packet: &RtpPacket) -> MediaEngineResult<Vec<u8>> {
         // Use packet's sequence number
@@ -247,6 +313,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_session_key_derivation_vectors() {
+        // HKDF-SHA256 Extract (no salt) + Expand; changing these breaks
+        // interoperability with peers already deriving session keys this way
+        let master_key: Vec<u8> = (0x00..0x10).collect();
+        let pipeline = SrtpPipeline::new(SrtpConfig {
+            master_key: master_key.clone(),
+            master_salt: (0x10..0x1e).collect(),
+            ssrc: 12345,
+            roc: 0,
+        })
+        .unwrap();
+        assert_eq!(hex::encode(&pipeline.encryption_key), "d7bd24f9ae51b1aecb35389faeaa4f80");
+        assert_eq!(hex::encode(&pipeline.auth_key), "f90524179138875b77d40578f337370d");
+        assert_eq!(hex::encode(&pipeline.salt_key), "8c5d379151aabd72e1aac820ffb7");
+        assert_eq!(
+            hex::encode(SrtpPipeline::master_prk(&master_key)),
+            "d926952ca8b7ec4a95941d1ada3a5203ceff8cceee34f574d23909eb314c40c0"
+        );
+    }
+
     #[test]
     fn test_srtp_encrypt_decrypt() {
         // Create test keys
@@ -302,5 +389,31 @@ mod tests {
         let last_seq = pipeline.current_sequence();
         assert!(last_seq >= 10, "Expected sequence >= 10, got {}", last_seq);
     }
-}
 
+    #[test]
+    fn test_from_session_keys_roundtrip() {
+        let keys = SrtpSessionKeys {
+            encryption_key: vec![1u8; 16],
+            auth_key: vec![2u8; 16],
+            salt_key: vec![3u8; 14],
+        };
+
+        let sender = SrtpPipeline::from_session_keys(keys.clone(), 12345, 0).unwrap();
+        let receiver = SrtpPipeline::from_session_keys(keys, 12345, 0).unwrap();
+        let packet = create_test_packet();
+
+        let encrypted = sender.encrypt(&packet).unwrap();
+        let decrypted = receiver.decrypt(&encrypted).unwrap();
+        assert_eq!(packet.payload, decrypted.payload);
+    }
+
+    #[test]
+    fn test_from_session_keys_rejects_bad_sizes() {
+        let keys = SrtpSessionKeys {
+            encryption_key: vec![1u8; 15],
+            auth_key: vec![2u8; 16],
+            salt_key: vec![3u8; 14],
+        };
+        assert!(SrtpPipeline::from_session_keys(keys, 1, 0).is_err());
+    }
+}
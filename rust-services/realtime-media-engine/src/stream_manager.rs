@@ -117,6 +117,31 @@ impl StreamManager {
 
     /// Create a new stream
     pub fn create_stream(&mut self, config: StreamConfig) -> MediaEngineResult<Uuid> {
+        // Create SRTP pipeline if encryption is enabled
+        let srtp_pipeline = if let Some(ref srtp_config) = config.srtp_config {
+            Some(Arc::new(SrtpPipeline::new(srtp_config.clone())?))
+        } else {
+            None
+        };
+
+        self.insert_stream(config, srtp_pipeline)
+    }
+
+    /// Create a new stream with an SRTP pipeline built elsewhere
+    /// (e.g. from session keys derived by `SrtpKeyManager`)
+    pub fn create_stream_with_srtp(
+        &mut self,
+        config: StreamConfig,
+        srtp_pipeline: Arc<SrtpPipeline>,
+    ) -> MediaEngineResult<Uuid> {
+        self.insert_stream(config, Some(srtp_pipeline))
+    }
+
+    fn insert_stream(
+        &mut self,
+        config: StreamConfig,
+        srtp_pipeline: Option<Arc<SrtpPipeline>>,
+    ) -> MediaEngineResult<Uuid> {
         // Check if SSRC is already in use
         if self.ssrc_to_stream.contains_key(&config.ssrc) {
             return Err(MediaEngineError::StreamExists {
@@ -126,13 +151,6 @@ impl StreamManager {
 
         let stream_id = Uuid::new_v4();
 
-        // Create SRTP pipeline if encryption is enabled
-        let srtp_pipeline = if let Some(ref srtp_config) = config.srtp_config {
-            Some(Arc::new(SrtpPipeline::new(srtp_config.clone())?))
-        } else {
-            None
-        };
-
         let stream = Stream {
             stream_id,
             config: config.clone(),
@@ -1,7 +1,7 @@
 //! Migration script to import environment variables into KeyManager (Rust)
 //!
 //! Usage:
-//!   cargo run --bin migrate-keys
+//!   armoricore-keys migrate
 //!
 //! This script reads environment variables and stores them in the KeyManager
 //! for secure key management.